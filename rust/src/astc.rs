@@ -0,0 +1,1048 @@
+// ASTC (Adaptive Scalable Texture Compression), 2D footprints only.
+// https://registry.khronos.org/DataFormat/specs/1.3/dataformat.1.3.html#ASTC
+//
+// Every block is 128 bits, covering 4x4 to 12x12 texels, stored in row-major order like
+// tegra_deswizzle produces. The LDR and sRGB profiles decode to RGBA8; blocks that use HDR
+// endpoint modes are illegal there and decode to the error color, magenta. The HDR profile
+// decodes to RGBA16F (half float bits), with NaN as its error color.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::tegra_texture::{CompressionType, get_format_block_width, get_format_block_height};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Profile {
+    Ldr,
+    LdrSrgb,
+    Hdr,
+}
+
+const MAX_BLOCK_TEXELS: usize = 12 * 12;
+const MAX_WEIGHTS: usize = 64;
+const MAX_COLOR_VALUES: usize = 18;
+
+fn get_bits(bits: u128, offs: usize, count: usize) -> u32 {
+    ((bits >> offs) & ((1 << count) - 1)) as u32
+}
+
+// Integer sequence encoding
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum IseKind {
+    Bits,
+    Trits,
+    Quints,
+}
+
+// A range of N levels is stored as a trit (N = 3 << bits), a quint (N = 5 << bits), or just bits.
+fn get_ise_params(levels: u32) -> (IseKind, usize) {
+    if levels.is_multiple_of(3) {
+        (IseKind::Trits, (levels / 3).trailing_zeros() as usize)
+    } else if levels.is_multiple_of(5) {
+        (IseKind::Quints, (levels / 5).trailing_zeros() as usize)
+    } else {
+        (IseKind::Bits, levels.trailing_zeros() as usize)
+    }
+}
+
+fn get_ise_bit_count(num_values: usize, levels: u32) -> usize {
+    let (kind, bits) = get_ise_params(levels);
+    match kind {
+        IseKind::Bits => num_values * bits,
+        IseKind::Trits => num_values * bits + (8 * num_values).div_ceil(5),
+        IseKind::Quints => num_values * bits + (7 * num_values).div_ceil(3),
+    }
+}
+
+fn decode_trits(t: u32) -> [u32; 5] {
+    let bit = |n: u32| (t >> n) & 1;
+    let (c, t4, t3) = if (t >> 2) & 0x07 == 0x07 {
+        (((t >> 5) & 0x07) << 2 | (t & 0x03), 2, 2)
+    } else if (t >> 5) & 0x03 == 0x03 {
+        (t & 0x1F, 2, bit(7))
+    } else {
+        (t & 0x1F, bit(7), (t >> 5) & 0x03)
+    };
+    let cbit = |n: u32| (c >> n) & 1;
+    let (t2, t1, t0) = if c & 0x03 == 0x03 {
+        (2, cbit(4), (cbit(3) << 1) | (cbit(2) & !cbit(3) & 1))
+    } else if (c >> 2) & 0x03 == 0x03 {
+        (2, 2, c & 0x03)
+    } else {
+        (cbit(4), (c >> 2) & 0x03, (cbit(1) << 1) | (cbit(0) & !cbit(1) & 1))
+    };
+    [t0, t1, t2, t3, t4]
+}
+
+fn decode_quints(q: u32) -> [u32; 3] {
+    let bit = |n: u32| (q >> n) & 1;
+    if (q >> 1) & 0x03 == 0x03 && (q >> 5) & 0x03 == 0 {
+        let q2 = (bit(0) << 2) | ((bit(4) & !bit(0) & 1) << 1) | (bit(3) & !bit(0) & 1);
+        return [4, 4, q2];
+    }
+    let (q2, c) = if (q >> 1) & 0x03 == 0x03 {
+        (4, (((q >> 3) & 0x03) << 3) | ((!(q >> 5) & 0x03) << 1) | bit(0))
+    } else {
+        ((q >> 5) & 0x03, q & 0x1F)
+    };
+    let (q1, q0) = if c & 0x07 == 0x05 {
+        (4, (c >> 3) & 0x03)
+    } else {
+        ((c >> 3) & 0x03, c & 0x07)
+    };
+    [q0, q1, q2]
+}
+
+// Decodes num_values values of the given range from the bit_count bits at offs. Trailing bits of
+// a partial trit/quint group read as zero.
+fn decode_ise(bits: u128, offs: usize, bit_count: usize, num_values: usize, levels: u32, dst: &mut [u32]) {
+    let stream = (bits >> offs) & ((1u128 << bit_count) - 1);
+    let (kind, num_bits) = get_ise_params(levels);
+    let mut pos = 0;
+    let mut read = |count: usize| {
+        let v = if pos < 128 { get_bits(stream, pos, count) } else { 0 };
+        pos += count;
+        v
+    };
+
+    match kind {
+        IseKind::Bits => {
+            for v in dst.iter_mut().take(num_values) {
+                *v = read(num_bits);
+            }
+        }
+        IseKind::Trits => {
+            // Five values share 8 bits of trits, interleaved as 2, 2, 1, 2, 1 bits.
+            for group in dst[..num_values].chunks_mut(5) {
+                let mut m = [0; 5];
+                let mut t = 0;
+                for (i, &(t_offs, t_count)) in [(0, 2), (2, 2), (4, 1), (5, 2), (7, 1)].iter().enumerate() {
+                    m[i] = read(num_bits);
+                    t |= read(t_count) << t_offs;
+                }
+                let trits = decode_trits(t);
+                for (i, v) in group.iter_mut().enumerate() {
+                    *v = (trits[i] << num_bits) | m[i];
+                }
+            }
+        }
+        IseKind::Quints => {
+            // Three values share 7 bits of quints, interleaved as 3, 2, 2 bits.
+            for group in dst[..num_values].chunks_mut(3) {
+                let mut m = [0; 3];
+                let mut q = 0;
+                for (i, &(q_offs, q_count)) in [(0, 3), (3, 2), (5, 2)].iter().enumerate() {
+                    m[i] = read(num_bits);
+                    q |= read(q_count) << q_offs;
+                }
+                let quints = decode_quints(q);
+                for (i, v) in group.iter_mut().enumerate() {
+                    *v = (quints[i] << num_bits) | m[i];
+                }
+            }
+        }
+    }
+}
+
+fn replicate_bits(v: u32, num_bits: usize, to_bits: usize) -> u32 {
+    let mut result = 0;
+    let mut filled = 0;
+    while filled < to_bits {
+        result = (result << num_bits) | v;
+        filled += num_bits;
+    }
+    result >> (filled - to_bits)
+}
+
+// Color endpoint values are unquantized to 0-255.
+fn unquantize_color(v: u32, levels: u32) -> u32 {
+    let (kind, num_bits) = get_ise_params(levels);
+    if kind == IseKind::Bits {
+        return replicate_bits(v, num_bits, 8);
+    }
+
+    let m = v & ((1 << num_bits) - 1);
+    let d = v >> num_bits;
+    let a = if m & 1 != 0 { 0x1FF } else { 0 };
+    let h = m >> 1;
+    let (b, c) = match (kind, num_bits) {
+        (IseKind::Trits, 1) => (0, 204),
+        (IseKind::Quints, 1) => (0, 113),
+        (IseKind::Trits, 2) => (h * 0x116, 93),
+        (IseKind::Quints, 2) => (h * 0x10C, 54),
+        (IseKind::Trits, 3) => ((h << 7) | (h << 2) | h, 44),
+        (IseKind::Quints, 3) => ((h << 7) | (h << 1) | (h >> 1), 26),
+        (IseKind::Trits, 4) => ((h << 6) | h, 22),
+        (IseKind::Quints, 4) => ((h << 6) | (h >> 1), 13),
+        (IseKind::Trits, 5) => ((h << 5) | (h >> 2), 11),
+        (IseKind::Quints, 5) => ((h << 5) | (h >> 3), 6),
+        (IseKind::Trits, 6) => ((h << 4) | (h >> 4), 5),
+        _ => unreachable!(),
+    };
+    let t = (d * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+// Weights are unquantized to 0-64.
+fn unquantize_weight(v: u32, levels: u32) -> u32 {
+    let (kind, num_bits) = get_ise_params(levels);
+    let w = match (kind, num_bits) {
+        (IseKind::Bits, _) => replicate_bits(v, num_bits, 6),
+        (IseKind::Trits, 0) => [0, 32, 63][v as usize],
+        (IseKind::Quints, 0) => [0, 16, 32, 47, 63][v as usize],
+        _ => {
+            let m = v & ((1 << num_bits) - 1);
+            let d = v >> num_bits;
+            let a = if m & 1 != 0 { 0x7F } else { 0 };
+            let h = m >> 1;
+            let (b, c) = match (kind, num_bits) {
+                (IseKind::Trits, 1) => (0, 50),
+                (IseKind::Quints, 1) => (0, 28),
+                (IseKind::Trits, 2) => (h * 0x45, 23),
+                (IseKind::Quints, 2) => (h * 0x42, 13),
+                (IseKind::Trits, 3) => ((h << 5) | h, 11),
+                _ => unreachable!(),
+            };
+            let t = (d * c + b) ^ a;
+            (a & 0x20) | (t >> 2)
+        }
+    };
+    if w > 32 { w + 1 } else { w }
+}
+
+// Block mode
+
+struct BlockMode {
+    grid_width: usize,
+    grid_height: usize,
+    dual_plane: bool,
+    weight_levels: u32,
+}
+
+static WEIGHT_LEVELS: [[u32; 8]; 2] = [
+    [0, 0, 2, 3, 4, 5, 6, 8],
+    [0, 0, 10, 12, 16, 20, 24, 32],
+];
+
+fn decode_block_mode(mode: u32) -> Option<BlockMode> {
+    let bit = |n: u32| (mode >> n) & 1;
+    let a = (mode >> 5) & 0x03;
+    let mut precision = bit(9);
+    let mut dual_plane = bit(10) != 0;
+    let (range, grid_width, grid_height);
+    if mode & 0x03 != 0 {
+        range = bit(4) | ((mode & 0x03) << 1);
+        let b = (mode >> 7) & 0x03;
+        let (w, h) = match (mode >> 2) & 0x03 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if bit(8) == 0 => (a + 2, bit(7) + 6),
+            _ => (bit(7) + 2, a + 2),
+        };
+        grid_width = w;
+        grid_height = h;
+    } else {
+        range = bit(4) | (((mode >> 2) & 0x03) << 1);
+        if range < 2 {
+            return None;
+        }
+        let (w, h) = match (mode >> 7) & 0x03 {
+            0 => (12, a + 2),
+            1 => (a + 2, 12),
+            2 => {
+                // Bits 9 and 10 hold the height here, so there's no dual plane or high precision.
+                precision = 0;
+                dual_plane = false;
+                (a + 6, ((mode >> 9) & 0x03) + 6)
+            }
+            _ => match a {
+                0 => (6, 10),
+                1 => (10, 6),
+                _ => return None,
+            },
+        };
+        grid_width = w;
+        grid_height = h;
+    }
+
+    Some(BlockMode {
+        grid_width: grid_width as usize,
+        grid_height: grid_height as usize,
+        dual_plane,
+        weight_levels: WEIGHT_LEVELS[precision as usize][range as usize],
+    })
+}
+
+// Partitions
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+fn select_partition(seed: u32, x: u32, y: u32, num_partitions: u32, small_block: bool) -> usize {
+    let (x, y, z) = if small_block { (x << 1, y << 1, 0) } else { (x, y, 0) };
+    let seed = seed + (num_partitions - 1) * 1024;
+    let rnum = hash52(seed);
+
+    let mut seeds = [0u32; 12];
+    for (i, s) in seeds.iter_mut().enumerate().take(8) {
+        *s = (rnum >> (i * 4)) & 0x0F;
+    }
+    seeds[8] = (rnum >> 18) & 0x0F;
+    seeds[9] = (rnum >> 22) & 0x0F;
+    seeds[10] = (rnum >> 26) & 0x0F;
+    seeds[11] = rnum.rotate_right(30) & 0x0F;
+    for s in seeds.iter_mut() {
+        *s *= *s;
+    }
+
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (if seed & 2 != 0 { 4 } else { 5 }, if num_partitions == 3 { 6 } else { 5 })
+    } else {
+        (if num_partitions == 3 { 6 } else { 5 }, if seed & 2 != 0 { 4 } else { 5 })
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= if i >= 8 { sh3 } else if i % 2 == 0 { sh1 } else { sh2 };
+    }
+
+    let a = (seeds[0] * x + seeds[1] * y + seeds[10] * z + (rnum >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + seeds[11] * z + (rnum >> 10)) & 0x3F;
+    let c = if num_partitions >= 3 { (seeds[4] * x + seeds[5] * y + seeds[8] * z + (rnum >> 6)) & 0x3F } else { 0 };
+    let d = if num_partitions >= 4 { (seeds[6] * x + seeds[7] * y + seeds[9] * z + (rnum >> 2)) & 0x3F } else { 0 };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+// Color endpoints
+
+struct Endpoints {
+    // 16-bit values: UNORM16 for LDR channels, and the logarithmic encoding for HDR channels.
+    e0: [i32; 4],
+    e1: [i32; 4],
+    rgb_hdr: bool,
+    alpha_hdr: bool,
+}
+
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let a = (a >> 1) & 0x3F;
+    let a = if a & 0x20 != 0 { a - 0x40 } else { a };
+    (a, b)
+}
+
+fn blue_contract(r: i32, g: i32, b: i32, a: i32) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+fn clamp_ldr(c: [i32; 4]) -> [i32; 4] {
+    [c[0].clamp(0, 0xFF), c[1].clamp(0, 0xFF), c[2].clamp(0, 0xFF), c[3].clamp(0, 0xFF)]
+}
+
+// Returns the two 8-bit endpoints of an LDR color endpoint mode.
+fn decode_ldr_endpoints(cem: u32, v: &[i32]) -> ([i32; 4], [i32; 4]) {
+    match cem {
+        // Luminance, direct.
+        0 => ([v[0], v[0], v[0], 0xFF], [v[1], v[1], v[1], 0xFF]),
+        // Luminance, base+offset.
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xC0);
+            let l1 = (l0 + (v[1] & 0x3F)).min(0xFF);
+            ([l0, l0, l0, 0xFF], [l1, l1, l1, 0xFF])
+        }
+        // Luminance+alpha, direct.
+        4 => ([v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]),
+        // Luminance+alpha, base+offset.
+        5 => {
+            let (d0, b0) = bit_transfer_signed(v[1], v[0]);
+            let (d1, b1) = bit_transfer_signed(v[3], v[2]);
+            (clamp_ldr([b0, b0, b0, b1]), clamp_ldr([b0 + d0, b0 + d0, b0 + d0, b1 + d1]))
+        }
+        // RGB, base+scale.
+        6 => ([(v[0] * v[3]) >> 8, (v[1] * v[3]) >> 8, (v[2] * v[3]) >> 8, 0xFF], [v[0], v[1], v[2], 0xFF]),
+        // RGB(A), direct.
+        8 | 12 => {
+            let (a0, a1) = if cem == 12 { (v[6], v[7]) } else { (0xFF, 0xFF) };
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                ([v[0], v[2], v[4], a0], [v[1], v[3], v[5], a1])
+            } else {
+                (blue_contract(v[1], v[3], v[5], a1), blue_contract(v[0], v[2], v[4], a0))
+            }
+        }
+        // RGB(A), base+offset.
+        9 | 13 => {
+            let (d0, b0) = bit_transfer_signed(v[1], v[0]);
+            let (d1, b1) = bit_transfer_signed(v[3], v[2]);
+            let (d2, b2) = bit_transfer_signed(v[5], v[4]);
+            let (d3, b3) = if cem == 13 { bit_transfer_signed(v[7], v[6]) } else { (0, 0xFF) };
+            if d0 + d1 + d2 >= 0 {
+                (clamp_ldr([b0, b1, b2, b3]), clamp_ldr([b0 + d0, b1 + d1, b2 + d2, b3 + d3]))
+            } else {
+                (clamp_ldr(blue_contract(b0 + d0, b1 + d1, b2 + d2, b3 + d3)), clamp_ldr(blue_contract(b0, b1, b2, b3)))
+            }
+        }
+        // RGB, base+scale, plus two alpha.
+        10 => ([(v[0] * v[3]) >> 8, (v[1] * v[3]) >> 8, (v[2] * v[3]) >> 8, v[4]], [v[0], v[1], v[2], v[5]]),
+        _ => unreachable!(),
+    }
+}
+
+fn decode_hdr_luminance_large_range(v: &[i32]) -> (i32, i32) {
+    if v[1] >= v[0] {
+        (v[0] << 4, v[1] << 4)
+    } else {
+        ((v[1] << 4) + 8, (v[0] << 4) - 8)
+    }
+}
+
+fn decode_hdr_luminance_small_range(v: &[i32]) -> (i32, i32) {
+    let (y0, d) = if v[0] & 0x80 != 0 {
+        (((v[1] & 0xE0) << 4) | ((v[0] & 0x7F) << 2), (v[1] & 0x1F) << 2)
+    } else {
+        (((v[1] & 0xF0) << 4) | ((v[0] & 0x7F) << 1), (v[1] & 0x0F) << 1)
+    };
+    (y0, (y0 + d).min(0xFFF))
+}
+
+// Returns 12-bit RGB endpoints.
+fn decode_hdr_rgb_base_scale(v: &[i32]) -> ([i32; 3], [i32; 3]) {
+    let mode_val = ((v[0] & 0xC0) >> 6) | ((v[1] & 0x80) >> 5) | ((v[2] & 0x80) >> 4);
+    let (major_component, mode) = if mode_val & 0x0C != 0x0C {
+        (mode_val >> 2, mode_val & 0x03)
+    } else if mode_val != 0x0F {
+        (mode_val & 0x03, 4)
+    } else {
+        (0, 5)
+    };
+
+    let mut red = v[0] & 0x3F;
+    let mut green = v[1] & 0x1F;
+    let mut blue = v[2] & 0x1F;
+    let mut scale = v[3] & 0x1F;
+
+    let bit0 = (v[1] >> 6) & 1;
+    let bit1 = (v[1] >> 5) & 1;
+    let bit2 = (v[2] >> 6) & 1;
+    let bit3 = (v[2] >> 5) & 1;
+    let bit4 = (v[3] >> 7) & 1;
+    let bit5 = (v[3] >> 6) & 1;
+    let bit6 = (v[3] >> 5) & 1;
+
+    // Which of the variable bits each field gets depends on the mode.
+    let one_hot = 1 << mode;
+    if one_hot & 0x30 != 0 { green |= bit0 << 6; }
+    if one_hot & 0x3A != 0 { green |= bit1 << 5; }
+    if one_hot & 0x30 != 0 { blue |= bit2 << 6; }
+    if one_hot & 0x3A != 0 { blue |= bit3 << 5; }
+    if one_hot & 0x3D != 0 { scale |= bit6 << 5; }
+    if one_hot & 0x2D != 0 { scale |= bit5 << 6; }
+    if one_hot & 0x04 != 0 { scale |= bit4 << 7; }
+    if one_hot & 0x3B != 0 { red |= bit4 << 6; }
+    if one_hot & 0x04 != 0 { red |= bit3 << 6; }
+    if one_hot & 0x10 != 0 { red |= bit5 << 7; }
+    if one_hot & 0x0F != 0 { red |= bit2 << 7; }
+    if one_hot & 0x05 != 0 { red |= bit1 << 8; }
+    if one_hot & 0x0A != 0 { red |= bit0 << 8; }
+    if one_hot & 0x05 != 0 { red |= bit0 << 9; }
+    if one_hot & 0x02 != 0 { red |= bit6 << 9; }
+    if one_hot & 0x01 != 0 { red |= bit3 << 10; }
+    if one_hot & 0x02 != 0 { red |= bit5 << 10; }
+
+    let shift = [1, 1, 2, 3, 4, 5][mode as usize];
+    red <<= shift;
+    green <<= shift;
+    blue <<= shift;
+    scale <<= shift;
+
+    // Except in mode 5, green and blue are stored as differences from red.
+    if mode != 5 {
+        green = red - green;
+        blue = red - blue;
+    }
+
+    let mut e1 = [red, green, blue];
+    if major_component != 0 {
+        e1.swap(0, major_component as usize);
+    }
+    let e0 = [e1[0] - scale, e1[1] - scale, e1[2] - scale];
+    (
+        [e0[0].clamp(0, 0xFFF), e0[1].clamp(0, 0xFFF), e0[2].clamp(0, 0xFFF)],
+        [e1[0].clamp(0, 0xFFF), e1[1].clamp(0, 0xFFF), e1[2].clamp(0, 0xFFF)],
+    )
+}
+
+// Returns 12-bit RGB endpoints.
+fn decode_hdr_rgb_direct(v: &[i32]) -> ([i32; 3], [i32; 3]) {
+    let mode_val = ((v[1] & 0x80) >> 7) | ((v[2] & 0x80) >> 6) | ((v[3] & 0x80) >> 5);
+    let major_component = ((v[4] & 0x80) >> 7) | ((v[5] & 0x80) >> 6);
+    if major_component == 3 {
+        return (
+            [v[0] << 4, v[2] << 4, (v[4] & 0x7F) << 5],
+            [v[1] << 4, v[3] << 4, (v[5] & 0x7F) << 5],
+        );
+    }
+
+    let mut a = v[0] | ((v[1] & 0x40) << 2);
+    let mut b0 = v[2] & 0x3F;
+    let mut b1 = v[3] & 0x3F;
+    let mut c = v[1] & 0x3F;
+    let mut d0 = v[4] & 0x7F;
+    let mut d1 = v[5] & 0x7F;
+
+    let d_bits = [7, 6, 7, 6, 5, 6, 5, 6][mode_val as usize];
+
+    let bit0 = (v[2] >> 6) & 1;
+    let bit1 = (v[3] >> 6) & 1;
+    let bit2 = (v[4] >> 6) & 1;
+    let bit3 = (v[5] >> 6) & 1;
+    let bit4 = (v[4] >> 5) & 1;
+    let bit5 = (v[5] >> 5) & 1;
+
+    // Which of the variable bits each field gets depends on the mode.
+    let one_hot = 1 << mode_val;
+    if one_hot & 0xA4 != 0 { a |= bit0 << 9; }
+    if one_hot & 0x08 != 0 { a |= bit2 << 9; }
+    if one_hot & 0x50 != 0 { a |= bit4 << 9; }
+    if one_hot & 0x50 != 0 { a |= bit5 << 10; }
+    if one_hot & 0xA0 != 0 { a |= bit1 << 10; }
+    if one_hot & 0xC0 != 0 { a |= bit2 << 11; }
+    if one_hot & 0x04 != 0 { c |= bit1 << 6; }
+    if one_hot & 0xE8 != 0 { c |= bit3 << 6; }
+    if one_hot & 0x20 != 0 { c |= bit2 << 7; }
+    if one_hot & 0x5B != 0 { b0 |= bit0 << 6; }
+    if one_hot & 0x5B != 0 { b1 |= bit1 << 6; }
+    if one_hot & 0x12 != 0 { b0 |= bit2 << 7; }
+    if one_hot & 0x12 != 0 { b1 |= bit3 << 7; }
+    if one_hot & 0xAF != 0 { d0 |= bit4 << 5; }
+    if one_hot & 0xAF != 0 { d1 |= bit5 << 5; }
+    if one_hot & 0x05 != 0 { d0 |= bit2 << 6; }
+    if one_hot & 0x05 != 0 { d1 |= bit3 << 6; }
+
+    let d0 = sign_extend(d0, d_bits);
+    let d1 = sign_extend(d1, d_bits);
+
+    let shift = (mode_val >> 1) ^ 3;
+    let (a, b0, b1, c, d0, d1) = (a << shift, b0 << shift, b1 << shift, c << shift, d0 << shift, d1 << shift);
+
+    let mut e0 = [a - c, a - b0 - c - d0, a - b1 - c - d1];
+    let mut e1 = [a, a - b0, a - b1];
+    if major_component != 0 {
+        e0.swap(0, major_component as usize);
+        e1.swap(0, major_component as usize);
+    }
+    (
+        [e0[0].clamp(0, 0xFFF), e0[1].clamp(0, 0xFFF), e0[2].clamp(0, 0xFFF)],
+        [e1[0].clamp(0, 0xFFF), e1[1].clamp(0, 0xFFF), e1[2].clamp(0, 0xFFF)],
+    )
+}
+
+// Returns 12-bit alpha endpoints.
+fn decode_hdr_alpha(v6: i32, v7: i32) -> (i32, i32) {
+    let selector = ((v6 >> 7) & 1) | ((v7 >> 6) & 2);
+    let v6 = v6 & 0x7F;
+    let v7 = v7 & 0x7F;
+    if selector == 3 {
+        return (v6 << 5, v7 << 5);
+    }
+
+    let a0 = v6 | ((v7 << (selector + 1)) & 0x780);
+    let d = v7 & (0x3F >> selector);
+    let d = (d ^ (0x20 >> selector)) - (0x20 >> selector);
+    let a0 = a0 << (4 - selector);
+    let a1 = a0 + (d << (4 - selector));
+    (a0, a1.clamp(0, 0xFFF))
+}
+
+fn sign_extend(v: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (v << shift) >> shift
+}
+
+fn decode_endpoints(cem: u32, v: &[i32], profile: Profile) -> Option<Endpoints> {
+    let (e0, e1, rgb_hdr, alpha_hdr) = match cem {
+        2 | 3 => {
+            let (y0, y1) = if cem == 2 { decode_hdr_luminance_large_range(v) } else { decode_hdr_luminance_small_range(v) };
+            ([y0 << 4, y0 << 4, y0 << 4, 0x7800], [y1 << 4, y1 << 4, y1 << 4, 0x7800], true, true)
+        }
+        7 | 11 | 14 | 15 => {
+            let (c0, c1) = if cem == 7 { decode_hdr_rgb_base_scale(v) } else { decode_hdr_rgb_direct(v) };
+            let (a0, a1, alpha_hdr) = match cem {
+                // LDR alpha.
+                14 => (v[6] * 0x101, v[7] * 0x101, false),
+                15 => {
+                    let (a0, a1) = decode_hdr_alpha(v[6], v[7]);
+                    (a0 << 4, a1 << 4, true)
+                }
+                _ => (0x7800, 0x7800, true),
+            };
+            ([c0[0] << 4, c0[1] << 4, c0[2] << 4, a0], [c1[0] << 4, c1[1] << 4, c1[2] << 4, a1], true, alpha_hdr)
+        }
+        _ => {
+            let (e0, e1) = decode_ldr_endpoints(cem, v);
+            let expand = |c: i32| if profile == Profile::LdrSrgb { (c << 8) | 0x80 } else { c * 0x101 };
+            (
+                [expand(e0[0]), expand(e0[1]), expand(e0[2]), expand(e0[3])],
+                [expand(e1[0]), expand(e1[1]), expand(e1[2]), expand(e1[3])],
+                false, false,
+            )
+        }
+    };
+
+    if rgb_hdr && profile != Profile::Hdr {
+        return None;
+    }
+    Some(Endpoints { e0, e1, rgb_hdr, alpha_hdr })
+}
+
+// Output conversion
+
+fn unorm16_to_sf16(p: u32) -> u16 {
+    if p == 0xFFFF {
+        return 0x3C00;
+    }
+    if p < 4 {
+        return (p << 8) as u16;
+    }
+    let lz = p.leading_zeros() - 16;
+    let m = ((p << (lz + 1)) & 0xFFFF) >> 6;
+    (((14 - lz) << 10) | m) as u16
+}
+
+fn lns_to_sf16(p: u32) -> u16 {
+    let mc = p & 0x7FF;
+    let ec = p >> 11;
+    let mt = if mc < 512 {
+        3 * mc
+    } else if mc < 1536 {
+        4 * mc - 512
+    } else {
+        5 * mc - 2048
+    };
+    (((ec << 10) | (mt >> 3)) as u16).min(0x7BFF)
+}
+
+// Block decoding
+
+// Texels are 16 bits per channel: UNORM16 for the LDR profiles, half floats for HDR.
+type Texel = [u16; 4];
+
+fn get_error_color(profile: Profile) -> Texel {
+    match profile {
+        Profile::Hdr => [0xFFFF; 4],
+        _ => [0xFFFF, 0x0000, 0xFFFF, 0xFFFF],
+    }
+}
+
+fn decode_void_extent(bits: u128, profile: Profile, dst: &mut [Texel]) -> Option<()> {
+    // Bits 10 and 11 are reserved, and must be set.
+    if get_bits(bits, 10, 2) != 0x03 {
+        return None;
+    }
+
+    let s_min = get_bits(bits, 12, 13);
+    let s_max = get_bits(bits, 25, 13);
+    let t_min = get_bits(bits, 38, 13);
+    let t_max = get_bits(bits, 51, 13);
+    let all_ones = s_min == 0x1FFF && s_max == 0x1FFF && t_min == 0x1FFF && t_max == 0x1FFF;
+    if !all_ones && (s_min >= s_max || t_min >= t_max) {
+        return None;
+    }
+
+    let hdr = get_bits(bits, 9, 1) != 0;
+    let mut color = [0; 4];
+    for (i, c) in color.iter_mut().enumerate() {
+        let v = get_bits(bits, 64 + i * 16, 16);
+        *c = match (profile, hdr) {
+            (Profile::Hdr, true) => v as u16,
+            (Profile::Hdr, false) => unorm16_to_sf16(v),
+            (_, true) => return None,
+            (_, false) => v as u16,
+        };
+    }
+    for texel in dst.iter_mut() {
+        *texel = color;
+    }
+    Some(())
+}
+
+// Bilinearly infills the weight grid to the block's texels.
+fn infill_weights(grid: &[u32], grid_width: usize, grid_height: usize, block_width: usize, block_height: usize, dst: &mut [u32]) {
+    let ds = (1024 + block_width / 2) / (block_width - 1);
+    let dt = (1024 + block_height / 2) / (block_height - 1);
+    for t in 0..block_height {
+        for s in 0..block_width {
+            let gs = (ds * s * (grid_width - 1) + 32) >> 6;
+            let gt = (dt * t * (grid_height - 1) + 32) >> 6;
+            let (js, fs) = (gs >> 4, (gs & 0x0F) as u32);
+            let (jt, ft) = (gt >> 4, (gt & 0x0F) as u32);
+
+            // The far neighbors only contribute when their fraction is nonzero.
+            let get = |x: usize, y: usize| if x < grid_width && y < grid_height { grid[y * grid_width + x] } else { 0 };
+            let w11 = (fs * ft + 8) >> 4;
+            let w10 = ft - w11;
+            let w01 = fs - w11;
+            let w00 = 16 + w11 - fs - ft;
+            let sum = get(js, jt) * w00 + get(js + 1, jt) * w01 + get(js, jt + 1) * w10 + get(js + 1, jt + 1) * w11;
+            dst[t * block_width + s] = (sum + 8) >> 4;
+        }
+    }
+}
+
+fn try_decode_block(src: &[u8], block_width: usize, block_height: usize, profile: Profile, dst: &mut [Texel]) -> Option<()> {
+    let mut block = [0; 16];
+    block.copy_from_slice(&src[..16]);
+    let bits = u128::from_le_bytes(block);
+
+    let mode = get_bits(bits, 0, 11);
+    if mode & 0x1FF == 0x1FC {
+        return decode_void_extent(bits, profile, dst);
+    }
+
+    let block_mode = decode_block_mode(mode)?;
+    if block_mode.grid_width > block_width || block_mode.grid_height > block_height {
+        return None;
+    }
+    let num_planes = if block_mode.dual_plane { 2 } else { 1 };
+    let num_weights = block_mode.grid_width * block_mode.grid_height * num_planes;
+    if num_weights > MAX_WEIGHTS {
+        return None;
+    }
+    let weight_bits = get_ise_bit_count(num_weights, block_mode.weight_levels);
+    if !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+
+    let num_partitions = get_bits(bits, 11, 2) as usize + 1;
+    if block_mode.dual_plane && num_partitions == 4 {
+        return None;
+    }
+
+    // Config data that doesn't fit at the bottom of the block goes just below the weights.
+    let mut below_weights = 128 - weight_bits;
+    let mut cems = [0; 4];
+    let partition_seed;
+    let color_offs;
+    if num_partitions == 1 {
+        partition_seed = 0;
+        cems[0] = get_bits(bits, 13, 4);
+        color_offs = 17;
+    } else {
+        partition_seed = get_bits(bits, 13, 10);
+        let cem_bits = get_bits(bits, 23, 6);
+        let base_class = cem_bits & 0x03;
+        if base_class == 0 {
+            for cem in cems.iter_mut() {
+                *cem = cem_bits >> 2;
+            }
+        } else {
+            // Each partition's mode is one of four in its class, the base class or the next one.
+            let extra_bits = 3 * num_partitions - 4;
+            below_weights -= extra_bits;
+            let cem_bits = cem_bits | (get_bits(bits, below_weights, extra_bits) << 6);
+            for (i, cem) in cems.iter_mut().enumerate().take(num_partitions) {
+                let class = base_class - 1 + ((cem_bits >> (2 + i)) & 1);
+                let mode = (cem_bits >> (2 + num_partitions + i * 2)) & 0x03;
+                *cem = (class << 2) | mode;
+            }
+        }
+        color_offs = 29;
+    }
+
+    let color_component = if block_mode.dual_plane {
+        below_weights -= 2;
+        Some(get_bits(bits, below_weights, 2) as usize)
+    } else {
+        None
+    };
+
+    // Color endpoints use the largest range that fits in the remaining bits.
+    let num_color_values: usize = cems[..num_partitions].iter().map(|&cem| ((cem as usize >> 2) + 1) * 2).sum();
+    if num_color_values > MAX_COLOR_VALUES || below_weights < color_offs {
+        return None;
+    }
+    let color_bits = below_weights - color_offs;
+    let color_levels = [256, 192, 160, 128, 96, 80, 64, 48, 40, 32, 24, 20, 16, 12, 10, 8, 6].iter()
+        .copied()
+        .find(|&levels| get_ise_bit_count(num_color_values, levels) <= color_bits)?;
+
+    let mut color_values = [0; MAX_COLOR_VALUES];
+    decode_ise(bits, color_offs, get_ise_bit_count(num_color_values, color_levels), num_color_values, color_levels, &mut color_values);
+    let mut color_values_i32 = [0; MAX_COLOR_VALUES];
+    for (dst, &v) in color_values_i32.iter_mut().zip(color_values[..num_color_values].iter()) {
+        *dst = unquantize_color(v, color_levels) as i32;
+    }
+
+    let mut endpoints = Vec::with_capacity(num_partitions);
+    let mut value_offs = 0;
+    for &cem in cems[..num_partitions].iter() {
+        endpoints.push(decode_endpoints(cem, &color_values_i32[value_offs..], profile)?);
+        value_offs += ((cem as usize >> 2) + 1) * 2;
+    }
+
+    // Weights are stored bit-reversed from the top of the block, interleaved between planes.
+    let mut weight_values = [0; MAX_WEIGHTS];
+    decode_ise(bits.reverse_bits(), 0, weight_bits, num_weights, block_mode.weight_levels, &mut weight_values);
+    let num_grid_weights = num_weights / num_planes;
+    let mut plane_weights = [[0; MAX_BLOCK_TEXELS]; 2];
+    for (plane, weights) in plane_weights.iter_mut().enumerate().take(num_planes) {
+        let mut grid = [0; MAX_WEIGHTS];
+        for (i, w) in grid.iter_mut().enumerate().take(num_grid_weights) {
+            *w = unquantize_weight(weight_values[i * num_planes + plane], block_mode.weight_levels);
+        }
+        infill_weights(&grid, block_mode.grid_width, block_mode.grid_height, block_width, block_height, weights);
+    }
+
+    let small_block = block_width * block_height < 31;
+    for y in 0..block_height {
+        for x in 0..block_width {
+            let i = y * block_width + x;
+            let partition = if num_partitions > 1 {
+                select_partition(partition_seed, x as u32, y as u32, num_partitions as u32, small_block)
+            } else {
+                0
+            };
+            let ep = &endpoints[partition];
+            for (c, out) in dst[i].iter_mut().enumerate() {
+                let plane = if color_component == Some(c) { 1 } else { 0 };
+                let w = plane_weights[plane][i] as i32;
+                let v = ((ep.e0[c] * (64 - w) + ep.e1[c] * w + 32) >> 6) as u32;
+                let hdr = if c == 3 { ep.alpha_hdr } else { ep.rgb_hdr };
+                *out = match (profile, hdr) {
+                    (Profile::Hdr, true) => lns_to_sf16(v),
+                    (Profile::Hdr, false) => unorm16_to_sf16(v),
+                    _ => v as u16,
+                };
+            }
+        }
+    }
+
+    Some(())
+}
+
+// Fails on block sizes outside 4..=12. Blocks past the end of src decode as the error color.
+fn decode_surface_texels(src: &[u8], block_width: usize, block_height: usize, w: usize, h: usize, profile: Profile, mut write: impl FnMut(usize, &Texel)) -> Option<()> {
+    if !(4..=12).contains(&block_width) || !(4..=12).contains(&block_height) {
+        return None;
+    }
+    let mut texels = [[0; 4]; MAX_BLOCK_TEXELS];
+    let width_in_blocks = w.div_ceil(block_width);
+    for by in 0..h.div_ceil(block_height) {
+        for bx in 0..width_in_blocks {
+            let src_offs = (by * width_in_blocks + bx) * 16;
+            let texels = &mut texels[..block_width * block_height];
+            let block = src.get(src_offs..src_offs + 16);
+            if block.and_then(|block| try_decode_block(block, block_width, block_height, profile, texels)).is_none() {
+                texels.fill(get_error_color(profile));
+            }
+
+            for y in 0..block_height.min(h - by * block_height) {
+                for x in 0..block_width.min(w - bx * block_width) {
+                    let dst_idx = (by * block_height + y) * w + bx * block_width + x;
+                    write(dst_idx, &texels[y * block_width + x]);
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+// Decodes an LDR surface to RGBA8. With srgb, endpoints are expanded as the sRGB profile requires;
+// the output is still sRGB-encoded. Returns an empty Vec if the block size is out of range.
+pub fn decode_surface(src: &[u8], block_width: usize, block_height: usize, w: usize, h: usize, srgb: bool) -> Vec<u8> {
+    let profile = if srgb { Profile::LdrSrgb } else { Profile::Ldr };
+    let mut dst = vec![0; w * h * 4];
+    let decoded = decode_surface_texels(src, block_width, block_height, w, h, profile, |i, texel| {
+        for (d, &c) in dst[i * 4..i * 4 + 4].iter_mut().zip(texel.iter()) {
+            *d = (c >> 8) as u8;
+        }
+    });
+    match decoded {
+        Some(()) => dst,
+        None => vec![],
+    }
+}
+
+// Decodes an HDR (or LDR) surface to RGBA16F. Returns an empty Vec if the block size is out of range.
+pub fn decode_surface_hdr(src: &[u8], block_width: usize, block_height: usize, w: usize, h: usize) -> Vec<u16> {
+    let mut dst = vec![0; w * h * 4];
+    let decoded = decode_surface_texels(src, block_width, block_height, w, h, Profile::Hdr, |i, texel| {
+        dst[i * 4..i * 4 + 4].copy_from_slice(texel);
+    });
+    match decoded {
+        Some(()) => dst,
+        None => vec![],
+    }
+}
+
+// The block footprint of a Tegra ASTC format.
+pub fn get_tegra_block_size(ct: CompressionType) -> Option<(usize, usize)> {
+    use CompressionType::*;
+    match ct {
+        Astc4x4 | Astc5x4 | Astc5x5 | Astc6x5 | Astc6x6 | Astc8x5 | Astc8x6 | Astc8x8 |
+        Astc10x5 | Astc10x6 | Astc10x8 | Astc10x10 | Astc12x10 | Astc12x12 => {
+            Some((get_format_block_width(ct), get_format_block_height(ct)))
+        }
+        _ => None,
+    }
+}
+
+#[wasm_bindgen]
+pub fn decode_astc(src: &[u8], block_width: usize, block_height: usize, w: usize, h: usize, srgb: bool) -> Vec<u8> {
+    decode_surface(src, block_width, block_height, w, h, srgb)
+}
+
+#[wasm_bindgen]
+pub fn decode_astc_hdr(src: &[u8], block_width: usize, block_height: usize, w: usize, h: usize) -> Vec<u16> {
+    decode_surface_hdr(src, block_width, block_height, w, h)
+}
+
+// Decodes a deswizzled Tegra ASTC surface to RGBA8, or returns an empty Vec for non-ASTC formats.
+#[wasm_bindgen]
+pub fn decode_astc_tegra(src: &[u8], compression_type: CompressionType, w: usize, h: usize, srgb: bool) -> Vec<u8> {
+    match get_tegra_block_size(compression_type) {
+        Some((block_width, block_height)) => decode_surface(src, block_width, block_height, w, h, srgb),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOOTPRINTS: [(usize, usize); 14] = [
+        (4, 4), (5, 4), (5, 5), (6, 5), (6, 6), (8, 5), (8, 6),
+        (8, 8), (10, 5), (10, 6), (10, 8), (10, 10), (12, 10), (12, 12),
+    ];
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/textures/astc/{}", name)).unwrap()
+    }
+
+    // Parses a .astc file header, returning the block footprint, image size and block data.
+    fn read_astc_file(name: &str) -> (usize, usize, usize, usize, Vec<u8>) {
+        let data = read_test_file(name);
+        assert_eq!(data[0..4], [0x13, 0xAB, 0xA1, 0x5C]);
+        let get_u24 = |offs: usize| data[offs] as usize | (data[offs + 1] as usize) << 8 | (data[offs + 2] as usize) << 16;
+        (data[4] as usize, data[5] as usize, get_u24(7), get_u24(10), data[16..].to_vec())
+    }
+
+    // See test_data/textures/astc/README.md for where the expected images come from.
+    #[test]
+    fn test_golden() {
+        for &(bw, bh) in FOOTPRINTS.iter() {
+            let name = format!("{}x{}", bw, bh);
+            let (block_width, block_height, w, h, src) = read_astc_file(&format!("{}.astc", name));
+            assert_eq!((block_width, block_height), (bw, bh));
+            assert_eq!(decode_surface(&src, bw, bh, w, h, false), read_test_file(&format!("{}.rgba", name)), "{}", name);
+            assert_eq!(decode_surface(&src, bw, bh, w, h, true), read_test_file(&format!("{}_srgb.rgba", name)), "{} srgb", name);
+            let expected: Vec<u16> = read_test_file(&format!("{}.rgba16f", name))
+                .chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            assert_eq!(decode_surface_hdr(&src, bw, bh, w, h), expected, "{} hdr", name);
+        }
+    }
+
+    // Hand-assembled blocks, with the expected texels worked out from the spec's decoding steps.
+    // Each block is one partition of CEM 12 (LDR RGBA direct) with 8-bit endpoints starting at bit 17,
+    // and weights stored bit-reversed from bit 127.
+
+    #[test]
+    fn test_rgba_direct() {
+        // Block mode 0x042: a 4x4 grid of 2-bit weights (0, 21, 43, 64), each row going 0, 1, 2, 3.
+        // Endpoints RGBA (0x00, 0xFF, 0x40, 0xFF) to (0xFF, 0x00, 0x80, 0x20).
+        let src = 0x27272727_000041FF_008001FF_FE018042_u128.to_le_bytes();
+        let row = [0x00, 0xFF, 0x40, 0xFF, 0x54, 0xAB, 0x55, 0xB6, 0xAB, 0x54, 0x6B, 0x69, 0xFF, 0x00, 0x80, 0x20];
+        assert_eq!(decode_surface(&src, 4, 4, 4, 4, false), row.repeat(4));
+        assert_eq!(decode_surface(&src, 4, 4, 4, 2, true), row.repeat(2));
+        // LDR endpoints in the HDR profile are UNORM16 converted to half floats.
+        assert_eq!(decode_surface_hdr(&src, 4, 4, 1, 1), [0x0000, 0x3C00, 0x3404, 0x3C00]);
+    }
+
+    #[test]
+    fn test_blue_contract() {
+        // The second endpoint sums to less than the first, so the endpoints are swapped and
+        // blue-contracted: (0xF0, 0xE0, 0xD0, 0x80) and (0x20, 0x10, 0x60, 0xFF) become
+        // (0x40, 0x38, 0x60, 0xFF) to (0xE0, 0xD8, 0xD0, 0x80).
+        let src = 0x27272727_0001FF00_C1A021C0_41E18042_u128.to_le_bytes();
+        let row = [0x40, 0x38, 0x60, 0xFF, 0x74, 0x6C, 0x85, 0xD6, 0xAC, 0xA4, 0xAB, 0xAA, 0xE0, 0xD8, 0xD0, 0x80];
+        assert_eq!(decode_surface(&src, 4, 4, 4, 4, false), row.repeat(4));
+    }
+
+    #[test]
+    fn test_weight_infill() {
+        // Block mode 0x1BF: a 3x3 grid of 3-bit weights 0, 1, ..., 7, 7, bilinearly infilled to
+        // the 4x4 block. Endpoints black to white.
+        let src = 0x11635FE0_0001FFFF_FE01FE01_FE0181BF_u128.to_le_bytes();
+        let expected = [
+            0x00, 0x18, 0x30, 0x48,
+            0x4C, 0x64, 0x7C, 0x93,
+            0x8F, 0xA7, 0xBB, 0xCF,
+            0xDB, 0xF3, 0xFF, 0xFF,
+        ];
+        let dst = decode_surface(&src, 4, 4, 4, 4, false);
+        assert_eq!(dst.chunks_exact(4).map(|texel| texel[0]).collect::<Vec<_>>(), expected);
+        assert!(dst.chunks_exact(4).all(|texel| texel[0] == texel[1] && texel[0] == texel[2] && texel[3] == 0xFF));
+
+        // The same weights on a 2x2 grid need only 12 bits, under the 24 the spec requires.
+        let src = 0x1AF00000_0001FFFF_FE01FE01_FE01811F_u128.to_le_bytes();
+        assert_eq!(decode_surface(&src, 4, 4, 1, 1, false), [0xFF, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_void_extent() {
+        // LDR void extent with no extent coordinates: RGBA = 0x1234, 0x5678, 0x9ABC, 0xFFFF.
+        let mut block = 0x1FC_u128 | 0x3 << 10 | ((1 << 52) - 1) << 12;
+        block |= 0x1234 << 64 | 0x5678 << 80 | 0x9ABC << 96 | 0xFFFF << 112;
+        let src = block.to_le_bytes();
+        assert_eq!(decode_surface(&src, 4, 4, 1, 1, false), [0x12, 0x56, 0x9A, 0xFF]);
+        assert_eq!(decode_surface_hdr(&src, 4, 4, 1, 1)[3], 0x3C00);
+
+        // The same block flagged HDR is an error in the LDR profile.
+        let src = (block | 1 << 9).to_le_bytes();
+        assert_eq!(decode_surface(&src, 4, 4, 1, 1, false), [0xFF, 0x00, 0xFF, 0xFF]);
+        assert_eq!(decode_surface_hdr(&src, 4, 4, 1, 1), [0x1234, 0x5678, 0x9ABC, 0xFFFF]);
+    }
+
+    #[test]
+    fn test_error_color() {
+        // Block mode 0 is reserved.
+        let src = [0; 16];
+        assert_eq!(decode_surface(&src, 6, 6, 2, 1, false), [0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF]);
+        assert_eq!(decode_surface_hdr(&src, 6, 6, 1, 1), [0xFFFF; 4]);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let src = 0x27272727_000041FF_008001FF_FE018042_u128.to_le_bytes();
+        assert!(decode_surface(&src, 3, 4, 4, 4, false).is_empty());
+        assert!(decode_surface_hdr(&src, 4, 13, 4, 4).is_empty());
+        assert!(decode_astc_tegra(&src, CompressionType::Bc1, 4, 4, false).is_empty());
+        // The second block is missing.
+        assert_eq!(decode_surface(&src, 4, 4, 8, 1, false)[16..], [0xFF, 0x00, 0xFF, 0xFF].repeat(4)[..]);
+    }
+
+    #[test]
+    fn test_tegra_block_size() {
+        assert_eq!(get_tegra_block_size(CompressionType::Astc4x4), Some((4, 4)));
+        assert_eq!(get_tegra_block_size(CompressionType::Astc10x6), Some((10, 6)));
+        assert_eq!(get_tegra_block_size(CompressionType::Astc12x10), Some((12, 10)));
+        assert_eq!(get_tegra_block_size(CompressionType::Bc1), None);
+    }
+}
@@ -0,0 +1,993 @@
+// BC1-BC7 block compressed textures, decoded in software for devices without S3TC/RGTC/BPTC.
+// https://docs.microsoft.com/en-us/windows/win32/direct3d11/texture-block-compression-in-direct3d-11
+//
+// All formats store 4x4 blocks in row-major order, with partial blocks at the right and bottom
+// edges. This is the layout tegra_deswizzle produces, and the one Halo DXT bitmaps are stored in.
+//
+// BC1-BC5 and BC7 decode to RGBA8. Signed BC4/BC5 decode to RGBA8 holding i8 values, like
+// Common/bc_texture.ts. BC6H decodes to RGBA16F (half float bits), with alpha 1.0.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::tegra_texture::CompressionType;
+use crate::util;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BcFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc4Snorm,
+    Bc5,
+    Bc5Snorm,
+    Bc7,
+}
+
+impl BcFormat {
+    pub const fn block_size(self) -> usize {
+        match self {
+            BcFormat::Bc1 | BcFormat::Bc4 | BcFormat::Bc4Snorm => 8,
+            _ => 16,
+        }
+    }
+
+    // The RGBA8 format for a Tegra surface, if it has one. BC6H goes through decode_bc6h.
+    pub fn from_compression_type(ct: CompressionType) -> Option<Self> {
+        match ct {
+            CompressionType::Bc1 => Some(BcFormat::Bc1),
+            CompressionType::Bc2 => Some(BcFormat::Bc2),
+            CompressionType::Bc3 => Some(BcFormat::Bc3),
+            CompressionType::Bc4 => Some(BcFormat::Bc4),
+            CompressionType::Bc5 => Some(BcFormat::Bc5),
+            CompressionType::Bc7 => Some(BcFormat::Bc7),
+            _ => None,
+        }
+    }
+}
+
+pub fn calc_surface_size(block_size: usize, w: usize, h: usize) -> usize {
+    w.div_ceil(4) * h.div_ceil(4) * block_size
+}
+
+// Runs decode_block over each block of the surface and copies the visible texels into dst.
+fn decode_blocks<T: Copy + Default>(src: &[u8], block_size: usize, w: usize, h: usize, mut decode_block: impl FnMut(&[u8], &mut [[T; 4]; 16])) -> Vec<T> {
+    let mut dst = vec![T::default(); w * h * 4];
+    let mut texels = [[T::default(); 4]; 16];
+    let width_in_blocks = w.div_ceil(4);
+    for by in 0..h.div_ceil(4) {
+        for bx in 0..width_in_blocks {
+            let src_offs = (by * width_in_blocks + bx) * block_size;
+            decode_block(&src[src_offs..src_offs + block_size], &mut texels);
+
+            for y in 0..4.min(h - by * 4) {
+                for x in 0..4.min(w - bx * 4) {
+                    let dst_offs = ((by * 4 + y) * w + bx * 4 + x) * 4;
+                    dst[dst_offs..dst_offs + 4].copy_from_slice(&texels[y * 4 + x]);
+                }
+            }
+        }
+    }
+    dst
+}
+
+// BC1-BC3 color
+
+fn decode_rgb565(p: u16) -> [u8; 4] {
+    [
+        util::expand_n_to_8(5, ((p >> 11) & 0x1F) as u8),
+        util::expand_n_to_8(6, ((p >>  5) & 0x3F) as u8),
+        util::expand_n_to_8(5, (p & 0x1F) as u8),
+        0xFF,
+    ]
+}
+
+fn lerp_u8(a: u8, b: u8, weight_a: u32, weight_b: u32) -> u8 {
+    let total = weight_a + weight_b;
+    ((a as u32 * weight_a + b as u32 * weight_b + total / 2) / total) as u8
+}
+
+fn lerp_rgba8(a: [u8; 4], b: [u8; 4], weight_a: u32, weight_b: u32) -> [u8; 4] {
+    [
+        lerp_u8(a[0], b[0], weight_a, weight_b),
+        lerp_u8(a[1], b[1], weight_a, weight_b),
+        lerp_u8(a[2], b[2], weight_a, weight_b),
+        0xFF,
+    ]
+}
+
+// BC2 and BC3 always use the four-color mode; only BC1 has the punch-through alpha mode.
+fn decode_color_block(src: &[u8], dst: &mut [[u8; 4]; 16], allow_punch_through: bool) {
+    let color0 = util::get_uint16_le(src, 0x00);
+    let color1 = util::get_uint16_le(src, 0x02);
+    let a = decode_rgb565(color0);
+    let b = decode_rgb565(color1);
+    let table = if color0 > color1 || !allow_punch_through {
+        [a, b, lerp_rgba8(a, b, 2, 1), lerp_rgba8(a, b, 1, 2)]
+    } else {
+        [a, b, lerp_rgba8(a, b, 1, 1), [0x00; 4]]
+    };
+
+    let indices = util::get_uint32_le(src, 0x04);
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let alpha = texel[3];
+        *texel = table[((indices >> (i * 2)) & 0x03) as usize];
+        if !allow_punch_through {
+            texel[3] = alpha;
+        }
+    }
+}
+
+// BC3-BC5 alpha/channel
+
+fn get_uint48_le(src: &[u8], offs: usize) -> u64 {
+    (util::get_uint16_le(src, offs) as u64) | ((util::get_uint32_le(src, offs + 2) as u64) << 16)
+}
+
+fn decode_channel_block_unorm(src: &[u8]) -> [u8; 16] {
+    let a = src[0];
+    let b = src[1];
+    let mut table = [a, b, 0, 0, 0, 0, 0x00, 0xFF];
+    if a > b {
+        for i in 1..7 {
+            table[i + 1] = lerp_u8(a, b, 7 - i as u32, i as u32);
+        }
+    } else {
+        for i in 1..5 {
+            table[i + 1] = lerp_u8(a, b, 5 - i as u32, i as u32);
+        }
+    }
+
+    let indices = get_uint48_le(src, 0x02);
+    let mut dst = [0; 16];
+    for (i, v) in dst.iter_mut().enumerate() {
+        *v = table[((indices >> (i * 3)) & 0x07) as usize];
+    }
+    dst
+}
+
+fn lerp_i8(a: i8, b: i8, weight_a: i32, weight_b: i32) -> i8 {
+    let total = weight_a + weight_b;
+    let v = a as i32 * weight_a + b as i32 * weight_b;
+    // Round half away from zero, so the table is symmetric around 0.
+    let bias = if v < 0 { -total / 2 } else { total / 2 };
+    ((v + bias) / total) as i8
+}
+
+fn decode_channel_block_snorm(src: &[u8]) -> [u8; 16] {
+    // -128 and -127 both map to -1.0.
+    let a = (src[0] as i8).max(-127);
+    let b = (src[1] as i8).max(-127);
+    let mut table = [a, b, 0, 0, 0, 0, -127, 127];
+    if a > b {
+        for i in 1..7 {
+            table[i + 1] = lerp_i8(a, b, 7 - i as i32, i as i32);
+        }
+    } else {
+        for i in 1..5 {
+            table[i + 1] = lerp_i8(a, b, 5 - i as i32, i as i32);
+        }
+    }
+
+    let indices = get_uint48_le(src, 0x02);
+    let mut dst = [0; 16];
+    for (i, v) in dst.iter_mut().enumerate() {
+        *v = table[((indices >> (i * 3)) & 0x07) as usize] as u8;
+    }
+    dst
+}
+
+fn decode_channel_block(src: &[u8], signed: bool) -> [u8; 16] {
+    if signed {
+        decode_channel_block_snorm(src)
+    } else {
+        decode_channel_block_unorm(src)
+    }
+}
+
+fn decode_bc1_block(src: &[u8], dst: &mut [[u8; 4]; 16]) {
+    decode_color_block(src, dst, true);
+}
+
+fn decode_bc2_block(src: &[u8], dst: &mut [[u8; 4]; 16]) {
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let nibble = (src[i / 2] >> ((i & 1) * 4)) & 0x0F;
+        texel[3] = util::expand_n_to_8(4, nibble);
+    }
+    decode_color_block(&src[0x08..], dst, false);
+}
+
+fn decode_bc3_block(src: &[u8], dst: &mut [[u8; 4]; 16]) {
+    let alpha = decode_channel_block_unorm(src);
+    for (texel, &a) in dst.iter_mut().zip(alpha.iter()) {
+        texel[3] = a;
+    }
+    decode_color_block(&src[0x08..], dst, false);
+}
+
+fn decode_bc4_block(src: &[u8], dst: &mut [[u8; 4]; 16], signed: bool) {
+    let red = decode_channel_block(src, signed);
+    for (texel, &r) in dst.iter_mut().zip(red.iter()) {
+        *texel = [r; 4];
+    }
+}
+
+fn decode_bc5_block(src: &[u8], dst: &mut [[u8; 4]; 16], signed: bool) {
+    let red = decode_channel_block(src, signed);
+    let green = decode_channel_block(&src[0x08..], signed);
+    let one = if signed { 0x7F } else { 0xFF };
+    for (i, texel) in dst.iter_mut().enumerate() {
+        *texel = [red[i], green[i], one, one];
+    }
+}
+
+// BC6H and BC7 share their bit reading, partitions and index weights.
+
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn new(src: &[u8]) -> Self {
+        let mut block = [0; 16];
+        block.copy_from_slice(&src[..16]);
+        BitReader { bits: u128::from_le_bytes(block) }
+    }
+
+    fn read(&mut self, num_bits: u32) -> u32 {
+        let v = (self.bits & ((1 << num_bits) - 1)) as u32;
+        self.bits >>= num_bits;
+        v
+    }
+}
+
+// Bit i is the subset of texel i.
+static PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+static PARTITIONS_3: [[u8; 16]; 64] = [
+    [0,0,1,1, 0,0,1,1, 0,2,2,1, 2,2,2,2], [0,0,0,1, 0,0,1,1, 2,2,1,1, 2,2,2,1],
+    [0,0,0,0, 2,0,0,1, 2,2,1,1, 2,2,1,1], [0,2,2,2, 0,0,2,2, 0,0,1,1, 0,1,1,1],
+    [0,0,0,0, 0,0,0,0, 1,1,2,2, 1,1,2,2], [0,0,1,1, 0,0,1,1, 0,0,2,2, 0,0,2,2],
+    [0,0,2,2, 0,0,2,2, 1,1,1,1, 1,1,1,1], [0,0,1,1, 0,0,1,1, 2,2,1,1, 2,2,1,1],
+    [0,0,0,0, 0,0,0,0, 1,1,1,1, 2,2,2,2], [0,0,0,0, 1,1,1,1, 1,1,1,1, 2,2,2,2],
+    [0,0,0,0, 1,1,1,1, 2,2,2,2, 2,2,2,2], [0,0,1,2, 0,0,1,2, 0,0,1,2, 0,0,1,2],
+    [0,1,1,2, 0,1,1,2, 0,1,1,2, 0,1,1,2], [0,1,2,2, 0,1,2,2, 0,1,2,2, 0,1,2,2],
+    [0,0,1,1, 0,1,1,2, 1,1,2,2, 1,2,2,2], [0,0,1,1, 2,0,0,1, 2,2,0,0, 2,2,2,0],
+    [0,0,0,1, 0,0,1,1, 0,1,1,2, 1,1,2,2], [0,1,1,1, 0,0,1,1, 2,0,0,1, 2,2,0,0],
+    [0,0,0,0, 1,1,2,2, 1,1,2,2, 1,1,2,2], [0,0,2,2, 0,0,2,2, 0,0,2,2, 1,1,1,1],
+    [0,1,1,1, 0,1,1,1, 0,2,2,2, 0,2,2,2], [0,0,0,1, 0,0,0,1, 2,2,2,1, 2,2,2,1],
+    [0,0,0,0, 0,0,1,1, 0,1,2,2, 0,1,2,2], [0,0,0,0, 1,1,0,0, 2,2,1,0, 2,2,1,0],
+    [0,1,2,2, 0,1,2,2, 0,0,1,1, 0,0,0,0], [0,0,1,2, 0,0,1,2, 1,1,2,2, 2,2,2,2],
+    [0,1,1,0, 1,2,2,1, 1,2,2,1, 0,1,1,0], [0,0,0,0, 0,1,1,0, 1,2,2,1, 1,2,2,1],
+    [0,0,2,2, 1,1,0,2, 1,1,0,2, 0,0,2,2], [0,1,1,0, 0,1,1,0, 2,0,0,2, 2,2,2,2],
+    [0,0,1,1, 0,1,2,2, 0,1,2,2, 0,0,1,1], [0,0,0,0, 2,0,0,0, 2,2,1,1, 2,2,2,1],
+    [0,0,0,0, 0,0,0,2, 1,1,2,2, 1,2,2,2], [0,2,2,2, 0,0,2,2, 0,0,1,2, 0,0,1,1],
+    [0,0,1,1, 0,0,1,2, 0,0,2,2, 0,2,2,2], [0,1,2,0, 0,1,2,0, 0,1,2,0, 0,1,2,0],
+    [0,0,0,0, 1,1,1,1, 2,2,2,2, 0,0,0,0], [0,1,2,0, 1,2,0,1, 2,0,1,2, 0,1,2,0],
+    [0,1,2,0, 2,0,1,2, 1,2,0,1, 0,1,2,0], [0,0,1,1, 2,2,0,0, 1,1,2,2, 0,0,1,1],
+    [0,0,1,1, 1,1,2,2, 2,2,0,0, 0,0,1,1], [0,1,0,1, 0,1,0,1, 2,2,2,2, 2,2,2,2],
+    [0,0,0,0, 0,0,0,0, 2,1,2,1, 2,1,2,1], [0,0,2,2, 1,1,2,2, 0,0,2,2, 1,1,2,2],
+    [0,0,2,2, 0,0,1,1, 0,0,2,2, 0,0,1,1], [0,2,2,0, 1,2,2,1, 0,2,2,0, 1,2,2,1],
+    [0,1,0,1, 2,2,2,2, 2,2,2,2, 0,1,0,1], [0,0,0,0, 2,1,2,1, 2,1,2,1, 2,1,2,1],
+    [0,1,0,1, 0,1,0,1, 0,1,0,1, 2,2,2,2], [0,2,2,2, 0,1,1,1, 0,2,2,2, 0,1,1,1],
+    [0,0,0,2, 1,1,1,2, 0,0,0,2, 1,1,1,2], [0,0,0,0, 2,1,1,2, 2,1,1,2, 2,1,1,2],
+    [0,2,2,2, 0,1,1,1, 0,1,1,1, 0,2,2,2], [0,0,0,2, 1,1,1,2, 1,1,1,2, 0,0,0,2],
+    [0,1,1,0, 0,1,1,0, 0,1,1,0, 2,2,2,2], [0,0,0,0, 0,0,0,0, 2,1,1,2, 2,1,1,2],
+    [0,1,1,0, 0,1,1,0, 2,2,2,2, 2,2,2,2], [0,0,2,2, 0,0,1,1, 0,0,1,1, 0,0,2,2],
+    [0,0,2,2, 1,1,2,2, 1,1,2,2, 0,0,2,2], [0,0,0,0, 0,0,0,0, 0,0,0,0, 2,1,1,2],
+    [0,0,0,2, 0,0,0,1, 0,0,0,2, 0,0,0,1], [0,2,2,2, 1,2,2,2, 0,2,2,2, 1,2,2,2],
+    [0,1,0,1, 2,2,2,2, 2,2,2,2, 2,2,2,2], [0,1,1,1, 2,0,1,1, 2,2,0,1, 2,2,2,0],
+];
+
+// The anchor texel of each subset stores its index with one bit less. Subset 0's is always texel 0.
+static ANCHORS_2: [u8; 64] = [
+    15,15,15,15,15,15,15,15, 15,15,15,15,15,15,15,15,
+    15, 2, 8, 2, 2, 8, 8,15,  2, 8, 2, 2, 8, 8, 2, 2,
+    15,15, 6, 8, 2, 8,15,15,  2, 8, 2, 2, 2,15,15, 6,
+     6, 2, 6, 8,15,15, 2, 2, 15,15,15,15,15, 2, 2,15,
+];
+
+static ANCHORS_3: [[u8; 2]; 64] = [
+    [ 3,15], [ 3, 8], [15, 8], [15, 3], [ 8,15], [ 3,15], [15, 3], [15, 8],
+    [ 8,15], [ 8,15], [ 6,15], [ 6,15], [ 6,15], [ 5,15], [ 3,15], [ 3, 8],
+    [ 3,15], [ 3, 8], [ 8,15], [15, 3], [ 3,15], [ 3, 8], [ 6,15], [10, 8],
+    [ 5, 3], [ 8,15], [ 8, 6], [ 6,10], [ 8,15], [ 5,15], [15,10], [15, 8],
+    [ 8,15], [15, 3], [ 3,15], [ 5,10], [ 6,10], [10, 8], [ 8, 9], [15,10],
+    [15, 6], [ 3,15], [15, 8], [ 5,15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [ 3,15], [15, 3], [ 5,15], [ 5,15], [ 5,15], [ 8,15], [ 5,15], [10,15],
+    [ 5,15], [10,15], [ 8,15], [13,15], [15, 3], [12,15], [ 3,15], [ 3, 8],
+];
+
+static WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+static WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+static WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn get_weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        4 => &WEIGHTS_4,
+        _ => unreachable!(),
+    }
+}
+
+fn get_subset(num_subsets: usize, partition: usize, texel: usize) -> usize {
+    match num_subsets {
+        1 => 0,
+        2 => ((PARTITIONS_2[partition] >> texel) & 1) as usize,
+        3 => PARTITIONS_3[partition][texel] as usize,
+        _ => unreachable!(),
+    }
+}
+
+fn is_anchor(num_subsets: usize, partition: usize, texel: usize) -> bool {
+    match num_subsets {
+        1 => texel == 0,
+        2 => texel == 0 || texel == ANCHORS_2[partition] as usize,
+        3 => texel == 0 || ANCHORS_3[partition].contains(&(texel as u8)),
+        _ => unreachable!(),
+    }
+}
+
+fn read_indices(reader: &mut BitReader, num_subsets: usize, partition: usize, index_bits: u32) -> [u32; 16] {
+    let mut indices = [0; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(num_subsets, partition, i);
+        *index = reader.read(index_bits - anchor as u32);
+    }
+    indices
+}
+
+// BC7
+
+struct Bc7Mode {
+    num_subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn bc7_mode(num_subsets: usize, partition_bits: u32, rotation_bits: u32, index_selection_bits: u32, color_bits: u32, alpha_bits: u32, endpoint_pbits: bool, shared_pbits: bool, index_bits: u32, index_bits2: u32) -> Bc7Mode {
+    Bc7Mode { num_subsets, partition_bits, rotation_bits, index_selection_bits, color_bits, alpha_bits, endpoint_pbits, shared_pbits, index_bits, index_bits2 }
+}
+
+static BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    bc7_mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    bc7_mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    bc7_mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    bc7_mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    bc7_mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    bc7_mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    bc7_mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+fn expand_bc7(v: u32, bits: u32) -> u8 {
+    let v = v << (8 - bits);
+    (v | (v >> bits)) as u8
+}
+
+fn lerp_bc7(a: u8, b: u8, weight: u32) -> u8 {
+    ((a as u32 * (64 - weight) + b as u32 * weight + 32) >> 6) as u8
+}
+
+fn decode_bc7_block(src: &[u8], dst: &mut [[u8; 4]; 16]) {
+    let mode_index = src[0].trailing_zeros() as usize;
+    if mode_index >= BC7_MODES.len() {
+        // Reserved mode; decodes to transparent black.
+        *dst = [[0x00; 4]; 16];
+        return;
+    }
+
+    let mode = &BC7_MODES[mode_index];
+    let mut reader = BitReader::new(src);
+    reader.read(mode_index as u32 + 1);
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits) as usize;
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    // Two endpoints per subset, each channel stored for all endpoints in turn.
+    let num_endpoints = mode.num_subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..4 {
+        let bits = if channel == 3 { mode.alpha_bits } else { mode.color_bits };
+        for endpoint in endpoints.iter_mut().take(num_endpoints) {
+            endpoint[channel] = reader.read(bits);
+        }
+    }
+
+    let mut pbits = [0; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits.iter_mut().take(num_endpoints) {
+            *pbit = reader.read(1);
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.num_subsets {
+            let pbit = reader.read(1);
+            pbits[subset * 2] = pbit;
+            pbits[subset * 2 + 1] = pbit;
+        }
+    }
+
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    let mut colors = [[0xFFu8; 4]; 6];
+    for i in 0..num_endpoints {
+        for channel in 0..4 {
+            let bits = if channel == 3 { mode.alpha_bits } else { mode.color_bits };
+            if bits == 0 {
+                continue;
+            }
+            let (v, bits) = if has_pbits {
+                ((endpoints[i][channel] << 1) | pbits[i], bits + 1)
+            } else {
+                (endpoints[i][channel], bits)
+            };
+            colors[i][channel] = expand_bc7(v, bits);
+        }
+    }
+
+    let indices = read_indices(&mut reader, mode.num_subsets, partition, mode.index_bits);
+    // Modes 4 and 5 have separate alpha indices; mode 4 can swap which set the color uses.
+    let (indices2, index_bits2) = if mode.index_bits2 != 0 {
+        (read_indices(&mut reader, 1, 0, mode.index_bits2), mode.index_bits2)
+    } else {
+        (indices, mode.index_bits)
+    };
+
+    let (color_indices, color_bits, alpha_indices, alpha_bits) = if index_selection == 0 {
+        (&indices, mode.index_bits, &indices2, index_bits2)
+    } else {
+        (&indices2, index_bits2, &indices, mode.index_bits)
+    };
+    let color_weights = get_weights(color_bits);
+    let alpha_weights = get_weights(alpha_bits);
+
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let subset = get_subset(mode.num_subsets, partition, i);
+        let a = colors[subset * 2];
+        let b = colors[subset * 2 + 1];
+        let color_weight = color_weights[color_indices[i] as usize];
+        let alpha_weight = alpha_weights[alpha_indices[i] as usize];
+        *texel = [
+            lerp_bc7(a[0], b[0], color_weight),
+            lerp_bc7(a[1], b[1], color_weight),
+            lerp_bc7(a[2], b[2], color_weight),
+            lerp_bc7(a[3], b[3], alpha_weight),
+        ];
+        if rotation != 0 {
+            texel.swap(rotation - 1, 3);
+        }
+    }
+}
+
+// BC6H
+
+// Endpoint fields: w and x are subset 0's endpoints, y and z subset 1's.
+const RW: u8 = 0; const RX: u8 = 1; const RY: u8 = 2; const RZ: u8 = 3;
+const GW: u8 = 4; const GX: u8 = 5; const GY: u8 = 6; const GZ: u8 = 7;
+const BW: u8 = 8; const BX: u8 = 9; const BY: u8 = 10; const BZ: u8 = 11;
+
+struct Bc6hMode {
+    mode: u32,
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    // Each entry (field, a, b) is the spec's field[a:b]: the first bit read goes to bit b,
+    // moving towards bit a. Fields like rw[10:15] are stored reversed.
+    layout: &'static [(u8, u8, u8)],
+}
+
+impl Bc6hMode {
+    fn num_subsets(&self) -> usize {
+        // The two-bit modes, and the five-bit modes whose low two bits are 10.
+        if self.mode & 0x03 == 0x03 { 1 } else { 2 }
+    }
+}
+
+static BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode { mode: 0x00, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], layout: &[
+        (GY,4,4), (BY,4,4), (BZ,4,4), (RW,9,0), (GW,9,0), (BW,9,0), (RX,4,0), (GZ,4,4), (GY,3,0), (GX,4,0),
+        (BZ,0,0), (GZ,3,0), (BX,4,0), (BZ,1,1), (BY,3,0), (RY,4,0), (BZ,2,2), (RZ,4,0), (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x01, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], layout: &[
+        (GY,5,5), (GZ,4,4), (GZ,5,5), (RW,6,0), (BZ,0,0), (BZ,1,1), (BY,4,4), (GW,6,0), (BY,5,5), (BZ,2,2),
+        (GY,4,4), (BW,6,0), (BZ,3,3), (BZ,5,5), (BZ,4,4), (RX,5,0), (GY,3,0), (GX,5,0), (GZ,3,0), (BX,5,0),
+        (BY,3,0), (RY,5,0), (RZ,5,0),
+    ] },
+    Bc6hMode { mode: 0x02, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,4,0), (RW,10,10), (GY,3,0), (GX,3,0), (GW,10,10), (BZ,0,0), (GZ,3,0),
+        (BX,3,0), (BW,10,10), (BZ,1,1), (BY,3,0), (RY,4,0), (BZ,2,2), (RZ,4,0), (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x06, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,3,0), (RW,10,10), (GZ,4,4), (GY,3,0), (GX,4,0), (GW,10,10), (GZ,3,0),
+        (BX,3,0), (BW,10,10), (BZ,1,1), (BY,3,0), (RY,3,0), (BZ,0,0), (BZ,2,2), (RZ,3,0), (GY,4,4), (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x0A, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,3,0), (RW,10,10), (BY,4,4), (GY,3,0), (GX,3,0), (GW,10,10), (BZ,0,0),
+        (GZ,3,0), (BX,4,0), (BW,10,10), (BY,3,0), (RY,3,0), (BZ,1,1), (BZ,2,2), (RZ,3,0), (BZ,4,4), (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x0E, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], layout: &[
+        (RW,8,0), (BY,4,4), (GW,8,0), (GY,4,4), (BW,8,0), (BZ,4,4), (RX,4,0), (GZ,4,4), (GY,3,0), (GX,4,0),
+        (BZ,0,0), (GZ,3,0), (BX,4,0), (BZ,1,1), (BY,3,0), (RY,4,0), (BZ,2,2), (RZ,4,0), (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x12, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], layout: &[
+        (RW,7,0), (GZ,4,4), (BY,4,4), (GW,7,0), (BZ,2,2), (GY,4,4), (BW,7,0), (BZ,3,3), (BZ,4,4), (RX,5,0),
+        (GY,3,0), (GX,4,0), (BZ,0,0), (GZ,3,0), (BX,4,0), (BZ,1,1), (BY,3,0), (RY,5,0), (RZ,5,0),
+    ] },
+    Bc6hMode { mode: 0x16, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], layout: &[
+        (RW,7,0), (BZ,0,0), (BY,4,4), (GW,7,0), (GY,5,5), (GY,4,4), (BW,7,0), (GZ,5,5), (BZ,4,4), (RX,4,0),
+        (GZ,4,4), (GY,3,0), (GX,5,0), (GZ,3,0), (BX,4,0), (BZ,1,1), (BY,3,0), (RY,4,0), (BZ,2,2), (RZ,4,0),
+        (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x1A, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], layout: &[
+        (RW,7,0), (BZ,1,1), (BY,4,4), (GW,7,0), (BY,5,5), (GY,4,4), (BW,7,0), (BZ,5,5), (BZ,4,4), (RX,4,0),
+        (GZ,4,4), (GY,3,0), (GX,4,0), (BZ,0,0), (GZ,3,0), (BX,5,0), (BY,3,0), (RY,4,0), (BZ,2,2), (RZ,4,0),
+        (BZ,3,3),
+    ] },
+    Bc6hMode { mode: 0x1E, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], layout: &[
+        (RW,5,0), (GZ,4,4), (BZ,0,0), (BZ,1,1), (BY,4,4), (GW,5,0), (GY,5,5), (BY,5,5), (BZ,2,2), (GY,4,4),
+        (BW,5,0), (GZ,5,5), (BZ,3,3), (BZ,5,5), (BZ,4,4), (RX,5,0), (GY,3,0), (GX,5,0), (GZ,3,0), (BX,5,0),
+        (BY,3,0), (RY,5,0), (RZ,5,0),
+    ] },
+    Bc6hMode { mode: 0x03, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,9,0), (GX,9,0), (BX,9,0),
+    ] },
+    Bc6hMode { mode: 0x07, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,8,0), (RW,10,10), (GX,8,0), (GW,10,10), (BX,8,0), (BW,10,10),
+    ] },
+    Bc6hMode { mode: 0x0B, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,7,0), (RW,10,11), (GX,7,0), (GW,10,11), (BX,7,0), (BW,10,11),
+    ] },
+    Bc6hMode { mode: 0x0F, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], layout: &[
+        (RW,9,0), (GW,9,0), (BW,9,0), (RX,3,0), (RW,10,15), (GX,3,0), (GW,10,15), (BX,3,0), (BW,10,15),
+    ] },
+];
+
+fn sign_extend(v: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (v << shift) >> shift
+}
+
+fn unquantize_bc6h(v: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return v;
+        }
+        let magnitude = v.abs();
+        let unq = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if v < 0 { -unq } else { unq }
+    } else if bits >= 15 {
+        v
+    } else if v == 0 {
+        0
+    } else if v == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((v << 16) + 0x8000) >> bits
+    }
+}
+
+// Scales the interpolated value to the largest finite half float, and returns its bits.
+fn finish_unquantize_bc6h(v: i32, signed: bool) -> u16 {
+    if !signed {
+        ((v * 31) >> 6) as u16
+    } else if v < 0 {
+        0x8000 | (((-v) * 31) >> 5) as u16
+    } else {
+        ((v * 31) >> 5) as u16
+    }
+}
+
+fn decode_bc6h_block(src: &[u8], dst: &mut [[u16; 4]; 16], signed: bool) {
+    let mut reader = BitReader::new(src);
+    let mut mode_bits = reader.read(2);
+    if mode_bits >= 2 {
+        mode_bits |= reader.read(3) << 2;
+    }
+    let mode = match BC6H_MODES.iter().find(|mode| mode.mode == mode_bits) {
+        Some(mode) => mode,
+        None => {
+            // Reserved mode; decodes to black.
+            *dst = [[0x0000, 0x0000, 0x0000, 0x3C00]; 16];
+            return;
+        }
+    };
+
+    let mut fields = [0i32; 12];
+    for &(field, a, b) in mode.layout {
+        if a >= b {
+            for bit in b..=a {
+                fields[field as usize] |= (reader.read(1) as i32) << bit;
+            }
+        } else {
+            for bit in (a..=b).rev() {
+                fields[field as usize] |= (reader.read(1) as i32) << bit;
+            }
+        }
+    }
+
+    let num_subsets = mode.num_subsets();
+    let partition = if num_subsets == 2 { reader.read(5) as usize } else { 0 };
+
+    // [subset * 2 + endpoint][channel]
+    let mut endpoints = [[0i32; 3]; 4];
+    for channel in 0..3 {
+        let w = fields[channel * 4];
+        endpoints[0][channel] = if signed { sign_extend(w, mode.endpoint_bits) } else { w };
+        for endpoint in 1..num_subsets * 2 {
+            let v = fields[channel * 4 + endpoint];
+            let v = if mode.transformed {
+                let delta = sign_extend(v, mode.delta_bits[channel]);
+                (w + delta) & ((1 << mode.endpoint_bits) - 1)
+            } else {
+                v
+            };
+            endpoints[endpoint][channel] = if signed { sign_extend(v, mode.endpoint_bits) } else { v };
+        }
+    }
+    for endpoint in endpoints.iter_mut() {
+        for v in endpoint.iter_mut() {
+            *v = unquantize_bc6h(*v, mode.endpoint_bits, signed);
+        }
+    }
+
+    let index_bits = if num_subsets == 2 { 3 } else { 4 };
+    let indices = read_indices(&mut reader, num_subsets, partition, index_bits);
+    let weights = get_weights(index_bits);
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let subset = get_subset(num_subsets, partition, i);
+        let a = endpoints[subset * 2];
+        let b = endpoints[subset * 2 + 1];
+        let weight = weights[indices[i] as usize] as i32;
+        for channel in 0..3 {
+            let v = (a[channel] * (64 - weight) + b[channel] * weight + 32) >> 6;
+            texel[channel] = finish_unquantize_bc6h(v, signed);
+        }
+        texel[3] = 0x3C00;
+    }
+}
+
+// Decodes a surface to RGBA8.
+pub fn decode_surface(src: &[u8], format: BcFormat, w: usize, h: usize) -> Vec<u8> {
+    let block_size = format.block_size();
+    match format {
+        BcFormat::Bc1 => decode_blocks(src, block_size, w, h, decode_bc1_block),
+        BcFormat::Bc2 => decode_blocks(src, block_size, w, h, decode_bc2_block),
+        BcFormat::Bc3 => decode_blocks(src, block_size, w, h, decode_bc3_block),
+        BcFormat::Bc4 => decode_blocks(src, block_size, w, h, |src, dst| decode_bc4_block(src, dst, false)),
+        BcFormat::Bc4Snorm => decode_blocks(src, block_size, w, h, |src, dst| decode_bc4_block(src, dst, true)),
+        BcFormat::Bc5 => decode_blocks(src, block_size, w, h, |src, dst| decode_bc5_block(src, dst, false)),
+        BcFormat::Bc5Snorm => decode_blocks(src, block_size, w, h, |src, dst| decode_bc5_block(src, dst, true)),
+        BcFormat::Bc7 => decode_blocks(src, block_size, w, h, decode_bc7_block),
+    }
+}
+
+// Decodes a BC6H surface to RGBA16F.
+pub fn decode_surface_bc6h(src: &[u8], w: usize, h: usize, signed: bool) -> Vec<u16> {
+    decode_blocks(src, 16, w, h, |src, dst| decode_bc6h_block(src, dst, signed))
+}
+
+// Decodes a run of surfaces with mip levels, like Halo's bitmaps: each level holds num_faces
+// surfaces back to back, and the levels follow each other. The output has the same layout, in RGBA8.
+pub fn decode_surface_mips(src: &[u8], format: BcFormat, w: usize, h: usize, num_faces: usize, mip_count: usize) -> Vec<u8> {
+    let mut dst = Vec::new();
+    let mut src_offs = 0;
+    let (mut w, mut h) = (w, h);
+    for _ in 0..mip_count {
+        let size = calc_surface_size(format.block_size(), w, h);
+        for _ in 0..num_faces {
+            dst.extend(decode_surface(&src[src_offs..src_offs + size], format, w, h));
+            src_offs += size;
+        }
+        w = 1.max(w >> 1);
+        h = 1.max(h >> 1);
+    }
+    dst
+}
+
+#[wasm_bindgen]
+pub fn decode_bc(src: &[u8], format: BcFormat, w: usize, h: usize) -> Vec<u8> {
+    decode_surface(src, format, w, h)
+}
+
+#[wasm_bindgen]
+pub fn decode_bc6h(src: &[u8], w: usize, h: usize, signed: bool) -> Vec<u16> {
+    decode_surface_bc6h(src, w, h, signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs fields LSB-first into a 128-bit block.
+    struct BitWriter {
+        bits: u128,
+        pos: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            BitWriter { bits: 0, pos: 0 }
+        }
+
+        fn write(&mut self, v: u32, num_bits: u32) {
+            self.bits |= ((v as u128) & ((1 << num_bits) - 1)) << self.pos;
+            self.pos += num_bits;
+        }
+
+        fn finish(self) -> [u8; 16] {
+            assert_eq!(self.pos, 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    #[test]
+    fn test_bc1() {
+        // Red to blue, four-color mode; texel i uses index i % 4.
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4];
+        let dst = decode_surface(&block, BcFormat::Bc1, 4, 4);
+        assert_eq!(dst[0..16], [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xAA, 0x00, 0x55, 0xFF, 0x55, 0x00, 0xAA, 0xFF]);
+
+        // Swapped endpoints pick the three-color mode, with index 3 transparent black.
+        let block = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+        let dst = decode_surface(&block, BcFormat::Bc1, 4, 4);
+        assert_eq!(dst[8..16], [0x80, 0x00, 0x80, 0xFF, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_bc2_bc3() {
+        let color = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+        let mut block = [0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0];
+        block[8..].copy_from_slice(&color);
+        let dst = decode_surface(&block, BcFormat::Bc2, 4, 4);
+        // Explicit 4-bit alpha, and the four-color mode even though color0 <= color1.
+        for i in 0..16 {
+            assert_eq!(dst[i * 4 + 3], (i * 0x11) as u8);
+        }
+        assert_eq!(dst[12..16], [0xAA, 0x00, 0x55, 0x33]);
+
+        // Alpha 0xFF to 0x00, eight-value mode; index i for texel i.
+        let mut block = [0xFF, 0x00, 0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA, 0, 0, 0, 0, 0, 0, 0, 0];
+        block[8..].copy_from_slice(&color);
+        let dst = decode_surface(&block, BcFormat::Bc3, 4, 4);
+        let alpha: Vec<u8> = (0..8).map(|i| dst[i * 4 + 3]).collect();
+        assert_eq!(alpha, [0xFF, 0x00, 0xDB, 0xB6, 0x92, 0x6D, 0x49, 0x24]);
+    }
+
+    #[test]
+    fn test_bc4_bc5() {
+        // Six-value mode: indices 6 and 7 are the extremes.
+        let block = [0x00, 0x50, 0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA];
+        let dst = decode_surface(&block, BcFormat::Bc4, 4, 4);
+        let red: Vec<u8> = (0..8).map(|i| dst[i * 4]).collect();
+        assert_eq!(red, [0x00, 0x50, 0x10, 0x20, 0x30, 0x40, 0x00, 0xFF]);
+        assert_eq!(dst[8..12], [0x10; 4]);
+
+        // Signed: -128 is clamped to -127, and the table is symmetric.
+        let block = [0x80, 0x7F, 0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA];
+        let dst = decode_surface(&block, BcFormat::Bc4Snorm, 4, 4);
+        let red: Vec<i8> = (0..8).map(|i| dst[i * 4] as i8).collect();
+        assert_eq!(red, [-127, 127, -76, -25, 25, 76, -127, 127]);
+
+        let mut block = [0u8; 16];
+        block[0] = 0x40;
+        block[8] = 0xC0;
+        let dst = decode_surface(&block, BcFormat::Bc5, 4, 4);
+        assert_eq!(dst[0..4], [0x40, 0xC0, 0xFF, 0xFF]);
+        let dst = decode_surface(&block, BcFormat::Bc5Snorm, 4, 4);
+        assert_eq!(dst[0..4], [0x40, 0xC0, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn test_partial_blocks() {
+        // A 6x5 BC4 surface is 2x2 blocks; each block is a single value.
+        let mut src = Vec::new();
+        for v in [0x10u8, 0x20, 0x30, 0x40].iter() {
+            src.extend_from_slice(&[*v, *v, 0, 0, 0, 0, 0, 0]);
+        }
+        assert_eq!(calc_surface_size(BcFormat::Bc4.block_size(), 6, 5), src.len());
+        let dst = decode_surface(&src, BcFormat::Bc4, 6, 5);
+        assert_eq!(dst.len(), 6 * 5 * 4);
+        let red: Vec<u8> = (0..6 * 5).map(|i| dst[i * 4]).collect();
+        assert_eq!(red[0..6], [0x10, 0x10, 0x10, 0x10, 0x20, 0x20]);
+        assert_eq!(red[24..30], [0x30, 0x30, 0x30, 0x30, 0x40, 0x40]);
+    }
+
+    #[test]
+    fn test_partition_tables() {
+        for p in 0..64 {
+            assert_eq!(get_subset(2, p, 0), 0);
+            assert_eq!(get_subset(2, p, ANCHORS_2[p] as usize), 1, "partition {}", p);
+            assert_eq!(get_subset(3, p, 0), 0);
+            assert_eq!(get_subset(3, p, ANCHORS_3[p][0] as usize), 1, "partition {}", p);
+            assert_eq!(get_subset(3, p, ANCHORS_3[p][1] as usize), 2, "partition {}", p);
+        }
+    }
+
+    #[test]
+    fn test_bc7_mode6() {
+        // Red 0 to 255; the p-bits nudge the other channels by one. Index i for texel i.
+        let mut w = BitWriter::new();
+        w.write(1 << 6, 7);
+        for &(a, b) in [(0, 0x7F), (0x40, 0x40), (0x10, 0x10), (0x7F, 0x7F)].iter() {
+            w.write(a, 7);
+            w.write(b, 7);
+        }
+        w.write(0, 1);
+        w.write(1, 1);
+        w.write(0, 3);
+        for i in 1..16 {
+            w.write(i, 4);
+        }
+        let dst = decode_surface(&w.finish(), BcFormat::Bc7, 4, 4);
+        for i in 0..16 {
+            let weight = WEIGHTS_4[i];
+            assert_eq!(dst[i * 4..i * 4 + 4], [lerp_bc7(0x00, 0xFF, weight), lerp_bc7(0x80, 0x81, weight), lerp_bc7(0x20, 0x21, weight), lerp_bc7(0xFE, 0xFF, weight)]);
+        }
+    }
+
+    #[test]
+    fn test_bc7_mode1() {
+        // Partition 13 (top two rows are subset 0): red/black in subset 0, blue/black in subset 1.
+        let mut w = BitWriter::new();
+        w.write(1 << 1, 2);
+        w.write(13, 6);
+        for &v in [0x3F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x3F, 0].iter() {
+            w.write(v, 6);
+        }
+        // Shared p-bits of 1 turn the zero channels into 0x02.
+        w.write(1, 1);
+        w.write(1, 1);
+        // Every texel uses endpoint 0; anchor texels 0 and 15 store two bits.
+        w.write(0, 2);
+        for _ in 1..15 {
+            w.write(0, 3);
+        }
+        w.write(0, 2);
+        let dst = decode_surface(&w.finish(), BcFormat::Bc7, 4, 4);
+        for i in 0..8 {
+            assert_eq!(dst[i * 4..i * 4 + 4], [0xFF, 0x02, 0x02, 0xFF]);
+        }
+        for i in 8..16 {
+            assert_eq!(dst[i * 4..i * 4 + 4], [0x02, 0x02, 0xFF, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn test_bc7_reserved_mode() {
+        assert_eq!(decode_surface(&[0; 16], BcFormat::Bc7, 4, 4), vec![0; 64]);
+    }
+
+    #[test]
+    fn test_bc6h_layouts() {
+        // Each mode fills every bit of its endpoints and deltas exactly once.
+        for mode in BC6H_MODES.iter() {
+            let mut seen = [0u32; 12];
+            for &(field, a, b) in mode.layout {
+                for bit in a.min(b)..=a.max(b) {
+                    assert_eq!(seen[field as usize] & (1 << bit), 0, "mode {:#x}", mode.mode);
+                    seen[field as usize] |= 1 << bit;
+                }
+            }
+            for channel in 0..3 {
+                assert_eq!(seen[channel * 4], (1 << mode.endpoint_bits) - 1, "mode {:#x}", mode.mode);
+                let num_fields = mode.num_subsets() * 2;
+                for field in 1..4 {
+                    let expected = if field < num_fields { (1 << mode.delta_bits[channel]) - 1 } else { 0 };
+                    assert_eq!(seen[channel * 4 + field], expected, "mode {:#x}", mode.mode);
+                }
+            }
+
+            let header_bits = if mode.mode < 2 { 2 } else { 5 };
+            let layout_bits: u32 = mode.layout.iter().map(|&(_, a, b)| (a.max(b) - a.min(b) + 1) as u32).sum();
+            let expected = if mode.num_subsets() == 2 { 82 - 5 } else { 65 };
+            assert_eq!(header_bits + layout_bits, expected, "mode {:#x}", mode.mode);
+        }
+    }
+
+    #[test]
+    fn test_bc6h_mode11() {
+        // Mode 0x03 (10-bit endpoints, no deltas): white-ish to black. Texel i uses index i.
+        let mut w = BitWriter::new();
+        w.write(0x03, 5);
+        for &v in [0x3FF, 0x200, 0x001, 0, 0, 0].iter() {
+            w.write(v, 10);
+        }
+        w.write(0, 3);
+        for i in 1..16 {
+            w.write(i, 4);
+        }
+        let block = w.finish();
+
+        let dst = decode_surface_bc6h(&block, 4, 4, false);
+        // The max endpoint is the largest finite half, 65504.
+        assert_eq!(dst[0..4], [0x7BFF, 0x3E0F, 0x002E, 0x3C00]);
+        assert_eq!(dst[60..64], [0x0000, 0x0000, 0x0000, 0x3C00]);
+
+        // Signed: 0x3FF is -1, and 0x200 is -512, which saturates to -65504.
+        let dst = decode_surface_bc6h(&block, 4, 4, true);
+        assert_eq!(dst[0..4], [0x805D, 0xFBFF, 0x005D, 0x3C00]);
+    }
+
+    #[test]
+    fn test_bc6h_transformed() {
+        // Mode 0x0F (16-bit base, 4-bit deltas): w = 0x8000, x = w - 1.
+        let mut w = BitWriter::new();
+        w.write(0x0F, 5);
+        for _ in 0..3 {
+            w.write(0, 10);
+        }
+        for _ in 0..3 {
+            w.write(0xF, 4);
+            // rw[10:15], stored reversed: bit 15 first.
+            w.write(1, 1);
+            w.write(0, 5);
+        }
+        w.write(0, 3);
+        w.write(0xF, 4);
+        for _ in 2..16 {
+            w.write(0, 4);
+        }
+        let dst = decode_surface_bc6h(&w.finish(), 4, 4, false);
+        let mid = ((0x8000 * 31) >> 6) as u16;
+        let mid_minus_one = ((0x7FFF * 31) >> 6) as u16;
+        assert_eq!(dst[0..4], [mid, mid, mid, 0x3C00]);
+        assert_eq!(dst[4..8], [mid_minus_one, mid_minus_one, mid_minus_one, 0x3C00]);
+    }
+
+    #[test]
+    fn test_from_tegra() {
+        use crate::tegra_texture::tegra_deswizzle;
+
+        // A single BC1 block survives deswizzling and decodes.
+        let mut src = vec![0; 512];
+        src[..8].copy_from_slice(&[0x00, 0xF8, 0x1F, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let format = BcFormat::from_compression_type(CompressionType::Bc1).unwrap();
+        let deswizzled = tegra_deswizzle(&src, CompressionType::Bc1, 4, 4, 0);
+        let dst = decode_surface(&deswizzled, format, 4, 4);
+        assert_eq!(dst, [0xFF, 0x00, 0x00, 0xFF].repeat(16));
+        assert_eq!(BcFormat::from_compression_type(CompressionType::Bc6h), None);
+    }
+
+    #[test]
+    fn test_surface_mips() {
+        // 8x8 BC1 cube map with two levels: six 4-block faces, then six 1-block faces.
+        let mut src = Vec::new();
+        for level in 0..2 {
+            for face in 0..6u16 {
+                let color = (face + 1) << (level * 11);
+                for _ in 0..(if level == 0 { 4 } else { 1 }) {
+                    src.extend_from_slice(&color.to_le_bytes());
+                    src.extend_from_slice(&[0; 6]);
+                }
+            }
+        }
+        let dst = decode_surface_mips(&src, BcFormat::Bc1, 8, 8, 6, 2);
+        assert_eq!(dst.len(), (8 * 8 + 4 * 4) * 4 * 6);
+        assert_eq!(dst[0..4], [0x00, 0x00, 0x08, 0xFF]);
+        assert_eq!(dst[8 * 8 * 4 * 5..8 * 8 * 4 * 5 + 4], [0x00, 0x00, 0x31, 0xFF]);
+        assert_eq!(dst[8 * 8 * 4 * 6..8 * 8 * 4 * 6 + 4], [0x08, 0x00, 0x00, 0xFF]);
+    }
+}
@@ -0,0 +1,327 @@
+// Error handling shared by the decompressors, and format auto-detection.
+
+use std::fmt;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::{cx, deflate, lz4, mio0, yay0, yaz0};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressionError {
+    // The header magic did not match the expected format.
+    BadMagic,
+    // The header was recognized but contains invalid or unsupported settings.
+    InvalidHeader,
+    // The input ended before the output was complete.
+    Truncated,
+    // A back-reference pointed before the start of the output buffer.
+    InvalidBackReference { dst_offs: usize, distance: usize },
+    // The data would write past the declared uncompressed size.
+    OutputOverrun,
+    // A stored checksum did not match the data.
+    ChecksumMismatch,
+    // The type byte names a compression type that isn't supported.
+    UnknownType(u8),
+    // The inflate crate rejected a deflate stream.
+    Inflate(String),
+}
+
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressionError::BadMagic => write!(f, "bad header magic"),
+            DecompressionError::InvalidHeader => write!(f, "invalid or unsupported header"),
+            DecompressionError::Truncated => write!(f, "compressed data is truncated"),
+            DecompressionError::InvalidBackReference { dst_offs, distance } =>
+                write!(f, "back-reference of distance {} at output offset {} is before the start of the output", distance, dst_offs),
+            DecompressionError::OutputOverrun => write!(f, "compressed data overruns the uncompressed size"),
+            DecompressionError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            DecompressionError::UnknownType(t) => write!(f, "unknown compression type 0x{:02X}", t),
+            DecompressionError::Inflate(msg) => write!(f, "inflate failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DecompressionError {}
+
+impl From<DecompressionError> for JsValue {
+    fn from(err: DecompressionError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DecompressionError>;
+
+pub(crate) fn read_u8(src: &[u8], src_offs: &mut usize) -> Result<u8> {
+    let v = *src.get(*src_offs).ok_or(DecompressionError::Truncated)?;
+    *src_offs += 1;
+    Ok(v)
+}
+
+pub(crate) fn read_u16_be(src: &[u8], src_offs: &mut usize) -> Result<u16> {
+    let hi = read_u8(src, src_offs)? as u16;
+    let lo = read_u8(src, src_offs)? as u16;
+    Ok((hi << 8) | lo)
+}
+
+pub(crate) fn read_u32_be(src: &[u8], src_offs: &mut usize) -> Result<u32> {
+    let hi = read_u16_be(src, src_offs)? as u32;
+    let lo = read_u16_be(src, src_offs)? as u32;
+    Ok((hi << 16) | lo)
+}
+
+// Every format here can expand each input byte into at most max_ratio output
+// bytes. A header declaring more than that can't be satisfied by the data that
+// follows it, so reject it before allocating the output.
+pub(crate) fn check_uncompressed_size(uncompressed_size: usize, src_len: usize, max_ratio: usize) -> Result<()> {
+    if uncompressed_size > src_len.saturating_mul(max_ratio) {
+        return Err(DecompressionError::Truncated);
+    }
+    Ok(())
+}
+
+// Copies length bytes from distance bytes back in the output. The ranges may
+// overlap, so this has to go byte by byte.
+pub(crate) fn copy_back_reference(dst: &mut [u8], dst_offs: usize, distance: usize, length: usize) -> Result<()> {
+    if distance == 0 || distance > dst_offs {
+        return Err(DecompressionError::InvalidBackReference { dst_offs, distance });
+    }
+
+    if dst_offs + length > dst.len() {
+        return Err(DecompressionError::OutputOverrun);
+    }
+
+    let copy_offs = dst_offs - distance;
+    for i in 0..length {
+        dst[dst_offs + i] = dst[copy_offs + i];
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Yaz0,
+    Yay0,
+    Mio0,
+    Lz4Frame,
+    Zlib,
+    Gzip,
+    Cx,
+}
+
+// Sniffs the header. CX has only a type byte to go on, so it is tried last.
+pub fn detect_format(src: &[u8]) -> Option<CompressionFormat> {
+    if src.starts_with(b"Yaz0") {
+        Some(CompressionFormat::Yaz0)
+    } else if src.starts_with(b"Yay0") {
+        Some(CompressionFormat::Yay0)
+    } else if src.starts_with(b"MIO0") {
+        Some(CompressionFormat::Mio0)
+    } else if src.starts_with(b"\x04\x22\x4D\x18") {
+        Some(CompressionFormat::Lz4Frame)
+    } else if src.starts_with(b"\x1F\x8B\x08") {
+        Some(CompressionFormat::Gzip)
+    } else if is_zlib_header(src) {
+        Some(CompressionFormat::Zlib)
+    } else if cx::detect(src).is_ok() {
+        Some(CompressionFormat::Cx)
+    } else {
+        None
+    }
+}
+
+// CM must be deflate with a window of at most 32 KiB, no preset dictionary,
+// and FCHECK must make CMF/FLG a multiple of 31.
+fn is_zlib_header(src: &[u8]) -> bool {
+    if src.len() < 2 {
+        return false;
+    }
+    let cmf = src[0];
+    let flg = src[1];
+    let fcheck = (31 - (((cmf as u16) << 8) | (flg & 0xE0) as u16) % 31) % 31;
+    (cmf & 0x0F) == 8 && (cmf >> 4) <= 7 && (flg & 0x20) == 0 && (flg & 0x1F) as u16 == fcheck
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+// RFC 1952. Only the first member is decompressed.
+fn decompress_gzip(src: &[u8]) -> Result<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if src.len() < 10 {
+        return Err(DecompressionError::Truncated);
+    }
+    let flags = src[3];
+    let mut src_offs = 10;
+
+    if (flags & FEXTRA) != 0 {
+        let lo = read_u8(src, &mut src_offs)? as usize;
+        let hi = read_u8(src, &mut src_offs)? as usize;
+        src_offs += (hi << 8) | lo;
+    }
+    for flag in [FNAME, FCOMMENT].iter() {
+        if (flags & flag) != 0 {
+            while read_u8(src, &mut src_offs)? != 0 {}
+        }
+    }
+    if (flags & FHCRC) != 0 {
+        src_offs += 2;
+    }
+
+    if src_offs > src.len() {
+        return Err(DecompressionError::Truncated);
+    }
+    let (dst, stream_len) = deflate::inflate_raw(&src[src_offs..])?;
+
+    // The trailer with the CRC-32 and size of the data follows the deflate
+    // stream directly; anything after it (more members, padding) is ignored.
+    let trailer = src.get(src_offs + stream_len..src_offs + stream_len + 8).ok_or(DecompressionError::Truncated)?;
+    let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let expected_size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if expected_size != dst.len() as u32 || expected_crc != crc32(&dst) {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    Ok(dst)
+}
+
+pub fn decompress_with_format(src: &[u8], format: CompressionFormat) -> Result<Vec<u8>> {
+    match format {
+        CompressionFormat::Yaz0 => yaz0::decompress(src),
+        CompressionFormat::Yay0 => yay0::decompress(src),
+        CompressionFormat::Mio0 => mio0::decompress(src),
+        CompressionFormat::Lz4Frame => lz4::decompress_frame(src, true),
+        CompressionFormat::Zlib => inflate::inflate_bytes_zlib(src).map_err(DecompressionError::Inflate),
+        CompressionFormat::Gzip => decompress_gzip(src),
+        CompressionFormat::Cx => cx::decompress(src),
+    }
+}
+
+// Detects the format with detect_format and decompresses it.
+pub fn decompress_auto(src: &[u8]) -> Result<(CompressionFormat, Vec<u8>)> {
+    let format = detect_format(src).ok_or(DecompressionError::BadMagic)?;
+    match decompress_with_format(src, format) {
+        // A CX Huffman-8 header (0x28) can also pass for a zlib header with a
+        // 1 KiB window, so if inflating fails, try it as CX.
+        Err(err) if format == CompressionFormat::Zlib && cx::detect(src).is_ok() =>
+            cx::decompress(src).map(|dst| (CompressionFormat::Cx, dst)).map_err(|_| err),
+        result => Ok((format, result?)),
+    }
+}
+
+#[wasm_bindgen]
+pub fn detect_compression_format(src: &[u8]) -> Option<CompressionFormat> {
+    detect_format(src)
+}
+
+// The result of autodec: the format that was detected, and the decompressed data.
+#[wasm_bindgen]
+pub struct AutoDecompressed {
+    pub format: CompressionFormat,
+    #[wasm_bindgen(skip)]
+    pub data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl AutoDecompressed {
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[wasm_bindgen]
+pub fn autodec(src: &[u8]) -> std::result::Result<AutoDecompressed, JsValue> {
+    let (format, data) = decompress_auto(src)?;
+    Ok(AutoDecompressed { format, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_golden() {
+        let expected = read_test_file("text.txt");
+        let yaz0 = yaz0::yaz0enc(&expected, yaz0::Yaz0Mode::Fast);
+        for (src, format) in [
+            (yaz0, CompressionFormat::Yaz0),
+            (read_test_file("text.txt.lz4"), CompressionFormat::Lz4Frame),
+            (read_test_file("text.txt.zlib"), CompressionFormat::Zlib),
+            (read_test_file("text.txt.gz"), CompressionFormat::Gzip),
+            (read_test_file("text.txt.lz11"), CompressionFormat::Cx),
+        ].iter() {
+            assert_eq!(decompress_auto(src).unwrap(), (*format, expected.clone()));
+        }
+    }
+
+    #[test]
+    fn test_small() {
+        // Same data as the Yay0 and MIO0 tests.
+        let yay0 = b"Yay0\x00\x00\x00\x1D\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x40\x02\x00\x00abc\x02";
+        let (format, dst) = decompress_auto(yay0).unwrap();
+        assert_eq!((format, dst.len()), (CompressionFormat::Yay0, 0x1D));
+        let mio0 = b"MIO0\x00\x00\x00\x1B\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x30\x02\xF0\x00abc";
+        let (format, dst) = decompress_auto(mio0).unwrap();
+        assert_eq!((format, dst.len()), (CompressionFormat::Mio0, 0x1B));
+    }
+
+    #[test]
+    fn test_cx_huffman_zlib_header() {
+        // 0x28 0x15 is a valid zlib header, but this is a CX Huffman-8 stream
+        // with a two-leaf tree, alternating between 'b' and 'a'.
+        let src = b"\x28\x15\x00\x00\x01\xC0ab\xAA\xAA\xAA\xAA";
+        assert_eq!(detect_format(src), Some(CompressionFormat::Zlib));
+        assert_eq!(decompress_auto(src).unwrap(), (CompressionFormat::Cx, b"babababababababababab".to_vec()));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(detect_format(b"\x00\x01\x02\x03"), None);
+        assert_eq!(decompress_auto(b"\x00\x01\x02\x03"), Err(DecompressionError::BadMagic));
+        assert_eq!(decompress_auto(b""), Err(DecompressionError::BadMagic));
+
+        let mut gz = read_test_file("text.txt.gz");
+        let len = gz.len();
+        gz[len - 8] ^= 1;
+        assert_eq!(decompress_auto(&gz), Err(DecompressionError::ChecksumMismatch));
+        assert_eq!(decompress_auto(&gz[..8]), Err(DecompressionError::Truncated));
+        assert_eq!(decompress_auto(&gz[..len - 4]), Err(DecompressionError::Truncated));
+    }
+
+    #[test]
+    fn test_gzip_trailing_data() {
+        // Dumps often pad archives with zeroes, and gzip allows several members
+        // back to back; only the first member is decompressed either way.
+        let expected = read_test_file("text.txt");
+        let gz = read_test_file("text.txt.gz");
+        let mut padded = gz.clone();
+        padded.resize(gz.len() + 0x800, 0);
+        assert_eq!(decompress_auto(&padded).unwrap(), (CompressionFormat::Gzip, expected.clone()));
+        let mut multi = gz.clone();
+        multi.extend_from_slice(&gz);
+        assert_eq!(decompress_auto(&multi).unwrap(), (CompressionFormat::Gzip, expected));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -0,0 +1,298 @@
+// Nintendo's "CX" formats, as decompressed by the GBA/DS BIOS and used on the Wii.
+// https://problemkaputt.de/gbatek.htm#biosdecompressionfunctions
+//
+// Header (4 bytes):
+//   Type (1 byte)
+//     0x10: LZ10
+//     0x11: LZ11
+//     0x24: Huffman, 4-bit data
+//     0x28: Huffman, 8-bit data
+//     0x30: RLE
+//   Uncompressed size (3 bytes, little endian)
+//     If 0, the real size follows as 4 more bytes, little endian.
+//
+// LZ10 data:
+//   Flags (1 byte)
+//   For each bit in the flags byte, from MSB to LSB:
+//     If flag is 1:
+//       LZ77 (2 bytes, big endian):
+//         Length: bits 12-15
+//         Offset: bits 0-11
+//         Copy Length+3 bytes from Offset+1 back in the output buffer.
+//     If flag is 0:
+//       Literal: copy one byte from src to dest.
+//
+// LZ11 data:
+//   Same as LZ10, except the LZ77 encoding depends on the top nibble (A) of the first byte:
+//     A > 1, two bytes: AB CD
+//       Length: A + 1
+//       Offset: BCD + 1
+//     A = 0, three bytes: AB CD EF
+//       Length: BC + 0x11
+//       Offset: DEF + 1
+//     A = 1, four bytes: AB CD EF GH
+//       Length: BCDE + 0x111
+//       Offset: FGH + 1
+//
+// Huffman data:
+//   Tree size (1 byte): the bitstream starts (size+1)*2 bytes after this byte.
+//   Tree nodes (1 byte each), starting with the root:
+//     Bits 0-5: offset to the children; they are at (address & ~1) + offset*2 + 2, and +1.
+//     Bit 6: the second child is a leaf, holding a data value.
+//     Bit 7: the first child is a leaf, holding a data value.
+//   Bitstream: 32-bit little endian words, read from MSB to LSB. 0 picks the first child.
+//   4-bit values fill the low nibble of each output byte first.
+//
+// RLE data:
+//   Flag (1 byte)
+//     If bit 7 is set:
+//       Repeat the next byte (bits 0-6)+3 times.
+//     Otherwise:
+//       Copy (bits 0-6)+1 literal bytes from src to dest.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, check_uncompressed_size, copy_back_reference, read_u8, read_u16_be};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CxType {
+    Lz10,
+    Lz11,
+    Huffman4,
+    Huffman8,
+    Rle,
+}
+
+impl CxType {
+    pub fn from_byte(v: u8) -> Result<Self> {
+        match v {
+            0x10 => Ok(CxType::Lz10),
+            0x11 => Ok(CxType::Lz11),
+            0x24 => Ok(CxType::Huffman4),
+            0x28 => Ok(CxType::Huffman8),
+            0x30 => Ok(CxType::Rle),
+            _ => Err(DecompressionError::UnknownType(v)),
+        }
+    }
+}
+
+// Returns the type and the uncompressed size, and advances src_offs past the header.
+fn read_header(src: &[u8], src_offs: &mut usize) -> Result<(CxType, usize)> {
+    let cx_type = CxType::from_byte(read_u8(src, src_offs)?)?;
+    let mut uncompressed_size = 0;
+    for i in 0..3 {
+        uncompressed_size |= (read_u8(src, src_offs)? as usize) << (i * 8);
+    }
+    if uncompressed_size == 0 {
+        for i in 0..4 {
+            uncompressed_size |= (read_u8(src, src_offs)? as usize) << (i * 8);
+        }
+    }
+    Ok((cx_type, uncompressed_size))
+}
+
+pub fn detect(src: &[u8]) -> Result<CxType> {
+    read_header(src, &mut 0).map(|(cx_type, _)| cx_type)
+}
+
+fn decompress_lz(src: &[u8], mut src_offs: usize, uncompressed_size: usize, extended: bool) -> Result<Vec<u8>> {
+    let mut dst = vec![0x00; uncompressed_size];
+    let mut dst_offs = 0x00;
+    while dst_offs < uncompressed_size {
+        let command_byte = read_u8(src, &mut src_offs)?;
+
+        for i in (0..8).rev() {
+            if dst_offs >= uncompressed_size {
+                break;
+            }
+
+            if (command_byte & (1 << i)) == 0 {
+                // Literal.
+                dst[dst_offs] = read_u8(src, &mut src_offs)?;
+                dst_offs += 1;
+                continue;
+            }
+
+            let tmp = read_u16_be(src, &mut src_offs)? as usize;
+            let indicator = tmp >> 12;
+            let (window_offset, window_length) = if !extended {
+                ((tmp & 0x0FFF) + 1, indicator + 3)
+            } else if indicator > 1 {
+                ((tmp & 0x0FFF) + 1, indicator + 1)
+            } else if indicator == 0 {
+                let tmp = (tmp << 8) | read_u8(src, &mut src_offs)? as usize;
+                ((tmp & 0x0FFF) + 1, (tmp >> 12) + 0x11)
+            } else {
+                let tmp = (tmp << 16) | read_u16_be(src, &mut src_offs)? as usize;
+                ((tmp & 0x0FFF) + 1, ((tmp >> 12) & 0xFFFF) + 0x111)
+            };
+
+            copy_back_reference(&mut dst, dst_offs, window_offset, window_length)?;
+            dst_offs += window_length;
+        }
+    }
+
+    Ok(dst)
+}
+
+fn decompress_huffman(src: &[u8], src_offs: usize, uncompressed_size: usize, data_bits: u32) -> Result<Vec<u8>> {
+    let tree_size = *src.get(src_offs).ok_or(DecompressionError::Truncated)? as usize;
+    let root_offs = src_offs + 1;
+    let mut bits_offs = src_offs + (tree_size + 1) * 2;
+
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    let mut half: Option<u8> = None;
+    let mut node_offs = root_offs;
+    while dst.len() < uncompressed_size {
+        let mut word = 0;
+        for i in 0..4 {
+            word |= (read_u8(src, &mut bits_offs)? as u32) << (i * 8);
+        }
+
+        for bit in (0..32).rev() {
+            if dst.len() >= uncompressed_size {
+                break;
+            }
+
+            let b = ((word >> bit) & 1) as usize;
+            let node = src[node_offs];
+            let child_offs = (node_offs & !1) + ((node & 0x3F) as usize) * 2 + 2 + b;
+            if child_offs >= src_offs + (tree_size + 1) * 2 {
+                return Err(DecompressionError::InvalidHeader);
+            }
+
+            if (node & (0x80 >> b)) == 0 {
+                node_offs = child_offs;
+                continue;
+            }
+
+            let v = src[child_offs];
+            node_offs = root_offs;
+            if data_bits == 8 {
+                dst.push(v);
+            } else if let Some(lo) = half.take() {
+                dst.push(lo | ((v & 0x0F) << 4));
+            } else {
+                half = Some(v & 0x0F);
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+fn decompress_rle(src: &[u8], mut src_offs: usize, uncompressed_size: usize) -> Result<Vec<u8>> {
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    while dst.len() < uncompressed_size {
+        let flag = read_u8(src, &mut src_offs)?;
+        if (flag & 0x80) != 0 {
+            let count = ((flag & 0x7F) as usize) + 3;
+            if dst.len() + count > uncompressed_size {
+                return Err(DecompressionError::OutputOverrun);
+            }
+            let v = read_u8(src, &mut src_offs)?;
+            dst.resize(dst.len() + count, v);
+        } else {
+            let count = ((flag & 0x7F) as usize) + 1;
+            if dst.len() + count > uncompressed_size {
+                return Err(DecompressionError::OutputOverrun);
+            }
+            let literals = src.get(src_offs..src_offs + count).ok_or(DecompressionError::Truncated)?;
+            dst.extend_from_slice(literals);
+            src_offs += count;
+        }
+    }
+
+    Ok(dst)
+}
+
+pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    let mut src_offs = 0;
+    let (cx_type, uncompressed_size) = read_header(src, &mut src_offs)?;
+
+    let max_ratio = match cx_type {
+        // The longest LZ10 back-reference is 2 bytes long and copies 0x12 bytes.
+        CxType::Lz10 => 0x12 / 2,
+        // The longest LZ11 back-reference is 4 bytes long and copies 0x10110 bytes.
+        CxType::Lz11 => 0x10110 / 4,
+        // Every bit of the bitstream decodes at most one value.
+        CxType::Huffman4 => 4,
+        CxType::Huffman8 => 8,
+        // The longest run is 2 bytes long and writes 0x82 bytes.
+        CxType::Rle => 0x82 / 2,
+    };
+    check_uncompressed_size(uncompressed_size, src.len() - src_offs, max_ratio)?;
+
+    match cx_type {
+        CxType::Lz10 => decompress_lz(src, src_offs, uncompressed_size, false),
+        CxType::Lz11 => decompress_lz(src, src_offs, uncompressed_size, true),
+        CxType::Huffman4 => decompress_huffman(src, src_offs, uncompressed_size, 4),
+        CxType::Huffman8 => decompress_huffman(src, src_offs, uncompressed_size, 8),
+        CxType::Rle => decompress_rle(src, src_offs, uncompressed_size),
+    }
+}
+
+#[wasm_bindgen]
+pub fn cxdec(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_golden() {
+        let expected = read_test_file("text.txt");
+        for (name, cx_type) in [
+            ("text.txt.lz10", CxType::Lz10),
+            ("text.txt.lz11", CxType::Lz11),
+            ("text.txt.huff4", CxType::Huffman4),
+            ("text.txt.huff8", CxType::Huffman8),
+        ].iter() {
+            let src = read_test_file(name);
+            assert_eq!(detect(&src), Ok(*cx_type));
+            assert_eq!(decompress(&src).unwrap(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_golden_rle() {
+        // Each byte of the start of text.txt, repeated 1-11 times.
+        let text = read_test_file("text.txt");
+        let mut expected = Vec::new();
+        for (i, &b) in text[..0x2000].iter().enumerate() {
+            expected.resize(expected.len() + 1 + (i * 7) % 11, b);
+        }
+        let src = read_test_file("runs.bin.rle");
+        assert_eq!(detect(&src), Ok(CxType::Rle));
+        assert_eq!(decompress(&src).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_lz11_long_copies() {
+        assert_eq!(decompress(b"\x11\x12\x00\x00\x40a\x00\x00\x00").unwrap(), vec![b'a'; 0x12]);
+        assert_eq!(decompress(b"\x11\x12\x01\x00\x40a\x10\x00\x00\x00").unwrap(), vec![b'a'; 0x112]);
+        // Extended size header.
+        assert_eq!(decompress(b"\x11\x00\x00\x00\x12\x00\x00\x00\x40a\x00\x00\x00").unwrap(), vec![b'a'; 0x12]);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(b"\x40\x04\x00\x00"), Err(DecompressionError::UnknownType(0x40)));
+        assert_eq!(decompress(b"\x10\x04\x00"), Err(DecompressionError::Truncated));
+        assert_eq!(decompress(b"\x10\x03\x00\x00\x40a\x00\x00"), Err(DecompressionError::OutputOverrun));
+        assert_eq!(decompress(b"\x10\x04\x00\x00\x40a\x00\x01"), Err(DecompressionError::InvalidBackReference { dst_offs: 1, distance: 2 }));
+        assert_eq!(decompress(b"\x30\x04\x00\x00\x82a"), Err(DecompressionError::OutputOverrun));
+
+        // Sizes the data can't decode to must fail without allocating.
+        assert_eq!(decompress(b"\x10\x00\x00\x00\xFF\xFF\xFF\xFF\x40a\x00\x00"), Err(DecompressionError::Truncated));
+        assert_eq!(decompress(b"\x28\xFF\xFF\xFF\x00\xC0\x00\x01\x00\x00\x00\x00"), Err(DecompressionError::Truncated));
+        assert_eq!(decompress(b"\x30\x83\x00\x00\xFFa"), Err(DecompressionError::Truncated));
+    }
+}
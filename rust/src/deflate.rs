@@ -0,0 +1,291 @@
+// Raw DEFLATE (RFC 1951) that reports where the stream ended.
+//
+// https://www.rfc-editor.org/rfc/rfc1951
+// Structured after zlib's contrib/puff/puff.c.
+//
+// The inflate crate covers the common case, but once it sees the final block
+// it swallows whatever input follows, so it can't say where the compressed
+// data stopped. Containers that put a trailer after the stream (gzip) need to
+// know that.
+
+use crate::compression::{DecompressionError, Result};
+
+const MAX_BITS: usize = 15;
+const MAX_LIT_CODES: usize = 286;
+const MAX_DIST_CODES: usize = 30;
+const FIXED_LIT_CODES: usize = 288;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// Order the code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Bytes are only pulled in when bits are needed, so once the final block is
+// done `offs` is exactly the end of the stream.
+struct BitReader<'a> {
+    src: &'a [u8],
+    offs: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.bit_count < n {
+            let b = *self.src.get(self.offs).ok_or(DecompressionError::Truncated)?;
+            self.offs += 1;
+            self.bit_buf |= (b as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let v = self.bit_buf & ((1u32 << n) - 1);
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(v)
+    }
+
+    fn align_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+// Canonical Huffman code: how many codes there are of each length, and the
+// symbols ordered by code.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+
+        // Reject over-subscribed codes. Incomplete ones are allowed, as a
+        // single distance code is legal.
+        let mut left: i32 = 1;
+        for &c in &count[1..] {
+            left <<= 1;
+            left -= c as i32;
+            if left < 0 {
+                return Err(DecompressionError::InvalidHeader);
+            }
+        }
+
+        let mut offs = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offs[len as usize] as usize] = sym as u16;
+                offs[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { count, symbol })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(DecompressionError::InvalidHeader)
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, dst: &mut Vec<u8>) -> Result<()> {
+    reader.align_byte();
+    let src = reader.src;
+    let offs = reader.offs;
+    if offs + 4 > src.len() {
+        return Err(DecompressionError::Truncated);
+    }
+    let len = u16::from_le_bytes([src[offs], src[offs + 1]]);
+    let nlen = u16::from_le_bytes([src[offs + 2], src[offs + 3]]);
+    if len != !nlen {
+        return Err(DecompressionError::InvalidHeader);
+    }
+    let start = offs + 4;
+    let end = start + len as usize;
+    if end > src.len() {
+        return Err(DecompressionError::Truncated);
+    }
+    dst.extend_from_slice(&src[start..end]);
+    reader.offs = end;
+    Ok(())
+}
+
+fn inflate_codes(reader: &mut BitReader, dst: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Result<()> {
+    loop {
+        let sym = lit.decode(reader)? as usize;
+        if sym < 256 {
+            dst.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let sym = sym - 257;
+            if sym >= LENGTH_BASE.len() {
+                return Err(DecompressionError::InvalidHeader);
+            }
+            let length = LENGTH_BASE[sym] as usize + reader.bits(LENGTH_EXTRA[sym] as u32)? as usize;
+
+            let sym = dist.decode(reader)? as usize;
+            if sym >= DIST_BASE.len() {
+                return Err(DecompressionError::InvalidHeader);
+            }
+            let distance = DIST_BASE[sym] as usize + reader.bits(DIST_EXTRA[sym] as u32)? as usize;
+            if distance > dst.len() {
+                return Err(DecompressionError::InvalidBackReference { dst_offs: dst.len(), distance });
+            }
+
+            let copy_offs = dst.len() - distance;
+            for i in 0..length {
+                dst.push(dst[copy_offs + i]);
+            }
+        }
+    }
+}
+
+fn fixed_tables() -> Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; FIXED_LIT_CODES];
+    for (sym, len) in lengths.iter_mut().enumerate() {
+        *len = match sym {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; MAX_DIST_CODES])?))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let nlen = reader.bits(5)? as usize + 257;
+    let ndist = reader.bits(5)? as usize + 1;
+    let ncode = reader.bits(4)? as usize + 4;
+    if nlen > MAX_LIT_CODES || ndist > MAX_DIST_CODES {
+        return Err(DecompressionError::InvalidHeader);
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..ncode] {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let sym = code_length_code.decode(reader)?;
+        let (len, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(DecompressionError::InvalidHeader);
+                }
+                (lengths[i - 1], 3 + reader.bits(2)? as usize)
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(DecompressionError::InvalidHeader);
+        }
+        lengths[i..i + repeat].iter_mut().for_each(|l| *l = len);
+        i += repeat;
+    }
+
+    // Without an end-of-block code the block could never finish.
+    if lengths[256] == 0 {
+        return Err(DecompressionError::InvalidHeader);
+    }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+// Decompresses the stream at the start of src, returning the data and the
+// number of bytes of src the stream took up.
+pub fn inflate_raw(src: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut reader = BitReader { src, offs: 0, bit_buf: 0, bit_count: 0 };
+    let mut dst = Vec::new();
+    loop {
+        let last = reader.bits(1)? != 0;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut dst)?,
+            1 => {
+                let (lit, dist) = fixed_tables()?;
+                inflate_codes(&mut reader, &mut dst, &lit, &dist)?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut reader)?;
+                inflate_codes(&mut reader, &mut dst, &lit, &dist)?;
+            },
+            _ => return Err(DecompressionError::InvalidHeader),
+        }
+        if last {
+            return Ok((dst, reader.offs));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_golden() {
+        // The zlib file is a two-byte header, the raw stream, then the Adler-32.
+        let expected = read_test_file("text.txt");
+        let zlib = read_test_file("text.txt.zlib");
+        let (dst, len) = inflate_raw(&zlib[2..]).unwrap();
+        assert_eq!(dst, expected);
+        assert_eq!(len, zlib.len() - 2 - 4);
+    }
+
+    #[test]
+    fn test_stored_and_fixed() {
+        // A stored block with "abc", then a final fixed block with "aaaa", then
+        // a byte that isn't part of the stream.
+        let src = b"\x00\x03\x00\xFC\xFFabc\x4B\x4C\x4C\x4C\x04\x00\xFF";
+        assert_eq!(inflate_raw(src).unwrap(), (b"abcaaaa".to_vec(), src.len() - 1));
+        assert_eq!(inflate_raw(&src[..src.len() - 3]), Err(DecompressionError::Truncated));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(inflate_raw(b"\x07"), Err(DecompressionError::InvalidHeader));
+        assert_eq!(inflate_raw(b"\x01\x03\x00\x00\x00"), Err(DecompressionError::InvalidHeader));
+    }
+}
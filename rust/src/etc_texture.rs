@@ -0,0 +1,541 @@
+// ETC1, ETC2 and EAC block compressed textures, decoded in software for devices without them.
+// https://registry.khronos.org/DataFormat/specs/1.3/dataformat.1.3.html#ETC2
+//
+// Standard (KTX, Unity) surfaces store big-endian 64-bit blocks of 4x4 texels in row-major
+// order, with partial blocks at the right and bottom edges. The 3DS stores ETC1 blocks
+// little-endian, four to an 8x8 tile (top left, top right, bottom left, bottom right), with the
+// tiles in row-major order; ETC1A4 puts 4-bit alpha for the block before each one, like
+// oot3d/pica_texture.ts.
+//
+// Everything decodes to RGBA8. R11 leaves G and B at 0, and RG11 leaves B at 0. Signed EAC
+// formats decode to RGBA8 holding i8 values, like the signed BC formats.
+
+use std::convert::TryInto;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EtcFormat {
+    Etc1,
+    Etc2Rgb,
+    Etc2RgbA1,
+    Etc2Rgba,
+    EacR11,
+    EacR11Snorm,
+    EacRg11,
+    EacRg11Snorm,
+    // 3DS
+    CtrEtc1,
+    CtrEtc1A4,
+}
+
+impl EtcFormat {
+    pub const fn block_size(self) -> usize {
+        match self {
+            EtcFormat::Etc1 | EtcFormat::Etc2Rgb | EtcFormat::Etc2RgbA1 | EtcFormat::EacR11 | EtcFormat::EacR11Snorm | EtcFormat::CtrEtc1 => 8,
+            EtcFormat::Etc2Rgba | EtcFormat::EacRg11 | EtcFormat::EacRg11Snorm | EtcFormat::CtrEtc1A4 => 16,
+        }
+    }
+}
+
+pub fn calc_surface_size(format: EtcFormat, w: usize, h: usize) -> usize {
+    match format {
+        EtcFormat::CtrEtc1 | EtcFormat::CtrEtc1A4 => w.div_ceil(8) * h.div_ceil(8) * 4 * format.block_size(),
+        _ => w.div_ceil(4) * h.div_ceil(4) * format.block_size(),
+    }
+}
+
+fn get_uint64_be(src: &[u8], offs: usize) -> u64 {
+    u64::from_be_bytes(src[offs..offs + 8].try_into().unwrap())
+}
+
+fn get_uint64_le(src: &[u8], offs: usize) -> u64 {
+    u64::from_le_bytes(src[offs..offs + 8].try_into().unwrap())
+}
+
+fn get_bits(v: u64, offs: u32, count: u32) -> u32 {
+    ((v >> offs) & ((1 << count) - 1)) as u32
+}
+
+// Copies a decoded 4x4 block into the surface at (x, y), cropping it at the edges.
+fn copy_block(dst: &mut [u8], texels: &[[u8; 4]; 16], x: usize, y: usize, w: usize, h: usize) {
+    for ty in 0..4.min(h.saturating_sub(y)) {
+        for tx in 0..4.min(w.saturating_sub(x)) {
+            let dst_offs = ((y + ty) * w + x + tx) * 4;
+            dst[dst_offs..dst_offs + 4].copy_from_slice(&texels[ty * 4 + tx]);
+        }
+    }
+}
+
+// ETC1 / ETC2 color
+
+const ETC1_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183],
+];
+
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+fn expand_4(v: u32) -> i32 {
+    (v * 0x11) as i32
+}
+
+fn expand_5(v: u32) -> i32 {
+    ((v << 3) | (v >> 2)) as i32
+}
+
+fn expand_6(v: u32) -> i32 {
+    ((v << 2) | (v >> 4)) as i32
+}
+
+fn expand_7(v: u32) -> i32 {
+    ((v << 1) | (v >> 6)) as i32
+}
+
+fn clamp_rgb(r: i32, g: i32, b: i32) -> [u8; 4] {
+    [r.clamp(0, 255) as u8, g.clamp(0, 255) as u8, b.clamp(0, 255) as u8, 0xFF]
+}
+
+fn add_rgb(c: [i32; 3], d: i32) -> [u8; 4] {
+    clamp_rgb(c[0] + d, c[1] + d, c[2] + d)
+}
+
+// The 2-bit index of texel i: the MSB is in bits 16-31, and the LSB in bits 0-15. Texels are
+// numbered down each column.
+fn get_texel_index(v: u64, i: usize) -> usize {
+    let x = i & 3;
+    let y = i >> 2;
+    let bit = x * 4 + y;
+    ((((v >> (bit + 16)) & 1) << 1) | ((v >> bit) & 1)) as usize
+}
+
+// Writes a block whose four paint colors are chosen by the texel index directly (T and H modes).
+// Without the opaque bit, index 2 is transparent black.
+fn write_paint_colors(v: u64, paint: [[u8; 4]; 4], opaque: bool, dst: &mut [[u8; 4]; 16]) {
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let index = get_texel_index(v, i);
+        *texel = if !opaque && index == 2 { [0x00; 4] } else { paint[index] };
+    }
+}
+
+fn decode_t_mode(v: u64, opaque: bool, dst: &mut [[u8; 4]; 16]) {
+    let c0 = [
+        expand_4(get_bits(v, 59, 2) << 2 | get_bits(v, 56, 2)),
+        expand_4(get_bits(v, 52, 4)),
+        expand_4(get_bits(v, 48, 4)),
+    ];
+    let c1 = [expand_4(get_bits(v, 44, 4)), expand_4(get_bits(v, 40, 4)), expand_4(get_bits(v, 36, 4))];
+    let d = ETC2_DISTANCES[(get_bits(v, 34, 2) << 1 | get_bits(v, 32, 1)) as usize];
+    let paint = [add_rgb(c0, 0), add_rgb(c1, d), add_rgb(c1, 0), add_rgb(c1, -d)];
+    write_paint_colors(v, paint, opaque, dst);
+}
+
+fn decode_h_mode(v: u64, opaque: bool, dst: &mut [[u8; 4]; 16]) {
+    let r0 = get_bits(v, 59, 4);
+    let g0 = get_bits(v, 56, 3) << 1 | get_bits(v, 52, 1);
+    let b0 = get_bits(v, 51, 1) << 3 | get_bits(v, 47, 3);
+    let r1 = get_bits(v, 43, 4);
+    let g1 = get_bits(v, 39, 4);
+    let b1 = get_bits(v, 35, 4);
+    // The lowest bit of the distance index is the order of the two base colors.
+    let order = ((r0 << 8 | g0 << 4 | b0) >= (r1 << 8 | g1 << 4 | b1)) as u32;
+    let d = ETC2_DISTANCES[(get_bits(v, 34, 1) << 2 | get_bits(v, 32, 1) << 1 | order) as usize];
+    let c0 = [expand_4(r0), expand_4(g0), expand_4(b0)];
+    let c1 = [expand_4(r1), expand_4(g1), expand_4(b1)];
+    let paint = [add_rgb(c0, d), add_rgb(c0, -d), add_rgb(c1, d), add_rgb(c1, -d)];
+    write_paint_colors(v, paint, opaque, dst);
+}
+
+fn decode_planar_mode(v: u64, dst: &mut [[u8; 4]; 16]) {
+    let o = [
+        expand_6(get_bits(v, 57, 6)),
+        expand_7(get_bits(v, 56, 1) << 6 | get_bits(v, 49, 6)),
+        expand_6(get_bits(v, 48, 1) << 5 | get_bits(v, 43, 2) << 3 | get_bits(v, 39, 3)),
+    ];
+    let h = [
+        expand_6(get_bits(v, 34, 5) << 1 | get_bits(v, 32, 1)),
+        expand_7(get_bits(v, 25, 7)),
+        expand_6(get_bits(v, 19, 6)),
+    ];
+    let vv = [expand_6(get_bits(v, 13, 6)), expand_7(get_bits(v, 6, 7)), expand_6(get_bits(v, 0, 6))];
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let x = (i & 3) as i32;
+        let y = (i >> 2) as i32;
+        let c = |ch: usize| (x * (h[ch] - o[ch]) + y * (vv[ch] - o[ch]) + 4 * o[ch] + 2) >> 2;
+        *texel = clamp_rgb(c(0), c(1), c(2));
+    }
+}
+
+// Decodes an ETC1 block, or an ETC2 block when etc2 is set. With punch_through (ETC2 RGB A1), the
+// diff bit is instead the opaque bit, and the individual mode doesn't exist.
+fn decode_etc_block(v: u64, etc2: bool, punch_through: bool, dst: &mut [[u8; 4]; 16]) {
+    let diff = punch_through || get_bits(v, 33, 1) != 0;
+    let opaque = !punch_through || get_bits(v, 33, 1) != 0;
+    let flip = get_bits(v, 32, 1) != 0;
+
+    let (c0, c1) = if diff {
+        let r = get_bits(v, 59, 5) as i32;
+        let g = get_bits(v, 51, 5) as i32;
+        let b = get_bits(v, 43, 5) as i32;
+        let dr = r + sign_extend_3(get_bits(v, 56, 3));
+        let dg = g + sign_extend_3(get_bits(v, 48, 3));
+        let db = b + sign_extend_3(get_bits(v, 40, 3));
+        // ETC2 uses the overflowing differential encodings for its extra modes.
+        if etc2 && !(0..32).contains(&dr) {
+            return decode_t_mode(v, opaque, dst);
+        } else if etc2 && !(0..32).contains(&dg) {
+            return decode_h_mode(v, opaque, dst);
+        } else if etc2 && !(0..32).contains(&db) {
+            return decode_planar_mode(v, dst);
+        }
+        (
+            [expand_5(r as u32), expand_5(g as u32), expand_5(b as u32)],
+            // An ETC1 block that overflows is invalid; wrap the sum around like hardware would.
+            [expand_5((dr & 0x1F) as u32), expand_5((dg & 0x1F) as u32), expand_5((db & 0x1F) as u32)],
+        )
+    } else {
+        (
+            [expand_4(get_bits(v, 60, 4)), expand_4(get_bits(v, 52, 4)), expand_4(get_bits(v, 44, 4))],
+            [expand_4(get_bits(v, 56, 4)), expand_4(get_bits(v, 48, 4)), expand_4(get_bits(v, 40, 4))],
+        )
+    };
+
+    let tables = [ETC1_MODIFIERS[get_bits(v, 37, 3) as usize], ETC1_MODIFIERS[get_bits(v, 34, 3) as usize]];
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let x = i & 3;
+        let y = i >> 2;
+        // Without the flip bit, the block is split into two 2x4 halves side by side; with it,
+        // into two 4x2 halves on top of each other.
+        let sub_block = if flip { y >> 1 } else { x >> 1 };
+        let base = if sub_block == 0 { c0 } else { c1 };
+        let table = tables[sub_block];
+        let index = get_texel_index(v, i);
+        *texel = if !opaque && index == 2 {
+            [0x00; 4]
+        } else {
+            // Index 0 is the small positive modifier, 1 the large positive, 2 the small negative
+            // and 3 the large negative. Without the opaque bit, the small modifiers are zero.
+            let modifier = match index {
+                0 if !opaque => 0,
+                0 => table[0],
+                1 => table[1],
+                2 => -table[0],
+                _ => -table[1],
+            };
+            add_rgb(base, modifier)
+        };
+    }
+}
+
+fn sign_extend_3(v: u32) -> i32 {
+    ((v << 29) as i32) >> 29
+}
+
+// EAC
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+// The modifier for texel i of an EAC block. The 3-bit indices start at the top of the low 48
+// bits, numbered down each column like ETC.
+fn get_eac_modifier(v: u64, i: usize) -> i32 {
+    let x = i & 3;
+    let y = i >> 2;
+    let index = get_bits(v, 45 - (x * 4 + y) as u32 * 3, 3);
+    EAC_MODIFIERS[get_bits(v, 48, 4) as usize][index as usize]
+}
+
+// Decodes an 8-bit EAC alpha block into channel 3.
+fn decode_eac_alpha_block(v: u64, dst: &mut [[u8; 4]; 16]) {
+    let base = get_bits(v, 56, 8) as i32;
+    let multiplier = get_bits(v, 52, 4) as i32;
+    for (i, texel) in dst.iter_mut().enumerate() {
+        texel[3] = (base + get_eac_modifier(v, i) * multiplier).clamp(0, 255) as u8;
+    }
+}
+
+// Decodes an 11-bit EAC block into channel ch, as u8, or as i8 when signed.
+fn decode_eac_r11_block(v: u64, signed: bool, ch: usize, dst: &mut [[u8; 4]; 16]) {
+    let multiplier = get_bits(v, 52, 4) as i32;
+    for (i, texel) in dst.iter_mut().enumerate() {
+        // A multiplier of 0 scales the modifier by 1 rather than 8.
+        let modifier = get_eac_modifier(v, i);
+        let modifier = if multiplier == 0 { modifier } else { modifier * multiplier * 8 };
+        texel[ch] = if signed {
+            // -128 and -127 both map to -1.0.
+            let base = (get_bits(v, 56, 8) as u8 as i8).max(-127) as i32;
+            let value = (base * 8 + modifier).clamp(-1023, 1023);
+            // Round half away from zero, so the result is symmetric around 0.
+            let bias = if value < 0 { -511 } else { 511 };
+            ((value * 127 + bias) / 1023) as i8 as u8
+        } else {
+            let base = get_bits(v, 56, 8) as i32;
+            let value = (base * 8 + 4 + modifier).clamp(0, 2047);
+            ((value * 255 + 1023) / 2047) as u8
+        };
+    }
+}
+
+// Alpha from a 3DS ETC1A4 block: 4-bit values, numbered down each column.
+fn decode_ctr_alpha_block(v: u64, dst: &mut [[u8; 4]; 16]) {
+    for (i, texel) in dst.iter_mut().enumerate() {
+        let x = i & 3;
+        let y = i >> 2;
+        texel[3] = (get_bits(v, (x * 4 + y) as u32 * 4, 4) * 0x11) as u8;
+    }
+}
+
+fn decode_block(src: &[u8], format: EtcFormat, dst: &mut [[u8; 4]; 16]) {
+    match format {
+        EtcFormat::Etc1 => decode_etc_block(get_uint64_be(src, 0x00), false, false, dst),
+        EtcFormat::Etc2Rgb => decode_etc_block(get_uint64_be(src, 0x00), true, false, dst),
+        EtcFormat::Etc2RgbA1 => decode_etc_block(get_uint64_be(src, 0x00), true, true, dst),
+        EtcFormat::Etc2Rgba => {
+            decode_etc_block(get_uint64_be(src, 0x08), true, false, dst);
+            decode_eac_alpha_block(get_uint64_be(src, 0x00), dst);
+        },
+        EtcFormat::EacR11 | EtcFormat::EacR11Snorm => {
+            let signed = format == EtcFormat::EacR11Snorm;
+            *dst = [[0x00, 0x00, 0x00, if signed { 0x7F } else { 0xFF }]; 16];
+            decode_eac_r11_block(get_uint64_be(src, 0x00), signed, 0, dst);
+        },
+        EtcFormat::EacRg11 | EtcFormat::EacRg11Snorm => {
+            let signed = format == EtcFormat::EacRg11Snorm;
+            *dst = [[0x00, 0x00, 0x00, if signed { 0x7F } else { 0xFF }]; 16];
+            decode_eac_r11_block(get_uint64_be(src, 0x00), signed, 0, dst);
+            decode_eac_r11_block(get_uint64_be(src, 0x08), signed, 1, dst);
+        },
+        EtcFormat::CtrEtc1 => decode_etc_block(get_uint64_le(src, 0x00), false, false, dst),
+        EtcFormat::CtrEtc1A4 => {
+            decode_etc_block(get_uint64_le(src, 0x08), false, false, dst);
+            decode_ctr_alpha_block(get_uint64_le(src, 0x00), dst);
+        },
+    }
+}
+
+// Decodes a surface to RGBA8.
+pub fn decode_surface(src: &[u8], format: EtcFormat, w: usize, h: usize) -> Vec<u8> {
+    let block_size = format.block_size();
+    let mut dst = vec![0x00; w * h * 4];
+    let mut texels = [[0x00; 4]; 16];
+    let mut src_offs = 0;
+    match format {
+        EtcFormat::CtrEtc1 | EtcFormat::CtrEtc1A4 => {
+            for yy in (0..h).step_by(8) {
+                for xx in (0..w).step_by(8) {
+                    for &(x, y) in [(0, 0), (4, 0), (0, 4), (4, 4)].iter() {
+                        decode_block(&src[src_offs..src_offs + block_size], format, &mut texels);
+                        copy_block(&mut dst, &texels, xx + x, yy + y, w, h);
+                        src_offs += block_size;
+                    }
+                }
+            }
+        },
+        _ => {
+            for y in (0..h).step_by(4) {
+                for x in (0..w).step_by(4) {
+                    decode_block(&src[src_offs..src_offs + block_size], format, &mut texels);
+                    copy_block(&mut dst, &texels, x, y, w, h);
+                    src_offs += block_size;
+                }
+            }
+        },
+    }
+    dst
+}
+
+#[wasm_bindgen]
+pub fn decode_etc(src: &[u8], format: EtcFormat, w: usize, h: usize) -> Vec<u8> {
+    decode_surface(src, format, w, h)
+}
+
+#[wasm_bindgen]
+pub fn calc_etc_surface_size(format: EtcFormat, w: usize, h: usize) -> usize {
+    calc_surface_size(format, w, h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets the 2-bit ETC index of texel (x, y).
+    fn with_index(v: u64, x: usize, y: usize, index: u64) -> u64 {
+        let bit = x * 4 + y;
+        v | (index >> 1) << (bit + 16) | (index & 1) << bit
+    }
+
+    fn texel(dst: &[u8], w: usize, x: usize, y: usize) -> [u8; 4] {
+        let offs = (y * w + x) * 4;
+        [dst[offs], dst[offs + 1], dst[offs + 2], dst[offs + 3]]
+    }
+
+    #[test]
+    fn test_etc1_individual() {
+        // R 0xF/0x1, G 0x2/0x3, B 0x4/0x5, tables 1 and 2, side by side.
+        let mut v = 0xF << 60 | 0x1 << 56 | 0x2 << 52 | 0x3 << 48 | 0x4 << 44 | 0x5 << 40 | 1 << 37 | 2 << 34;
+        v = with_index(v, 3, 0, 1);
+        v = with_index(v, 0, 1, 2);
+        v = with_index(v, 2, 3, 3);
+        let dst = decode_surface(&v.to_be_bytes(), EtcFormat::Etc1, 4, 4);
+        assert_eq!(texel(&dst, 4, 0, 0), [0xFF, 0x22 + 5, 0x44 + 5, 0xFF]);
+        assert_eq!(texel(&dst, 4, 3, 0), [0x11 + 29, 0x33 + 29, 0x55 + 29, 0xFF]);
+        assert_eq!(texel(&dst, 4, 0, 1), [0xFF - 5, 0x22 - 5, 0x44 - 5, 0xFF]);
+        assert_eq!(texel(&dst, 4, 2, 3), [0x00, 0x33 - 29, 0x55 - 29, 0xFF]);
+    }
+
+    #[test]
+    fn test_etc1_differential() {
+        // R 31/-1, G 0/+3, B 16/-4, flipped into top and bottom halves.
+        let v: u64 = 31 << 59 | 0b111 << 56 | 0b011 << 48 | 16 << 43 | 0b100 << 40 | 1 << 33 | 1 << 32;
+        let dst = decode_surface(&v.to_be_bytes(), EtcFormat::Etc1, 4, 4);
+        assert_eq!(texel(&dst, 4, 3, 1), [0xFF, 0x02, 0x86, 0xFF]);
+        assert_eq!(texel(&dst, 4, 0, 2), [0xF9, 0x1A, 0x65, 0xFF]);
+        // Blocks that don't overflow decode the same in ETC2.
+        assert_eq!(decode_surface(&v.to_be_bytes(), EtcFormat::Etc2Rgb, 4, 4), dst);
+    }
+
+    #[test]
+    fn test_etc2_t_mode() {
+        // R1 0b01|0b10 with R forced to overflow, G1 0x8, B1 0x4, C2 0x2AF, distance index 5.
+        let mut v: u64 = 0b01 << 59 | 1 << 58 | 0b10 << 56 | 0x8 << 52 | 0x4 << 48 | 0x2 << 44 | 0xA << 40 | 0xF << 36;
+        v |= 0b10 << 34 | 1 << 33 | 1 << 32;
+        for (x, index) in (0..4).zip(0..4) {
+            v = with_index(v, x, 0, index);
+        }
+        let dst = decode_surface(&v.to_be_bytes(), EtcFormat::Etc2Rgb, 4, 1);
+        assert_eq!(dst, [
+            0x66, 0x88, 0x44, 0xFF,
+            0x42, 0xCA, 0xFF, 0xFF,
+            0x22, 0xAA, 0xFF, 0xFF,
+            0x02, 0x8A, 0xDF, 0xFF,
+        ]);
+
+        // Without the opaque bit, index 2 is transparent.
+        let dst = decode_surface(&(v & !(1 << 33)).to_be_bytes(), EtcFormat::Etc2RgbA1, 4, 1);
+        assert_eq!(dst[8..12], [0x00; 4]);
+        assert_eq!(dst[12..16], [0x02, 0x8A, 0xDF, 0xFF]);
+    }
+
+    #[test]
+    fn test_etc2_h_mode() {
+        // C1 0x351 and C2 0x352 with G forced to overflow, distance index 4.
+        let mut v: u64 = 0x3 << 59 | 0b010 << 56 | 1 << 52 | 1 << 50 | 0b001 << 47 | 0x3 << 43 | 0x5 << 39 | 0x2 << 35;
+        v |= 1 << 34 | 1 << 33;
+        for (x, index) in (0..4).zip(0..4) {
+            v = with_index(v, x, 0, index);
+        }
+        let dst = decode_surface(&v.to_be_bytes(), EtcFormat::Etc2Rgb, 4, 1);
+        assert_eq!(dst, [
+            0x4A, 0x6C, 0x28, 0xFF,
+            0x1C, 0x3E, 0x00, 0xFF,
+            0x4A, 0x6C, 0x39, 0xFF,
+            0x1C, 0x3E, 0x0B, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn test_etc2_planar_mode() {
+        // O (0, 0, 27), H (63, 0, 0), V (0, 127, 63), with B forced to overflow.
+        let v: u64 = 0b111 << 45 | 0b11 << 43 | 0b011 << 39 | 0b11111 << 34 | 1 << 33 | 1 << 32 | 127 << 6 | 63;
+        let dst = decode_surface(&v.to_be_bytes(), EtcFormat::Etc2Rgb, 4, 4);
+        assert_eq!(texel(&dst, 4, 0, 0), [0, 0, 109, 0xFF]);
+        assert_eq!(texel(&dst, 4, 3, 0), [191, 0, 27, 0xFF]);
+        assert_eq!(texel(&dst, 4, 0, 3), [0, 191, 219, 0xFF]);
+        assert_eq!(texel(&dst, 4, 3, 3), [191, 191, 137, 0xFF]);
+        assert_eq!(texel(&dst, 4, 1, 2), [64, 128, 155, 0xFF]);
+    }
+
+    // Base 100, multiplier 2, table 13: texel (0, 0) uses index 7, (0, 1) index 3, (1, 0) index 0,
+    // and the rest index 4.
+    fn eac_block(base: u64, multiplier: u64) -> u64 {
+        let mut v = base << 56 | multiplier << 52 | 13 << 48;
+        for k in 0..16 {
+            let index = match k { 0 => 7, 1 => 3, 4 => 0, _ => 4 };
+            v |= index << (45 - k * 3);
+        }
+        v
+    }
+
+    #[test]
+    fn test_eac() {
+        let alpha = eac_block(100, 2);
+        let mut src = alpha.to_be_bytes().to_vec();
+        src.extend_from_slice(&(1u64 << 33).to_be_bytes());
+        let dst = decode_surface(&src, EtcFormat::Etc2Rgba, 4, 4);
+        assert_eq!([dst[3], dst[4 * 4 + 3], dst[4 + 3], dst[8 + 3]], [118, 80, 98, 100]);
+
+        let dst = decode_surface(&alpha.to_be_bytes(), EtcFormat::EacR11, 4, 4);
+        assert_eq!(texel(&dst, 4, 0, 0), [118, 0x00, 0x00, 0xFF]);
+        assert_eq!([texel(&dst, 4, 0, 1)[0], texel(&dst, 4, 1, 0)[0], texel(&dst, 4, 3, 3)[0]], [80, 98, 100]);
+
+        // A multiplier of 0 adds the modifier to the 11-bit value unscaled.
+        let dst = decode_surface(&eac_block(100, 0).to_be_bytes(), EtcFormat::EacR11, 4, 4);
+        assert_eq!(dst[0], 101);
+
+        // -128 acts as -127, and the result clamps to -1.0.
+        let mut src = eac_block(0x80, 1).to_be_bytes().to_vec();
+        src.extend_from_slice(&eac_block(100, 2).to_be_bytes());
+        let dst = decode_surface(&src, EtcFormat::EacRg11Snorm, 4, 4);
+        assert_eq!(texel(&dst, 4, 0, 1), [(-127i8) as u8, 79, 0x00, 0x7F]);
+        assert_eq!(texel(&dst, 4, 2, 2), [(-126i8) as u8, 99, 0x00, 0x7F]);
+    }
+
+    // An ETC1 block of a single color, (k * 0x11 + 2, 2, 2).
+    fn solid_etc1_block(k: u64) -> u64 {
+        k << 60 | k << 56
+    }
+
+    #[test]
+    fn test_ctr_tiling() {
+        // Two 8x8 tiles, each holding four blocks.
+        let src: Vec<u8> = (0..8).flat_map(|k| solid_etc1_block(k).to_le_bytes().to_vec()).collect();
+        let dst = decode_surface(&src, EtcFormat::CtrEtc1, 16, 8);
+        let block_colors: Vec<u8> = [(0, 0), (4, 0), (8, 0), (12, 0), (0, 4), (4, 4), (8, 4), (12, 4)].iter()
+            .map(|&(x, y)| texel(&dst, 16, x + 3, y + 3)[0]).collect();
+        assert_eq!(block_colors, [0x02, 0x13, 0x46, 0x57, 0x24, 0x35, 0x68, 0x79]);
+    }
+
+    #[test]
+    fn test_ctr_etc1a4() {
+        // Alpha for texel (x, y) is x * 0x11, except (3, 1), which is 0x55.
+        let alpha = (0..16).fold(0u64, |v, i| v | ((i >> 2) as u64) << (i * 4)) & !(0xF << 52) | 0x5 << 52;
+        let mut src = vec![];
+        for k in 0..4 {
+            src.extend_from_slice(&alpha.to_le_bytes());
+            src.extend_from_slice(&solid_etc1_block(k).to_le_bytes());
+        }
+        let dst = decode_surface(&src, EtcFormat::CtrEtc1A4, 8, 8);
+        assert_eq!(texel(&dst, 8, 0, 0), [0x02, 0x02, 0x02, 0x00]);
+        assert_eq!(texel(&dst, 8, 6, 1), [0x13, 0x02, 0x02, 0x22]);
+        assert_eq!(texel(&dst, 8, 7, 5), [0x35, 0x02, 0x02, 0x55]);
+        assert_eq!(texel(&dst, 8, 3, 7), [0x24, 0x02, 0x02, 0x33]);
+    }
+
+    #[test]
+    fn test_partial_blocks() {
+        let src: Vec<u8> = (0..4).flat_map(|k| solid_etc1_block(k).to_be_bytes().to_vec()).collect();
+        let dst = decode_surface(&src, EtcFormat::Etc1, 5, 6);
+        assert_eq!(dst.len(), 5 * 6 * 4);
+        assert_eq!(texel(&dst, 5, 3, 3)[0], 0x02);
+        assert_eq!(texel(&dst, 5, 4, 0)[0], 0x13);
+        assert_eq!(texel(&dst, 5, 0, 5)[0], 0x24);
+        assert_eq!(texel(&dst, 5, 4, 5)[0], 0x35);
+        assert_eq!(calc_surface_size(EtcFormat::Etc1, 5, 6), src.len());
+        assert_eq!(calc_surface_size(EtcFormat::CtrEtc1A4, 5, 6), 0x40);
+    }
+}
@@ -1,57 +1,858 @@
-
-use wasm_bindgen::prelude::wasm_bindgen;
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
 use web_sys::console;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::fmt;
+use std::ops::Range;
 
-fn show_error(place: &str, error: impl Error) {
-    console::log_2(&place.into(), &error.to_string().into());
+/// A region of the shader source that a diagnostic refers to. `file` is the name of the
+/// include it lies in, or empty for the shader itself. Lines and columns are 1-based, columns
+/// count characters, and the end is exclusive.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticSpan {
+    #[wasm_bindgen(skip)]
+    pub file: String,
+    pub line_start: u32,
+    pub column_start: u32,
+    pub line_end: u32,
+    pub column_end: u32,
+    #[wasm_bindgen(skip)]
+    pub label: String,
+}
 
-    let mut e = error.source();
-    while let Some(source) = e {
-        console::log_1(&source.to_string().into());
-        e = source.source();
+#[wasm_bindgen]
+impl DiagnosticSpan {
+    #[wasm_bindgen(getter)] pub fn file(&self) -> String { self.file.clone() }
+    #[wasm_bindgen(getter)] pub fn label(&self) -> String { self.label.clone() }
+}
+
+impl DiagnosticSpan {
+    fn new(file: &str, source: &str, range: Range<usize>, label: String) -> Self {
+        let (line_start, column_start) = line_column(source, range.start);
+        let (line_end, column_end) = line_column(source, range.end);
+        DiagnosticSpan { file: file.to_string(), line_start, column_start, line_end, column_end, label }
     }
 }
 
+fn line_column(source: &str, offset: usize) -> (u32, u32) {
+    let mut line = 1;
+    let mut column = 1;
+    for (i, c) in source.char_indices() {
+        if i >= offset {
+            break;
+        }
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+/// A single error hit while compiling a shader: which stage of the pipeline raised it, its
+/// message, the messages of its underlying causes, and where in the source it happened.
 #[wasm_bindgen]
-pub fn glsl_compile(source: &str, stage: &str, validation_enabled: bool) -> String {
-    let naga_stage = match stage {
-        "vertex" => Ok(naga::ShaderStage::Vertex),
-        "fragment" => Ok(naga::ShaderStage::Fragment),
-        "compute" => Ok(naga::ShaderStage::Compute),
-        _ => Err("unknown shader stage")
-    }.unwrap();
+#[derive(Debug, Clone)]
+pub struct CompileDiagnostic {
+    #[wasm_bindgen(skip)]
+    pub place: &'static str,
+    #[wasm_bindgen(skip)]
+    pub message: String,
+    #[wasm_bindgen(skip)]
+    pub source_chain: Vec<String>,
+    #[wasm_bindgen(skip)]
+    pub spans: Vec<DiagnosticSpan>,
+}
 
-    let mut parser = naga::front::glsl::Parser::default();
-    let module = match parser.parse(&naga::front::glsl::Options {
-        stage: naga_stage,
-        defines: Default::default(),
-    }, &source) {
+#[wasm_bindgen]
+impl CompileDiagnostic {
+    #[wasm_bindgen(getter)] pub fn place(&self) -> String { self.place.to_string() }
+    #[wasm_bindgen(getter)] pub fn message(&self) -> String { self.message.clone() }
+
+    pub fn source_chain(&self) -> Array {
+        self.source_chain.iter().map(|s| JsValue::from(s.as_str())).collect()
+    }
+
+    pub fn spans(&self) -> Array {
+        self.spans.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+impl CompileDiagnostic {
+    fn new(place: &'static str, error: &dyn Error, spans: Vec<DiagnosticSpan>) -> Self {
+        let mut source_chain = Vec::new();
+        let mut e = error.source();
+        while let Some(source) = e {
+            source_chain.push(source.to_string());
+            e = source.source();
+        }
+        CompileDiagnostic { place, message: error.to_string(), source_chain, spans }
+    }
+
+    fn log(&self) {
+        console::log_2(&self.place.into(), &self.message.as_str().into());
+        for source in &self.source_chain {
+            console::log_1(&source.as_str().into());
+        }
+    }
+}
+
+/// Every error that stopped a shader from compiling.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct CompileDiagnostics {
+    #[wasm_bindgen(skip)]
+    pub diagnostics: Vec<CompileDiagnostic>,
+}
+
+#[wasm_bindgen]
+impl CompileDiagnostics {
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<CompileDiagnostic> {
+        self.diagnostics.get(i).cloned()
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for CompileDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match d.spans.first() {
+                Some(span) if !span.file.is_empty() => write!(f, "{}:{}:{}: {}: {}", span.file, span.line_start, span.column_start, d.place, d.message)?,
+                Some(span) => write!(f, "{}:{}: {}: {}", span.line_start, span.column_start, d.place, d.message)?,
+                None => write!(f, "{}: {}", d.place, d.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<CompileDiagnostic> for CompileDiagnostics {
+    fn from(diagnostic: CompileDiagnostic) -> Self {
+        CompileDiagnostics { diagnostics: vec![diagnostic] }
+    }
+}
+
+#[derive(Debug)]
+enum CompileError {
+    UnknownStage(String),
+    UnknownTarget(String),
+    MalformedInclude,
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    InvalidDefineName(String),
+    InvalidDefineValue(String, pp_rs::token::PreprocessorError),
+    NoEntryPoint,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::UnknownStage(stage) => write!(f, "unknown shader stage: {}", stage),
+            CompileError::UnknownTarget(target) => write!(f, "unknown compile target: {}", target),
+            CompileError::MalformedInclude => write!(f, "expected #include \"name\" or #include <name>"),
+            CompileError::IncludeNotFound(name) => write!(f, "include not found: {}", name),
+            CompileError::IncludeCycle(name) => write!(f, "include cycle through {}", name),
+            CompileError::InvalidDefineName(name) => write!(f, "invalid macro name: {:?}", name),
+            CompileError::InvalidDefineValue(name, e) => write!(f, "invalid value for {}: {:?}", name, e),
+            CompileError::NoEntryPoint => write!(f, "shader has no entry point"),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// Preprocessor input for glsl_compile_with_options: #defines to start the shader with, and
+/// the table of sources that #include directives are resolved against.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct GlslCompileOptions {
+    #[wasm_bindgen(skip)]
+    pub defines: BTreeMap<String, String>,
+    #[wasm_bindgen(skip)]
+    pub includes: HashMap<String, String>,
+}
+
+#[wasm_bindgen]
+impl GlslCompileOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn add_include(&mut self, name: &str, source: &str) {
+        self.includes.insert(name.to_string(), source.to_string());
+    }
+}
+
+// Splits the identifier (or number) off the front of s.
+fn split_ident(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+// Splits a preprocessor line into its directive name and the rest of the line.
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let (directive, rest) = split_ident(line.trim_start().strip_prefix('#')?.trim_start());
+    Some((directive, rest.trim()))
+}
+
+fn parse_include_name(arg: &str) -> Option<&str> {
+    let close = match arg.chars().next()? {
+        '"' => '"',
+        '<' => '>',
+        _ => return None,
+    };
+    let end = arg[1..].find(close)?;
+    Some(&arg[1..end + 1])
+}
+
+struct SourceSegment {
+    start: usize,
+    file: usize,
+    file_start: usize,
+    len: usize,
+}
+
+// naga's preprocessor doesn't know #include, so includes are pasted in textually before
+// parsing. The segments map each run of the pasted text back to the file it came from, so
+// that diagnostics point at the include rather than at the combined source.
+//
+// Which includes are compiled is up to the #if branches around them, and only the
+// preprocessor knows that. So every include is pasted, and one that can't be resolved becomes
+// an #error, which the preprocessor reports only if it keeps the branch it's in.
+struct ExpandedSource<'a> {
+    text: String,
+    // (name, source) pairs; 0 is the shader itself.
+    files: Vec<(&'a str, &'a str)>,
+    segments: Vec<SourceSegment>,
+    // The #error lines standing in for failed includes, and the diagnostics they stand for.
+    deferred: Vec<(Range<usize>, CompileDiagnostic)>,
+}
+
+impl<'a> ExpandedSource<'a> {
+    fn new(source: &'a str, includes: &'a HashMap<String, String>) -> Self {
+        let mut expanded = ExpandedSource {
+            text: String::with_capacity(source.len()),
+            files: vec![("", source)],
+            segments: Vec::new(),
+            deferred: Vec::new(),
+        };
+        expanded.expand(0, 0..source.len(), includes, &mut vec![0]);
+        expanded
+    }
+
+    fn push(&mut self, file: usize, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.segments.push(SourceSegment { start: self.text.len(), file, file_start: range.start, len: range.len() });
+        self.text.push_str(&self.files[file].1[range]);
+    }
+
+    fn resolve_include(&mut self, arg: &'a str, includes: &'a HashMap<String, String>, stack: &[usize]) -> Result<(usize, &'a str), CompileError> {
+        let name = parse_include_name(arg).ok_or(CompileError::MalformedInclude)?;
+        let include = includes.get(name).ok_or_else(|| CompileError::IncludeNotFound(name.to_string()))?;
+        let index = match self.files.iter().skip(1).position(|&(n, _)| n == name) {
+            Some(i) => i + 1,
+            None => {
+                self.files.push((name, include));
+                self.files.len() - 1
+            },
+        };
+        // A file may come round once more, so that an include guard gets to see its own
+        // #define and skip the body. A third time it's a cycle.
+        if stack.iter().filter(|&&i| i == index).count() >= 2 {
+            return Err(CompileError::IncludeCycle(name.to_string()));
+        }
+        Ok((index, include))
+    }
+
+    fn expand(&mut self, file: usize, range: Range<usize>, includes: &'a HashMap<String, String>, stack: &mut Vec<usize>) {
+        let (file_name, source) = self.files[file];
+        let mut copied = range.start;
+        let mut offset = range.start;
+        for line in source[range.clone()].split_inclusive('\n') {
+            let line_end = offset + line.trim_end_matches(&['\r', '\n'][..]).len();
+            if let Some(("include", arg)) = parse_directive(line) {
+                let directive_start = offset + line.len() - line.trim_start().len();
+                let resolved = self.resolve_include(arg, includes, stack);
+                self.push(file, copied..offset);
+                match resolved {
+                    Ok((index, include)) => {
+                        stack.push(index);
+                        self.expand(index, 0..include.len(), includes, stack);
+                        stack.pop();
+                    },
+                    Err(e) => {
+                        let span = DiagnosticSpan::new(file_name, source, directive_start..line_end, String::new());
+                        self.defer(file, directive_start..line_end, CompileDiagnostic::new("include", &e, vec![span]));
+                    },
+                }
+                // The directive's own line break stays, ending the included text's last line.
+                copied = line_end;
+            }
+            offset += line.len();
+        }
+        self.push(file, copied..range.end);
+    }
+
+    fn defer(&mut self, file: usize, range: Range<usize>, diagnostic: CompileDiagnostic) {
+        let start = self.text.len();
+        self.segments.push(SourceSegment { start, file, file_start: range.start, len: range.len() });
+        self.text.push_str("#error");
+        self.deferred.push((start..self.text.len(), diagnostic));
+    }
+
+    // The include error an #error at span stands for, if it's one of ours.
+    fn deferred_error(&self, span: naga::Span) -> Option<&CompileDiagnostic> {
+        let start = span.to_range()?.start;
+        self.deferred.iter().find(|(range, _)| range.contains(&start)).map(|(_, diagnostic)| diagnostic)
+    }
+
+    // Maps an offset in the expanded text to (file, offset in file, end of the run in file).
+    fn locate(&self, offset: usize) -> (usize, usize, usize) {
+        let i = match self.segments.binary_search_by_key(&offset, |s| s.start) {
+            Ok(i) => i,
+            Err(0) => return (0, 0, 0),
+            Err(i) => i - 1,
+        };
+        let s = &self.segments[i];
+        (s.file, s.file_start + (offset - s.start).min(s.len), s.file_start + s.len)
+    }
+
+    fn span(&self, span: naga::Span, label: String) -> Option<DiagnosticSpan> {
+        let range = span.to_range()?;
+        let (file, start, run_end) = self.locate(range.start);
+        let end = if range.end > range.start {
+            match self.locate(range.end - 1) {
+                (end_file, end, _) if end_file == file && end >= start => end + 1,
+                _ => run_end,
+            }
+        } else {
+            start
+        };
+        let (name, source) = self.files[file];
+        Some(DiagnosticSpan::new(name, source, start..end, label))
+    }
+}
+
+/// A resource the compiled shader binds, as declared in the source. `target_name` is the
+/// name the target gave it where that differs in a way the caller has to bind by: the
+/// uniform block or combined sampler name in GLSL, or the argument table slot in MSL.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderResource {
+    #[wasm_bindgen(skip)]
+    pub name: String,
+    #[wasm_bindgen(skip)]
+    pub kind: &'static str,
+    #[wasm_bindgen(skip)]
+    pub binding: Option<(u32, u32)>,
+    #[wasm_bindgen(skip)]
+    pub target_name: Option<String>,
+}
+
+#[wasm_bindgen]
+impl ShaderResource {
+    #[wasm_bindgen(getter)] pub fn name(&self) -> String { self.name.clone() }
+    #[wasm_bindgen(getter)] pub fn kind(&self) -> String { self.kind.to_string() }
+    #[wasm_bindgen(getter)] pub fn group(&self) -> Option<u32> { self.binding.map(|(group, _)| group) }
+    #[wasm_bindgen(getter)] pub fn binding(&self) -> Option<u32> { self.binding.map(|(_, binding)| binding) }
+    #[wasm_bindgen(getter)] pub fn target_name(&self) -> Option<String> { self.target_name.clone() }
+}
+
+/// The translated shader: `text` for the source targets, `binary` holding SPIR-V words for
+/// "spirv", along with the entry point's name in the output and the resources it binds.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct CompileOutput {
+    #[wasm_bindgen(skip)]
+    pub text: Option<String>,
+    #[wasm_bindgen(skip)]
+    pub binary: Option<Vec<u32>>,
+    #[wasm_bindgen(skip)]
+    pub entry_point: String,
+    #[wasm_bindgen(skip)]
+    pub resources: Vec<ShaderResource>,
+}
+
+#[wasm_bindgen]
+impl CompileOutput {
+    #[wasm_bindgen(getter)] pub fn text(&self) -> Option<String> { self.text.clone() }
+    #[wasm_bindgen(getter)] pub fn binary(&self) -> Option<Vec<u32>> { self.binary.clone() }
+    #[wasm_bindgen(getter)] pub fn entry_point(&self) -> String { self.entry_point.clone() }
+
+    pub fn resources(&self) -> Array {
+        self.resources.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+type GlobalNames = HashMap<naga::Handle<naga::GlobalVariable>, String>;
+
+fn shader_resources(module: &naga::Module, target_names: &GlobalNames) -> Vec<ShaderResource> {
+    module.global_variables.iter().filter_map(|(handle, var)| {
+        let kind = match (var.space, &module.types[var.ty].inner) {
+            (naga::AddressSpace::Uniform, _) => "uniform",
+            (naga::AddressSpace::Storage { .. }, _) => "storage",
+            (naga::AddressSpace::PushConstant, _) => "push_constant",
+            (naga::AddressSpace::Handle, naga::TypeInner::Image { class: naga::ImageClass::Storage { .. }, .. }) => "storage_texture",
+            (naga::AddressSpace::Handle, naga::TypeInner::Image { .. }) => "texture",
+            (naga::AddressSpace::Handle, naga::TypeInner::Sampler { .. }) => "sampler",
+            _ => return None,
+        };
+        Some(ShaderResource {
+            // Anonymous uniform blocks have no variable name, only the block's.
+            name: var.name.clone().or_else(|| module.types[var.ty].name.clone()).unwrap_or_default(),
+            kind,
+            binding: var.binding.as_ref().map(|b| (b.group, b.binding)),
+            target_name: target_names.get(&handle).cloned(),
+        })
+    }).collect()
+}
+
+fn next_msl_slot(count: &mut usize) -> Result<naga::back::msl::Slot, CompileDiagnostic> {
+    use std::convert::TryFrom;
+    let slot = naga::back::msl::Slot::try_from(*count).map_err(|e| CompileDiagnostic::new("msl::Options", &e, vec![]))?;
+    *count += 1;
+    Ok(slot)
+}
+
+// Metal has separate argument tables for buffers, textures and samplers, so each resource
+// takes the next slot of its kind, in (group, binding) order. Push constants and the buffer
+// sizes naga needs for runtime-sized arrays go in the buffer slots after those.
+fn msl_resources(module: &naga::Module, target_names: &mut GlobalNames) -> Result<naga::back::msl::PerStageResources, CompileDiagnostic> {
+    let mut bound: Vec<_> = module.global_variables.iter()
+        .filter_map(|(handle, var)| Some((var.binding.clone()?, handle, var)))
+        .collect();
+    bound.sort_by_key(|(binding, _, _)| (binding.group, binding.binding));
+
+    let mut resources = naga::back::msl::PerStageResources::default();
+    let (mut buffers, mut textures, mut samplers) = (0, 0, 0);
+    for (binding, handle, var) in bound {
+        let mut target = naga::back::msl::BindTarget::default();
+        let name = match module.types[var.ty].inner {
+            naga::TypeInner::Image { .. } => {
+                let slot = next_msl_slot(&mut textures)?;
+                target.texture = Some(slot);
+                format!("texture({})", slot)
+            },
+            naga::TypeInner::Sampler { .. } => {
+                let slot = next_msl_slot(&mut samplers)?;
+                target.sampler = Some(naga::back::msl::BindSamplerTarget::Resource(slot));
+                format!("sampler({})", slot)
+            },
+            _ => {
+                let slot = next_msl_slot(&mut buffers)?;
+                target.buffer = Some(slot);
+                format!("buffer({})", slot)
+            },
+        };
+        resources.resources.insert(binding, target);
+        target_names.insert(handle, name);
+    }
+
+    let push_constant_buffer = next_msl_slot(&mut buffers)?;
+    for (handle, var) in module.global_variables.iter() {
+        if var.space == naga::AddressSpace::PushConstant {
+            target_names.insert(handle, format!("buffer({})", push_constant_buffer));
+        }
+    }
+    resources.push_constant_buffer = Some(push_constant_buffer);
+    resources.sizes_buffer = Some(next_msl_slot(&mut buffers)?);
+    Ok(resources)
+}
+
+#[wasm_bindgen]
+pub fn glsl_compile(source: &str, stage: &str, validation_enabled: bool) -> String {
+    match glsl_compile_checked(source, stage, validation_enabled) {
         Ok(v) => v,
-        Err(errors) => {
-            for e in errors {
-                show_error(&"glsl::parse_str", e);
+        Err(diagnostics) => {
+            for d in &diagnostics.diagnostics {
+                d.log();
             }
 
             panic!();
         },
+    }
+}
+
+/// Like glsl_compile, but hands every parse and validation error back to the caller instead
+/// of logging it and aborting.
+#[wasm_bindgen]
+pub fn glsl_compile_checked(source: &str, stage: &str, validation_enabled: bool) -> Result<String, CompileDiagnostics> {
+    let output = glsl_compile_with_options(source, stage, validation_enabled, "wgsl", &GlslCompileOptions::new())?;
+    Ok(output.text.unwrap())
+}
+
+const TARGETS: &[&str] = &["wgsl", "glsl", "spirv", "msl", "hlsl"];
+
+/// Compiles GLSL with extra #defines and a table of sources for #include, and translates it
+/// to `target`: "wgsl", "glsl" (GLSL ES 3.00), "spirv", "msl" or "hlsl".
+#[wasm_bindgen]
+pub fn glsl_compile_with_options(source: &str, stage: &str, validation_enabled: bool, target: &str, options: &GlslCompileOptions) -> Result<CompileOutput, CompileDiagnostics> {
+    let naga_stage = match stage {
+        "vertex" => naga::ShaderStage::Vertex,
+        "fragment" => naga::ShaderStage::Fragment,
+        "compute" => naga::ShaderStage::Compute,
+        _ => return Err(CompileDiagnostic::new("stage", &CompileError::UnknownStage(stage.to_string()), vec![]).into()),
     };
+    if !TARGETS.contains(&target) {
+        return Err(CompileDiagnostic::new("target", &CompileError::UnknownTarget(target.to_string()), vec![]).into());
+    }
 
-    let validation_flags = if validation_enabled { naga::valid::ValidationFlags::all() } else { naga::valid::ValidationFlags::empty() };
-    let info = match naga::valid::Validator::new(validation_flags, naga::valid::Capabilities::all()).validate(&module) {
-        Ok(v) => v,
-        Err(e) => {
-            show_error(&"validator", e);
-            panic!();
+    // naga hands the defines to its preprocessor and unwraps the result, so a value that
+    // doesn't lex would panic. Try each on a preprocessor of our own first.
+    for (name, value) in &options.defines {
+        let (ident, rest) = split_ident(name);
+        if !rest.is_empty() || !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return Err(CompileDiagnostic::new("define", &CompileError::InvalidDefineName(name.clone()), vec![]).into());
+        }
+        if let Err((e, _)) = pp_rs::pp::Preprocessor::new("").add_define(name, value) {
+            return Err(CompileDiagnostic::new("define", &CompileError::InvalidDefineValue(name.clone(), e), vec![]).into());
         }
+    }
+    let expanded = ExpandedSource::new(source, &options.includes);
+
+    let mut parser = naga::front::glsl::Parser::default();
+    let module = parser.parse(&naga::front::glsl::Options {
+        stage: naga_stage,
+        defines: options.defines.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+    }, &expanded.text).map_err(|errors| CompileDiagnostics {
+        diagnostics: errors.iter().map(|e| match expanded.deferred_error(e.meta) {
+            Some(diagnostic) => diagnostic.clone(),
+            None => {
+                let spans = expanded.span(e.meta, String::new()).into_iter().collect();
+                CompileDiagnostic::new("glsl::parse_str", e, spans)
+            },
+        }).collect(),
+    })?;
+
+    let validation_flags = if validation_enabled { naga::valid::ValidationFlags::all() } else { naga::valid::ValidationFlags::empty() };
+    let info = naga::valid::Validator::new(validation_flags, naga::valid::Capabilities::all()).validate(&module).map_err(|e| {
+        let spans = e.spans().filter_map(|(span, label)| expanded.span(*span, label.clone())).collect();
+        CompileDiagnostic::new("validator", &e, spans)
+    })?;
+
+    let entry_point = match module.entry_points.first() {
+        Some(entry_point) => entry_point.name.clone(),
+        None => return Err(CompileDiagnostic::new("entry point", &CompileError::NoEntryPoint, vec![]).into()),
     };
+    let mut output = CompileOutput { text: None, binary: None, entry_point, resources: Vec::new() };
+    let target_names = match target {
+        "wgsl" => {
+            let writer_flags = naga::back::wgsl::WriterFlags::all();
+            let text = naga::back::wgsl::write_string(&module, &info, writer_flags)
+                .map_err(|e| CompileDiagnostic::new("wgsl::write_string", &e, vec![]))?;
+            output.text = Some(text);
+            GlobalNames::new()
+        },
+        "glsl" => {
+            let glsl_options = naga::back::glsl::Options {
+                version: naga::back::glsl::Version::new_gles(300),
+                ..Default::default()
+            };
+            let pipeline_options = naga::back::glsl::PipelineOptions {
+                shader_stage: naga_stage,
+                entry_point: output.entry_point.clone(),
+                multiview: None,
+            };
+            let mut text = String::new();
+            let reflection = naga::back::glsl::Writer::new(&mut text, &module, &info, &glsl_options, &pipeline_options, Default::default())
+                .and_then(|mut writer| writer.write())
+                .map_err(|e| CompileDiagnostic::new("glsl::Writer::write", &e, vec![]))?;
 
-    let writer_flags = naga::back::wgsl::WriterFlags::all();
-    match naga::back::wgsl::write_string(&module, &info, writer_flags) {
-        Ok(v) => v,
-        Err(e) => {
-            show_error(&"wgsl::write_string", e);
-            panic!();
+            let mut target_names = GlobalNames::new();
+            target_names.extend(reflection.uniforms);
+            // A texture sampled through several samplers gets a combined name for each; take the
+            // first in sorted order so the reflection doesn't depend on hash map order.
+            let mut texture_mapping: Vec<_> = reflection.texture_mapping.into_iter().collect();
+            texture_mapping.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, mapping) in texture_mapping {
+                target_names.entry(mapping.texture).or_insert(name);
+            }
+            output.text = Some(text);
+            target_names
+        },
+        "spirv" => {
+            let pipeline_options = naga::back::spv::PipelineOptions {
+                shader_stage: naga_stage,
+                entry_point: output.entry_point.clone(),
+            };
+            let words = naga::back::spv::write_vec(&module, &info, &Default::default(), Some(&pipeline_options))
+                .map_err(|e| CompileDiagnostic::new("spv::write_vec", &e, vec![]))?;
+            output.binary = Some(words);
+            GlobalNames::new()
+        },
+        "msl" => {
+            let mut msl_options = naga::back::msl::Options {
+                // An unmapped binding is an error rather than a made-up [[user(fake0)]].
+                fake_missing_bindings: false,
+                ..Default::default()
+            };
+            let mut target_names = GlobalNames::new();
+            let resources = msl_resources(&module, &mut target_names)?;
+            match naga_stage {
+                naga::ShaderStage::Vertex => msl_options.per_stage_map.vs = resources,
+                naga::ShaderStage::Fragment => msl_options.per_stage_map.fs = resources,
+                naga::ShaderStage::Compute => msl_options.per_stage_map.cs = resources,
+            }
+            let (text, translation) = naga::back::msl::write_string(&module, &info, &msl_options, &Default::default())
+                .map_err(|e| CompileDiagnostic::new("msl::write_string", &e, vec![]))?;
+            output.entry_point = translation.entry_point_names.into_iter().next().unwrap()
+                .map_err(|e| CompileDiagnostic::new("msl::write_string", &e, vec![]))?;
+            output.text = Some(text);
+            target_names
+        },
+        "hlsl" => {
+            let hlsl_options = Default::default();
+            let mut text = String::new();
+            let reflection = naga::back::hlsl::Writer::new(&mut text, &hlsl_options).write(&module, &info)
+                .map_err(|e| CompileDiagnostic::new("hlsl::Writer::write", &e, vec![]))?;
+            output.entry_point = reflection.entry_point_names.into_iter().next().unwrap()
+                .map_err(|e| CompileDiagnostic::new("hlsl::Writer::write", &e, vec![]))?;
+            output.text = Some(text);
+            GlobalNames::new()
+        },
+        _ => unreachable!(),
+    };
+    output.resources = shader_resources(&module, &target_names);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAGMENT: &str = "#version 450\n\nlayout(location = 0) out vec4 o_color;\n\nvoid main() {\n    o_color = vec4(1.0);\n}\n";
+
+    #[test]
+    fn test_compile() {
+        let wgsl = glsl_compile_checked(FRAGMENT, "fragment", true).unwrap();
+        assert!(wgsl.contains("@fragment"));
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let source = FRAGMENT.replace("vec4(1.0)", "vec4(u_missing)");
+        let diagnostics = glsl_compile_checked(&source, "fragment", true).unwrap_err();
+        assert!(!diagnostics.is_empty());
+        let d = &diagnostics.diagnostics[0];
+        assert_eq!(d.place, "glsl::parse_str");
+        assert!(d.message.contains("u_missing"));
+        let span = &d.spans[0];
+        assert_eq!((span.line_start, span.column_start), (6, 20));
+        assert_eq!((span.line_end, span.column_end), (6, 29));
+        assert!(diagnostics.to_string().starts_with("6:20: glsl::parse_str: "));
+    }
+
+    #[test]
+    fn test_validation_error_span() {
+        let source = FRAGMENT.replace("vec4(1.0)", "vec4(1.0) * mat3(1.0)");
+        let diagnostics = glsl_compile_checked(&source, "fragment", true).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        let d = &diagnostics.diagnostics[0];
+        assert_eq!(d.place, "validator");
+        assert!(d.source_chain.iter().any(|s| s.contains("Multiply")));
+        let span = d.spans.iter().find(|span| span.label.starts_with("naga::Expression")).unwrap();
+        assert_eq!((span.line_start, span.column_start), (6, 15));
+        assert_eq!((span.line_end, span.column_end), (6, 36));
+    }
+
+    #[test]
+    fn test_unknown_stage() {
+        let diagnostics = glsl_compile_checked(FRAGMENT, "geometry", true).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics.diagnostics[0].message, "unknown shader stage: geometry");
+        assert!(diagnostics.diagnostics[0].spans.is_empty());
+    }
+
+    #[test]
+    fn test_line_column() {
+        let source = "ab\ncé\nx";
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 3), (2, 1));
+        assert_eq!(line_column(source, 6), (2, 3));
+        assert_eq!(line_column(source, 7), (3, 1));
+    }
+
+    const LIGHTING: &str = "vec4 shade(vec4 c) {\n    return c * LIGHT_SCALE;\n}\n";
+
+    #[test]
+    fn test_defines_and_includes() {
+        let source = FRAGMENT.replace("void main", "#include \"lighting.glsl\"\n\nvoid main").replace("vec4(1.0)", "shade(vec4(COLOR))");
+        let mut options = GlslCompileOptions::new();
+        options.define("COLOR", "0.5");
+        options.define("LIGHT_SCALE", "2.0");
+        options.add_include("lighting.glsl", LIGHTING);
+        let wgsl = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap().text.unwrap();
+        assert!(wgsl.contains("fn shade("));
+        assert!(wgsl.contains("2.0"));
+        assert!(wgsl.contains("0.5"));
+    }
+
+    #[test]
+    fn test_define_errors() {
+        let error = |name: &str, value: &str| {
+            let mut options = GlslCompileOptions::new();
+            options.define(name, value);
+            glsl_compile_with_options(FRAGMENT, "fragment", true, "wgsl", &options).unwrap_err().to_string()
+        };
+        assert_eq!(error("2X", "1"), "define: invalid macro name: \"2X\"");
+        assert_eq!(error("X 1\n#define Y", "1"), "define: invalid macro name: \"X 1\\n#define Y\"");
+        assert_eq!(error("", "1"), "define: invalid macro name: \"\"");
+        assert_eq!(error("X", "1\n#error"), "define: invalid value for X: UnexpectedHash");
+        assert_eq!(error("X", "1 @"), "define: invalid value for X: UnexpectedCharacter");
+    }
+
+    #[test]
+    fn test_include_error_span() {
+        let source = FRAGMENT.replace("void main", "#include <lighting.glsl>\n\nvoid main").replace("vec4(1.0)", "shade(vec4(1.0))");
+        let mut options = GlslCompileOptions::new();
+        options.add_include("lighting.glsl", LIGHTING);
+        let diagnostics = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap_err();
+        let span = &diagnostics.diagnostics[0].spans[0];
+        assert_eq!(span.file, "lighting.glsl");
+        assert_eq!((span.line_start, span.column_start), (2, 16));
+        assert_eq!((span.line_end, span.column_end), (2, 27));
+
+        // Lines after the include still map back to the shader itself.
+        options.define("LIGHT_SCALE", "1.0");
+        let source = source.replace("shade(vec4(1.0))", "shade(u_missing)");
+        let diagnostics = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap_err();
+        let span = &diagnostics.diagnostics[0].spans[0];
+        assert_eq!(span.file, "");
+        assert_eq!((span.line_start, span.column_start), (8, 21));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let mut options = GlslCompileOptions::new();
+        let source = FRAGMENT.replace("void main", "#include \"a.glsl\"\nvoid main");
+        let diagnostics = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap_err();
+        assert_eq!(diagnostics.to_string(), "5:1: include: include not found: a.glsl");
+
+        options.add_include("a.glsl", "#include \"b.glsl\"\n");
+        options.add_include("b.glsl", "\n  #include \"a.glsl\"\n");
+        let diagnostics = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap_err();
+        assert_eq!(diagnostics.to_string(), "b.glsl:2:3: include: include cycle through a.glsl");
+    }
+
+    #[test]
+    fn test_conditional_includes() {
+        let mut options = GlslCompileOptions::new();
+        options.define("QUALITY", "3");
+        let with_include = |condition: &str| FRAGMENT.replace("void main", &format!("{}\n#include \"fog.glsl\"\n#endif\n\nvoid main", condition));
+
+        // A missing include is fine in a branch that isn't compiled.
+        for &condition in ["#if 0", "#ifdef USE_FOG", "#ifndef QUALITY", "#if 1\n#else", "#if QUALITY == 3\n#elif 1", "#if defined(USE_FOG) || (QUALITY < 2)", "#if (1 << 2) == 5"].iter() {
+            let result = glsl_compile_with_options(&with_include(condition), "fragment", true, "wgsl", &options);
+            assert!(result.is_ok(), "{}: {}", condition, result.unwrap_err());
+        }
+
+        // Where the branch is compiled, it's still an error.
+        for &condition in ["#if QUALITY > 2 && !defined(USE_FOG)", "#if (1 << 2) == 4"].iter() {
+            let diagnostics = glsl_compile_with_options(&with_include(condition), "fragment", true, "wgsl", &options).unwrap_err();
+            assert_eq!(diagnostics.to_string(), "6:1: include: include not found: fog.glsl", "{}", condition);
         }
     }
+
+    #[test]
+    fn test_include_guard() {
+        let mut options = GlslCompileOptions::new();
+        options.define("LIGHT_SCALE", "2.0");
+        let lighting = format!("#ifndef LIGHTING_GLSL\n#define LIGHTING_GLSL\n#include \"lighting.glsl\"\n{}#endif\n", LIGHTING);
+        options.add_include("lighting.glsl", &lighting);
+        let source = FRAGMENT.replace("void main", "#include \"lighting.glsl\"\n#include \"lighting.glsl\"\n\nvoid main").replace("vec4(1.0)", "shade(vec4(1.0))");
+        let wgsl = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap().text.unwrap();
+        assert_eq!(wgsl.matches("fn shade(").count(), 1);
+    }
+
+    const TEXTURED: &str = "#version 450
+layout(std140, set = 0, binding = 0) uniform ub_Params {
+    vec4 u_Color;
+};
+layout(set = 0, binding = 1) uniform texture2D u_Texture;
+layout(set = 0, binding = 2) uniform sampler u_Sampler;
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Color;
+
+void main() {
+    o_Color = texture(sampler2D(u_Texture, u_Sampler), v_TexCoord) * u_Color;
+}
+";
+
+    #[test]
+    fn test_targets() {
+        let options = GlslCompileOptions::new();
+        let wgsl = glsl_compile_with_options(TEXTURED, "fragment", true, "wgsl", &options).unwrap();
+        assert!(wgsl.text.unwrap().contains("textureSample(u_Texture, u_Sampler"));
+        assert_eq!(wgsl.entry_point, "main");
+        assert_eq!(wgsl.resources, vec![
+            ShaderResource { name: "ub_Params".to_string(), kind: "uniform", binding: Some((0, 0)), target_name: None },
+            ShaderResource { name: "u_Texture".to_string(), kind: "texture", binding: Some((0, 1)), target_name: None },
+            ShaderResource { name: "u_Sampler".to_string(), kind: "sampler", binding: Some((0, 2)), target_name: None },
+        ]);
+
+        let diagnostics = glsl_compile_with_options(TEXTURED, "fragment", true, "dxil", &options).unwrap_err();
+        assert_eq!(diagnostics.to_string(), "target: unknown compile target: dxil");
+
+        // Without a main there's nothing to translate, which is a diagnostic rather than a panic.
+        let source = TEXTURED.replace("void main()", "void shade()");
+        let diagnostics = glsl_compile_with_options(&source, "fragment", true, "wgsl", &options).unwrap_err();
+        assert_eq!(diagnostics.to_string(), "glsl::parse_str: Missing entry point");
+    }
+
+    #[test]
+    fn test_shader_targets() {
+        let options = GlslCompileOptions::new();
+        let output = |target| glsl_compile_with_options(TEXTURED, "fragment", true, target, &options).unwrap();
+
+        let glsl = output("glsl");
+        assert!(glsl.text.unwrap().starts_with("#version 300 es\n"));
+        assert_eq!(glsl.resources[0].target_name.as_deref(), Some("ub_Params_block_0Fragment"));
+        assert_eq!(glsl.resources[1].target_name.as_deref(), Some("_group_0_binding_1_fs"));
+        assert_eq!(glsl.resources[2].target_name, None);
+
+        let spirv = output("spirv");
+        assert!(spirv.text.is_none());
+        assert_eq!(spirv.binary.unwrap()[0], 0x07230203);
+
+        let msl = output("msl");
+        let text = msl.text.unwrap();
+        assert!(text.contains("fragment main_Output main_("));
+        assert!(text.contains("[[buffer(0)]]"));
+        assert!(text.contains("[[texture(0)]]"));
+        assert!(text.contains("[[sampler(0)]]"));
+        assert!(!text.contains("fake"));
+        assert_eq!(msl.entry_point, "main_");
+        let slots: Vec<_> = msl.resources.iter().map(|r| r.target_name.as_deref()).collect();
+        assert_eq!(slots, [Some("buffer(0)"), Some("texture(0)"), Some("sampler(0)")]);
+
+        let hlsl = output("hlsl");
+        assert!(hlsl.text.unwrap().contains("Texture2D<float4> u_Texture : register(t1);"));
+        assert_eq!(hlsl.entry_point, "main");
+    }
 }
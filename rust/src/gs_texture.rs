@@ -0,0 +1,585 @@
+// PS2 Graphics Synthesizer local memory, ported from Common/PS2/GS.ts.
+//
+// GS memory is 4MB, divided into 8KB pages. Each pixel storage format (PSM) lays its pixels
+// out differently within a page: a page is 32 blocks, a block is 4 columns, and each format has
+// its own block and column arrangement. Buffer widths (DBW/TBW) are in units of 64 pixels, and
+// buffer base pointers (DBP/TBP/CBP) in units of 256-byte blocks.
+//
+// Textures are uploaded the way the game's GIF transfers upload them, and read back as RGBA8,
+// looking indexed formats up in a CLUT that is also in GS memory. Only CSM1 CLUTs are
+// supported, whose entries are stored in 8x2 pixel rectangles, with the second and third
+// rectangles of each 32 entries swapped.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+const GS_MEMORY_SIZE: usize = 4 * 1024 * 1024;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GsPixelStorageFormat {
+    PSMCT32 = 0x00,
+    PSMCT16 = 0x02,
+    PSMT8 = 0x13,
+    PSMT4 = 0x14,
+    PSMT8H = 0x1B,
+    PSMT4HL = 0x24,
+    PSMT4HH = 0x2C,
+}
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GsClutPixelStorageFormat {
+    PSMCT32 = 0x00,
+    PSMCT16 = 0x02,
+}
+
+const BLOCK_TABLE_PSMCT32: [usize; 32] = [
+     0,  1,  4,  5, 16, 17, 20, 21,
+     2,  3,  6,  7, 18, 19, 22, 23,
+     8,  9, 12, 13, 24, 25, 28, 29,
+    10, 11, 14, 15, 26, 27, 30, 31,
+];
+
+const COLUMN_TABLE_PSMCT32: [usize; 16] = [
+     0,  1,  4,  5,  8,  9, 12, 13,
+     2,  3,  6,  7, 10, 11, 14, 15,
+];
+
+const BLOCK_TABLE_PSMT4: [usize; 16] = [
+     0,  2,  8, 10,
+     1,  3,  9, 11,
+     4,  6, 12, 14,
+     5,  7, 13, 15,
+];
+
+// Byte offsets of each pixel within a 16x16 PSMT8 block.
+const COLUMN_TABLE_PSMT8: [usize; 256] = [
+      0,   4,  16,  20,  32,  36,  48,  52,   2,   6,  18,  22,  34,  38,  50,  54,
+      8,  12,  24,  28,  40,  44,  56,  60,  10,  14,  26,  30,  42,  46,  58,  62,
+     33,  37,  49,  53,   1,   5,  17,  21,  35,  39,  51,  55,   3,   7,  19,  23,
+     41,  45,  57,  61,   9,  13,  25,  29,  43,  47,  59,  63,  11,  15,  27,  31,
+     96, 100, 112, 116,  64,  68,  80,  84,  98, 102, 114, 118,  66,  70,  82,  86,
+    104, 108, 120, 124,  72,  76,  88,  92, 106, 110, 122, 126,  74,  78,  90,  94,
+     65,  69,  81,  85,  97, 101, 113, 117,  67,  71,  83,  87,  99, 103, 115, 119,
+     73,  77,  89,  93, 105, 109, 121, 125,  75,  79,  91,  95, 107, 111, 123, 127,
+    128, 132, 144, 148, 160, 164, 176, 180, 130, 134, 146, 150, 162, 166, 178, 182,
+    136, 140, 152, 156, 168, 172, 184, 188, 138, 142, 154, 158, 170, 174, 186, 190,
+    161, 165, 177, 181, 129, 133, 145, 149, 163, 167, 179, 183, 131, 135, 147, 151,
+    169, 173, 185, 189, 137, 141, 153, 157, 171, 175, 187, 191, 139, 143, 155, 159,
+    224, 228, 240, 244, 192, 196, 208, 212, 226, 230, 242, 246, 194, 198, 210, 214,
+    232, 236, 248, 252, 200, 204, 216, 220, 234, 238, 250, 254, 202, 206, 218, 222,
+    193, 197, 209, 213, 225, 229, 241, 245, 195, 199, 211, 215, 227, 231, 243, 247,
+    201, 205, 217, 221, 233, 237, 249, 253, 203, 207, 219, 223, 235, 239, 251, 255,
+];
+
+// Nibble offsets of each pixel within a 32x16 PSMT4 block.
+const COLUMN_TABLE_PSMT4: [usize; 512] = [
+      0,   8,  32,  40,  64,  72,  96, 104,   2,  10,  34,  42,  66,  74,  98, 106,
+      4,  12,  36,  44,  68,  76, 100, 108,   6,  14,  38,  46,  70,  78, 102, 110,
+     16,  24,  48,  56,  80,  88, 112, 120,  18,  26,  50,  58,  82,  90, 114, 122,
+     20,  28,  52,  60,  84,  92, 116, 124,  22,  30,  54,  62,  86,  94, 118, 126,
+     65,  73,  97, 105,   1,   9,  33,  41,  67,  75,  99, 107,   3,  11,  35,  43,
+     69,  77, 101, 109,   5,  13,  37,  45,  71,  79, 103, 111,   7,  15,  39,  47,
+     81,  89, 113, 121,  17,  25,  49,  57,  83,  91, 115, 123,  19,  27,  51,  59,
+     85,  93, 117, 125,  21,  29,  53,  61,  87,  95, 119, 127,  23,  31,  55,  63,
+    192, 200, 224, 232, 128, 136, 160, 168, 194, 202, 226, 234, 130, 138, 162, 170,
+    196, 204, 228, 236, 132, 140, 164, 172, 198, 206, 230, 238, 134, 142, 166, 174,
+    208, 216, 240, 248, 144, 152, 176, 184, 210, 218, 242, 250, 146, 154, 178, 186,
+    212, 220, 244, 252, 148, 156, 180, 188, 214, 222, 246, 254, 150, 158, 182, 190,
+    129, 137, 161, 169, 193, 201, 225, 233, 131, 139, 163, 171, 195, 203, 227, 235,
+    133, 141, 165, 173, 197, 205, 229, 237, 135, 143, 167, 175, 199, 207, 231, 239,
+    145, 153, 177, 185, 209, 217, 241, 249, 147, 155, 179, 187, 211, 219, 243, 251,
+    149, 157, 181, 189, 213, 221, 245, 253, 151, 159, 183, 191, 215, 223, 247, 255,
+    256, 264, 288, 296, 320, 328, 352, 360, 258, 266, 290, 298, 322, 330, 354, 362,
+    260, 268, 292, 300, 324, 332, 356, 364, 262, 270, 294, 302, 326, 334, 358, 366,
+    272, 280, 304, 312, 336, 344, 368, 376, 274, 282, 306, 314, 338, 346, 370, 378,
+    276, 284, 308, 316, 340, 348, 372, 380, 278, 286, 310, 318, 342, 350, 374, 382,
+    321, 329, 353, 361, 257, 265, 289, 297, 323, 331, 355, 363, 259, 267, 291, 299,
+    325, 333, 357, 365, 261, 269, 293, 301, 327, 335, 359, 367, 263, 271, 295, 303,
+    337, 345, 369, 377, 273, 281, 305, 313, 339, 347, 371, 379, 275, 283, 307, 315,
+    341, 349, 373, 381, 277, 285, 309, 317, 343, 351, 375, 383, 279, 287, 311, 319,
+    448, 456, 480, 488, 384, 392, 416, 424, 450, 458, 482, 490, 386, 394, 418, 426,
+    452, 460, 484, 492, 388, 396, 420, 428, 454, 462, 486, 494, 390, 398, 422, 430,
+    464, 472, 496, 504, 400, 408, 432, 440, 466, 474, 498, 506, 402, 410, 434, 442,
+    468, 476, 500, 508, 404, 412, 436, 444, 470, 478, 502, 510, 406, 414, 438, 446,
+    385, 393, 417, 425, 449, 457, 481, 489, 387, 395, 419, 427, 451, 459, 483, 491,
+    389, 397, 421, 429, 453, 461, 485, 493, 391, 399, 423, 431, 455, 463, 487, 495,
+    401, 409, 433, 441, 465, 473, 497, 505, 403, 411, 435, 443, 467, 475, 499, 507,
+    405, 413, 437, 445, 469, 477, 501, 509, 407, 415, 439, 447, 471, 479, 503, 511,
+];
+
+// Pixel addressing. PSMCT32 and PSMCT16 return byte addresses, PSMT8 a byte address and
+// PSMT4 a nibble address.
+
+fn get_block_id_psmct32(block: usize, x: usize, y: usize) -> usize {
+    let block_y = (y >> 3) & 0x03;
+    let block_x = (x >> 3) & 0x07;
+    block + ((x >> 1) & !0x1F) + BLOCK_TABLE_PSMCT32[(block_y << 3) | block_x]
+}
+
+fn get_pixel_address_psmct32(block: usize, width: usize, x: usize, y: usize) -> usize {
+    let page = (block >> 5) + (y >> 5) * width + (x >> 6);
+    let column_base = ((y >> 1) & 0x03) << 4;
+    let column = column_base + COLUMN_TABLE_PSMCT32[((y & 0x01) << 3) | (x & 0x07)];
+    let addr = (page << 11) + (get_block_id_psmct32(block & 0x1F, x & 0x3F, y & 0x1F) << 6) + column;
+    (addr << 2) & (GS_MEMORY_SIZE - 4)
+}
+
+fn get_block_id_psmct16(block: usize, x: usize, y: usize) -> usize {
+    let block_y = (y >> 3) & 0x07;
+    let block_x = (x >> 4) & 0x03;
+    // The PSMCT16 block layout is the transpose of the PSMCT32 one.
+    block + ((x >> 1) & !0x1F) + BLOCK_TABLE_PSMCT32[(block_x << 3) | block_y]
+}
+
+fn get_pixel_address_psmct16(block: usize, width: usize, x: usize, y: usize) -> usize {
+    let page = (block >> 5) + (y >> 6) * width + (x >> 6);
+    let column_base = ((y >> 1) & 0x03) << 4;
+    // A column holds two side-by-side copies of the PSMCT32 layout, in the lower and upper halves
+    // of each word.
+    let column = column_base + COLUMN_TABLE_PSMCT32[((y & 0x01) << 3) | (x & 0x07)];
+    let addr = (page << 11) + (get_block_id_psmct16(block & 0x1F, x & 0x3F, y & 0x3F) << 6) + column;
+    ((addr << 2) & (GS_MEMORY_SIZE - 4)) + ((x & 0x08) >> 2)
+}
+
+fn get_block_id_psmt8(block: usize, x: usize, y: usize) -> usize {
+    let block_y = (y >> 4) & 0x03;
+    let block_x = (x >> 4) & 0x07;
+    block + ((x >> 2) & !0x1F) + BLOCK_TABLE_PSMCT32[(block_y << 3) | block_x]
+}
+
+fn get_pixel_address_psmt8(block: usize, width: usize, x: usize, y: usize) -> usize {
+    let page = (block >> 5) + (y >> 6) * (width >> 1) + (x >> 7);
+    let column = COLUMN_TABLE_PSMT8[((y & 0x0F) << 4) | (x & 0x0F)];
+    let addr = (page << 13) + (get_block_id_psmt8(block & 0x1F, x & 0x7F, y & 0x3F) << 8) + column;
+    addr & (GS_MEMORY_SIZE - 1)
+}
+
+fn get_block_id_psmt4(block: usize, x: usize, y: usize) -> usize {
+    let block_base = ((y >> 6) & 0x01) << 4;
+    let block_y = (y >> 4) & 0x03;
+    let block_x = (x >> 5) & 0x03;
+    block + ((x >> 2) & !0x1F) + block_base + BLOCK_TABLE_PSMT4[(block_y << 2) | block_x]
+}
+
+fn get_pixel_address_psmt4(block: usize, width: usize, x: usize, y: usize) -> usize {
+    let page = (block >> 5) + (y >> 7) * (width >> 1) + (x >> 7);
+    let column = COLUMN_TABLE_PSMT4[((y & 0x0F) << 5) | (x & 0x1F)];
+    let addr = (page << 14) + (get_block_id_psmt4(block & 0x1F, x & 0x7F, y & 0x7F) << 9) + column;
+    addr & (GS_MEMORY_SIZE * 2 - 1)
+}
+
+// CLUT index to its position in a CSM1 CLUT buffer: bits 3 and 4 of the index are swapped, and
+// the entries are laid out 16 to a row (8 for 4-bit CLUTs, where CSA picks the row pair).
+fn get_clut_position(index: usize, csa: usize, psm: GsPixelStorageFormat) -> (usize, usize) {
+    match psm {
+        GsPixelStorageFormat::PSMT4 | GsPixelStorageFormat::PSMT4HL | GsPixelStorageFormat::PSMT4HH => {
+            ((index & 0x07) + ((csa & 0x01) << 3), ((index >> 3) & 0x01) + (csa & 0x0E))
+        },
+        _ => {
+            ((index & 0x07) + ((index & 0x10) >> 1), ((index & 0xE0) >> 4) + ((index & 0x08) >> 3))
+        },
+    }
+}
+
+// Position of entry i of a 256-entry CLUT once it's been stored in CSM1 order.
+fn get_csm1_index(index: usize) -> usize {
+    (index & !0x18) | ((index & 0x08) << 1) | ((index & 0x10) >> 1)
+}
+
+fn scale_alpha(a: u8) -> u8 {
+    // 0x80 is 1.0 on the GS.
+    (a as u32 * 2).min(0xFF) as u8
+}
+
+#[wasm_bindgen]
+pub struct GsMemory {
+    #[wasm_bindgen(skip)]
+    pub data: Vec<u8>,
+    ta0: u8,
+    ta1: u8,
+    aem: bool,
+}
+
+impl Default for GsMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GsMemory {
+    // Converts a PSMCT16 pixel to RGBA8, taking its alpha from TEXA. The PS2 doesn't interpolate
+    // the 5-bit channels, it just shifts them.
+    fn convert_psmct16(&self, p: u16) -> [u8; 4] {
+        let alpha = if p & 0x8000 != 0 {
+            self.ta1
+        } else if self.aem && p == 0 {
+            0x00
+        } else {
+            self.ta0
+        };
+        [((p & 0x1F) << 3) as u8, (((p >> 5) & 0x1F) << 3) as u8, (((p >> 10) & 0x1F) << 3) as u8, alpha]
+    }
+
+    fn read_psmct32(&self, addr: usize) -> [u8; 4] {
+        [self.data[addr], self.data[addr + 1], self.data[addr + 2], self.data[addr + 3]]
+    }
+
+    fn read_psmct16(&self, addr: usize) -> u16 {
+        u16::from_le_bytes([self.data[addr], self.data[addr + 1]])
+    }
+
+    fn read_psmt4(&self, addr: usize) -> u8 {
+        (self.data[addr >> 1] >> ((addr & 0x01) << 2)) & 0x0F
+    }
+
+    fn write_psmt4(&mut self, addr: usize, v: u8) {
+        let shift = (addr & 0x01) << 2;
+        let byte = &mut self.data[addr >> 1];
+        *byte = (*byte & !(0x0F << shift)) | ((v & 0x0F) << shift);
+    }
+
+    // The CLUT index of pixel (x, y) of an indexed texture.
+    fn read_index(&self, psm: GsPixelStorageFormat, tbp: usize, tbw: usize, x: usize, y: usize) -> usize {
+        let index = match psm {
+            GsPixelStorageFormat::PSMT8 => self.data[get_pixel_address_psmt8(tbp, tbw, x, y)],
+            GsPixelStorageFormat::PSMT4 => self.read_psmt4(get_pixel_address_psmt4(tbp, tbw, x, y)),
+            GsPixelStorageFormat::PSMT8H => self.data[get_pixel_address_psmct32(tbp, tbw, x, y) + 3],
+            GsPixelStorageFormat::PSMT4HL => self.data[get_pixel_address_psmct32(tbp, tbw, x, y) + 3] & 0x0F,
+            GsPixelStorageFormat::PSMT4HH => self.data[get_pixel_address_psmct32(tbp, tbw, x, y) + 3] >> 4,
+            GsPixelStorageFormat::PSMCT32 | GsPixelStorageFormat::PSMCT16 => unreachable!(),
+        };
+        index as usize
+    }
+
+    fn read_clut(&self, cpsm: GsClutPixelStorageFormat, cbp: usize, x: usize, y: usize) -> [u8; 4] {
+        match cpsm {
+            GsClutPixelStorageFormat::PSMCT32 => self.read_psmct32(get_pixel_address_psmct32(cbp, 1, x, y)),
+            GsClutPixelStorageFormat::PSMCT16 => self.convert_psmct16(self.read_psmct16(get_pixel_address_psmct16(cbp, 1, x, y))),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GsMemory {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        // TEXA defaults to an alpha of 1.0 for both PSMCT16 alpha bit values.
+        GsMemory { data: vec![0x00; GS_MEMORY_SIZE], ta0: 0x80, ta1: 0x80, aem: false }
+    }
+
+    // Sets the TEXA register, which gives PSMCT16 pixels their alpha: TA0 when the alpha bit is
+    // 0, and TA1 when it's 1. With AEM, black pixels with the alpha bit 0 are transparent.
+    pub fn set_texa(&mut self, ta0: u8, aem: bool, ta1: u8) {
+        self.ta0 = ta0;
+        self.aem = aem;
+        self.ta1 = ta1;
+    }
+
+    // Stores a host-to-local transfer (BITBLTBUF/TRXPOS/TRXREG) of an rrw x rrh image at
+    // (dsax, dsay) in the buffer at dbp. The source is packed like the transfer: 4-bit formats
+    // have the first pixel in the low nibble.
+    #[allow(clippy::too_many_arguments)]
+    pub fn upload_image(&mut self, dpsm: GsPixelStorageFormat, dbp: usize, dbw: usize, dsax: usize, dsay: usize, rrw: usize, rrh: usize, src: &[u8]) {
+        let mut src_idx = 0;
+        for y in dsay..dsay + rrh {
+            for x in dsax..dsax + rrw {
+                match dpsm {
+                    GsPixelStorageFormat::PSMCT32 => {
+                        let p = get_pixel_address_psmct32(dbp, dbw, x, y);
+                        self.data[p..p + 4].copy_from_slice(&src[src_idx * 4..src_idx * 4 + 4]);
+                    },
+                    GsPixelStorageFormat::PSMCT16 => {
+                        let p = get_pixel_address_psmct16(dbp, dbw, x, y);
+                        self.data[p..p + 2].copy_from_slice(&src[src_idx * 2..src_idx * 2 + 2]);
+                    },
+                    GsPixelStorageFormat::PSMT8 => {
+                        self.data[get_pixel_address_psmt8(dbp, dbw, x, y)] = src[src_idx];
+                    },
+                    GsPixelStorageFormat::PSMT4 => {
+                        let nibble = src[src_idx >> 1] >> ((src_idx & 0x01) << 2);
+                        self.write_psmt4(get_pixel_address_psmt4(dbp, dbw, x, y), nibble);
+                    },
+                    // The H/L formats share the PSMCT32 layout, filling in just the top byte or
+                    // one of its nibbles.
+                    GsPixelStorageFormat::PSMT8H => {
+                        self.data[get_pixel_address_psmct32(dbp, dbw, x, y) + 3] = src[src_idx];
+                    },
+                    GsPixelStorageFormat::PSMT4HL | GsPixelStorageFormat::PSMT4HH => {
+                        let nibble = (src[src_idx >> 1] >> ((src_idx & 0x01) << 2)) & 0x0F;
+                        let p = get_pixel_address_psmct32(dbp, dbw, x, y) + 3;
+                        self.data[p] = if dpsm == GsPixelStorageFormat::PSMT4HH {
+                            (self.data[p] & 0x0F) | (nibble << 4)
+                        } else {
+                            (self.data[p] & 0xF0) | nibble
+                        };
+                    },
+                }
+                src_idx += 1;
+            }
+        }
+    }
+
+    // Reads an rrw x rrh image at the start of the buffer at dbp back out, packed the same way
+    // upload_image takes it.
+    pub fn read_image_raw(&self, psm: GsPixelStorageFormat, dbp: usize, dbw: usize, rrw: usize, rrh: usize) -> Vec<u8> {
+        let bits_per_pixel = match psm {
+            GsPixelStorageFormat::PSMCT32 => 32,
+            GsPixelStorageFormat::PSMCT16 => 16,
+            GsPixelStorageFormat::PSMT8 | GsPixelStorageFormat::PSMT8H => 8,
+            GsPixelStorageFormat::PSMT4 | GsPixelStorageFormat::PSMT4HL | GsPixelStorageFormat::PSMT4HH => 4,
+        };
+        let mut dst = vec![0x00; (rrw * rrh * bits_per_pixel).div_ceil(8)];
+        for y in 0..rrh {
+            for x in 0..rrw {
+                let i = y * rrw + x;
+                match psm {
+                    GsPixelStorageFormat::PSMCT32 => {
+                        dst[i * 4..i * 4 + 4].copy_from_slice(&self.read_psmct32(get_pixel_address_psmct32(dbp, dbw, x, y)));
+                    },
+                    GsPixelStorageFormat::PSMCT16 => {
+                        dst[i * 2..i * 2 + 2].copy_from_slice(&self.read_psmct16(get_pixel_address_psmct16(dbp, dbw, x, y)).to_le_bytes());
+                    },
+                    GsPixelStorageFormat::PSMT8 | GsPixelStorageFormat::PSMT8H => {
+                        dst[i] = self.read_index(psm, dbp, dbw, x, y) as u8;
+                    },
+                    GsPixelStorageFormat::PSMT4 | GsPixelStorageFormat::PSMT4HL | GsPixelStorageFormat::PSMT4HH => {
+                        dst[i >> 1] |= (self.read_index(psm, dbp, dbw, x, y) as u8) << ((i & 0x01) << 2);
+                    },
+                }
+            }
+        }
+        dst
+    }
+
+    // Reads an rrw x rrh texture at tbp as RGBA8. Indexed formats look up the CSM1 CLUT at cbp;
+    // for 4-bit formats, csa picks which 16 entries. With alpha, every pixel gets that alpha
+    // instead of its own (TEX0.TCC = RGB). With alpha_scale, alpha is scaled from the GS's
+    // 0x80 = 1.0 to 0xFF.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read_image(&self, psm: GsPixelStorageFormat, tbp: usize, tbw: usize, rrw: usize, rrh: usize, cpsm: GsClutPixelStorageFormat, cbp: usize, csa: usize, alpha: Option<u8>, alpha_scale: bool) -> Vec<u8> {
+        let mut dst = vec![0x00; rrw * rrh * 4];
+        for y in 0..rrh {
+            for x in 0..rrw {
+                let mut p = match psm {
+                    GsPixelStorageFormat::PSMCT32 => self.read_psmct32(get_pixel_address_psmct32(tbp, tbw, x, y)),
+                    GsPixelStorageFormat::PSMCT16 => self.convert_psmct16(self.read_psmct16(get_pixel_address_psmct16(tbp, tbw, x, y))),
+                    _ => {
+                        let (cx, cy) = get_clut_position(self.read_index(psm, tbp, tbw, x, y), csa, psm);
+                        self.read_clut(cpsm, cbp, cx, cy)
+                    },
+                };
+                if let Some(alpha) = alpha {
+                    p[3] = alpha;
+                }
+                if alpha_scale {
+                    p[3] = scale_alpha(p[3]);
+                }
+                let dst_offs = (y * rrw + x) * 4;
+                dst[dst_offs..dst_offs + 4].copy_from_slice(&p);
+            }
+        }
+        dst
+    }
+}
+
+// Converts a CLUT that was loaded straight from a file (entries in the order a CSM1 upload stores
+// them) to an RGBA8 palette in index order. 256-entry CLUTs are unshuffled; 16-entry ones are
+// already in order. PSMCT16 entries take their alpha from the alpha bit, as 0x00 or 0x80.
+pub fn decode_clut_csm1(src: &[u8], cpsm: GsClutPixelStorageFormat, alpha_scale: bool) -> Vec<u8> {
+    let entry_size = match cpsm {
+        GsClutPixelStorageFormat::PSMCT32 => 4,
+        GsClutPixelStorageFormat::PSMCT16 => 2,
+    };
+    let num_entries = src.len() / entry_size;
+    let mut dst = vec![0x00; num_entries * 4];
+    for (i, p) in dst.chunks_exact_mut(4).enumerate() {
+        let src_idx = if num_entries == 256 { get_csm1_index(i) } else { i };
+        let src_offs = src_idx * entry_size;
+        match cpsm {
+            GsClutPixelStorageFormat::PSMCT32 => p.copy_from_slice(&src[src_offs..src_offs + 4]),
+            GsClutPixelStorageFormat::PSMCT16 => {
+                let v = u16::from_le_bytes([src[src_offs], src[src_offs + 1]]);
+                p[0] = ((v & 0x1F) << 3) as u8;
+                p[1] = (((v >> 5) & 0x1F) << 3) as u8;
+                p[2] = (((v >> 10) & 0x1F) << 3) as u8;
+                p[3] = if v & 0x8000 != 0 { 0x80 } else { 0x00 };
+            },
+        }
+        if alpha_scale {
+            p[3] = scale_alpha(p[3]);
+        }
+    }
+    dst
+}
+
+#[wasm_bindgen]
+pub fn decode_gs_clut_csm1(src: &[u8], cpsm: GsClutPixelStorageFormat, alpha_scale: bool) -> Vec<u8> {
+    decode_clut_csm1(src, cpsm, alpha_scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    const ALL_PSMS: [GsPixelStorageFormat; 7] = [
+        GsPixelStorageFormat::PSMCT32,
+        GsPixelStorageFormat::PSMCT16,
+        GsPixelStorageFormat::PSMT8,
+        GsPixelStorageFormat::PSMT4,
+        GsPixelStorageFormat::PSMT8H,
+        GsPixelStorageFormat::PSMT4HL,
+        GsPixelStorageFormat::PSMT4HH,
+    ];
+
+    #[test]
+    fn test_addressing() {
+        // Pixels within a PSMCT32 column, then the next column, block and page.
+        assert_eq!(get_pixel_address_psmct32(0, 1, 1, 0), 0x04);
+        assert_eq!(get_pixel_address_psmct32(0, 1, 0, 1), 0x08);
+        assert_eq!(get_pixel_address_psmct32(0, 1, 0, 2), 0x40);
+        assert_eq!(get_pixel_address_psmct32(0, 1, 8, 0), 0x100);
+        assert_eq!(get_pixel_address_psmct32(0, 1, 0, 8), 0x200);
+        assert_eq!(get_pixel_address_psmct32(0, 1, 0, 32), 0x2000);
+        assert_eq!(get_pixel_address_psmct32(0, 2, 64, 0), 0x2000);
+        assert_eq!(get_pixel_address_psmct32(0, 2, 0, 32), 0x4000);
+        assert_eq!(get_pixel_address_psmct32(3, 1, 0, 0), 0x300);
+
+        // PSMCT16 puts pixels 8-15 of a row in the upper halves of the words.
+        assert_eq!(get_pixel_address_psmct16(0, 1, 1, 0), 0x04);
+        assert_eq!(get_pixel_address_psmct16(0, 1, 8, 0), 0x02);
+        assert_eq!(get_pixel_address_psmct16(0, 1, 0, 8), 0x100);
+
+        assert_eq!(get_pixel_address_psmt8(0, 2, 1, 0), 0x04);
+        assert_eq!(get_pixel_address_psmt8(0, 2, 8, 0), 0x02);
+        assert_eq!(get_pixel_address_psmt8(0, 2, 0, 1), 0x08);
+        assert_eq!(get_pixel_address_psmt8(0, 2, 16, 0), 0x100);
+        assert_eq!(get_pixel_address_psmt8(0, 2, 128, 0), 0x2000);
+        assert_eq!(get_pixel_address_psmt4(0, 2, 1, 0), 0x08);
+        assert_eq!(get_pixel_address_psmt4(0, 2, 32, 0), 0x400);
+        assert_eq!(get_pixel_address_psmt4(0, 2, 0, 64), 0x2000);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Uploads then reads back a 2x2 page image at an offset for each format, without the
+        // formats trampling each other's pages.
+        let mut mem = GsMemory::new();
+        let (w, h) = (160, 136);
+        for (i, &psm) in ALL_PSMS.iter().enumerate() {
+            let bpp = match psm {
+                GsPixelStorageFormat::PSMCT32 => 32,
+                GsPixelStorageFormat::PSMCT16 => 16,
+                GsPixelStorageFormat::PSMT8 | GsPixelStorageFormat::PSMT8H => 8,
+                _ => 4,
+            };
+            let src = noise(w * h * bpp / 8);
+            let dbp = 0x400 * (i + 1);
+            mem.upload_image(psm, dbp, 4, 0, 0, w, h, &src);
+            assert_eq!(mem.read_image_raw(psm, dbp, 4, w, h), src, "{:?}", psm);
+        }
+
+        // The H/L formats only fill in the top byte of each word, and so share their pages.
+        let src = noise(64 * 32 * 4);
+        mem.upload_image(GsPixelStorageFormat::PSMCT32, 0, 1, 0, 0, 64, 32, &src);
+        let hl = noise(64 * 32 / 2);
+        mem.upload_image(GsPixelStorageFormat::PSMT4HL, 0, 1, 0, 0, 64, 32, &hl);
+        let hh = noise(64 * 32 / 2 + 1)[1..].to_vec();
+        mem.upload_image(GsPixelStorageFormat::PSMT4HH, 0, 1, 0, 0, 64, 32, &hh);
+        let rgba = mem.read_image_raw(GsPixelStorageFormat::PSMCT32, 0, 1, 64, 32);
+        for i in 0..64 * 32 {
+            assert_eq!(rgba[i * 4..i * 4 + 3], src[i * 4..i * 4 + 3]);
+        }
+        assert_eq!(mem.read_image_raw(GsPixelStorageFormat::PSMT4HL, 0, 1, 64, 32), hl);
+        assert_eq!(mem.read_image_raw(GsPixelStorageFormat::PSMT4HH, 0, 1, 64, 32), hh);
+    }
+
+    #[test]
+    fn test_upload_offset() {
+        // A transfer to (dsax, dsay) lands where reading from the buffer origin expects it.
+        let mut mem = GsMemory::new();
+        let src = noise(16 * 16);
+        mem.upload_image(GsPixelStorageFormat::PSMT8, 0, 2, 16, 48, 16, 16, &src);
+        let full = mem.read_image_raw(GsPixelStorageFormat::PSMT8, 0, 2, 32, 64);
+        for y in 0..16 {
+            assert_eq!(full[(48 + y) * 32 + 16..(48 + y) * 32 + 32], src[y * 16..y * 16 + 16]);
+        }
+    }
+
+    #[test]
+    fn test_clut_psmt8() {
+        // A 16x16 PSMT8 texture using every index once, and a CLUT uploaded straight from a file.
+        let mut mem = GsMemory::new();
+        let indices: Vec<u8> = (0..=255).collect();
+        mem.upload_image(GsPixelStorageFormat::PSMT8, 0, 2, 0, 0, 16, 16, &indices);
+        let mut clut = noise(256 * 4);
+        clut[3] = 0x80;
+        clut[7] = 0x40;
+        mem.upload_image(GsPixelStorageFormat::PSMCT32, 0x100, 1, 0, 0, 16, 16, &clut);
+
+        let palette = decode_clut_csm1(&clut, GsClutPixelStorageFormat::PSMCT32, false);
+        let dst = mem.read_image(GsPixelStorageFormat::PSMT8, 0, 2, 16, 16, GsClutPixelStorageFormat::PSMCT32, 0x100, 0, None, false);
+        assert_eq!(dst, palette);
+        // Entries 8-15 and 16-23 swap places.
+        assert_eq!(dst[8 * 4..8 * 4 + 4], clut[16 * 4..16 * 4 + 4]);
+        assert_eq!(dst[16 * 4..16 * 4 + 4], clut[8 * 4..8 * 4 + 4]);
+        assert_eq!(dst[40 * 4..40 * 4 + 4], clut[48 * 4..48 * 4 + 4]);
+
+        let dst = mem.read_image(GsPixelStorageFormat::PSMT8, 0, 2, 2, 1, GsClutPixelStorageFormat::PSMCT32, 0x100, 0, None, true);
+        assert_eq!([dst[3], dst[7]], [0xFF, 0x80]);
+        let dst = mem.read_image(GsPixelStorageFormat::PSMT8, 0, 2, 2, 1, GsClutPixelStorageFormat::PSMCT32, 0x100, 0, Some(0x20), true);
+        assert_eq!([dst[3], dst[7]], [0x40, 0x40]);
+    }
+
+    #[test]
+    fn test_clut_psmt4() {
+        // CSA selects 16 entries out of a 16x16 CLUT: odd CSAs are the right half of a row pair.
+        let mut mem = GsMemory::new();
+        mem.upload_image(GsPixelStorageFormat::PSMT4, 0, 2, 0, 0, 16, 1, &[0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE]);
+        let clut = noise(256 * 4);
+        mem.upload_image(GsPixelStorageFormat::PSMCT32, 0x100, 1, 0, 0, 16, 16, &clut);
+        for &csa in [0, 1, 6, 15].iter() {
+            let dst = mem.read_image(GsPixelStorageFormat::PSMT4, 0, 2, 16, 1, GsClutPixelStorageFormat::PSMCT32, 0x100, csa, None, false);
+            for i in 0..16 {
+                let (cx, cy) = ((i & 7) + (csa & 1) * 8, (i >> 3) + (csa & 0xE));
+                let src_offs = (cy * 16 + cx) * 4;
+                assert_eq!(dst[i * 4..i * 4 + 4], clut[src_offs..src_offs + 4], "csa {} index {}", csa, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_psmct16() {
+        let mut mem = GsMemory::new();
+        let pixels: [u16; 4] = [0x801F, 0x03E0, 0x7C00, 0x0000];
+        let src: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes().to_vec()).collect();
+        mem.upload_image(GsPixelStorageFormat::PSMCT16, 0, 1, 0, 0, 4, 1, &src);
+        let read = |mem: &GsMemory| mem.read_image(GsPixelStorageFormat::PSMCT16, 0, 1, 4, 1, GsClutPixelStorageFormat::PSMCT32, 0, 0, None, false);
+        assert_eq!(read(&mem), [
+            0xF8, 0x00, 0x00, 0x80,
+            0x00, 0xF8, 0x00, 0x80,
+            0x00, 0x00, 0xF8, 0x80,
+            0x00, 0x00, 0x00, 0x80,
+        ]);
+
+        mem.set_texa(0x10, true, 0x70);
+        let dst = read(&mem);
+        assert_eq!([dst[3], dst[7], dst[11], dst[15]], [0x70, 0x10, 0x10, 0x00]);
+
+        // PSMCT16 CLUTs go through TEXA as well.
+        mem.upload_image(GsPixelStorageFormat::PSMT4, 0x20, 2, 0, 0, 2, 1, &[0x10]);
+        mem.upload_image(GsPixelStorageFormat::PSMCT16, 0x40, 1, 0, 0, 2, 1, &src[0..4]);
+        let dst = mem.read_image(GsPixelStorageFormat::PSMT4, 0x20, 2, 2, 1, GsClutPixelStorageFormat::PSMCT16, 0x40, 0, None, true);
+        assert_eq!(dst, [0xF8, 0x00, 0x00, 0xE0, 0x00, 0xF8, 0x00, 0x20]);
+    }
+
+    #[test]
+    fn test_decode_clut_csm1() {
+        // 16-entry CLUTs are left in order.
+        let clut: Vec<u8> = (0..16u16).flat_map(|i| (i | 0x8000).to_le_bytes().to_vec()).collect();
+        let dst = decode_clut_csm1(&clut, GsClutPixelStorageFormat::PSMCT16, true);
+        assert_eq!(dst[4..8], [0x08, 0x00, 0x00, 0xFF]);
+        assert_eq!(dst[15 * 4..16 * 4], [0x78, 0x00, 0x00, 0xFF]);
+    }
+}
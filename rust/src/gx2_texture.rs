@@ -0,0 +1,652 @@
+// Wii U GX2 surface tiling, as implemented by AMD's R600 addrlib (the Wii U's GPU7 is an R700).
+// https://github.com/GPUOpen-Drivers/pal/tree/dev/src/core/imported/addrlib
+//
+// Surfaces are addressed in elements: single pixels, or 4x4 blocks for BCn. GX2 uses 2 pipes
+// and 4 banks, with a 256-byte pipe interleave and 2048-byte rows and splits.
+//
+// Micro tiles are 8x8 elements (x4 slices for thick modes). 1D tiled surfaces store micro tiles
+// in row order. 2D/3D tiled surfaces group micro tiles into macro tiles, and spread each macro
+// tile across all pipes and banks, rotated by the surface's pipe/bank swizzle. The bank-swapped
+// (2B/3B) modes additionally swap banks every few macro tiles across a row.
+//
+// Each mip level gets its own layout: levels are padded out to powers of two, and the tile mode
+// steps down to 1D once a level is smaller than a macro tile.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::util;
+
+const NUM_PIPES: usize = 2;
+const NUM_BANKS: usize = 4;
+const PIPE_INTERLEAVE_BYTES: usize = 256;
+const ROW_SIZE: usize = 2048;
+const SPLIT_SIZE: usize = 2048;
+const SWAP_SIZE: usize = 256;
+const MICRO_TILE_WIDTH: usize = 8;
+const MICRO_TILE_HEIGHT: usize = 8;
+
+const BANK_SWAP_ORDER: [usize; 4] = [0, 1, 3, 2];
+
+// GX2TileMode.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileMode {
+    LinearGeneral = 0x00,
+    LinearAligned = 0x01,
+    Tiled1DThin1 = 0x02,
+    Tiled1DThick = 0x03,
+    Tiled2DThin1 = 0x04,
+    Tiled2DThin2 = 0x05,
+    Tiled2DThin4 = 0x06,
+    Tiled2DThick = 0x07,
+    Tiled2BThin1 = 0x08,
+    Tiled2BThin2 = 0x09,
+    Tiled2BThin4 = 0x0A,
+    Tiled2BThick = 0x0B,
+    Tiled3DThin1 = 0x0C,
+    Tiled3DThick = 0x0D,
+    Tiled3BThin1 = 0x0E,
+    Tiled3BThick = 0x0F,
+    LinearSpecial = 0x10,
+}
+
+impl TileMode {
+    fn thickness(self) -> usize {
+        use TileMode::*;
+        match self {
+            Tiled1DThick | Tiled2DThick | Tiled2BThick | Tiled3DThick | Tiled3BThick => 4,
+            _ => 1,
+        }
+    }
+
+    fn is_thick_macro_tiled(self) -> bool {
+        use TileMode::*;
+        matches!(self, Tiled2DThick | Tiled2BThick | Tiled3DThick | Tiled3BThick)
+    }
+
+    fn is_bank_swapped(self) -> bool {
+        use TileMode::*;
+        matches!(self, Tiled2BThin1 | Tiled2BThin2 | Tiled2BThin4 | Tiled2BThick | Tiled3BThin1 | Tiled3BThick)
+    }
+
+    fn non_bank_swapped(self) -> TileMode {
+        use TileMode::*;
+        match self {
+            Tiled2BThin1 => Tiled2DThin1,
+            Tiled2BThin2 => Tiled2DThin2,
+            Tiled2BThin4 => Tiled2DThin4,
+            Tiled2BThick => Tiled2DThick,
+            Tiled3BThin1 => Tiled3DThin1,
+            Tiled3BThick => Tiled3DThick,
+            mode => mode,
+        }
+    }
+
+    // Macro tiles are 32x16 elements; THIN2 and THIN4 make them narrower and taller.
+    fn macro_tile_aspect_ratio(self) -> usize {
+        use TileMode::*;
+        match self {
+            Tiled2DThin2 | Tiled2BThin2 => 2,
+            Tiled2DThin4 | Tiled2BThin4 => 4,
+            _ => 1,
+        }
+    }
+
+    // How much the pipe/bank rotates with each slice.
+    fn rotation(self) -> usize {
+        use TileMode::*;
+        match self {
+            Tiled2DThin1 | Tiled2DThin2 | Tiled2DThin4 | Tiled2DThick |
+            Tiled2BThin1 | Tiled2BThin2 | Tiled2BThin4 | Tiled2BThick => NUM_PIPES * ((NUM_BANKS >> 1) - 1),
+            Tiled3DThin1 | Tiled3DThick | Tiled3BThin1 | Tiled3BThick => 1,
+            _ => 0,
+        }
+    }
+}
+
+// The hardware format, the low 6 bits of GX2SurfaceFormat. Only the element size matters for
+// tiling, so formats that share a size are listed once.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    R8 = 0x01,
+    R8G8 = 0x07,
+    R5G6B5 = 0x08,
+    R5G5B5A1 = 0x0A,
+    R4G4B4A4 = 0x0B,
+    R32 = 0x0D,
+    R16G16 = 0x0F,
+    R10G10B10A2 = 0x19,
+    R8G8B8A8 = 0x1A,
+    R32G32 = 0x1D,
+    R16G16B16A16 = 0x1F,
+    R32G32B32A32 = 0x22,
+    Bc1 = 0x31,
+    Bc2 = 0x32,
+    Bc3 = 0x33,
+    Bc4 = 0x34,
+    Bc5 = 0x35,
+}
+
+const fn get_format_bits_per_element(format: SurfaceFormat) -> usize {
+    use SurfaceFormat::*;
+    match format {
+        R8 => 8,
+        R8G8 | R5G6B5 | R5G5B5A1 | R4G4B4A4 => 16,
+        R32 | R16G16 | R10G10B10A2 | R8G8B8A8 => 32,
+        R32G32 | R16G16B16A16 | Bc1 | Bc4 => 64,
+        R32G32B32A32 | Bc2 | Bc3 | Bc5 => 128,
+    }
+}
+
+const fn get_format_block_size(format: SurfaceFormat) -> usize {
+    use SurfaceFormat::*;
+    match format {
+        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 => 4,
+        _ => 1,
+    }
+}
+
+fn align(v: usize, alignment: usize) -> usize {
+    v.div_ceil(alignment) * alignment
+}
+
+// Layout of one mip level. Pitch and height are in elements, and include padding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SurfaceInfo {
+    pub tile_mode: TileMode,
+    pub bits_per_element: usize,
+    pub pitch: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub surf_size: usize,
+    pub base_align: usize,
+}
+
+fn compute_surface_tile_slices(tile_mode: TileMode, bpp: usize) -> usize {
+    let bytes_per_sample = (bpp << 6).div_ceil(8);
+    // Thick modes count as four samples.
+    let num_samples: usize = if tile_mode.thickness() > 1 { 4 } else { 1 };
+    let samples_per_tile = SPLIT_SIZE / bytes_per_sample;
+    num_samples.checked_div(samples_per_tile).map_or(1, |n| n.max(1))
+}
+
+fn compute_mip_level_tile_mode(base_tile_mode: TileMode, bpp: usize, level: usize, width: usize, height: usize) -> TileMode {
+    use TileMode::*;
+
+    let mut tile_mode = base_tile_mode;
+    // Thick tiles that don't fit in a split are stored thin.
+    if compute_surface_tile_slices(base_tile_mode, bpp) > 1 {
+        tile_mode = match base_tile_mode {
+            Tiled2DThick => Tiled2DThin1,
+            Tiled2BThick => Tiled2BThin1,
+            Tiled3DThick => Tiled3DThin1,
+            Tiled3BThick => Tiled3BThin1,
+            mode => mode,
+        };
+    }
+
+    if level == 0 {
+        return tile_mode;
+    }
+
+    // Mip levels never use bank swapping, and drop down to 1D tiling once they are smaller than a macro tile.
+    let width = util::next_pow2(width);
+    let height = util::next_pow2(height);
+    let tile_mode = tile_mode.non_bank_swapped();
+    let micro_tile_bytes = (bpp * (tile_mode.thickness() << 6)).div_ceil(8);
+    let width_align_factor = if micro_tile_bytes < PIPE_INTERLEAVE_BYTES { PIPE_INTERLEAVE_BYTES / micro_tile_bytes } else { 1 };
+    let aspect_ratio = tile_mode.macro_tile_aspect_ratio();
+    let macro_tile_width = MICRO_TILE_WIDTH * NUM_BANKS / aspect_ratio;
+    let macro_tile_height = MICRO_TILE_HEIGHT * NUM_PIPES * aspect_ratio;
+    let too_small = width < width_align_factor * macro_tile_width || height < macro_tile_height;
+
+    let tile_mode = match tile_mode {
+        Tiled2DThin1 | Tiled2DThin2 | Tiled2DThin4 | Tiled3DThin1 if too_small => Tiled1DThin1,
+        Tiled2DThick | Tiled3DThick if too_small => Tiled1DThick,
+        mode => mode,
+    };
+
+    // 2D surfaces have a single slice, too few for thick tiling.
+    match tile_mode {
+        Tiled1DThick => Tiled1DThin1,
+        Tiled2DThick => Tiled2DThin1,
+        Tiled3DThick => Tiled3DThin1,
+        mode => mode,
+    }
+}
+
+fn compute_bank_swapped_width(tile_mode: TileMode, bpp: usize, pitch: usize) -> usize {
+    if !tile_mode.is_bank_swapped() {
+        return 0;
+    }
+
+    let bytes_per_sample = 8 * bpp;
+    let samples_per_tile = SPLIT_SIZE / bytes_per_sample;
+    let num_samples: usize = if tile_mode.is_thick_macro_tiled() { 4 } else { 1 };
+    let slices_per_tile = num_samples.checked_div(samples_per_tile).map_or(1, |n| n.max(1));
+    let bytes_per_tile_slice = num_samples * bytes_per_sample / slices_per_tile;
+
+    let factor = tile_mode.macro_tile_aspect_ratio();
+    let swap_tiles = ((SWAP_SIZE >> 1) / bpp).max(1);
+    let swap_width = swap_tiles * 8 * NUM_BANKS;
+    let height_bytes = num_samples * factor * NUM_PIPES * bpp / slices_per_tile;
+    let swap_max = NUM_PIPES * NUM_BANKS * ROW_SIZE / height_bytes;
+    let swap_min = PIPE_INTERLEAVE_BYTES * 8 * NUM_BANKS / bytes_per_tile_slice;
+
+    let mut bank_swap_width = swap_max.min(swap_min.max(swap_width));
+    while bank_swap_width >= 2 * pitch {
+        bank_swap_width >>= 1;
+    }
+    bank_swap_width
+}
+
+fn compute_surface_info_linear(tile_mode: TileMode, bpp: usize, width: usize, height: usize, level: usize) -> SurfaceInfo {
+    let (width, height) = if level > 0 { (util::next_pow2(width), util::next_pow2(height)) } else { (width, height) };
+    let (base_align, pitch_align) = match tile_mode {
+        TileMode::LinearAligned => (PIPE_INTERLEAVE_BYTES, (PIPE_INTERLEAVE_BYTES * 8 / bpp).max(64)),
+        _ => (1, 1),
+    };
+    let pitch = align(width, pitch_align);
+    SurfaceInfo {
+        tile_mode,
+        bits_per_element: bpp,
+        pitch,
+        height,
+        depth: 1,
+        surf_size: (pitch * height * bpp).div_ceil(8),
+        base_align,
+    }
+}
+
+fn compute_surface_info_micro_tiled(tile_mode: TileMode, bpp: usize, width: usize, height: usize, level: usize) -> SurfaceInfo {
+    let (width, height) = if level > 0 { (util::next_pow2(width), util::next_pow2(height)) } else { (width, height) };
+    let thickness = tile_mode.thickness();
+    let pitch_align = (PIPE_INTERLEAVE_BYTES / bpp / thickness).max(MICRO_TILE_WIDTH);
+    let pitch = align(width, pitch_align);
+    let height = align(height, MICRO_TILE_HEIGHT);
+    let depth = thickness;
+    SurfaceInfo {
+        tile_mode,
+        bits_per_element: bpp,
+        pitch,
+        height,
+        depth,
+        surf_size: (pitch * height * depth * bpp).div_ceil(8),
+        base_align: PIPE_INTERLEAVE_BYTES,
+    }
+}
+
+// Pitch alignment, height alignment and base alignment.
+fn compute_macro_tiled_alignments(tile_mode: TileMode, bpp: usize) -> (usize, usize, usize) {
+    let aspect_ratio = tile_mode.macro_tile_aspect_ratio();
+    let thickness = tile_mode.thickness();
+    let macro_tile_width = MICRO_TILE_WIDTH * NUM_BANKS / aspect_ratio;
+    let macro_tile_height = MICRO_TILE_HEIGHT * NUM_PIPES * aspect_ratio;
+    let pitch_align = macro_tile_width.max(macro_tile_width * (PIPE_INTERLEAVE_BYTES / bpp / (8 * thickness)));
+    let height_align = macro_tile_height;
+    let macro_tile_bytes = (bpp * macro_tile_height * macro_tile_width).div_ceil(8);
+    let base_align = macro_tile_bytes.max((height_align * bpp * pitch_align).div_ceil(8));
+    (pitch_align, height_align, base_align)
+}
+
+fn compute_surface_info_macro_tiled(tile_mode: TileMode, base_tile_mode: TileMode, bpp: usize, width: usize, height: usize, level: usize) -> SurfaceInfo {
+    let (exp_width, exp_height) = if level > 0 { (util::next_pow2(width), util::next_pow2(height)) } else { (width, height) };
+
+    // A thick base mode that was thinned for this level can still be too small for macro tiling.
+    if level > 0 && base_tile_mode.is_thick_macro_tiled() && !tile_mode.is_thick_macro_tiled() {
+        let (pitch_align, height_align, _) = compute_macro_tiled_alignments(base_tile_mode, bpp);
+        let pitch_align_factor = (32 / bpp).max(1);
+        if exp_width < pitch_align * pitch_align_factor || exp_height < height_align {
+            return compute_surface_info_micro_tiled(TileMode::Tiled1DThin1, bpp, width, height, level);
+        }
+    }
+
+    let (mut pitch_align, height_align, base_align) = compute_macro_tiled_alignments(tile_mode, bpp);
+    pitch_align = pitch_align.max(compute_bank_swapped_width(tile_mode, bpp, width));
+    let pitch = align(exp_width, pitch_align);
+    let height = align(exp_height, height_align);
+    let depth = tile_mode.thickness();
+    SurfaceInfo {
+        tile_mode,
+        bits_per_element: bpp,
+        pitch,
+        height,
+        depth,
+        surf_size: (pitch * height * depth * bpp).div_ceil(8),
+        base_align,
+    }
+}
+
+// Computes the layout of a mip level of a 2D surface; w and h are the size of level 0, in pixels.
+pub fn compute_surface_info(format: SurfaceFormat, tile_mode: TileMode, w: usize, h: usize, level: usize) -> SurfaceInfo {
+    let bpp = get_format_bits_per_element(format);
+    let block_size = get_format_block_size(format);
+    let mut width = (w >> level).max(1);
+    let mut height = (h >> level).max(1);
+
+    if tile_mode == TileMode::LinearSpecial {
+        let pitch = width.div_ceil(block_size);
+        let height = height.div_ceil(block_size);
+        return SurfaceInfo {
+            tile_mode,
+            bits_per_element: bpp,
+            pitch,
+            height,
+            depth: 1,
+            surf_size: pitch * height * bpp / 8,
+            base_align: 1,
+        };
+    }
+
+    if block_size > 1 {
+        if level == 0 {
+            width = align(width, block_size);
+            height = align(height, block_size);
+        } else {
+            width = util::next_pow2(width);
+            height = util::next_pow2(height);
+        }
+    }
+    let width = width.div_ceil(block_size);
+    let height = height.div_ceil(block_size);
+
+    let level_tile_mode = compute_mip_level_tile_mode(tile_mode, bpp, level, width, height);
+    match level_tile_mode {
+        TileMode::LinearGeneral | TileMode::LinearAligned => compute_surface_info_linear(level_tile_mode, bpp, width, height, level),
+        TileMode::Tiled1DThin1 | TileMode::Tiled1DThick => compute_surface_info_micro_tiled(level_tile_mode, bpp, width, height, level),
+        _ => compute_surface_info_macro_tiled(level_tile_mode, tile_mode, bpp, width, height, level),
+    }
+}
+
+// Offsets of levels 1 and up from the start of the mip data, which starts with level 1.
+// GX2Surface::mipOffsets is the same, except that its first entry holds the offset of the mip
+// data from the start of the image data.
+pub fn compute_mip_offsets(format: SurfaceFormat, tile_mode: TileMode, w: usize, h: usize, mip_count: usize) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(mip_count.saturating_sub(1));
+    let mut offs = 0;
+    for level in 1..mip_count {
+        let info = compute_surface_info(format, tile_mode, w, h, level);
+        offs = align(offs, info.base_align);
+        offsets.push(offs);
+        offs += info.surf_size;
+    }
+    offsets
+}
+
+#[wasm_bindgen]
+pub fn gx2_calc_mip_offsets(format: SurfaceFormat, tile_mode: TileMode, w: usize, h: usize, mip_count: usize) -> Vec<u32> {
+    compute_mip_offsets(format, tile_mode, w, h, mip_count).into_iter().map(|offs| offs as u32).collect()
+}
+
+#[wasm_bindgen]
+pub fn gx2_calc_surface_size(format: SurfaceFormat, tile_mode: TileMode, w: usize, h: usize, level: usize) -> usize {
+    compute_surface_info(format, tile_mode, w, h, level).surf_size
+}
+
+fn compute_pixel_index_within_micro_tile(x: usize, y: usize, z: usize, bpp: usize, tile_mode: TileMode) -> usize {
+    let (x0, x1, x2) = (x & 1, (x >> 1) & 1, (x >> 2) & 1);
+    let (y0, y1, y2) = (y & 1, (y >> 1) & 1, (y >> 2) & 1);
+    let bits = match bpp {
+        8 => [x0, x1, x2, y1, y0, y2],
+        16 => [x0, x1, x2, y0, y1, y2],
+        64 => [x0, y0, x1, x2, y1, y2],
+        128 => [y0, x0, x1, x2, y1, y2],
+        _ => [x0, x1, y0, x2, y1, y2],
+    };
+    let mut index = 0;
+    for (i, bit) in bits.iter().enumerate() {
+        index |= bit << i;
+    }
+    if tile_mode.thickness() > 1 {
+        index |= (z & 3) << 6;
+    }
+    index
+}
+
+fn compute_addr_linear(x: usize, y: usize, bpp: usize, pitch: usize) -> usize {
+    (y * pitch + x) * bpp / 8
+}
+
+fn compute_addr_micro_tiled(x: usize, y: usize, slice: usize, info: &SurfaceInfo) -> usize {
+    let bpp = info.bits_per_element;
+    let thickness = info.tile_mode.thickness();
+    let micro_tile_bytes = (MICRO_TILE_WIDTH * MICRO_TILE_HEIGHT * thickness * bpp).div_ceil(8);
+    let micro_tiles_per_row = info.pitch / MICRO_TILE_WIDTH;
+    let micro_tile_offset = micro_tile_bytes * (x / MICRO_TILE_WIDTH + (y / MICRO_TILE_HEIGHT) * micro_tiles_per_row);
+    let slice_bytes = (info.pitch * info.height * thickness * bpp).div_ceil(8);
+    let slice_offset = (slice / thickness) * slice_bytes;
+    let pixel_index = compute_pixel_index_within_micro_tile(x, y, slice, bpp, info.tile_mode);
+    let pixel_offset = (bpp * pixel_index) >> 3;
+    pixel_offset + micro_tile_offset + slice_offset
+}
+
+fn compute_addr_macro_tiled(x: usize, y: usize, slice: usize, info: &SurfaceInfo, pipe_swizzle: usize, bank_swizzle: usize) -> usize {
+    let bpp = info.bits_per_element;
+    let tile_mode = info.tile_mode;
+    let thickness = tile_mode.thickness();
+    let num_group_bits = PIPE_INTERLEAVE_BYTES.trailing_zeros();
+    let num_pipe_bits = NUM_PIPES.trailing_zeros();
+    let num_bank_bits = NUM_BANKS.trailing_zeros();
+
+    let pixel_index = compute_pixel_index_within_micro_tile(x, y, slice, bpp, tile_mode);
+    let elem_offset = (bpp * pixel_index).div_ceil(8);
+
+    let pipe = ((y >> 3) ^ (x >> 3)) & 1;
+    let bank_bit0 = ((y / (16 * NUM_PIPES)) ^ (x >> 3)) & 1;
+    let bank_bit1 = ((y / (8 * NUM_PIPES)) ^ (x >> 4)) & 1;
+    let bank = bank_bit0 | (bank_bit1 << 1);
+
+    let slice_in = if tile_mode.is_thick_macro_tiled() { slice >> 2 } else { slice };
+    let swizzle = pipe_swizzle + NUM_PIPES * bank_swizzle;
+    let bank_pipe = (pipe + NUM_PIPES * bank) ^ (swizzle + slice_in * tile_mode.rotation());
+    let bank_pipe = bank_pipe % (NUM_PIPES * NUM_BANKS);
+    let pipe = bank_pipe % NUM_PIPES;
+    let mut bank = bank_pipe / NUM_PIPES;
+
+    let slice_bytes = (info.height * info.pitch * thickness * bpp).div_ceil(8);
+    let slice_offset = slice_bytes * (slice / thickness);
+
+    let aspect_ratio = tile_mode.macro_tile_aspect_ratio();
+    let macro_tile_pitch = MICRO_TILE_WIDTH * NUM_BANKS / aspect_ratio;
+    let macro_tile_height = MICRO_TILE_HEIGHT * NUM_PIPES * aspect_ratio;
+    let macro_tiles_per_row = info.pitch / macro_tile_pitch;
+    let macro_tile_bytes = (thickness * bpp * macro_tile_height * macro_tile_pitch).div_ceil(8);
+    let macro_tile_index_x = x / macro_tile_pitch;
+    let macro_tile_index_y = y / macro_tile_height;
+    let macro_tile_offset = (macro_tile_index_x + macro_tiles_per_row * macro_tile_index_y) * macro_tile_bytes;
+
+    if tile_mode.is_bank_swapped() {
+        let bank_swap_width = compute_bank_swapped_width(tile_mode, bpp, info.pitch);
+        let swap_index = macro_tile_pitch * macro_tile_index_x / bank_swap_width;
+        bank ^= BANK_SWAP_ORDER[swap_index & (NUM_BANKS - 1)];
+    }
+
+    let group_mask = (1 << num_group_bits) - 1;
+    let num_swizzle_bits = num_bank_bits + num_pipe_bits;
+    let total_offset = elem_offset + ((macro_tile_offset + slice_offset) >> num_swizzle_bits);
+    let offset_high = (total_offset & !group_mask) << num_swizzle_bits;
+    let offset_low = total_offset & group_mask;
+    let pipe_bits = pipe << num_group_bits;
+    let bank_bits = bank << (num_pipe_bits + num_group_bits);
+    bank_bits | pipe_bits | offset_low | offset_high
+}
+
+// Byte offset of the element at (x, y) in a level with the given layout. swizzle is
+// GX2Surface::swizzle; bits 8-10 hold the pipe and bank swizzle.
+pub fn compute_surface_addr(x: usize, y: usize, info: &SurfaceInfo, swizzle: u32) -> usize {
+    match info.tile_mode {
+        TileMode::LinearGeneral | TileMode::LinearAligned | TileMode::LinearSpecial => compute_addr_linear(x, y, info.bits_per_element, info.pitch),
+        TileMode::Tiled1DThin1 | TileMode::Tiled1DThick => compute_addr_micro_tiled(x, y, 0, info),
+        _ => {
+            let pipe_swizzle = ((swizzle >> 8) & 1) as usize;
+            let bank_swizzle = ((swizzle >> 9) & 3) as usize;
+            compute_addr_macro_tiled(x, y, 0, info, pipe_swizzle, bank_swizzle)
+        },
+    }
+}
+
+// Deswizzles one mip level of a 2D surface; src starts at the level's data, and w and h are
+// the size of level 0, in pixels. Returns the level's elements in row order, without padding.
+#[wasm_bindgen]
+pub fn gx2_deswizzle(src: &[u8], format: SurfaceFormat, tile_mode: TileMode, swizzle: u32, w: usize, h: usize, level: usize) -> Vec<u8> {
+    let info = compute_surface_info(format, tile_mode, w, h, level);
+    let block_size = get_format_block_size(format);
+    let bytes_per_element = info.bits_per_element / 8;
+    let width_in_blocks = (w >> level).max(1).div_ceil(block_size);
+    let height_in_blocks = (h >> level).max(1).div_ceil(block_size);
+
+    let mut dst = vec![0x00; width_in_blocks * height_in_blocks * bytes_per_element];
+    for y in 0..height_in_blocks {
+        for x in 0..width_in_blocks {
+            let src_offs = compute_surface_addr(x, y, &info, swizzle);
+            let dst_offs = ((y * width_in_blocks) + x) * bytes_per_element;
+            dst[dst_offs..dst_offs + bytes_per_element].copy_from_slice(&src[src_offs..src_offs + bytes_per_element]);
+        }
+    }
+
+    dst
+}
+
+// Deswizzles every level of a surface. Level 0 is in image, the rest are in mips at the
+// offsets from compute_mip_offsets. Stops at the first level whose data is cut off, so a
+// truncated file gives fewer levels (none if image itself is short).
+#[allow(clippy::too_many_arguments)]
+pub fn gx2_deswizzle_mips(image: &[u8], mips: &[u8], format: SurfaceFormat, tile_mode: TileMode, swizzle: u32, w: usize, h: usize, mip_count: usize) -> Vec<Vec<u8>> {
+    let mut levels = vec![];
+    if image.len() < compute_surface_info(format, tile_mode, w, h, 0).surf_size {
+        return levels;
+    }
+    levels.push(gx2_deswizzle(image, format, tile_mode, swizzle, w, h, 0));
+    for (i, offs) in compute_mip_offsets(format, tile_mode, w, h, mip_count).into_iter().enumerate() {
+        let level = i + 1;
+        let size = compute_surface_info(format, tile_mode, w, h, level).surf_size;
+        match mips.get(offs..offs + size) {
+            Some(src) => levels.push(gx2_deswizzle(src, format, tile_mode, swizzle, w, h, level)),
+            None => break,
+        }
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_TILE_MODES: [TileMode; 17] = [
+        TileMode::LinearGeneral, TileMode::LinearAligned, TileMode::Tiled1DThin1, TileMode::Tiled1DThick,
+        TileMode::Tiled2DThin1, TileMode::Tiled2DThin2, TileMode::Tiled2DThin4, TileMode::Tiled2DThick,
+        TileMode::Tiled2BThin1, TileMode::Tiled2BThin2, TileMode::Tiled2BThin4, TileMode::Tiled2BThick,
+        TileMode::Tiled3DThin1, TileMode::Tiled3DThick, TileMode::Tiled3BThin1, TileMode::Tiled3BThick,
+        TileMode::LinearSpecial,
+    ];
+
+    #[test]
+    fn test_macro_tiled_addr() {
+        // The first 8x8 micro tile of each pipe/bank pair.
+        let info = compute_surface_info(SurfaceFormat::R8G8B8A8, TileMode::Tiled2DThin1, 32, 16, 0);
+        assert_eq!((info.pitch, info.height, info.surf_size), (32, 16, 0x800));
+        assert_eq!(compute_surface_addr(0, 0, &info, 0), 0x000);
+        assert_eq!(compute_surface_addr(1, 0, &info, 0), 0x004);
+        assert_eq!(compute_surface_addr(0, 1, &info, 0), 0x010);
+        assert_eq!(compute_surface_addr(2, 0, &info, 0), 0x008);
+        assert_eq!(compute_surface_addr(0, 8, &info, 0), 0x100);
+        assert_eq!(compute_surface_addr(8, 0, &info, 0), 0x300);
+        assert_eq!(compute_surface_addr(16, 0, &info, 0), 0x400);
+        // Pipe swizzle flips the pipe.
+        assert_eq!(compute_surface_addr(0, 0, &info, 0x100), 0x100);
+        assert_eq!(compute_surface_addr(0, 0, &info, 0x600), 0x600);
+    }
+
+    #[test]
+    fn test_mip_tile_modes() {
+        let info = |level| compute_surface_info(SurfaceFormat::Bc1, TileMode::Tiled2BThin1, 256, 128, level);
+        assert_eq!(info(0).tile_mode, TileMode::Tiled2BThin1);
+        assert_eq!((info(0).pitch, info(0).height), (64, 32));
+        assert_eq!(info(1).tile_mode, TileMode::Tiled2DThin1);
+        assert_eq!(info(2).tile_mode, TileMode::Tiled1DThin1);
+        // Padded to whole micro tiles: 4x2 blocks becomes 8x8.
+        assert_eq!((info(4).pitch, info(4).height, info(4).surf_size), (8, 8, 0x200));
+        assert_eq!(compute_mip_offsets(SurfaceFormat::Bc1, TileMode::Tiled2BThin1, 256, 128, 4), vec![0x0000, 0x1000, 0x1400]);
+
+        let info = compute_surface_info(SurfaceFormat::R8G8B8A8, TileMode::Tiled2DThick, 64, 64, 1);
+        assert_eq!(info.tile_mode, TileMode::Tiled2DThin1);
+        let info = compute_surface_info(SurfaceFormat::R8G8B8A8, TileMode::LinearAligned, 20, 20, 0);
+        assert_eq!((info.pitch, info.height), (64, 20));
+        let info = compute_surface_info(SurfaceFormat::Bc3, TileMode::LinearSpecial, 20, 20, 0);
+        assert_eq!((info.pitch, info.height, info.surf_size), (5, 5, 5 * 5 * 16));
+    }
+
+    #[test]
+    fn test_addresses_are_unique() {
+        // Every element of a level should land in its own spot inside the level's data.
+        for &format in [SurfaceFormat::R8, SurfaceFormat::R5G6B5, SurfaceFormat::R8G8B8A8, SurfaceFormat::Bc1, SurfaceFormat::Bc3].iter() {
+            for &tile_mode in ALL_TILE_MODES.iter() {
+                for &swizzle in [0, 0x500, 0x700].iter() {
+                    for level in 0..4 {
+                        let info = compute_surface_info(format, tile_mode, 300, 200, level);
+                        let bytes_per_element = info.bits_per_element / 8;
+                        let block_size = get_format_block_size(format);
+                        let width_in_blocks = (300usize >> level).div_ceil(block_size);
+                        let height_in_blocks = (200usize >> level).div_ceil(block_size);
+                        let mut used = vec![false; info.surf_size / bytes_per_element];
+                        for y in 0..height_in_blocks {
+                            for x in 0..width_in_blocks {
+                                let addr = compute_surface_addr(x, y, &info, swizzle);
+                                assert_eq!(addr % bytes_per_element, 0);
+                                let i = addr / bytes_per_element;
+                                assert!(i < used.len() && !used[i], "{:?} {:?} level {}: ({}, {})", format, tile_mode, level, x, y);
+                                used[i] = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_deswizzle_mips() {
+        let (w, h, mip_count) = (64, 32, 4);
+        let format = SurfaceFormat::R8G8B8A8;
+        let tile_mode = TileMode::Tiled2DThin1;
+        let swizzle = 0x300;
+
+        // Tag each element with its level and position.
+        let tag = |level: usize, x: usize, y: usize| [level as u8, x as u8, y as u8, 0xFF];
+        let info = compute_surface_info(format, tile_mode, w, h, 0);
+        let mut image = vec![0; info.surf_size];
+        for y in 0..h {
+            for x in 0..w {
+                let addr = compute_surface_addr(x, y, &info, swizzle);
+                image[addr..addr + 4].copy_from_slice(&tag(0, x, y));
+            }
+        }
+        let offsets = compute_mip_offsets(format, tile_mode, w, h, mip_count);
+        let mut mips = vec![];
+        for level in 1..mip_count {
+            let info = compute_surface_info(format, tile_mode, w, h, level);
+            let base = offsets[level - 1];
+            mips.resize(base + info.surf_size, 0);
+            for y in 0..h >> level {
+                for x in 0..w >> level {
+                    let addr = base + compute_surface_addr(x, y, &info, swizzle);
+                    mips[addr..addr + 4].copy_from_slice(&tag(level, x, y));
+                }
+            }
+        }
+
+        let levels = gx2_deswizzle_mips(&image, &mips, format, tile_mode, swizzle, w, h, mip_count);
+        assert_eq!(levels.len(), mip_count);
+        for (level, data) in levels.iter().enumerate() {
+            let expected: Vec<u8> = (0..h >> level).flat_map(|y| (0..w >> level).flat_map(move |x| tag(level, x, y).to_vec())).collect();
+            assert_eq!(*data, expected, "level {}", level);
+        }
+
+        // Cutting into the last level drops it, and levels that start past the end of mips are skipped.
+        let truncated = gx2_deswizzle_mips(&image, &mips[..mips.len() - 1], format, tile_mode, swizzle, w, h, mip_count);
+        assert_eq!(truncated, levels[..mip_count - 1]);
+        let truncated = gx2_deswizzle_mips(&image, &mips[..offsets[1]], format, tile_mode, swizzle, w, h, mip_count);
+        assert_eq!(truncated, levels[..2]);
+        assert!(gx2_deswizzle_mips(&image[..16], &mips, format, tile_mode, swizzle, w, h, mip_count).is_empty());
+    }
+}
@@ -1,5 +1,8 @@
 
+use std::convert::TryFrom;
+use std::fmt;
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
 use crate::util;
 
 fn s3tcblend(a_: u8, b_: u8) -> u8 {
@@ -197,6 +200,47 @@ fn decode_rgba8(src: &[u8], w: usize, h: usize) -> Vec<u8> {
     dst
 }
 
+fn cmpr_color_table(color1: u16, color2: u16) -> [u8; 16] {
+    // Fill in first two colors in color table.
+    let mut color_table = [0x00; 16];
+
+    color_table[0] = util::expand_n_to_8(5, ((color1 >> 11) & 0x1F) as u8);
+    color_table[1] = util::expand_n_to_8(6, ((color1 >> 5) & 0x3F) as u8);
+    color_table[2] = util::expand_n_to_8(5, (color1 & 0x1F) as u8);
+    color_table[3] = 0xFF;
+
+    color_table[4] = util::expand_n_to_8(5, ((color2 >> 11) & 0x1F) as u8);
+    color_table[5] = util::expand_n_to_8(6, ((color2 >> 5) & 0x3F) as u8);
+    color_table[6] = util::expand_n_to_8(5, (color2 & 0x1F) as u8);
+    color_table[7] = 0xFF;
+
+    if color1 > color2 {
+        // Predict gradients.
+        color_table[8]  = s3tcblend(color_table[4], color_table[0]);
+        color_table[9]  = s3tcblend(color_table[5], color_table[1]);
+        color_table[10] = s3tcblend(color_table[6], color_table[2]);
+        color_table[11] = 0xFF;
+
+        color_table[12] = s3tcblend(color_table[0], color_table[4]);
+        color_table[13] = s3tcblend(color_table[1], color_table[5]);
+        color_table[14] = s3tcblend(color_table[2], color_table[6]);
+        color_table[15] = 0xFF;
+    } else {
+        color_table[8] =  halfblend(color_table[0], color_table[4]);
+        color_table[9] =  halfblend(color_table[1], color_table[5]);
+        color_table[10] = halfblend(color_table[2], color_table[6]);
+        color_table[11] = 0xFF;
+
+        // CMPR difference: GX fills with an alpha 0 midway point here.
+        color_table[12] = color_table[8];
+        color_table[13] = color_table[9];
+        color_table[14] = color_table[10];
+        color_table[15] = 0x00;
+    }
+
+    color_table
+}
+
 fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
     // CMPR swizzles macroblocks to be in a 2x2 grid of UL, UR, BL, BR.
     let mut src_offs = 0;
@@ -213,42 +257,7 @@ fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
                     let color1 = util::get_uint16_be(src, src_offs_idx + 0x00);
                     let color2 = util::get_uint16_be(src, src_offs_idx + 0x02);
 
-                    // Fill in first two colors in color table.
-                    let mut color_table = [0x00; 16];
-
-                    color_table[0] = util::expand_n_to_8(5, ((color1 >> 11) & 0x1F) as u8);
-                    color_table[1] = util::expand_n_to_8(6, ((color1 >> 5) & 0x3F) as u8);
-                    color_table[2] = util::expand_n_to_8(5, (color1 & 0x1F) as u8);
-                    color_table[3] = 0xFF;
-
-                    color_table[4] = util::expand_n_to_8(5, ((color2 >> 11) & 0x1F) as u8);
-                    color_table[5] = util::expand_n_to_8(6, ((color2 >> 5) & 0x3F) as u8);
-                    color_table[6] = util::expand_n_to_8(5, (color2 & 0x1F) as u8);
-                    color_table[7] = 0xFF;
-
-                    if color1 > color2 {
-                        // Predict gradients.
-                        color_table[8]  = s3tcblend(color_table[4], color_table[0]);
-                        color_table[9]  = s3tcblend(color_table[5], color_table[1]);
-                        color_table[10] = s3tcblend(color_table[6], color_table[2]);
-                        color_table[11] = 0xFF;
-
-                        color_table[12] = s3tcblend(color_table[0], color_table[4]);
-                        color_table[13] = s3tcblend(color_table[1], color_table[5]);
-                        color_table[14] = s3tcblend(color_table[2], color_table[6]);
-                        color_table[15] = 0xFF;
-                    } else {
-                        color_table[8] =  halfblend(color_table[0], color_table[4]);
-                        color_table[9] =  halfblend(color_table[1], color_table[5]);
-                        color_table[10] = halfblend(color_table[2], color_table[6]);
-                        color_table[11] = 0xFF;
-
-                        // CMPR difference: GX fills with an alpha 0 midway point here.
-                        color_table[12] = color_table[8];
-                        color_table[13] = color_table[9];
-                        color_table[14] = color_table[10];
-                        color_table[15] = 0x00;
-                    }
+                    let color_table = cmpr_color_table(color1, color2);
 
                     for y in 0..4 {
                         let mut bits = src[src_offs_idx + 0x04 + y];
@@ -274,6 +283,7 @@ fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
 }
 
 #[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PaletteFormat {
     IA8,
     RGB565,
@@ -281,6 +291,7 @@ pub enum PaletteFormat {
 }
 
 #[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PixelFormat {
     I4,
     I8,
@@ -381,8 +392,15 @@ impl TiledDecoder for TiledDecoderC14X2<'_> {
     fn block_height() -> usize { 4 }
 }
 
-#[wasm_bindgen]
-pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize) -> Vec<u8> {
+// Decodes the palette for the palette formats, or returns an empty one for the others.
+fn decode_texture_palette(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, palette_src: Option<&[u8]>) -> Vec<u8> {
+    match fmt {
+        PixelFormat::C4 | PixelFormat::C8 | PixelFormat::C14X2 => decode_palette(palette_fmt.unwrap(), palette_src.unwrap()),
+        _ => vec![],
+    }
+}
+
+fn decode_texture_with_palette(fmt: PixelFormat, palette: &[u8], src: &[u8], w: usize, h: usize) -> Vec<u8> {
     match fmt {
         PixelFormat::I4 => decode_tiled(TiledDecoderI4{}, src, w, h),
         PixelFormat::I8 => decode_tiled(TiledDecoderI8{}, src, w, h),
@@ -392,17 +410,1140 @@ pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src:
         PixelFormat::RGB5A3 => decode_tiled(TiledDecoderRGB5A3{}, src, w, h),
         PixelFormat::RGBA8 => decode_rgba8(src, w, h),
         PixelFormat::CMPR => decode_cmpr(src, w, h),
+        PixelFormat::C4 => decode_tiled(TiledDecoderC4{ palette }, src, w, h),
+        PixelFormat::C8 => decode_tiled(TiledDecoderC8{ palette }, src, w, h),
+        PixelFormat::C14X2 => decode_tiled(TiledDecoderC14X2{ palette }, src, w, h),
+    }
+}
+
+#[wasm_bindgen]
+pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize) -> Vec<u8> {
+    let palette = decode_texture_palette(fmt, palette_fmt, palette_src.as_deref());
+    decode_texture_with_palette(fmt, &palette, src, w, h)
+}
+
+// Mip chains
+
+// Block width, block height and bits per pixel.
+fn block_info(fmt: PixelFormat) -> (usize, usize, usize) {
+    match fmt {
+        PixelFormat::I4 => (8, 8, 4),
+        PixelFormat::I8 => (8, 4, 8),
+        PixelFormat::IA4 => (8, 4, 8),
+        PixelFormat::IA8 => (4, 4, 16),
+        PixelFormat::RGB565 => (4, 4, 16),
+        PixelFormat::RGB5A3 => (4, 4, 16),
+        PixelFormat::RGBA8 => (4, 4, 32),
+        PixelFormat::CMPR => (8, 8, 4),
+        PixelFormat::C4 => (8, 8, 4),
+        PixelFormat::C8 => (8, 4, 8),
+        PixelFormat::C14X2 => (4, 4, 16),
+    }
+}
+
+// Size in bytes of a single image, padded out to whole blocks. Every block is
+// 32 bytes (RGBA8 stores its AR and GB halves as two), so levels stay aligned.
+#[wasm_bindgen]
+pub fn calc_texture_size(fmt: PixelFormat, w: usize, h: usize) -> usize {
+    let (bw, bh, bpp) = block_info(fmt);
+    w.div_ceil(bw) * bw * h.div_ceil(bh) * bh * bpp / 8
+}
+
+// Dimensions of a mip level; each level halves the previous one, down to 1x1.
+pub fn calc_mip_level_size(w: usize, h: usize, level: usize) -> (usize, usize) {
+    let shift = u32::try_from(level).unwrap_or(u32::MAX);
+    (w.checked_shr(shift).unwrap_or(0).max(1), h.checked_shr(shift).unwrap_or(0).max(1))
+}
+
+// Offset of a mip level from the start of the texture data.
+#[wasm_bindgen]
+pub fn calc_mip_level_offset(fmt: PixelFormat, w: usize, h: usize, level: usize) -> usize {
+    (0..level).map(|i| {
+        let (mw, mh) = calc_mip_level_size(w, h, i);
+        calc_texture_size(fmt, mw, mh)
+    }).sum()
+}
+
+// Decodes a single level out of a full mip chain; w and h are the size of level 0.
+// A level that runs past the end of src decodes to nothing, as in decode_texture_mips.
+#[wasm_bindgen]
+pub fn decode_texture_level(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize, level: usize) -> Vec<u8> {
+    let (mw, mh) = calc_mip_level_size(w, h, level);
+    let offs = calc_mip_level_offset(fmt, w, h, level);
+    match src.get(offs..offs + calc_texture_size(fmt, mw, mh)) {
+        Some(level_src) => decode_texture(fmt, palette_fmt, level_src, palette_src, mw, mh),
+        None => Vec::new(),
+    }
+}
+
+// Decodes up to mip_count levels. All levels share one palette. Like the
+// TypeScript calcMipChain, levels that run past the end of src are dropped, since
+// some games (Metroid Prime) claim more mips than they store.
+pub fn decode_texture_mips(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<&[u8]>, w: usize, h: usize, mip_count: usize) -> Vec<Vec<u8>> {
+    let palette = decode_texture_palette(fmt, palette_fmt, palette_src);
+    let mut levels = Vec::with_capacity(mip_count);
+    let mut offs = 0;
+    for level in 0..mip_count {
+        let (mw, mh) = calc_mip_level_size(w, h, level);
+        let size = calc_texture_size(fmt, mw, mh);
+        if offs + size > src.len() {
+            break;
+        }
+        levels.push(decode_texture_with_palette(fmt, &palette, &src[offs..offs + size], mw, mh));
+        offs += size;
+    }
+    levels
+}
+
+#[wasm_bindgen]
+pub struct DecodedMipChain {
+    width: usize,
+    height: usize,
+    #[wasm_bindgen(skip)]
+    pub levels: Vec<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl DecodedMipChain {
+    #[wasm_bindgen(getter)] pub fn mip_count(&self) -> usize { self.levels.len() }
+    pub fn level_width(&self, level: usize) -> usize { calc_mip_level_size(self.width, self.height, level).0 }
+    pub fn level_height(&self, level: usize) -> usize { calc_mip_level_size(self.width, self.height, level).1 }
+    // Empty for levels past mip_count.
+    pub fn level_data(&self, level: usize) -> Vec<u8> { self.levels.get(level).cloned().unwrap_or_default() }
+}
+
+#[wasm_bindgen]
+pub fn decode_texture_mip_chain(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize, mip_count: usize) -> DecodedMipChain {
+    let levels = decode_texture_mips(fmt, palette_fmt, src, palette_src.as_deref(), w, h, mip_count);
+    DecodedMipChain { width: w, height: h, levels }
+}
+
+// Validation
+
+// The largest texture GX can sample; TEXIMAGE0 stores width - 1 and height - 1 in 10 bits each.
+pub const MAX_TEXTURE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureError {
+    // The width or height is zero or larger than MAX_TEXTURE_SIZE.
+    InvalidDimensions { width: usize, height: usize },
+    // The texture data is shorter than the format's tiled size.
+    SourceTooShort { expected: usize, actual: usize },
+    // A palette format (C4, C8, C14X2) was given no palette data or palette format.
+    MissingPalette,
+    // The texture uses a palette index past the end of the palette.
+    PaletteTooShort { needed: usize, actual: usize },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::InvalidDimensions { width, height } =>
+                write!(f, "invalid texture dimensions {}x{} (must be between 1 and {})", width, height, MAX_TEXTURE_SIZE),
+            TextureError::SourceTooShort { expected, actual } =>
+                write!(f, "texture data is {} bytes, expected at least {}", actual, expected),
+            TextureError::MissingPalette => write!(f, "palette texture has no palette"),
+            TextureError::PaletteTooShort { needed, actual } =>
+                write!(f, "palette has {} entries, but the texture uses {}", actual, needed),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<TextureError> for JsValue {
+    fn from(err: TextureError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, TextureError>;
+
+// Highest palette index used by the visible pixels of a C4, C8 or C14X2 texture.
+fn max_palette_index(fmt: PixelFormat, src: &[u8], w: usize, h: usize) -> usize {
+    let (bw, bh, _) = block_info(fmt);
+    let mut max_index = 0;
+    let mut idx: usize = 0;
+    for yy in (0..h).step_by(bh) {
+        for xx in (0..w).step_by(bw) {
+            for y in 0..bh {
+                for x in 0..bw {
+                    if xx + x < w && yy + y < h {
+                        let index = match fmt {
+                            PixelFormat::C4 => ((src[idx >> 1] >> (if (idx & 1) != 0 { 0 } else { 4 })) & 0x0F) as usize,
+                            PixelFormat::C8 => src[idx] as usize,
+                            PixelFormat::C14X2 => (util::get_uint16_be(src, idx * 2) as usize) & 0x3FFF,
+                            _ => unreachable!(),
+                        };
+                        max_index = max_index.max(index);
+                    }
+                    idx += 1;
+                }
+            }
+        }
+    }
+    max_index
+}
+
+fn check_dimensions(w: usize, h: usize) -> Result<()> {
+    if w == 0 || h == 0 || w > MAX_TEXTURE_SIZE || h > MAX_TEXTURE_SIZE {
+        return Err(TextureError::InvalidDimensions { width: w, height: h });
+    }
+    Ok(())
+}
+
+fn validate_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<&[u8]>, w: usize, h: usize) -> Result<()> {
+    check_dimensions(w, h)?;
+
+    let expected = calc_texture_size(fmt, w, h);
+    if src.len() < expected {
+        return Err(TextureError::SourceTooShort { expected, actual: src.len() });
+    }
+
+    if let PixelFormat::C4 | PixelFormat::C8 | PixelFormat::C14X2 = fmt {
+        let palette_src = match (palette_fmt, palette_src) {
+            (Some(_), Some(palette_src)) => palette_src,
+            _ => return Err(TextureError::MissingPalette),
+        };
+        let needed = max_palette_index(fmt, src, w, h) + 1;
+        let actual = palette_src.len() / 2;
+        if actual < needed {
+            return Err(TextureError::PaletteTooShort { needed, actual });
+        }
+    }
+
+    Ok(())
+}
+
+// Like decode_texture, but checks the dimensions, data size and palette first
+// instead of panicking on bad input.
+pub fn try_decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<&[u8]>, w: usize, h: usize) -> Result<Vec<u8>> {
+    validate_texture(fmt, palette_fmt, src, palette_src, w, h)?;
+    let palette = decode_texture_palette(fmt, palette_fmt, palette_src);
+    Ok(decode_texture_with_palette(fmt, &palette, src, w, h))
+}
+
+// Like decode_texture_level, with the same checks as try_decode_texture.
+pub fn try_decode_texture_level(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<&[u8]>, w: usize, h: usize, level: usize) -> Result<Vec<u8>> {
+    check_dimensions(w, h)?;
+    let (mw, mh) = calc_mip_level_size(w, h, level);
+    let offs = calc_mip_level_offset(fmt, w, h, level);
+    let expected = offs + calc_texture_size(fmt, mw, mh);
+    if src.len() < expected {
+        return Err(TextureError::SourceTooShort { expected, actual: src.len() });
+    }
+    try_decode_texture(fmt, palette_fmt, &src[offs..], palette_src, mw, mh)
+}
+
+#[wasm_bindgen]
+pub fn decode_texture_checked(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(try_decode_texture(fmt, palette_fmt, src, palette_src.as_deref(), w, h)?)
+}
+
+#[wasm_bindgen]
+pub fn decode_texture_level_checked(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize, level: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(try_decode_texture_level(fmt, palette_fmt, src, palette_src.as_deref(), w, h, level)?)
+}
+
+// Encoding
+
+// Rounds an 8-bit value to the nearest n-bit value, as expanded by expand_n_to_8.
+fn quantize_8_to_n(n: u8, v: u8) -> u8 {
+    let max = (1u32 << n) - 1;
+    ((v as u32 * max + 127) / 255) as u8
+}
+
+fn intensity(px: &[u8]) -> u8 {
+    // BT.601 luma. The weights sum to 256, so grays come back unchanged.
+    ((px[0] as u32 * 77 + px[1] as u32 * 150 + px[2] as u32 * 29 + 128) >> 8) as u8
+}
+
+fn encode_rgba8_to_rgb565(px: &[u8]) -> u16 {
+    let r = quantize_8_to_n(5, px[0]) as u16;
+    let g = quantize_8_to_n(6, px[1]) as u16;
+    let b = quantize_8_to_n(5, px[2]) as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn encode_rgba8_to_rgb5a3(px: &[u8]) -> u16 {
+    // Use the opaque RGB5 mode if the alpha is closer to 0xFF than to the
+    // largest A3 value below it (0xDB).
+    if px[3] >= 0xED {
+        let r = quantize_8_to_n(5, px[0]) as u16;
+        let g = quantize_8_to_n(5, px[1]) as u16;
+        let b = quantize_8_to_n(5, px[2]) as u16;
+        0x8000 | (r << 10) | (g << 5) | b
+    } else {
+        let a = quantize_8_to_n(3, px[3]) as u16;
+        let r = quantize_8_to_n(4, px[0]) as u16;
+        let g = quantize_8_to_n(4, px[1]) as u16;
+        let b = quantize_8_to_n(4, px[2]) as u16;
+        (a << 12) | (r << 8) | (g << 4) | b
+    }
+}
+
+fn encode_palette_color(palette_fmt: PaletteFormat, px: &[u8]) -> u16 {
+    match palette_fmt {
+        PaletteFormat::IA8 => ((px[3] as u16) << 8) | intensity(px) as u16,
+        PaletteFormat::RGB565 => encode_rgba8_to_rgb565(px),
+        PaletteFormat::RGB5A3 => encode_rgba8_to_rgb5a3(px),
+    }
+}
+
+trait TiledEncoder {
+    // The decoder for the same format, which sets the block size.
+    type Decoder: TiledDecoder;
+    fn bits_per_pixel() -> usize;
+    // src_px is the pixel's position in the RGBA8 source image.
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]);
+}
+
+fn encode_tiled<T: TiledEncoder>(t: T, src: &[u8], w: usize, h: usize) -> Vec<u8> {
+    let bw = T::Decoder::block_width();
+    let bh = T::Decoder::block_height();
+    let num_blocks = w.div_ceil(bw) * h.div_ceil(bh);
+    let mut dst = vec![0x00; num_blocks * bw * bh * T::bits_per_pixel() / 8];
+
+    // Pixels past the edge of the image are left as zero.
+    let mut idx: usize = 0;
+    for yy in (0..h).step_by(bh) {
+        for xx in (0..w).step_by(bw) {
+            for y in 0..bh {
+                for x in 0..bw {
+                    if xx + x < w && yy + y < h {
+                        let src_px = (yy + y) * w + (xx + x);
+                        t.encode_single_pixel(src, src_px, idx, &mut dst);
+                    }
+                    idx += 1;
+                }
+            }
+        }
+    }
+
+    dst
+}
+
+fn put_nibble(dst: &mut [u8], idx: usize, v: u8) {
+    dst[idx >> 1] |= (v & 0x0F) << (if (idx & 1) != 0 { 0 } else { 4 });
+}
+
+fn put_uint16_be(dst: &mut [u8], offs: usize, v: u16) {
+    dst[offs..offs + 2].copy_from_slice(&v.to_be_bytes());
+}
+
+struct TiledEncoderI4 {}
+impl TiledEncoder for TiledEncoderI4 {
+    type Decoder = TiledDecoderI4;
+    fn bits_per_pixel() -> usize { 4 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        put_nibble(dst, idx, quantize_8_to_n(4, intensity(px)));
+    }
+}
+
+struct TiledEncoderI8 {}
+impl TiledEncoder for TiledEncoderI8 {
+    type Decoder = TiledDecoderI8;
+    fn bits_per_pixel() -> usize { 8 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        dst[idx] = intensity(px);
+    }
+}
+
+struct TiledEncoderIA4 {}
+impl TiledEncoder for TiledEncoderIA4 {
+    type Decoder = TiledDecoderIA4;
+    fn bits_per_pixel() -> usize { 8 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        dst[idx] = (quantize_8_to_n(4, px[3]) << 4) | quantize_8_to_n(4, intensity(px));
+    }
+}
+
+struct TiledEncoderIA8 {}
+impl TiledEncoder for TiledEncoderIA8 {
+    type Decoder = TiledDecoderIA8;
+    fn bits_per_pixel() -> usize { 16 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        dst[idx * 2] = px[3];
+        dst[idx * 2 + 1] = intensity(px);
+    }
+}
+
+struct TiledEncoderRGB565 {}
+impl TiledEncoder for TiledEncoderRGB565 {
+    type Decoder = TiledDecoderRGB565;
+    fn bits_per_pixel() -> usize { 16 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        put_uint16_be(dst, idx * 2, encode_rgba8_to_rgb565(px));
+    }
+}
+
+struct TiledEncoderRGB5A3 {}
+impl TiledEncoder for TiledEncoderRGB5A3 {
+    type Decoder = TiledDecoderRGB5A3;
+    fn bits_per_pixel() -> usize { 16 }
+
+    fn encode_single_pixel(&self, src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        let px = &src[src_px * 4..src_px * 4 + 4];
+        put_uint16_be(dst, idx * 2, encode_rgba8_to_rgb5a3(px));
+    }
+}
+
+fn encode_rgba8(src: &[u8], w: usize, h: usize) -> Vec<u8> {
+    let num_blocks = w.div_ceil(4) * h.div_ceil(4);
+    let mut dst = vec![0x00; num_blocks * 0x40];
+    let mut dst_offs = 0;
+
+    // AR pairs for the whole block, then GB pairs; see decode_rgba8.
+    for yy in (0..h).step_by(4) {
+        for xx in (0..w).step_by(4) {
+            for y in 0..4 {
+                for x in 0..4 {
+                    if xx + x < w && yy + y < h {
+                        let src_offs = ((yy + y) * w + (xx + x)) * 4;
+                        dst[dst_offs] = src[src_offs + 3];
+                        dst[dst_offs + 0x01] = src[src_offs];
+                        dst[dst_offs + 0x20] = src[src_offs + 1];
+                        dst[dst_offs + 0x21] = src[src_offs + 2];
+                    }
+                    dst_offs += 2;
+                }
+            }
+            dst_offs += 0x20;
+        }
+    }
+
+    dst
+}
+
+// CMPR (S3TC / DXT1) block compression.
+//
+// Endpoints start at the extremes along the principal axis of the block's
+// colors, then get refined by least squares on the chosen indices, and finally
+// by nudging each 565 component to see if the error goes down. Every candidate
+// is scored against the exact color table the decoder would build. Pixels with
+// alpha below 0x80 force the three-color mode, where index 3 is transparent.
+
+const CMPR_TRANSPARENT_THRESHOLD: u8 = 0x80;
+
+// None for pixels past the edge of the image.
+type CmprBlock = [Option<[u8; 4]>; 16];
+
+fn color_distance(a: &[u8], b: &[u8]) -> u32 {
+    let dr = a[0] as i32 - b[0] as i32;
+    let dg = a[1] as i32 - b[1] as i32;
+    let db = a[2] as i32 - b[2] as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+// Picks the best index for each pixel with these endpoints. Returns the total
+// error, or None if the block needs transparency this mode can't give.
+fn cmpr_choose_indices(block: &CmprBlock, color1: u16, color2: u16, indices: &mut [u8; 16]) -> Option<u32> {
+    let color_table = cmpr_color_table(color1, color2);
+    let four_color = color1 > color2;
+
+    let mut error = 0;
+    for (i, px) in block.iter().enumerate() {
+        let px = match px {
+            Some(px) => px,
+            None => {
+                indices[i] = 0;
+                continue;
+            },
+        };
+
+        if px[3] < CMPR_TRANSPARENT_THRESHOLD {
+            if four_color {
+                return None;
+            }
+            indices[i] = 3;
+            continue;
+        }
+
+        let num_colors = if four_color { 4 } else { 3 };
+        let mut best = (u32::MAX, 0);
+        for c in 0..num_colors {
+            let d = color_distance(px, &color_table[c * 4..c * 4 + 4]);
+            if d < best.0 {
+                best = (d, c);
+            }
+        }
+        error += best.0;
+        indices[i] = best.1 as u8;
+    }
+
+    Some(error)
+}
+
+fn pack_rgb565_f32(c: &[f32; 3]) -> u16 {
+    let q = |v: f32, n: u8| quantize_8_to_n(n, v.clamp(0.0, 255.0).round() as u8) as u16;
+    (q(c[0], 5) << 11) | (q(c[1], 6) << 5) | q(c[2], 5)
+}
+
+// The initial endpoints: the extremes along the principal axis.
+fn cmpr_principal_endpoints(colors: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let n = colors.len() as f32;
+    let mut mean = [0.0; 3];
+    for c in colors {
+        for k in 0..3 {
+            mean[k] += c[k] / n;
+        }
+    }
+
+    let mut cov = [[0.0f32; 3]; 3];
+    for c in colors {
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[i][j] += (c[i] - mean[i]) * (c[j] - mean[j]);
+            }
+        }
+    }
+
+    // Power iteration.
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..8 {
+        let mut next = [0.0f32; 3];
+        for i in 0..3 {
+            next[i] = cov[i][0] * axis[0] + cov[i][1] * axis[1] + cov[i][2] * axis[2];
+        }
+        let len = (next[0] * next[0] + next[1] * next[1] + next[2] * next[2]).sqrt();
+        if len < 1e-6 {
+            break;
+        }
+        axis = [next[0] / len, next[1] / len, next[2] / len];
+    }
+
+    let mut lo = (f32::MAX, mean);
+    let mut hi = (f32::MIN, mean);
+    for c in colors {
+        let t = (c[0] - mean[0]) * axis[0] + (c[1] - mean[1]) * axis[1] + (c[2] - mean[2]) * axis[2];
+        if t < lo.0 {
+            lo = (t, *c);
+        }
+        if t > hi.0 {
+            hi = (t, *c);
+        }
+    }
+
+    (hi.1, lo.1)
+}
+
+// Solves for the endpoints that best fit the pixels with these indices.
+fn cmpr_least_squares(block: &CmprBlock, indices: &[u8; 16], four_color: bool) -> Option<([f32; 3], [f32; 3])> {
+    // Weight of color1 for each index; see s3tcblend and halfblend.
+    let weights: [f32; 4] = if four_color { [1.0, 0.0, 5.0 / 8.0, 3.0 / 8.0] } else { [1.0, 0.0, 0.5, 0.0] };
+
+    let (mut aa, mut ab, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ap = [0.0f32; 3];
+    let mut bp = [0.0f32; 3];
+    for (i, px) in block.iter().enumerate() {
+        let px = match px {
+            Some(px) if px[3] >= CMPR_TRANSPARENT_THRESHOLD => px,
+            _ => continue,
+        };
+        let a = weights[indices[i] as usize];
+        let b = 1.0 - a;
+        aa += a * a;
+        ab += a * b;
+        bb += b * b;
+        for k in 0..3 {
+            ap[k] += a * px[k] as f32;
+            bp[k] += b * px[k] as f32;
+        }
+    }
+
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-3 {
+        return None;
+    }
+
+    let mut e1 = [0.0; 3];
+    let mut e2 = [0.0; 3];
+    for k in 0..3 {
+        e1[k] = (ap[k] * bb - bp[k] * ab) / det;
+        e2[k] = (bp[k] * aa - ap[k] * ab) / det;
+    }
+    Some((e1, e2))
+}
+
+struct CmprCandidate {
+    error: u32,
+    color1: u16,
+    color2: u16,
+    indices: [u8; 16],
+}
+
+impl CmprCandidate {
+    fn try_endpoints(&mut self, block: &CmprBlock, color1: u16, color2: u16) -> bool {
+        let mut indices = [0; 16];
+        match cmpr_choose_indices(block, color1, color2, &mut indices) {
+            Some(error) if error < self.error => {
+                *self = CmprCandidate { error, color1, color2, indices };
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Tries both orders, which select the four- and three-color modes.
+    fn try_pair(&mut self, block: &CmprBlock, a: u16, b: u16) -> bool {
+        let hi = a.max(b);
+        let lo = a.min(b);
+        let four = self.try_endpoints(block, hi, lo);
+        let three = self.try_endpoints(block, lo, hi);
+        four || three
+    }
+}
+
+fn encode_cmpr_block(block: &CmprBlock, dst: &mut [u8]) {
+    let colors: Vec<[f32; 3]> = block.iter()
+        .filter_map(|px| px.filter(|px| px[3] >= CMPR_TRANSPARENT_THRESHOLD))
+        .map(|px| [px[0] as f32, px[1] as f32, px[2] as f32])
+        .collect();
+
+    // Fully transparent: color1 <= color2, and every index is 3.
+    let mut best = CmprCandidate { error: u32::MAX, color1: 0, color2: 0, indices: [3; 16] };
+
+    if !colors.is_empty() {
+        let (e1, e2) = cmpr_principal_endpoints(&colors);
+        best.try_pair(block, pack_rgb565_f32(&e1), pack_rgb565_f32(&e2));
+
+        for _ in 0..2 {
+            let four_color = best.color1 > best.color2;
+            match cmpr_least_squares(block, &best.indices, four_color) {
+                Some((e1, e2)) => {
+                    if !best.try_pair(block, pack_rgb565_f32(&e1), pack_rgb565_f32(&e2)) {
+                        break;
+                    }
+                },
+                None => break,
+            }
+        }
+
+        // Nudge each component of each endpoint by one step, until nothing helps.
+        let fields: [(u16, u16); 3] = [(11, 0x1F), (5, 0x3F), (0, 0x1F)];
+        let mut improved = true;
+        while improved && best.error > 0 {
+            improved = false;
+            for endpoint in 0..2 {
+                for &(shift, mask) in fields.iter() {
+                    for &delta in [-1i32, 1].iter() {
+                        let (c1, c2) = (best.color1, best.color2);
+                        let c = if endpoint == 0 { c1 } else { c2 };
+                        let v = ((c >> shift) & mask) as i32 + delta;
+                        if v < 0 || v > mask as i32 {
+                            continue;
+                        }
+                        let c = (c & !(mask << shift)) | ((v as u16) << shift);
+                        let (c1, c2) = if endpoint == 0 { (c, c2) } else { (c1, c) };
+                        // Keep the current mode; the order decides it.
+                        if (c1 > c2) != (best.color1 > best.color2) {
+                            continue;
+                        }
+                        improved |= best.try_endpoints(block, c1, c2);
+                    }
+                }
+            }
+        }
+    }
+
+    put_uint16_be(dst, 0x00, best.color1);
+    put_uint16_be(dst, 0x02, best.color2);
+    for y in 0..4 {
+        let mut bits = 0;
+        for x in 0..4 {
+            bits = (bits << 2) | best.indices[y * 4 + x];
+        }
+        dst[0x04 + y] = bits;
+    }
+}
+
+fn encode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
+    let num_blocks = w.div_ceil(8) * h.div_ceil(8) * 4;
+    let mut dst = vec![0x00; num_blocks * 0x08];
+    let mut dst_offs = 0;
+
+    // Same 2x2 macroblock order as decode_cmpr.
+    for yy in (0..h).step_by(8) {
+        for xx in (0..w).step_by(8) {
+            for yb in (0..8).step_by(4) {
+                for xb in (0..8).step_by(4) {
+                    let mut block: CmprBlock = [None; 16];
+                    for y in 0..4 {
+                        for x in 0..4 {
+                            if xx + xb + x >= w || yy + yb + y >= h {
+                                continue;
+                            }
+                            let src_offs = ((yy + yb + y) * w + (xx + xb + x)) * 4;
+                            let mut px = [0; 4];
+                            px.copy_from_slice(&src[src_offs..src_offs + 4]);
+                            block[y * 4 + x] = Some(px);
+                        }
+                    }
+
+                    encode_cmpr_block(&block, &mut dst[dst_offs..dst_offs + 0x08]);
+                    dst_offs += 0x08;
+                }
+            }
+        }
+    }
+
+    dst
+}
+
+// Palette generation.
+//
+// Colors are first reduced to what the palette format can hold. If there are
+// few enough of those, the palette is exact; otherwise it is built by median
+// cut, weighted by how many pixels use each color.
+
+struct PaletteEntry {
+    color: u16,
+    rgba: [u8; 4],
+    count: u32,
+}
+
+fn decode_palette_color(palette_fmt: PaletteFormat, color: u16) -> [u8; 4] {
+    let v = decode_palette(palette_fmt, &color.to_be_bytes());
+    [v[0], v[1], v[2], v[3]]
+}
+
+// Returns the palette colors, and the palette index for each pixel.
+fn generate_palette(palette_fmt: PaletteFormat, src: &[u8], max_colors: usize) -> (Vec<u16>, Vec<u16>) {
+    use std::collections::HashMap;
+
+    let pixel_colors: Vec<u16> = src.chunks_exact(4).map(|px| encode_palette_color(palette_fmt, px)).collect();
+
+    let mut counts: HashMap<u16, u32> = HashMap::new();
+    for &color in &pixel_colors {
+        *counts.entry(color).or_insert(0) += 1;
+    }
+    let mut entries: Vec<PaletteEntry> = counts.iter()
+        .map(|(&color, &count)| PaletteEntry { color, rgba: decode_palette_color(palette_fmt, color), count })
+        .collect();
+    entries.sort_by_key(|e| e.color);
+
+    if entries.len() <= max_colors {
+        let palette: Vec<u16> = entries.iter().map(|e| e.color).collect();
+        let lookup: HashMap<u16, u16> = palette.iter().enumerate().map(|(i, &c)| (c, i as u16)).collect();
+        let indices = pixel_colors.iter().map(|c| lookup[c]).collect();
+        return (palette, indices);
+    }
+
+    // Median cut. Each box is a (start, end) range of entries.
+    let mut boxes: Vec<(usize, usize)> = vec![(0, entries.len())];
+    while boxes.len() < max_colors {
+        // Split the box with the widest channel.
+        let mut widest: Option<(u8, usize, usize)> = None;
+        for (i, &(start, end)) in boxes.iter().enumerate() {
+            if end - start < 2 {
+                continue;
+            }
+            for k in 0..4 {
+                let lo = entries[start..end].iter().map(|e| e.rgba[k]).min().unwrap();
+                let hi = entries[start..end].iter().map(|e| e.rgba[k]).max().unwrap();
+                let range = hi - lo;
+                match widest {
+                    Some((r, _, _)) if r >= range => {},
+                    _ => widest = Some((range, i, k)),
+                }
+            }
+        }
+
+        let (_, i, k) = match widest {
+            Some(w) => w,
+            None => break,
+        };
+
+        // Split at the weighted median, leaving at least one entry on each side.
+        let (start, end) = boxes[i];
+        entries[start..end].sort_by_key(|e| e.rgba[k]);
+        let total: u32 = entries[start..end].iter().map(|e| e.count).sum();
+        let mut acc = 0;
+        let mut split = start + 1;
+        for (j, e) in entries[start..end].iter().enumerate() {
+            acc += e.count;
+            if acc * 2 >= total {
+                split = (start + j + 1).max(start + 1).min(end - 1);
+                break;
+            }
+        }
+        boxes[i] = (start, split);
+        boxes.push((split, end));
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut lookup: HashMap<u16, u16> = HashMap::new();
+    for (i, &(start, end)) in boxes.iter().enumerate() {
+        let total: u64 = entries[start..end].iter().map(|e| e.count as u64).sum();
+        let mut avg = [0; 4];
+        for (k, v) in avg.iter_mut().enumerate() {
+            let sum: u64 = entries[start..end].iter().map(|e| e.rgba[k] as u64 * e.count as u64).sum();
+            *v = ((sum + total / 2) / total) as u8;
+        }
+        palette.push(encode_palette_color(palette_fmt, &avg));
+        for e in &entries[start..end] {
+            lookup.insert(e.color, i as u16);
+        }
+    }
+
+    // For small palettes, search for the nearest entry rather than trusting the box.
+    if palette.len() <= 256 {
+        let palette_rgba: Vec<[u8; 4]> = palette.iter().map(|&c| decode_palette_color(palette_fmt, c)).collect();
+        for e in &entries {
+            let mut best = (u32::MAX, 0);
+            for (i, p) in palette_rgba.iter().enumerate() {
+                let da = e.rgba[3] as i32 - p[3] as i32;
+                let d = color_distance(&e.rgba, p) + (da * da) as u32;
+                if d < best.0 {
+                    best = (d, i);
+                }
+            }
+            lookup.insert(e.color, best.1 as u16);
+        }
+    }
+
+    let indices = pixel_colors.iter().map(|c| lookup[c]).collect();
+    (palette, indices)
+}
+
+struct TiledEncoderC4<'a> {
+    indices: &'a [u16],
+}
+
+impl TiledEncoder for TiledEncoderC4<'_> {
+    type Decoder = TiledDecoderC4<'static>;
+    fn bits_per_pixel() -> usize { 4 }
+
+    fn encode_single_pixel(&self, _src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        put_nibble(dst, idx, self.indices[src_px] as u8);
+    }
+}
+
+struct TiledEncoderC8<'a> {
+    indices: &'a [u16],
+}
+
+impl TiledEncoder for TiledEncoderC8<'_> {
+    type Decoder = TiledDecoderC8<'static>;
+    fn bits_per_pixel() -> usize { 8 }
+
+    fn encode_single_pixel(&self, _src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        dst[idx] = self.indices[src_px] as u8;
+    }
+}
+
+struct TiledEncoderC14X2<'a> {
+    indices: &'a [u16],
+}
+
+impl TiledEncoder for TiledEncoderC14X2<'_> {
+    type Decoder = TiledDecoderC14X2<'static>;
+    fn bits_per_pixel() -> usize { 16 }
+
+    fn encode_single_pixel(&self, _src: &[u8], src_px: usize, idx: usize, dst: &mut [u8]) {
+        put_uint16_be(dst, idx * 2, self.indices[src_px] & 0x3FFF);
+    }
+}
+
+fn encode_palette(palette: &[u16]) -> Vec<u8> {
+    palette.iter().flat_map(|c| c.to_be_bytes().to_vec()).collect()
+}
+
+#[wasm_bindgen]
+pub struct EncodedTexture {
+    #[wasm_bindgen(skip)]
+    pub data: Vec<u8>,
+    // Palette data for C4, C8 and C14X2, in the requested palette format.
+    #[wasm_bindgen(skip)]
+    pub palette: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl EncodedTexture {
+    #[wasm_bindgen(getter)] pub fn data(&self) -> Vec<u8> { self.data.clone() }
+    #[wasm_bindgen(getter)] pub fn palette(&self) -> Option<Vec<u8>> { self.palette.clone() }
+}
+
+// Encodes RGBA8 pixels. The palette formats need palette_fmt; the palette is
+// generated from the image, with at most 16, 256 or 16384 colors.
+#[wasm_bindgen]
+pub fn encode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], w: usize, h: usize) -> EncodedTexture {
+    let src = &src[..w * h * 4];
+    let data = match fmt {
+        PixelFormat::I4 => encode_tiled(TiledEncoderI4{}, src, w, h),
+        PixelFormat::I8 => encode_tiled(TiledEncoderI8{}, src, w, h),
+        PixelFormat::IA4 => encode_tiled(TiledEncoderIA4{}, src, w, h),
+        PixelFormat::IA8 => encode_tiled(TiledEncoderIA8{}, src, w, h),
+        PixelFormat::RGB565 => encode_tiled(TiledEncoderRGB565{}, src, w, h),
+        PixelFormat::RGB5A3 => encode_tiled(TiledEncoderRGB5A3{}, src, w, h),
+        PixelFormat::RGBA8 => encode_rgba8(src, w, h),
+        PixelFormat::CMPR => encode_cmpr(src, w, h),
         PixelFormat::C4 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
-            decode_tiled(TiledDecoderC4{ palette: &palette }, src, w, h)
+            let (palette, indices) = generate_palette(palette_fmt.unwrap(), src, 16);
+            let data = encode_tiled(TiledEncoderC4{ indices: &indices }, src, w, h);
+            return EncodedTexture { data, palette: Some(encode_palette(&palette)) };
         },
         PixelFormat::C8 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
-            decode_tiled(TiledDecoderC8{ palette: &palette }, src, w, h)
+            let (palette, indices) = generate_palette(palette_fmt.unwrap(), src, 256);
+            let data = encode_tiled(TiledEncoderC8{ indices: &indices }, src, w, h);
+            return EncodedTexture { data, palette: Some(encode_palette(&palette)) };
         },
         PixelFormat::C14X2 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
-            decode_tiled(TiledDecoderC14X2{ palette: &palette }, src, w, h)
+            let (palette, indices) = generate_palette(palette_fmt.unwrap(), src, 0x4000);
+            let data = encode_tiled(TiledEncoderC14X2{ indices: &indices }, src, w, h);
+            return EncodedTexture { data, palette: Some(encode_palette(&palette)) };
         },
+    };
+    EncodedTexture { data, palette: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+
+    fn gen_image(w: usize, h: usize, f: impl Fn(&mut Rng, usize, usize) -> [u8; 4]) -> Vec<u8> {
+        let mut rng = Rng::new();
+        let mut dst = Vec::with_capacity(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                dst.extend_from_slice(&f(&mut rng, x, y));
+            }
+        }
+        dst
+    }
+
+    fn roundtrip(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], w: usize, h: usize) -> Vec<u8> {
+        let encoded = encode_texture(fmt, palette_fmt, src, w, h);
+        let palette = encoded.palette.map(|p| p.into_boxed_slice());
+        decode_texture(fmt, palette_fmt, &encoded.data, palette, w, h)
+    }
+
+    // Largest difference in each channel.
+    fn max_error(a: &[u8], b: &[u8]) -> [u8; 4] {
+        assert_eq!(a.len(), b.len());
+        let mut err = [0; 4];
+        for (i, (&x, &y)) in a.iter().zip(b.iter()).enumerate() {
+            let d = (x as i32 - y as i32).unsigned_abs() as u8;
+            err[i % 4] = err[i % 4].max(d);
+        }
+        err
+    }
+
+    fn check(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], w: usize, h: usize, tolerance: [u8; 4]) {
+        let err = max_error(src, &roundtrip(fmt, palette_fmt, src, w, h));
+        for k in 0..4 {
+            assert!(err[k] <= tolerance[k], "{:?} {:?}: error {:?} exceeds {:?}", fmt, palette_fmt, err, tolerance);
+        }
+    }
+
+    fn random(rng: &mut Rng, _x: usize, _y: usize) -> [u8; 4] {
+        [rng.next_u8(), rng.next_u8(), rng.next_u8(), rng.next_u8()]
+    }
+
+    fn gray(rng: &mut Rng, _x: usize, _y: usize) -> [u8; 4] {
+        let i = rng.next_u8();
+        [i, i, i, i]
+    }
+
+    fn gray_alpha(rng: &mut Rng, _x: usize, _y: usize) -> [u8; 4] {
+        let i = rng.next_u8();
+        [i, i, i, rng.next_u8()]
+    }
+
+    fn opaque(rng: &mut Rng, _x: usize, _y: usize) -> [u8; 4] {
+        [rng.next_u8(), rng.next_u8(), rng.next_u8(), 0xFF]
+    }
+
+    #[test]
+    fn test_direct_formats() {
+        // Sizes that are and aren't multiples of the block size.
+        for &(w, h) in [(16, 8), (13, 7)].iter() {
+            check(PixelFormat::I4, None, &gen_image(w, h, gray), w, h, [8, 8, 8, 8]);
+            check(PixelFormat::I8, None, &gen_image(w, h, gray), w, h, [0, 0, 0, 0]);
+            check(PixelFormat::IA4, None, &gen_image(w, h, gray_alpha), w, h, [8, 8, 8, 8]);
+            check(PixelFormat::IA8, None, &gen_image(w, h, gray_alpha), w, h, [0, 0, 0, 0]);
+            check(PixelFormat::RGB565, None, &gen_image(w, h, opaque), w, h, [5, 3, 5, 0]);
+            check(PixelFormat::RGB5A3, None, &gen_image(w, h, opaque), w, h, [5, 5, 5, 0]);
+            check(PixelFormat::RGB5A3, None, &gen_image(w, h, random), w, h, [8, 8, 8, 19]);
+            check(PixelFormat::RGBA8, None, &gen_image(w, h, random), w, h, [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_cmpr() {
+        // Smooth gradients with a transparent hole.
+        let (w, h) = (36, 20);
+        let src = gen_image(w, h, |_, x, y| {
+            let a = if (10..20).contains(&x) && (5..9).contains(&y) { 0x00 } else { 0xFF };
+            [(x * 7) as u8, (y * 12) as u8, (255 - x * 3 - y * 4) as u8, a]
+        });
+        let dst = roundtrip(PixelFormat::CMPR, None, &src, w, h);
+
+        // The gradients are planar within each block, so four colors on a line can't match them
+        // exactly; blocks next to the hole only get three.
+        let mut squared_error = 0;
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            assert_eq!(s[3], d[3]);
+            if s[3] != 0 {
+                let err = max_error(s, d);
+                assert!(err.iter().all(|&e| e <= 24), "{:?} -> {:?}", s, d);
+                squared_error += err[..3].iter().map(|&e| (e as u32) * (e as u32)).sum::<u32>();
+            }
+        }
+        assert!(squared_error / ((w * h * 3) as u32) <= 32);
+
+        // A single-color block should come back exactly.
+        let src = gen_image(8, 8, |_, _, _| [0x84, 0x82, 0x84, 0xFF]);
+        check(PixelFormat::CMPR, None, &src, 8, 8, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_palette_exact() {
+        // Colors the palette format can hold exactly, and few enough to fit.
+        for &(fmt, num_colors) in [(PixelFormat::C4, 16), (PixelFormat::C8, 200), (PixelFormat::C14X2, 1000)].iter() {
+            for &palette_fmt in [PaletteFormat::IA8, PaletteFormat::RGB565, PaletteFormat::RGB5A3].iter() {
+                let colors: Vec<[u8; 4]> = gen_image(num_colors, 1, random).chunks_exact(4)
+                    .map(|px| decode_palette_color(palette_fmt, encode_palette_color(palette_fmt, px)))
+                    .collect();
+                let src = gen_image(40, 25, |rng, x, y| colors[(x + y * 40 + rng.next_u8() as usize) % colors.len()]);
+
+                let encoded = encode_texture(fmt, Some(palette_fmt), &src, 40, 25);
+                assert!(encoded.palette.as_ref().unwrap().len() <= num_colors * 2);
+                check(fmt, Some(palette_fmt), &src, 40, 25, [0, 0, 0, 0]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_palette_reduced() {
+        // Many more colors than fit; the palette should still be a reasonable fit.
+        let (w, h) = (32, 32);
+        let src = gen_image(w, h, |rng, x, y| [(x * 8) as u8, (y * 8) as u8, rng.next_u8() & 0x1F, 0xFF]);
+        for &(fmt, num_colors, max_mean_error) in [(PixelFormat::C4, 16, 40), (PixelFormat::C8, 256, 12)].iter() {
+            let encoded = encode_texture(fmt, Some(PaletteFormat::RGB565), &src, w, h);
+            assert_eq!(encoded.palette.as_ref().unwrap().len(), num_colors * 2);
+
+            let dst = roundtrip(fmt, Some(PaletteFormat::RGB565), &src, w, h);
+            let total_error: u32 = src.iter().zip(dst.iter()).map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs()).sum();
+            let mean_error = total_error / (w * h * 3) as u32;
+            assert!(mean_error <= max_mean_error, "{:?}: mean error {}", fmt, mean_error);
+        }
+    }
+
+    #[test]
+    fn test_mip_offsets() {
+        assert_eq!(calc_texture_size(PixelFormat::CMPR, 64, 64), 0x800);
+        assert_eq!(calc_texture_size(PixelFormat::I4, 13, 7), 0x40);
+        assert_eq!(calc_texture_size(PixelFormat::RGBA8, 13, 7), 0x200);
+        // Below the block size, every level still takes a whole block.
+        let offsets: Vec<usize> = (0..8).map(|i| calc_mip_level_offset(PixelFormat::CMPR, 64, 64, i)).collect();
+        assert_eq!(offsets, vec![0x000, 0x800, 0xA00, 0xA80, 0xAA0, 0xAC0, 0xAE0, 0xB00]);
+        assert_eq!(calc_mip_level_size(64, 16, 5), (2, 1));
+        assert_eq!(calc_mip_level_offset(PixelFormat::C14X2, 20, 12, 2), 20 * 12 * 2 + 12 * 8 * 2);
+    }
+
+    #[test]
+    fn test_mip_chain() {
+        let (w, h) = (20, 12);
+        let src = gen_image(w, h, random);
+        for &(fmt, palette_fmt) in [
+            (PixelFormat::I4, None),
+            (PixelFormat::RGBA8, None),
+            (PixelFormat::CMPR, None),
+            (PixelFormat::C8, Some(PaletteFormat::RGB5A3)),
+        ].iter() {
+            // Each level is a crop of the source; the contents don't matter here.
+            let mip_count = 5;
+            let mut data = Vec::new();
+            let mut palette = None;
+            for level in 0..mip_count {
+                let (mw, mh) = calc_mip_level_size(w, h, level);
+                assert_eq!(data.len(), calc_mip_level_offset(fmt, w, h, level));
+                let level_src: Vec<u8> = (0..mh).flat_map(|y| src[y * w * 4..(y * w + mw) * 4].to_vec()).collect();
+                let encoded = encode_texture(fmt, palette_fmt, &level_src, mw, mh);
+                assert_eq!(encoded.data.len(), calc_texture_size(fmt, mw, mh));
+                data.extend_from_slice(&encoded.data);
+                palette = palette.or(encoded.palette);
+            }
+            // All levels share level 0's palette; decoding only has to be consistent.
+            let palette = palette.map(|p| p.into_boxed_slice());
+
+            let levels = decode_texture_mips(fmt, palette_fmt, &data, palette.as_deref(), w, h, mip_count);
+            assert_eq!(levels.len(), mip_count);
+            for (level, dst) in levels.iter().enumerate() {
+                let (mw, mh) = calc_mip_level_size(w, h, level);
+                assert_eq!(dst.len(), mw * mh * 4);
+                assert_eq!(*dst, decode_texture_level(fmt, palette_fmt, &data, palette.clone(), w, h, level), "{:?} level {}", fmt, level);
+            }
+
+            // A truncated chain drops the levels that don't fit.
+            let short = &data[..calc_mip_level_offset(fmt, w, h, 3) + 1];
+            assert_eq!(decode_texture_mips(fmt, palette_fmt, short, palette.as_deref(), w, h, mip_count).len(), 3);
+            assert_eq!(decode_texture_level(fmt, palette_fmt, short, palette.clone(), w, h, 2), levels[2]);
+            assert_eq!(decode_texture_level(fmt, palette_fmt, short, palette.clone(), w, h, 3), Vec::<u8>::new());
+            assert_eq!(decode_texture_level(fmt, palette_fmt, &data[..1], palette.clone(), w, h, 0), Vec::<u8>::new());
+
+            // Levels past the end of the chain are empty rather than panicking.
+            let chain = decode_texture_mip_chain(fmt, palette_fmt, short, palette.clone(), w, h, mip_count);
+            assert_eq!(chain.mip_count(), 3);
+            assert_eq!(chain.level_data(2), levels[2]);
+            assert_eq!(chain.level_data(3), Vec::<u8>::new());
+            assert_eq!((chain.level_width(usize::MAX), chain.level_height(usize::MAX)), (1, 1));
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let (w, h) = (12, 10);
+        let src = gen_image(w, h, random);
+        let encoded = encode_texture(PixelFormat::RGB565, None, &src, w, h);
+        let data = &encoded.data;
+        assert_eq!(try_decode_texture(PixelFormat::RGB565, None, data, None, w, h).unwrap(), decode_texture(PixelFormat::RGB565, None, data, None, w, h));
+
+        assert_eq!(try_decode_texture(PixelFormat::RGB565, None, data, None, 0, h), Err(TextureError::InvalidDimensions { width: 0, height: h }));
+        assert_eq!(try_decode_texture(PixelFormat::RGB565, None, data, None, w, 2048), Err(TextureError::InvalidDimensions { width: w, height: 2048 }));
+        assert_eq!(try_decode_texture(PixelFormat::RGB565, None, &data[..data.len() - 1], None, w, h), Err(TextureError::SourceTooShort { expected: 12 * 12 * 2, actual: 12 * 12 * 2 - 1 }));
+        assert_eq!(try_decode_texture(PixelFormat::RGBA8, None, data, None, w, h), Err(TextureError::SourceTooShort { expected: 12 * 12 * 4, actual: 12 * 12 * 2 }));
+
+        // The second level of a 24x20 chain starts after 0x3C0 bytes of level 0.
+        assert_eq!(try_decode_texture_level(PixelFormat::RGB565, None, data, None, w * 2, h * 2, 1), Err(TextureError::SourceTooShort { expected: 0x3C0 + 0x120, actual: 0x120 }));
+        let mut chain = vec![0; calc_texture_size(PixelFormat::RGB565, w * 2, h * 2)];
+        chain.extend_from_slice(data);
+        assert_eq!(try_decode_texture_level(PixelFormat::RGB565, None, &chain, None, w * 2, h * 2, 1).unwrap(), decode_texture(PixelFormat::RGB565, None, data, None, w, h));
+    }
+
+    #[test]
+    fn test_validation_palette() {
+        let (w, h) = (12, 10);
+        let src = gen_image(w, h, |_rng, x, y| [(x * 20) as u8, (y * 20) as u8, 0x80, 0xFF]);
+        let encoded = encode_texture(PixelFormat::C8, Some(PaletteFormat::RGB565), &src, w, h);
+        let palette = encoded.palette.unwrap();
+        let num_colors = palette.len() / 2;
+        assert_eq!(num_colors, w * h);
+        let fmt = PixelFormat::C8;
+        let palette_fmt = Some(PaletteFormat::RGB565);
+
+        assert!(try_decode_texture(fmt, palette_fmt, &encoded.data, Some(&palette), w, h).is_ok());
+        assert_eq!(try_decode_texture(fmt, palette_fmt, &encoded.data, None, w, h), Err(TextureError::MissingPalette));
+        assert_eq!(try_decode_texture(fmt, None, &encoded.data, Some(&palette), w, h), Err(TextureError::MissingPalette));
+        assert_eq!(try_decode_texture(fmt, palette_fmt, &encoded.data, Some(&palette[..palette.len() - 2]), w, h), Err(TextureError::PaletteTooShort { needed: num_colors, actual: num_colors - 1 }));
+
+        // Indices in the padding past the edge of the image aren't used.
+        let mut data = encoded.data.clone();
+        data[8 * 4 + 4] = 0xFF;
+        assert!(try_decode_texture(fmt, palette_fmt, &data, Some(&palette), w, h).is_ok());
+        data[4] = 0xFF;
+        assert_eq!(try_decode_texture(fmt, palette_fmt, &data, Some(&palette), w, h), Err(TextureError::PaletteTooShort { needed: 0x100, actual: num_colors }));
     }
 }
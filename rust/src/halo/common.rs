@@ -3,6 +3,8 @@ use byteorder::{ReadBytesExt, LittleEndian};
 use num_enum::{TryFromPrimitive, TryFromPrimitiveError};
 use wasm_bindgen::prelude::*;
 
+use crate::compression::DecompressionError;
+
 pub type Result<T> = std::result::Result<T, MapReaderError>;
 pub type Pointer = u32;
 
@@ -11,6 +13,13 @@ pub enum MapReaderError {
     IO(String),
     UnimplementedTag(String),
     InvalidTag(String),
+    Decompression(DecompressionError),
+}
+
+impl From<DecompressionError> for MapReaderError {
+    fn from(err: DecompressionError) -> Self {
+        MapReaderError::Decompression(err)
+    }
 }
 
 impl From<std::io::Error> for MapReaderError {
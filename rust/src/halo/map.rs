@@ -1,6 +1,7 @@
 use std::{io::{Cursor, Seek, SeekFrom, Read}, convert::{TryFrom}};
 use num_enum::{TryFromPrimitive};
 
+use crate::lzx;
 use crate::halo::common::*;
 use crate::halo::util::*;
 use crate::halo::tag::*;
@@ -51,6 +52,12 @@ impl MapManager {
         Ok(buf)
     }
 
+    // Reads an XMemCompress-compressed (LZX) region of the map.
+    pub fn read_map_bytes_xmem(&mut self, offset: u64, size: usize, uncompressed_size: usize, window_bits: u32) -> Result<Vec<u8>> {
+        let buf = self.read_map_bytes(offset, size)?;
+        Ok(lzx::decompress_xmem(&buf, window_bits, uncompressed_size)?)
+    }
+
     pub fn read_map_u16s(&mut self, offset: u64, length: usize) -> Result<Vec<u16>> {
         self.reader.data.seek(SeekFrom::Start(offset))?;
         let mut buf = vec![0; length];
@@ -9,6 +9,7 @@ use crate::halo::bitmap::*;
 use crate::halo::bitmap_utils::*;
 use crate::halo::tag::*;
 use crate::halo::model::*;
+use crate::bc_texture::{self, BcFormat};
 
 #[wasm_bindgen]
 pub struct HaloSceneManager {
@@ -31,6 +32,11 @@ impl HaloBitmapReader {
         get_and_convert_bitmap_data(self.inner.data.get_ref(), bitmap_data)
     }
 
+    pub fn get_and_decompress_bitmap_data(&mut self, bitmap: &HaloBitmap, submap: usize) -> Vec<u8> {
+        let bitmap_data = &bitmap.inner.data.items.as_ref().unwrap()[submap];
+        get_and_decompress_bitmap_data(self.inner.data.get_ref(), bitmap_data)
+    }
+
     pub fn destroy(self) {}
 }
 
@@ -49,6 +55,26 @@ fn get_and_convert_bitmap_data(bytes: &[u8], bitmap_data: &BitmapData) -> Vec<u8
     }
 }
 
+// Like get_and_convert_bitmap_data, but DXT bitmaps are decompressed to RGBA8, keeping the
+// same level-major, face-minor layout.
+fn get_and_decompress_bitmap_data(bytes: &[u8], bitmap_data: &BitmapData) -> Vec<u8> {
+    let format = match bitmap_data.format {
+        BitmapFormat::Dxt1 => BcFormat::Bc1,
+        BitmapFormat::Dxt3 => BcFormat::Bc2,
+        BitmapFormat::Dxt5 => BcFormat::Bc3,
+        _ => return get_and_convert_bitmap_data(bytes, bitmap_data),
+    };
+    let offset = bitmap_data.pixel_data_offset as usize;
+    let length = bitmap_data.pixel_data_size as usize;
+    let byte_range = &bytes[offset..offset+length];
+    let num_faces = match bitmap_data.bitmap_type {
+        BitmapDataType::CubeMap => 6,
+        _ => 1,
+    };
+    let mip_count = (bitmap_data.mipmap_count as usize).max(1);
+    bc_texture::decode_surface_mips(byte_range, format, bitmap_data.width as usize, bitmap_data.height as usize, num_faces, mip_count)
+}
+
 #[wasm_bindgen]
 #[derive(Debug, Clone)]
 pub struct HaloSky {
@@ -834,6 +860,11 @@ impl HaloSceneManager {
         get_and_convert_bitmap_data(self.mgr.reader.data.get_ref(), bitmap_data)
     }
 
+    pub fn get_and_decompress_bitmap_data(&mut self, bitmap: &HaloBitmap, submap: usize) -> Vec<u8> {
+        let bitmap_data = &bitmap.inner.data.items.as_ref().unwrap()[submap];
+        get_and_decompress_bitmap_data(self.mgr.reader.data.get_ref(), bitmap_data)
+    }
+
     pub fn get_material_vertex_data(&mut self, material: &HaloMaterial, bsp: &HaloBSP) -> Vec<u8> {
         let offset = bsp.inner.header.as_ref().unwrap().rendered_vertices_offset + material.inner.rendered_vertices.base_pointer;
         let count = material.inner.rendered_vertices.count;
@@ -5,10 +5,28 @@
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+pub mod astc;
+pub mod bc_texture;
+pub mod compression;
+pub mod cx;
+pub mod deflate;
+pub mod etc_texture;
+pub mod gs_texture;
 pub mod gx_texture;
+pub mod gx2_texture;
+pub mod mio0;
+pub mod n64_texture;
 pub mod glsl_compile;
+pub mod lz4;
+pub mod lzma;
+pub mod lzo;
+pub mod lzss;
+pub mod lzx;
 pub mod tegra_texture;
 pub mod util;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub mod unity;
+pub mod yay0;
 pub mod yaz0;
 pub mod halo;
\ No newline at end of file
@@ -0,0 +1,346 @@
+// LZ4 block and frame formats.
+//
+// https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+// https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md
+//
+// Block:
+//   Sequence of:
+//     Token (1 byte): literal length in the high nibble, match length in the low nibble.
+//       A nibble of 15 is followed by extra bytes that are added on until one is not 255.
+//     Literals.
+//     Offset (2 bytes, little endian), then the match length extension.
+//       Copy match length + 4 bytes from Offset back in the output buffer.
+//   The last sequence has only literals.
+//
+// Frame:
+//   Magic: 0x184D2204 (4 bytes, little endian)
+//   Frame descriptor: FLG, BD, optional content size and dictionary ID, header checksum.
+//   Blocks, each with a 4-byte size (top bit set for stored blocks) and an optional checksum.
+//   End mark (4 zero bytes), then an optional checksum of the whole content.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, read_u8};
+
+const FRAME_MAGIC: u32 = 0x184D2204;
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A50;
+const SKIPPABLE_FRAME_MASK: u32 = 0xFFFFFFF0;
+
+fn read_u32_le(src: &[u8], src_offs: &mut usize) -> Result<u32> {
+    let bytes = src.get(*src_offs..*src_offs + 4).ok_or(DecompressionError::Truncated)?;
+    *src_offs += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_slice<'a>(src: &'a [u8], src_offs: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let v = src.get(*src_offs..*src_offs + len).ok_or(DecompressionError::Truncated)?;
+    *src_offs += len;
+    Ok(v)
+}
+
+fn read_length_extension(src: &[u8], src_offs: &mut usize, mut length: usize) -> Result<usize> {
+    loop {
+        let b = read_u8(src, src_offs)?;
+        length += b as usize;
+        if b != 0xFF {
+            return Ok(length);
+        }
+    }
+}
+
+// Decodes one block, appending to dst. Back-references may reach back as far
+// as window_start, which lets linked blocks in a frame refer to earlier ones.
+fn decode_block_into(src: &[u8], dst: &mut Vec<u8>, window_start: usize, max_size: usize) -> Result<()> {
+    let mut src_offs = 0;
+    loop {
+        let token = read_u8(src, &mut src_offs)?;
+
+        let mut literal_length = (token >> 4) as usize;
+        if literal_length == 0x0F {
+            literal_length = read_length_extension(src, &mut src_offs, literal_length)?;
+        }
+
+        if dst.len() + literal_length > max_size {
+            return Err(DecompressionError::OutputOverrun);
+        }
+        dst.extend_from_slice(read_slice(src, &mut src_offs, literal_length)?);
+
+        // The last sequence has no match.
+        if src_offs == src.len() {
+            return Ok(());
+        }
+
+        let offset = (read_u8(src, &mut src_offs)? as usize) | ((read_u8(src, &mut src_offs)? as usize) << 8);
+
+        let mut match_length = (token & 0x0F) as usize;
+        if match_length == 0x0F {
+            match_length = read_length_extension(src, &mut src_offs, match_length)?;
+        }
+        match_length += 4;
+
+        let dst_offs = dst.len();
+        if offset == 0 || offset > dst_offs - window_start {
+            return Err(DecompressionError::InvalidBackReference { dst_offs, distance: offset });
+        }
+
+        if dst_offs + match_length > max_size {
+            return Err(DecompressionError::OutputOverrun);
+        }
+
+        let copy_offs = dst_offs - offset;
+        for i in 0..match_length {
+            let v = dst[copy_offs + i];
+            dst.push(v);
+        }
+    }
+}
+
+// Decompresses a raw LZ4 block with a known uncompressed size.
+pub fn decompress_block(src: &[u8], uncompressed_size: usize) -> Result<Vec<u8>> {
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    decode_block_into(src, &mut dst, 0, uncompressed_size)?;
+
+    if dst.len() != uncompressed_size {
+        return Err(DecompressionError::Truncated);
+    }
+
+    Ok(dst)
+}
+
+// Decompresses one or more concatenated LZ4 frames. Skippable frames are
+// ignored. If verify_checksums is set, the header, block and content
+// checksums are checked when present.
+pub fn decompress_frame(src: &[u8], verify_checksums: bool) -> Result<Vec<u8>> {
+    let mut dst = Vec::new();
+    let mut src_offs = 0;
+
+    loop {
+        let magic = read_u32_le(src, &mut src_offs)?;
+
+        if (magic & SKIPPABLE_FRAME_MASK) == SKIPPABLE_FRAME_MAGIC {
+            let size = read_u32_le(src, &mut src_offs)? as usize;
+            read_slice(src, &mut src_offs, size)?;
+        } else if magic == FRAME_MAGIC {
+            decode_frame_into(src, &mut src_offs, &mut dst, verify_checksums)?;
+        } else {
+            return Err(DecompressionError::BadMagic);
+        }
+
+        if src_offs == src.len() {
+            return Ok(dst);
+        }
+    }
+}
+
+fn decode_frame_into(src: &[u8], src_offs: &mut usize, dst: &mut Vec<u8>, verify_checksums: bool) -> Result<()> {
+    let descriptor_offs = *src_offs;
+    let flg = read_u8(src, src_offs)?;
+    let bd = read_u8(src, src_offs)?;
+
+    let version = flg >> 6;
+    let block_independence = (flg & 0x20) != 0;
+    let has_block_checksum = (flg & 0x10) != 0;
+    let has_content_size = (flg & 0x08) != 0;
+    let has_content_checksum = (flg & 0x04) != 0;
+    let has_dictionary_id = (flg & 0x01) != 0;
+
+    if version != 0x01 || (flg & 0x02) != 0 || (bd & 0x8F) != 0 {
+        return Err(DecompressionError::InvalidHeader);
+    }
+
+    let block_max_size = match (bd >> 4) & 0x07 {
+        4 => 0x10000,
+        5 => 0x40000,
+        6 => 0x100000,
+        7 => 0x400000,
+        _ => return Err(DecompressionError::InvalidHeader),
+    };
+
+    let content_size = if has_content_size {
+        let lo = read_u32_le(src, src_offs)? as u64;
+        let hi = read_u32_le(src, src_offs)? as u64;
+        Some((hi << 32) | lo)
+    } else {
+        None
+    };
+
+    // Preset dictionaries are not supported; any back-reference into one will
+    // fail as an invalid back-reference.
+    if has_dictionary_id {
+        read_u32_le(src, src_offs)?;
+    }
+
+    let header_checksum = read_u8(src, src_offs)?;
+    if verify_checksums && ((xxh32(&src[descriptor_offs..*src_offs - 1], 0) >> 8) as u8) != header_checksum {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    let frame_start = dst.len();
+    loop {
+        let block_header = read_u32_le(src, src_offs)?;
+        if block_header == 0 {
+            break;
+        }
+
+        let is_stored = (block_header & 0x80000000) != 0;
+        let block_size = (block_header & 0x7FFFFFFF) as usize;
+        if block_size > block_max_size {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        let block = read_slice(src, src_offs, block_size)?;
+
+        if has_block_checksum {
+            let checksum = read_u32_le(src, src_offs)?;
+            if verify_checksums && xxh32(block, 0) != checksum {
+                return Err(DecompressionError::ChecksumMismatch);
+            }
+        }
+
+        if is_stored {
+            dst.extend_from_slice(block);
+        } else {
+            let window_start = if block_independence { dst.len() } else { frame_start };
+            decode_block_into(block, dst, window_start, dst.len() + block_max_size)?;
+        }
+    }
+
+    if let Some(content_size) = content_size {
+        if (dst.len() - frame_start) as u64 != content_size {
+            return Err(DecompressionError::Truncated);
+        }
+    }
+
+    if has_content_checksum {
+        let checksum = read_u32_le(src, src_offs)?;
+        if verify_checksums && xxh32(&dst[frame_start..], 0) != checksum {
+            return Err(DecompressionError::ChecksumMismatch);
+        }
+    }
+
+    Ok(())
+}
+
+// xxHash32, used for all LZ4 frame checksums.
+const PRIME32_1: u32 = 0x9E3779B1;
+const PRIME32_2: u32 = 0x85EBCA77;
+const PRIME32_3: u32 = 0xC2B2AE3D;
+const PRIME32_4: u32 = 0x27D4EB2F;
+const PRIME32_5: u32 = 0x165667B1;
+
+fn xxh32_round(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME32_2)).rotate_left(13).wrapping_mul(PRIME32_1)
+}
+
+fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let get_u32 = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+    let mut i = 0;
+    let mut h = if data.len() >= 16 {
+        let mut v1 = seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2);
+        let mut v2 = seed.wrapping_add(PRIME32_2);
+        let mut v3 = seed;
+        let mut v4 = seed.wrapping_sub(PRIME32_1);
+        while i + 16 <= data.len() {
+            v1 = xxh32_round(v1, get_u32(i));
+            v2 = xxh32_round(v2, get_u32(i + 4));
+            v3 = xxh32_round(v3, get_u32(i + 8));
+            v4 = xxh32_round(v4, get_u32(i + 12));
+            i += 16;
+        }
+        v1.rotate_left(1).wrapping_add(v2.rotate_left(7)).wrapping_add(v3.rotate_left(12)).wrapping_add(v4.rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+
+    h = h.wrapping_add(data.len() as u32);
+
+    while i + 4 <= data.len() {
+        h = h.wrapping_add(get_u32(i).wrapping_mul(PRIME32_3)).rotate_left(17).wrapping_mul(PRIME32_4);
+        i += 4;
+    }
+
+    while i < data.len() {
+        h = h.wrapping_add((data[i] as u32).wrapping_mul(PRIME32_5)).rotate_left(11).wrapping_mul(PRIME32_1);
+        i += 1;
+    }
+
+    h ^= h >> 15;
+    h = h.wrapping_mul(PRIME32_2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(PRIME32_3);
+    h ^= h >> 16;
+    h
+}
+
+#[wasm_bindgen]
+pub fn lz4dec_block(src: &[u8], uncompressed_size: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_block(src, uncompressed_size)?)
+}
+
+#[wasm_bindgen]
+pub fn lz4dec_frame(src: &[u8], verify_checksums: bool) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_frame(src, verify_checksums)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_xxh32() {
+        assert_eq!(xxh32(b"", 0), 0x02CC5D05);
+        assert_eq!(xxh32(b"a", 0), 0x550D7456);
+        assert_eq!(xxh32(b"abc", 0), 0x32D153FF);
+    }
+
+    #[test]
+    fn test_block() {
+        // "abcd" literals, then a 9-byte match at offset 4, then "e".
+        let src = b"\x45abcd\x04\x00\x10e";
+        assert_eq!(decompress_block(src, 14).unwrap(), b"abcdabcdabcdae");
+        assert_eq!(decompress_block(src, 13), Err(DecompressionError::OutputOverrun));
+        assert_eq!(decompress_block(src, 15), Err(DecompressionError::Truncated));
+        assert_eq!(decompress_block(&src[..7], 14), Err(DecompressionError::Truncated));
+        assert_eq!(decompress_block(b"\x40abcd\x05\x00\x10e", 14), Err(DecompressionError::InvalidBackReference { dst_offs: 4, distance: 5 }));
+    }
+
+    #[test]
+    fn test_frame_linked_blocks() {
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text.txt.lz4");
+        assert_eq!(decompress_frame(&src, true).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_frame_independent_blocks() {
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text_nocrc.txt.lz4");
+        assert_eq!(decompress_frame(&src, true).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_frame_checksum() {
+        let mut src = read_test_file("text.txt.lz4");
+        let len = src.len();
+        src[len - 1] ^= 0xFF;
+        assert_eq!(decompress_frame(&src, true), Err(DecompressionError::ChecksumMismatch));
+        assert_eq!(decompress_frame(&src, false).unwrap(), read_test_file("text.txt"));
+    }
+
+    #[test]
+    fn test_frame_concatenated() {
+        let src = read_test_file("text_nocrc.txt.lz4");
+        let mut doubled = src.clone();
+        // Skippable frame in between.
+        doubled.extend_from_slice(&[0x5A, 0x2A, 0x4D, 0x18, 0x02, 0x00, 0x00, 0x00, 0xAA, 0xBB]);
+        doubled.extend_from_slice(&src);
+        let expected = read_test_file("text.txt");
+        assert_eq!(decompress_frame(&doubled, true).unwrap(), [&expected[..], &expected[..]].concat());
+    }
+}
@@ -0,0 +1,534 @@
+// LZMA and LZMA2.
+//
+// References:
+// - "LZMA SDK" by Igor Pavlov, in particular the reference decoder in LzmaSpec.cpp
+//   http://www.7-zip.org/sdk.html
+// - "The .lzma File Format" from xz documentation
+//   https://github.com/joachimmetz/xz/blob/master/doc/lzma-file-format.txt
+//
+// The whole output is kept in memory, so back-references are resolved
+// directly against it rather than through a separate dictionary buffer.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use std::convert::TryFrom;
+
+use crate::compression::{DecompressionError, Result, check_uncompressed_size, read_u8};
+
+const NUM_STATES: usize = 12;
+const NUM_POS_BITS_MAX: usize = 4;
+const NUM_LEN_TO_POS_STATES: usize = 4;
+const NUM_ALIGN_BITS: usize = 4;
+const START_POS_MODEL_INDEX: u32 = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const NUM_FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+
+const PROB_INIT: u16 = 0x400;
+
+// A bit can narrow the range by at most 31/2048, so each input byte codes at
+// most ~360 bits. Spent on 14-bit rep0 matches of 273 bytes each, that's a
+// little over 7000 output bytes per input byte.
+const MAX_RATIO: usize = 0x2000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LzmaProperties {
+    pub lc: u32,
+    pub lp: u32,
+    pub pb: u32,
+}
+
+impl LzmaProperties {
+    // Unpacks the properties byte: (pb * 5 + lp) * 9 + lc.
+    pub fn from_byte(v: u8) -> Result<LzmaProperties> {
+        if v >= 9 * 5 * 5 {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        let v = v as u32;
+        Ok(LzmaProperties { lc: v % 9, lp: (v / 9) % 5, pb: v / 45 })
+    }
+}
+
+struct RangeDecoder<'a> {
+    src: &'a [u8],
+    src_offs: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(src: &'a [u8]) -> Result<Self> {
+        let mut src_offs = 0;
+        if read_u8(src, &mut src_offs)? != 0 {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        let mut code = 0;
+        for _ in 0..4 {
+            code = (code << 8) | (read_u8(src, &mut src_offs)? as u32);
+        }
+
+        let range = 0xFFFFFFFF;
+        if code == range {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        Ok(RangeDecoder { src, src_offs, range, code })
+    }
+
+    fn normalize(&mut self) -> Result<()> {
+        if self.range < (1 << 24) {
+            self.range <<= 8;
+            self.code = (self.code << 8) | (read_u8(self.src, &mut self.src_offs)? as u32);
+        }
+        Ok(())
+    }
+
+    fn decode_bit(&mut self, prob: &mut u16) -> Result<u32> {
+        let bound = (self.range >> 11) * (*prob as u32);
+        let bit = if self.code < bound {
+            *prob += (0x800 - *prob) >> 5;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> 5;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    fn decode_direct_bits(&mut self, num_bits: u32) -> Result<u32> {
+        let mut res = 0u32;
+        for _ in 0..num_bits {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let t = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & t);
+            self.normalize()?;
+            res = (res << 1).wrapping_add(t.wrapping_add(1));
+        }
+        Ok(res)
+    }
+
+    fn decode_bit_tree(&mut self, probs: &mut [u16], num_bits: u32) -> Result<u32> {
+        let mut m = 1;
+        for _ in 0..num_bits {
+            m = (m << 1) + self.decode_bit(&mut probs[m as usize])?;
+        }
+        Ok(m - (1 << num_bits))
+    }
+
+    fn decode_bit_tree_reverse(&mut self, probs: &mut [u16], num_bits: u32) -> Result<u32> {
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..num_bits {
+            let bit = self.decode_bit(&mut probs[m as usize])?;
+            m = (m << 1) + bit;
+            symbol |= bit << i;
+        }
+        Ok(symbol)
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    mid: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> Self {
+        LenDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            mid: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> Result<usize> {
+        if rc.decode_bit(&mut self.choice)? == 0 {
+            return Ok(rc.decode_bit_tree(&mut self.low[pos_state], 3)? as usize);
+        }
+        if rc.decode_bit(&mut self.choice2)? == 0 {
+            return Ok(8 + rc.decode_bit_tree(&mut self.mid[pos_state], 3)? as usize);
+        }
+        Ok(16 + rc.decode_bit_tree(&mut self.high, 8)? as usize)
+    }
+}
+
+struct LzmaDecoder {
+    props: LzmaProperties,
+    literal_probs: Vec<u16>,
+    pos_slot: [[u16; 1 << 6]; NUM_LEN_TO_POS_STATES],
+    pos_decoders: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << NUM_ALIGN_BITS],
+    is_match: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    is_rep: [u16; NUM_STATES],
+    is_rep_g0: [u16; NUM_STATES],
+    is_rep_g1: [u16; NUM_STATES],
+    is_rep_g2: [u16; NUM_STATES],
+    is_rep0_long: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    len_decoder: LenDecoder,
+    rep_len_decoder: LenDecoder,
+    state: usize,
+    reps: [usize; 4],
+}
+
+impl LzmaDecoder {
+    fn new(props: LzmaProperties) -> Self {
+        LzmaDecoder {
+            props,
+            literal_probs: vec![PROB_INIT; 0x300 << (props.lc + props.lp)],
+            pos_slot: [[PROB_INIT; 1 << 6]; NUM_LEN_TO_POS_STATES],
+            pos_decoders: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROB_INIT; 1 << NUM_ALIGN_BITS],
+            is_match: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            len_decoder: LenDecoder::new(),
+            rep_len_decoder: LenDecoder::new(),
+            state: 0,
+            reps: [0; 4],
+        }
+    }
+
+    fn decode_literal(&mut self, rc: &mut RangeDecoder, dst: &mut Vec<u8>, dict_start: usize) -> Result<()> {
+        let pos = dst.len();
+        let prev_byte = if pos > dict_start { dst[pos - 1] as usize } else { 0 };
+        let lc = self.props.lc;
+        let lp_mask = (1 << self.props.lp) - 1;
+        let lit_state = ((pos & lp_mask) << lc) + (prev_byte >> (8 - lc));
+        let probs = &mut self.literal_probs[0x300 * lit_state..0x300 * (lit_state + 1)];
+
+        let mut symbol = 1;
+        if self.state >= 7 {
+            // Matched literal: use the byte at rep0 as context until the first mismatching bit.
+            let mut match_byte = dst[pos - self.reps[0] - 1] as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = rc.decode_bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                symbol = (symbol << 1) | bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | rc.decode_bit(&mut probs[symbol])? as usize;
+        }
+
+        dst.push((symbol - 0x100) as u8);
+        Ok(())
+    }
+
+    fn decode_distance(&mut self, rc: &mut RangeDecoder, len: usize) -> Result<u32> {
+        let len_state = len.min(NUM_LEN_TO_POS_STATES - 1);
+        let pos_slot = rc.decode_bit_tree(&mut self.pos_slot[len_state], 6)?;
+        if pos_slot < START_POS_MODEL_INDEX {
+            return Ok(pos_slot);
+        }
+
+        let num_direct_bits = (pos_slot >> 1) - 1;
+        let mut dist = (2 | (pos_slot & 1)) << num_direct_bits;
+        if pos_slot < END_POS_MODEL_INDEX {
+            let probs = &mut self.pos_decoders[(dist - pos_slot) as usize..];
+            dist += rc.decode_bit_tree_reverse(probs, num_direct_bits)?;
+        } else {
+            dist += rc.decode_direct_bits(num_direct_bits - NUM_ALIGN_BITS as u32)? << NUM_ALIGN_BITS;
+            dist += rc.decode_bit_tree_reverse(&mut self.align, NUM_ALIGN_BITS as u32)?;
+        }
+        Ok(dist)
+    }
+
+    // Decodes until dst reaches size, or until the end marker if size is None.
+    // Back-references may not reach before dict_start.
+    fn decode(&mut self, rc: &mut RangeDecoder, dst: &mut Vec<u8>, dict_start: usize, dict_size: usize, size: Option<usize>) -> Result<()> {
+        let pb_mask = (1 << self.props.pb) - 1;
+
+        loop {
+            if Some(dst.len()) == size {
+                return Ok(());
+            }
+
+            let pos_state = dst.len() & pb_mask;
+            let state = self.state;
+
+            if rc.decode_bit(&mut self.is_match[(state << NUM_POS_BITS_MAX) + pos_state])? == 0 {
+                self.decode_literal(rc, dst, dict_start)?;
+                self.state = if state < 4 { 0 } else if state < 10 { state - 3 } else { state - 6 };
+                continue;
+            }
+
+            let len;
+            if rc.decode_bit(&mut self.is_rep[state])? == 0 {
+                // Simple match.
+                len = self.len_decoder.decode(rc, pos_state)?;
+                self.state = if state < 7 { 7 } else { 10 };
+                let dist = self.decode_distance(rc, len)?;
+                if dist == 0xFFFFFFFF {
+                    // End marker.
+                    return match size {
+                        Some(_) => Err(DecompressionError::Truncated),
+                        None => Ok(()),
+                    };
+                }
+                self.reps = [dist as usize, self.reps[0], self.reps[1], self.reps[2]];
+            } else {
+                if dst.len() == dict_start {
+                    return Err(DecompressionError::InvalidBackReference { dst_offs: dst.len(), distance: self.reps[0] + 1 });
+                }
+
+                if rc.decode_bit(&mut self.is_rep_g0[state])? == 0 {
+                    if rc.decode_bit(&mut self.is_rep0_long[(state << NUM_POS_BITS_MAX) + pos_state])? == 0 {
+                        // Short rep: a single byte from rep0.
+                        self.state = if state < 7 { 9 } else { 11 };
+                        let v = dst[dst.len() - self.reps[0] - 1];
+                        dst.push(v);
+                        continue;
+                    }
+                } else {
+                    let dist;
+                    if rc.decode_bit(&mut self.is_rep_g1[state])? == 0 {
+                        dist = self.reps[1];
+                    } else {
+                        if rc.decode_bit(&mut self.is_rep_g2[state])? == 0 {
+                            dist = self.reps[2];
+                        } else {
+                            dist = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                        }
+                        self.reps[2] = self.reps[1];
+                    }
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = dist;
+                }
+
+                len = self.rep_len_decoder.decode(rc, pos_state)?;
+                self.state = if state < 7 { 8 } else { 11 };
+            }
+
+            let length = len + MATCH_MIN_LEN;
+            let distance = self.reps[0] + 1;
+            let dst_offs = dst.len();
+            if distance > dst_offs - dict_start || distance > dict_size {
+                return Err(DecompressionError::InvalidBackReference { dst_offs, distance });
+            }
+
+            if let Some(size) = size {
+                if dst_offs + length > size {
+                    return Err(DecompressionError::OutputOverrun);
+                }
+            }
+
+            let copy_offs = dst_offs - distance;
+            for i in 0..length {
+                let v = dst[copy_offs + i];
+                dst.push(v);
+            }
+        }
+    }
+}
+
+// Decompresses raw LZMA data with explicit properties and dictionary size. If
+// uncompressed_size is None, the data must end with an end marker.
+pub fn decompress_raw(src: &[u8], props: LzmaProperties, dict_size: u32, uncompressed_size: Option<usize>) -> Result<Vec<u8>> {
+    let mut decoder = LzmaDecoder::new(props);
+    let mut rc = RangeDecoder::new(src)?;
+    if let Some(size) = uncompressed_size {
+        check_uncompressed_size(size, src.len(), MAX_RATIO)?;
+    }
+    // The size may still be far more than this data decodes to, so let the
+    // output grow past a modest first guess rather than trusting it.
+    let capacity = uncompressed_size.map_or(src.len() * 4, |size| size.min(src.len().saturating_mul(64)));
+    let mut dst = Vec::with_capacity(capacity);
+    // As in the reference decoder, dictionary sizes below 4 KiB are treated as 4 KiB.
+    let dict_size = (dict_size as usize).max(0x1000);
+    decoder.decode(&mut rc, &mut dst, 0, dict_size, uncompressed_size)?;
+    Ok(dst)
+}
+
+// Decompresses the .lzma ("LZMA alone") format.
+//
+// Header (13 bytes):
+//   Properties (1 byte)
+//   Dictionary size (4 bytes, little endian)
+//   Uncompressed size (8 bytes, little endian), all ones if unknown
+pub fn decompress_alone(src: &[u8]) -> Result<Vec<u8>> {
+    if src.len() < 13 {
+        return Err(DecompressionError::Truncated);
+    }
+
+    let props = LzmaProperties::from_byte(src[0])?;
+    let dict_size = u32::from_le_bytes([src[1], src[2], src[3], src[4]]);
+    let mut size_bytes = [0x00; 8];
+    size_bytes.copy_from_slice(&src[5..13]);
+    let uncompressed_size = match u64::from_le_bytes(size_bytes) {
+        0xFFFFFFFFFFFFFFFF => None,
+        v => Some(usize::try_from(v).unwrap_or(usize::MAX)),
+    };
+
+    decompress_raw(&src[13..], props, dict_size, uncompressed_size)
+}
+
+// Decompresses a raw LZMA2 stream.
+//
+// Chunks, each starting with a control byte:
+//   0x00: End of stream.
+//   0x01: Uncompressed chunk, dictionary reset.
+//   0x02: Uncompressed chunk, no reset.
+//     Followed by the size - 1 (2 bytes, big endian) and the data.
+//   0x80-0xFF: LZMA chunk.
+//     Bits 0-4: Bits 16-20 of the uncompressed size - 1.
+//     Bits 5-6: 0 = no reset, 1 = state reset, 2 = state reset and new properties,
+//               3 = state reset, new properties and dictionary reset.
+//     Followed by the rest of the uncompressed size - 1 (2 bytes, big endian),
+//     the compressed size - 1 (2 bytes, big endian), the properties byte if
+//     they are new, and the range coded data.
+pub fn decompress_lzma2(src: &[u8]) -> Result<Vec<u8>> {
+    let mut dst = Vec::with_capacity(src.len() * 4);
+    let mut decoder: Option<LzmaDecoder> = None;
+    let mut dict_start = 0;
+    let mut src_offs = 0;
+
+    loop {
+        let control = read_u8(src, &mut src_offs)?;
+
+        if control == 0x00 {
+            return Ok(dst);
+        }
+
+        let read_size = |src_offs: &mut usize| -> Result<usize> {
+            let hi = read_u8(src, src_offs)? as usize;
+            let lo = read_u8(src, src_offs)? as usize;
+            Ok(((hi << 8) | lo) + 1)
+        };
+
+        if control == 0x01 || control == 0x02 {
+            if control == 0x01 {
+                dict_start = dst.len();
+            }
+
+            let size = read_size(&mut src_offs)?;
+            let data = src.get(src_offs..src_offs + size).ok_or(DecompressionError::Truncated)?;
+            dst.extend_from_slice(data);
+            src_offs += size;
+            continue;
+        }
+
+        if control < 0x80 {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        let unpacked_size = (((control & 0x1F) as usize) << 16) + read_size(&mut src_offs)?;
+        let packed_size = read_size(&mut src_offs)?;
+        let reset = (control >> 5) & 0x03;
+
+        if reset == 3 {
+            dict_start = dst.len();
+        }
+
+        if reset >= 2 {
+            let props = LzmaProperties::from_byte(read_u8(src, &mut src_offs)?)?;
+            if props.lc + props.lp > 4 {
+                return Err(DecompressionError::InvalidHeader);
+            }
+            decoder = Some(LzmaDecoder::new(props));
+        } else if reset == 1 {
+            let props = decoder.as_ref().ok_or(DecompressionError::InvalidHeader)?.props;
+            decoder = Some(LzmaDecoder::new(props));
+        }
+
+        let decoder = decoder.as_mut().ok_or(DecompressionError::InvalidHeader)?;
+        let packed = src.get(src_offs..src_offs + packed_size).ok_or(DecompressionError::Truncated)?;
+        src_offs += packed_size;
+
+        let mut rc = RangeDecoder::new(packed)?;
+        let size = dst.len() + unpacked_size;
+        decoder.decode(&mut rc, &mut dst, dict_start, usize::MAX, Some(size))?;
+    }
+}
+
+#[wasm_bindgen]
+pub fn lzmadec_raw(src: &[u8], props: u8, dict_size: u32, uncompressed_size: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_raw(src, LzmaProperties::from_byte(props)?, dict_size, Some(uncompressed_size))?)
+}
+
+#[wasm_bindgen]
+pub fn lzmadec_alone(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_alone(src)?)
+}
+
+#[wasm_bindgen]
+pub fn lzma2dec(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_lzma2(src)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_properties() {
+        assert_eq!(LzmaProperties::from_byte(0x5D).unwrap(), LzmaProperties { lc: 3, lp: 0, pb: 2 });
+        assert_eq!(LzmaProperties::from_byte(225), Err(DecompressionError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_alone_end_marker() {
+        let src = read_test_file("text.txt.lzma");
+        assert_eq!(decompress_alone(&src).unwrap(), read_test_file("text.txt"));
+    }
+
+    #[test]
+    fn test_raw_known_size() {
+        let src = read_test_file("text_lc0_lp2_pb0.txt.lzma1");
+        let expected = read_test_file("text.txt");
+        let props = LzmaProperties { lc: 0, lp: 2, pb: 0 };
+        assert_eq!(decompress_raw(&src, props, 0x10000, Some(expected.len())).unwrap(), expected);
+        assert_eq!(decompress_raw(&src, props, 0x10000, None).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_raw_truncated() {
+        let src = read_test_file("text.txt.lzma");
+        assert_eq!(decompress_alone(&src[..src.len() / 2]), Err(DecompressionError::Truncated));
+
+        // A header size the data couldn't possibly decode to.
+        let mut src = src;
+        src[5..13].copy_from_slice(&0xFFFFFFFFFFFFFFFEu64.to_le_bytes());
+        assert_eq!(decompress_alone(&src), Err(DecompressionError::Truncated));
+        src[5..13].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(decompress_alone(&src), Err(DecompressionError::Truncated));
+    }
+
+    #[test]
+    fn test_lzma2() {
+        // text.txt, then 0x20000 bytes of xorshift noise (stored as uncompressed chunks), then text.txt again.
+        let text = read_test_file("text.txt");
+        let mut expected = text.clone();
+        expected.extend_from_slice(&noise(0x20000));
+        expected.extend_from_slice(&text);
+
+        let src = read_test_file("text_noise.lzma2");
+        assert_eq!(decompress_lzma2(&src).unwrap(), expected);
+    }
+}
@@ -0,0 +1,258 @@
+// LZO1X decompressor ported from lzokay:
+// https://github.com/jackoalan/lzokay
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, read_u8};
+
+const M3_MARKER: u8 = 0x20;
+const M4_MARKER: u8 = 0x10;
+
+struct Decoder<'a> {
+    src: &'a [u8],
+    inp: usize,
+    dst: Vec<u8>,
+    max_dst_size: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn needs_in(&self, count: usize) -> Result<()> {
+        if self.inp + count > self.src.len() {
+            return Err(DecompressionError::Truncated);
+        }
+        Ok(())
+    }
+
+    fn needs_out(&self, count: usize) -> Result<()> {
+        if self.dst.len() + count > self.max_dst_size {
+            return Err(DecompressionError::OutputOverrun);
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        read_u8(self.src, &mut self.inp)
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    fn consume_zero_byte_length(&mut self) -> Result<usize> {
+        let old_inp = self.inp;
+        while *self.src.get(self.inp).ok_or(DecompressionError::Truncated)? == 0 {
+            self.inp += 1;
+        }
+        Ok(self.inp - old_inp)
+    }
+
+    fn copy_literals(&mut self, len: usize) -> Result<()> {
+        self.needs_in(len)?;
+        self.needs_out(len)?;
+        self.dst.extend_from_slice(&self.src[self.inp..self.inp + len]);
+        self.inp += len;
+        Ok(())
+    }
+}
+
+pub fn decompress(src: &[u8], max_dst_size: usize) -> Result<Vec<u8>> {
+    if src.len() < 3 {
+        return Err(DecompressionError::Truncated);
+    }
+
+    let mut d = Decoder { src, inp: 0, dst: Vec::with_capacity(max_dst_size), max_dst_size };
+    let mut state = 0;
+
+    // First byte encoding
+    if src[0] >= 22 {
+        // 22..255 : copy literal string
+        //           length = (byte - 17) = 4..238
+        //           state = 4 [ don't copy extra literals ]
+        //           skip byte
+        let len = (d.read_u8()? - 17) as usize;
+        d.copy_literals(len)?;
+        state = 4;
+    } else if src[0] >= 18 {
+        // 18..21 : copy 0..3 literals
+        //          state = (byte - 17) = 0..3  [ copy <state> literals ]
+        //          skip byte
+        state = (d.read_u8()? - 17) as usize;
+        d.copy_literals(state)?;
+    }
+    // 0..17 : follow regular instruction encoding, see below. It is worth
+    //         noting that codes 16 and 17 will represent a block copy from
+    //         the dictionary which is empty, and that they will always be
+    //         invalid at this place.
+
+    let mut lblen;
+    loop {
+        let inst = d.read_u8()?;
+        let distance;
+        let nstate;
+        if (inst & 0xC0) != 0 {
+            // [M2]
+            // 1 L L D D D S S  (128..255)
+            //   Copy 5-8 bytes from block within 2kB distance
+            //   state = S (copy S literals after this block)
+            //   length = 5 + L
+            // Always followed by exactly one byte : H H H H H H H H
+            //   distance = (H << 3) + D + 1
+            //
+            // 0 1 L D D D S S  (64..127)
+            //   Copy 3-4 bytes from block within 2kB distance
+            //   state = S (copy S literals after this block)
+            //   length = 3 + L
+            // Always followed by exactly one byte : H H H H H H H H
+            //   distance = (H << 3) + D + 1
+            distance = ((d.read_u8()? as usize) << 3) + (((inst >> 2) & 0x07) as usize) + 1;
+            lblen = ((inst >> 5) as usize) + 1;
+            nstate = (inst & 0x03) as usize;
+        } else if (inst & M3_MARKER) != 0 {
+            // [M3]
+            // 0 0 1 L L L L L  (32..63)
+            //   Copy of small block within 16kB distance (preferably less than 34B)
+            //   length = 2 + (L ?: 31 + (zero_bytes * 255) + non_zero_byte)
+            // Always followed by exactly one LE16 :  D D D D D D D D : D D D D D D S S
+            //   distance = D + 1
+            //   state = S (copy S literals after this block)
+            lblen = ((inst & 0x1F) as usize) + 2;
+            if lblen == 2 {
+                let offset = d.consume_zero_byte_length()?;
+                lblen += offset * 255 + 31 + (d.read_u8()? as usize);
+            }
+            let v = d.read_u16_le()? as usize;
+            distance = (v >> 2) + 1;
+            nstate = v & 0x03;
+        } else if (inst & M4_MARKER) != 0 {
+            // [M4]
+            // 0 0 0 1 H L L L  (16..31)
+            //   Copy of a block within 16..48kB distance (preferably less than 10B)
+            //   length = 2 + (L ?: 7 + (zero_bytes * 255) + non_zero_byte)
+            // Always followed by exactly one LE16 :  D D D D D D D D : D D D D D D S S
+            //   distance = 16384 + (H << 14) + D
+            //   state = S (copy S literals after this block)
+            //   End of stream is reached if distance == 16384
+            lblen = ((inst & 0x07) as usize) + 2;
+            if lblen == 2 {
+                let offset = d.consume_zero_byte_length()?;
+                lblen += offset * 255 + 7 + (d.read_u8()? as usize);
+            }
+            let v = d.read_u16_le()? as usize;
+            let dist = (((inst & 0x08) as usize) << 11) + (v >> 2);
+            if dist == 0 {
+                // Stream finished
+                break;
+            }
+            distance = dist + 16384;
+            nstate = v & 0x03;
+        } else if state == 0 {
+            // [M1] Depends on the number of literals copied by the last instruction.
+            //
+            // If last instruction did not copy any literal (state == 0), this
+            // encoding will be a copy of 4 or more literal, and must be interpreted
+            // like this :
+            //
+            //    0 0 0 0 L L L L  (0..15)  : copy long literal string
+            //    length = 3 + (L ?: 15 + (zero_bytes * 255) + non_zero_byte)
+            //    state = 4  (no extra literals are copied)
+            let mut len = (inst as usize) + 3;
+            if len == 3 {
+                let offset = d.consume_zero_byte_length()?;
+                len += offset * 255 + 15 + (d.read_u8()? as usize);
+            }
+            d.copy_literals(len)?;
+            state = 4;
+            continue;
+        } else if state != 4 {
+            // If last instruction used to copy between 1 to 3 literals (encoded in
+            // the instruction's opcode or distance), the instruction is a copy of a
+            // 2-byte block from the dictionary within a 1kB distance. It is worth
+            // noting that this instruction provides little savings since it uses 2
+            // bytes to encode a copy of 2 other bytes but it encodes the number of
+            // following literals for free. It must be interpreted like this :
+            //
+            //    0 0 0 0 D D S S  (0..15)  : copy 2 bytes from <= 1kB distance
+            //    length = 2
+            //    state = S (copy S literals after this block)
+            //  Always followed by exactly one byte : H H H H H H H H
+            //    distance = (H << 2) + D + 1
+            nstate = (inst & 0x03) as usize;
+            distance = ((inst >> 2) as usize) + ((d.read_u8()? as usize) << 2) + 1;
+            lblen = 2;
+        } else {
+            // If last instruction used to copy 4 or more literals (as detected by
+            // state == 4), the instruction becomes a copy of a 3-byte block from the
+            // dictionary from a 2..3kB distance, and must be interpreted like this :
+            //
+            //    0 0 0 0 D D S S  (0..15)  : copy 3 bytes from 2..3 kB distance
+            //    length = 3
+            //    state = S (copy S literals after this block)
+            //  Always followed by exactly one byte : H H H H H H H H
+            //    distance = (H << 2) + D + 2049
+            nstate = (inst & 0x03) as usize;
+            distance = ((inst >> 2) as usize) + ((d.read_u8()? as usize) << 2) + 2049;
+            lblen = 3;
+        }
+
+        let outp = d.dst.len();
+        if distance > outp {
+            return Err(DecompressionError::InvalidBackReference { dst_offs: outp, distance });
+        }
+        d.needs_in(nstate)?;
+        d.needs_out(lblen + nstate)?;
+
+        // Copy lookbehind
+        let lbcur = outp - distance;
+        for i in 0..lblen {
+            let v = d.dst[lbcur + i];
+            d.dst.push(v);
+        }
+        state = nstate;
+
+        // Copy literal
+        d.copy_literals(nstate)?;
+    }
+
+    // Ensure terminating M4 was encountered
+    if lblen != 3 {
+        return Err(DecompressionError::Truncated);
+    }
+
+    Ok(d.dst)
+}
+
+#[wasm_bindgen]
+pub fn lzodec(src: &[u8], max_dst_size: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src, max_dst_size)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_golden() {
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text.txt.lzo");
+        assert_eq!(decompress(&src, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text.txt.lzo");
+        assert_eq!(decompress(&src, expected.len() - 1), Err(DecompressionError::OutputOverrun));
+        assert_eq!(decompress(&src[..src.len() - 3], expected.len()), Err(DecompressionError::Truncated));
+
+        // "abcd", then a 3-byte copy from 5 back.
+        assert_eq!(decompress(b"\x15abcd\x21\x10\x00\x11\x00\x00", 0x10), Err(DecompressionError::InvalidBackReference { dst_offs: 4, distance: 5 }));
+        assert_eq!(decompress(b"\x15abcd\x22\x0C\x00\x11\x00\x00", 0x10).unwrap(), b"abcdabcd");
+    }
+}
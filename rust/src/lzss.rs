@@ -0,0 +1,152 @@
+// A decompressor for Haruhiko Okumura's LZSS, with MATCHLEN 18
+// http://read.pudn.com/downloads4/sourcecode/zip/14045/LZSS.C__.htm
+//
+// Games tweak this in a few ways, so the ring buffer size, the byte it is
+// initially filled with, and the order flag bits are read in are configurable.
+//
+// Data:
+//   Flags (1 byte)
+//   For each bit in the flags byte:
+//     If flag is 1:
+//       Literal: copy one byte from src to dest.
+//     If flag is 0:
+//       Back-reference (2 bytes):
+//         Position in the ring buffer: byte 0, plus the high nibble of byte 1 as bits 8-11.
+//         Length: low nibble of byte 1, plus 3.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, read_u8};
+
+// MATCHLEN; the ring buffer write position starts this far from the end.
+const MAX_MATCH: usize = 18;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlagBitOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LzssParameters {
+    // Must be a power of two, at most 0x1000.
+    pub window_size: usize,
+    pub fill_byte: u8,
+    pub flag_bit_order: FlagBitOrder,
+}
+
+impl Default for LzssParameters {
+    fn default() -> Self {
+        LzssParameters {
+            window_size: 0x1000,
+            fill_byte: 0x00,
+            flag_bit_order: FlagBitOrder::LsbFirst,
+        }
+    }
+}
+
+pub fn decompress(src: &[u8], uncompressed_size: usize, params: &LzssParameters) -> Result<Vec<u8>> {
+    let n = params.window_size;
+    if !n.is_power_of_two() || n > 0x1000 || n <= MAX_MATCH {
+        return Err(DecompressionError::InvalidHeader);
+    }
+
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    let mut ring = vec![params.fill_byte; n];
+    let mut ring_wp = n - MAX_MATCH;
+
+    let mut src_offs = 0;
+    while dst.len() < uncompressed_size {
+        let command_byte = read_u8(src, &mut src_offs)?;
+
+        for i in 0..8 {
+            if dst.len() >= uncompressed_size {
+                break;
+            }
+
+            let bit = match params.flag_bit_order {
+                FlagBitOrder::LsbFirst => 1 << i,
+                FlagBitOrder::MsbFirst => 0x80 >> i,
+            };
+
+            if (command_byte & bit) != 0 {
+                // Literal.
+                let v = read_u8(src, &mut src_offs)?;
+                ring[ring_wp] = v;
+                ring_wp = (ring_wp + 1) % n;
+                dst.push(v);
+            } else {
+                let b0 = read_u8(src, &mut src_offs)? as usize;
+                let b1 = read_u8(src, &mut src_offs)? as usize;
+
+                let ring_rp = b0 | ((b1 & 0xF0) << 4);
+                let copy_length = (b1 & 0x0F) + 3;
+
+                if dst.len() + copy_length > uncompressed_size {
+                    return Err(DecompressionError::OutputOverrun);
+                }
+
+                for j in 0..copy_length {
+                    let v = ring[(ring_rp + j) % n];
+                    ring[ring_wp] = v;
+                    ring_wp = (ring_wp + 1) % n;
+                    dst.push(v);
+                }
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn lzssdec(src: &[u8], uncompressed_size: usize, window_size: usize, fill_byte: u8, flag_bit_order: FlagBitOrder) -> std::result::Result<Vec<u8>, JsValue> {
+    let params = LzssParameters { window_size, fill_byte, flag_bit_order };
+    Ok(decompress(src, uncompressed_size, &params)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn test_golden_okumura() {
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text.txt.lzss");
+        assert_eq!(decompress(&src, expected.len(), &LzssParameters::default()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_golden_variant() {
+        // 2 KiB window, filled with spaces, flags read MSB first.
+        let params = LzssParameters { window_size: 0x800, fill_byte: b' ', flag_bit_order: FlagBitOrder::MsbFirst };
+        let expected = read_test_file("text.txt");
+        let src = read_test_file("text_800_20_msb.txt.lzss");
+        assert_eq!(decompress(&src, expected.len(), &params).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_fill_byte() {
+        // A literal, then 3 bytes copied from the untouched part of the ring buffer.
+        let src = b"\x01a\x00\x00";
+        let params = LzssParameters { fill_byte: b'z', ..Default::default() };
+        assert_eq!(decompress(src, 4, &params).unwrap(), b"azzz");
+        let params = LzssParameters { fill_byte: b'z', flag_bit_order: FlagBitOrder::MsbFirst, ..Default::default() };
+        assert_eq!(decompress(b"\x80a\x00\x00", 4, &params).unwrap(), b"azzz");
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let params = LzssParameters::default();
+        assert_eq!(decompress(b"\x01a\x00\x00", 3, &params), Err(DecompressionError::OutputOverrun));
+        assert_eq!(decompress(b"\x01a\x00", 4, &params), Err(DecompressionError::Truncated));
+        let params = LzssParameters { window_size: 0x900, ..Default::default() };
+        assert_eq!(decompress(b"\x01a\x00\x00", 4, &params), Err(DecompressionError::InvalidHeader));
+    }
+}
@@ -0,0 +1,651 @@
+// Microsoft LZX, as used by XNA content (XNB) and XMemCompress.
+//
+// https://docs.microsoft.com/en-us/previous-versions/bb417343(v=msdn.10)#microsoft-lzx-data-compression-format
+// https://github.com/FNA-XNA/FNA/blob/master/src/Content/LzxDecoder.cs
+//
+// Ported from src/Common/Compression/LZX.ts. Frame realignment and the Intel E8
+// call translation follow libmspack's lzxd.c.
+//
+// Output is produced in frames of 0x8000 bytes. A plain LZX stream is one
+// bitstream, realigned to 16 bits after every frame. XMemCompress instead
+// stores each frame as a separate chunk:
+//   If the first byte is 0xFF:
+//     0x01: u16 BE uncompressed frame size
+//     0x03: u16 BE compressed chunk size
+//     0x05: compressed chunk
+//   Otherwise:
+//     0x00: u16 BE compressed chunk size (frame size is 0x8000)
+//     0x02: compressed chunk
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, read_u8, read_u16_be};
+
+pub const FRAME_SIZE: usize = 0x8000;
+
+const NUM_CHARS: usize = 256;
+const PRETREE_NUM_ELEMENTS: usize = 20;
+const ALIGNED_NUM_ELEMENTS: usize = 8;
+const NUM_SECONDARY_LENGTHS: usize = 249;
+const MAX_POSITION_SLOTS: usize = 50;
+
+// The translation is only applied to the first 32768 frames.
+const INTEL_MAX_FRAMES: usize = 32768;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BlockType {
+    Undefined,
+    Verbatim,
+    Aligned,
+    Uncompressed,
+}
+
+struct BitReader<'a> {
+    src: &'a [u8],
+    offs: usize,
+    sink: u32,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        BitReader { src, offs: 0, sink: 0, nbits: 0 }
+    }
+
+    // Words past the end of the input read as zero; check_truncated catches
+    // any that were actually consumed.
+    fn fill(&mut self, nbits: u32) {
+        while self.nbits < nbits {
+            let w = if self.offs + 1 < self.src.len() {
+                (self.src[self.offs] as u32) | ((self.src[self.offs + 1] as u32) << 8)
+            } else {
+                0
+            };
+            self.sink |= w << (16 - self.nbits);
+            self.nbits += 16;
+            self.offs += 2;
+        }
+    }
+
+    fn peek(&self, nbits: u32) -> u32 {
+        self.sink >> (32 - nbits)
+    }
+
+    fn eat(&mut self, nbits: u32) {
+        self.sink <<= nbits;
+        self.nbits -= nbits;
+    }
+
+    // At most 17 bits at a time.
+    fn read(&mut self, nbits: u32) -> u32 {
+        if nbits == 0 {
+            return 0;
+        }
+        self.fill(nbits);
+        let v = self.peek(nbits);
+        self.eat(nbits);
+        v
+    }
+
+    // Drop the rest of the current 16-bit word.
+    fn align(&mut self) {
+        self.eat(self.nbits & 15);
+    }
+
+    // Uncompressed blocks skip 1-16 bits of padding, then switch to bytes.
+    fn start_bytes(&mut self) {
+        if self.nbits == 0 {
+            self.fill(16);
+        }
+        self.sink = 0;
+        self.nbits = 0;
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        read_u8(self.src, &mut self.offs)
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32> {
+        let mut v = 0;
+        for i in 0..4 {
+            v |= (self.read_byte()? as u32) << (i * 8);
+        }
+        Ok(v)
+    }
+
+    fn check_truncated(&self) -> Result<()> {
+        if self.offs * 8 - self.nbits as usize > self.src.len() * 8 {
+            return Err(DecompressionError::Truncated);
+        }
+        Ok(())
+    }
+}
+
+struct HuffmanTable {
+    // Code length of each symbol; carried over between blocks, since new
+    // lengths are coded as deltas from the previous ones.
+    len: Vec<u8>,
+    // Symbol for every table_bits-wide prefix.
+    table: Vec<u16>,
+    table_bits: u32,
+}
+
+impl HuffmanTable {
+    fn new(num_symbols: usize) -> Self {
+        HuffmanTable { len: vec![0; num_symbols], table: Vec::new(), table_bits: 0 }
+    }
+
+    // Builds the lookup table for a canonical code. An all-zero length table
+    // is allowed, as long as nothing is decoded with it.
+    fn build(&mut self) -> Result<()> {
+        let max_bits = *self.len.iter().max().unwrap_or(&0) as u32;
+        self.table_bits = max_bits;
+        self.table.clear();
+        if max_bits == 0 {
+            return Ok(());
+        }
+
+        let table_size = 1 << max_bits;
+        self.table.resize(table_size, 0);
+
+        let mut pos = 0;
+        for bits in 1..=max_bits {
+            let span = 1 << (max_bits - bits);
+            for (sym, &sym_bits) in self.len.iter().enumerate() {
+                if sym_bits as u32 != bits {
+                    continue;
+                }
+                if pos + span > table_size {
+                    // Over-subscribed.
+                    return Err(DecompressionError::InvalidHeader);
+                }
+                for v in &mut self.table[pos..pos + span] {
+                    *v = sym as u16;
+                }
+                pos += span;
+            }
+        }
+
+        if pos != table_size {
+            // Incomplete.
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, bs: &mut BitReader) -> Result<usize> {
+        if self.table_bits == 0 {
+            return Err(DecompressionError::InvalidHeader);
+        }
+        bs.fill(16);
+        let sym = self.table[bs.peek(self.table_bits) as usize] as usize;
+        bs.eat(self.len[sym] as u32);
+        Ok(sym)
+    }
+}
+
+fn num_position_slots(window_bits: u32) -> Option<usize> {
+    match window_bits {
+        15 => Some(30),
+        16 => Some(32),
+        17 => Some(34),
+        18 => Some(36),
+        19 => Some(38),
+        20 => Some(42),
+        21 => Some(50),
+        _ => None,
+    }
+}
+
+fn extra_bits(slot: usize) -> u32 {
+    if slot < 4 {
+        0
+    } else {
+        std::cmp::min((slot as u32 - 2) / 2, 17)
+    }
+}
+
+// Reads a run of code lengths for dst through the pretree, which is sent
+// first. Each length is coded as a delta (mod 17) from its previous value.
+fn read_lengths(pretree: &mut HuffmanTable, bs: &mut BitReader, dst: &mut [u8]) -> Result<()> {
+    for i in 0..PRETREE_NUM_ELEMENTS {
+        pretree.len[i] = bs.read(4) as u8;
+    }
+    pretree.build()?;
+
+    let mut i = 0;
+    while i < dst.len() {
+        let sym = pretree.decode(bs)?;
+        let (count, value) = match sym {
+            // Run of zeroes.
+            17 => (4 + bs.read(4) as usize, 0),
+            18 => (20 + bs.read(5) as usize, 0),
+            // Run of the same delta.
+            19 => {
+                let count = 4 + bs.read(1) as usize;
+                let delta = pretree.decode(bs)?;
+                if delta > 16 {
+                    return Err(DecompressionError::InvalidHeader);
+                }
+                (count, (dst[i] + 17 - delta as u8) % 17)
+            },
+            _ => (1, (dst[i] + 17 - sym as u8) % 17),
+        };
+
+        if i + count > dst.len() {
+            return Err(DecompressionError::InvalidHeader);
+        }
+        for v in &mut dst[i..i + count] {
+            *v = value;
+        }
+        i += count;
+    }
+
+    Ok(())
+}
+
+pub struct LzxDecoder {
+    window: Vec<u8>,
+    position_base: [usize; MAX_POSITION_SLOTS + 1],
+
+    // Total bytes decoded. This can run past the current frame, since a match
+    // may cross a frame boundary.
+    bytes_decoded: usize,
+    // Total bytes in all frames output so far.
+    frame_start: usize,
+    frames_decoded: usize,
+
+    // Repeated offsets.
+    r: [usize; 3],
+
+    header_read: bool,
+    intel_file_size: i32,
+    intel_cur_pos: i32,
+    intel_started: bool,
+
+    block_type: BlockType,
+    block_length: usize,
+    block_remaining: usize,
+
+    pretree: HuffmanTable,
+    main_tree: HuffmanTable,
+    length_tree: HuffmanTable,
+    aligned_tree: HuffmanTable,
+}
+
+impl LzxDecoder {
+    // The window is 2^window_bits bytes; 15 through 21 are valid.
+    pub fn new(window_bits: u32) -> Result<Self> {
+        let num_slots = num_position_slots(window_bits).ok_or(DecompressionError::InvalidHeader)?;
+
+        let mut position_base = [0; MAX_POSITION_SLOTS + 1];
+        for i in 1..position_base.len() {
+            position_base[i] = position_base[i - 1] + (1 << extra_bits(i - 1));
+        }
+
+        let main_elements = NUM_CHARS + num_slots * 8;
+        Ok(LzxDecoder {
+            // The reference decoder fills the window with 0xDC, but a valid
+            // stream never reads from before the start.
+            window: vec![0xDC; 1 << window_bits],
+            position_base,
+            bytes_decoded: 0,
+            frame_start: 0,
+            frames_decoded: 0,
+            r: [1, 1, 1],
+            header_read: false,
+            intel_file_size: 0,
+            intel_cur_pos: 0,
+            intel_started: false,
+            block_type: BlockType::Undefined,
+            block_length: 0,
+            block_remaining: 0,
+            pretree: HuffmanTable::new(PRETREE_NUM_ELEMENTS),
+            main_tree: HuffmanTable::new(main_elements),
+            length_tree: HuffmanTable::new(NUM_SECONDARY_LENGTHS),
+            aligned_tree: HuffmanTable::new(ALIGNED_NUM_ELEMENTS),
+        })
+    }
+
+    // Decodes one frame from a chunk that starts on its own, as XMemCompress
+    // stores them. frame_size is at most FRAME_SIZE, and only the last frame
+    // of a stream may be shorter.
+    pub fn decompress_frame(&mut self, src: &[u8], frame_size: usize, dst: &mut Vec<u8>) -> Result<()> {
+        let mut bs = BitReader::new(src);
+        self.decode_frame(&mut bs, frame_size, dst)
+    }
+
+    fn window_mask(&self) -> usize {
+        self.window.len() - 1
+    }
+
+    fn put(&mut self, v: u8) {
+        let mask = self.window_mask();
+        self.window[self.bytes_decoded & mask] = v;
+        self.bytes_decoded += 1;
+    }
+
+    fn read_main_and_length_trees(&mut self, bs: &mut BitReader) -> Result<()> {
+        read_lengths(&mut self.pretree, bs, &mut self.main_tree.len[..NUM_CHARS])?;
+        read_lengths(&mut self.pretree, bs, &mut self.main_tree.len[NUM_CHARS..])?;
+        self.main_tree.build()?;
+        if self.main_tree.len[0xE8] != 0 {
+            self.intel_started = true;
+        }
+
+        read_lengths(&mut self.pretree, bs, &mut self.length_tree.len)?;
+        self.length_tree.build()?;
+        Ok(())
+    }
+
+    fn read_block_header(&mut self, bs: &mut BitReader) -> Result<()> {
+        // Odd-sized uncompressed blocks are padded to 16 bits. Like libmspack,
+        // tolerate the padding being left off at the end of the input.
+        if self.block_type == BlockType::Uncompressed && (self.block_length & 1) != 0 && bs.offs < bs.src.len() {
+            bs.read_byte()?;
+        }
+
+        let block_type = bs.read(3);
+        let hi = bs.read(16) as usize;
+        let lo = bs.read(8) as usize;
+        self.block_length = (hi << 8) | lo;
+        self.block_remaining = self.block_length;
+
+        self.block_type = match block_type {
+            0x01 => {
+                self.read_main_and_length_trees(bs)?;
+                BlockType::Verbatim
+            },
+            0x02 => {
+                // The aligned offset tree comes first, and isn't delta-coded.
+                for i in 0..ALIGNED_NUM_ELEMENTS {
+                    self.aligned_tree.len[i] = bs.read(3) as u8;
+                }
+                self.aligned_tree.build()?;
+                self.read_main_and_length_trees(bs)?;
+                BlockType::Aligned
+            },
+            0x03 => {
+                // The data might be anything, so translate from here on.
+                self.intel_started = true;
+                bs.start_bytes();
+                for i in 0..3 {
+                    self.r[i] = bs.read_u32_le()? as usize;
+                }
+                BlockType::Uncompressed
+            },
+            _ => return Err(DecompressionError::InvalidHeader),
+        };
+
+        Ok(())
+    }
+
+    // Decodes at least run bytes of a verbatim or aligned block. Returns how far
+    // the last match went past run.
+    fn decode_compressed(&mut self, bs: &mut BitReader, run: usize) -> Result<usize> {
+        let mask = self.window_mask();
+        let mut remaining = run as isize;
+
+        while remaining > 0 {
+            let main_element = self.main_tree.decode(bs)?;
+            if main_element < NUM_CHARS {
+                self.put(main_element as u8);
+                remaining -= 1;
+                continue;
+            }
+
+            let main_element = main_element - NUM_CHARS;
+            let mut match_length = main_element & 0x07;
+            if match_length == 0x07 {
+                match_length += self.length_tree.decode(bs)?;
+            }
+            match_length += 2;
+
+            let slot = main_element >> 3;
+            let distance = match slot {
+                0 => self.r[0],
+                1 => {
+                    self.r.swap(0, 1);
+                    self.r[0]
+                },
+                2 => {
+                    self.r.swap(0, 2);
+                    self.r[0]
+                },
+                _ => {
+                    let extra = extra_bits(slot);
+                    let verbatim = if self.block_type == BlockType::Aligned && extra >= 3 {
+                        ((bs.read(extra - 3) as usize) << 3) + self.aligned_tree.decode(bs)?
+                    } else {
+                        bs.read(extra) as usize
+                    };
+                    let distance = self.position_base[slot] - 2 + verbatim;
+                    self.r[2] = self.r[1];
+                    self.r[1] = self.r[0];
+                    self.r[0] = distance;
+                    distance
+                },
+            };
+
+            if distance == 0 || distance > self.bytes_decoded || distance >= self.window.len() {
+                return Err(DecompressionError::InvalidBackReference { dst_offs: self.bytes_decoded, distance });
+            }
+
+            for _ in 0..match_length {
+                let v = self.window[(self.bytes_decoded - distance) & mask];
+                self.put(v);
+            }
+            remaining -= match_length as isize;
+        }
+
+        Ok((-remaining) as usize)
+    }
+
+    fn decode_frame(&mut self, bs: &mut BitReader, frame_size: usize, dst: &mut Vec<u8>) -> Result<()> {
+        if frame_size == 0 || frame_size > FRAME_SIZE {
+            return Err(DecompressionError::InvalidHeader);
+        }
+
+        let frame_end = self.frame_start + frame_size;
+        if self.bytes_decoded > frame_end {
+            return Err(DecompressionError::OutputOverrun);
+        }
+
+        if !self.header_read {
+            if bs.read(1) != 0 {
+                let hi = bs.read(16);
+                let lo = bs.read(16);
+                self.intel_file_size = ((hi << 16) | lo) as i32;
+            }
+            self.header_read = true;
+        }
+
+        let mut bytes_todo = frame_end - self.bytes_decoded;
+        while bytes_todo > 0 {
+            if self.block_remaining == 0 {
+                self.read_block_header(bs)?;
+            }
+
+            let run = std::cmp::min(self.block_remaining, bytes_todo);
+            bytes_todo -= run;
+            self.block_remaining -= run;
+
+            let overrun = match self.block_type {
+                BlockType::Verbatim | BlockType::Aligned => self.decode_compressed(bs, run)?,
+                BlockType::Uncompressed => {
+                    for _ in 0..run {
+                        let v = bs.read_byte()?;
+                        self.put(v);
+                    }
+                    0
+                },
+                BlockType::Undefined => unreachable!(),
+            };
+
+            // A match crossing into the next frame still has to fit in the block.
+            if overrun > self.block_remaining {
+                return Err(DecompressionError::OutputOverrun);
+            }
+            self.block_remaining -= overrun;
+        }
+
+        bs.check_truncated()?;
+
+        let dst_start = dst.len();
+        let mask = self.window_mask();
+        let start = self.frame_start & mask;
+        if start + frame_size <= self.window.len() {
+            dst.extend_from_slice(&self.window[start..start + frame_size]);
+        } else {
+            dst.extend_from_slice(&self.window[start..]);
+            dst.extend_from_slice(&self.window[..(frame_end & mask)]);
+        }
+
+        if self.intel_file_size != 0 {
+            if self.intel_started && self.frames_decoded < INTEL_MAX_FRAMES && frame_size > 10 {
+                self.undo_e8_translation(&mut dst[dst_start..]);
+            }
+            self.intel_cur_pos += frame_size as i32;
+        }
+
+        self.frame_start = frame_end;
+        self.frames_decoded += 1;
+        Ok(())
+    }
+
+    // The encoder rewrites the relative targets of x86 CALL instructions (0xE8)
+    // as absolute ones, which compress better. Turn them back.
+    fn undo_e8_translation(&self, data: &mut [u8]) {
+        let file_size = self.intel_file_size;
+        let mut cur_pos = self.intel_cur_pos;
+        let mut i = 0;
+        while i < data.len() - 10 {
+            if data[i] != 0xE8 {
+                i += 1;
+                cur_pos += 1;
+                continue;
+            }
+
+            let abs_offs = i32::from_le_bytes([data[i + 1], data[i + 2], data[i + 3], data[i + 4]]);
+            if abs_offs >= -cur_pos && abs_offs < file_size {
+                let rel_offs = if abs_offs >= 0 { abs_offs - cur_pos } else { abs_offs + file_size };
+                data[i + 1..i + 5].copy_from_slice(&rel_offs.to_le_bytes());
+            }
+            i += 5;
+            cur_pos += 5;
+        }
+    }
+}
+
+// Decompresses a plain LZX stream.
+pub fn decompress(src: &[u8], window_bits: u32, uncompressed_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = LzxDecoder::new(window_bits)?;
+    let mut bs = BitReader::new(src);
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    while dst.len() < uncompressed_size {
+        let frame_size = std::cmp::min(FRAME_SIZE, uncompressed_size - dst.len());
+        decoder.decode_frame(&mut bs, frame_size, &mut dst)?;
+        bs.align();
+    }
+    Ok(dst)
+}
+
+// Decompresses XMemCompress chunks, as found in XNB files. Stops at a chunk with
+// a size of zero, or once uncompressed_size bytes have been produced.
+pub fn decompress_xmem(src: &[u8], window_bits: u32, uncompressed_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = LzxDecoder::new(window_bits)?;
+    let mut dst = Vec::with_capacity(uncompressed_size);
+    let mut src_offs = 0;
+    while dst.len() < uncompressed_size {
+        let (frame_size, chunk_size) = if read_u8(src, &mut src_offs)? == 0xFF {
+            let frame_size = read_u16_be(src, &mut src_offs)? as usize;
+            let chunk_size = read_u16_be(src, &mut src_offs)? as usize;
+            (frame_size, chunk_size)
+        } else {
+            src_offs -= 1;
+            (FRAME_SIZE, read_u16_be(src, &mut src_offs)? as usize)
+        };
+
+        if frame_size == 0 || chunk_size == 0 {
+            break;
+        }
+
+        if dst.len() + frame_size > uncompressed_size {
+            return Err(DecompressionError::OutputOverrun);
+        }
+
+        let chunk = src.get(src_offs..src_offs + chunk_size).ok_or(DecompressionError::Truncated)?;
+        decoder.decompress_frame(chunk, frame_size, &mut dst)?;
+        src_offs += chunk_size;
+    }
+
+    if dst.len() < uncompressed_size {
+        return Err(DecompressionError::Truncated);
+    }
+
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn lzxdec(src: &[u8], window_bits: u32, uncompressed_size: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src, window_bits, uncompressed_size)?)
+}
+
+#[wasm_bindgen]
+pub fn xmemdec(src: &[u8], window_bits: u32, uncompressed_size: usize) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress_xmem(src, window_bits, uncompressed_size)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        std::fs::read(format!("test_data/compression/{}", name)).unwrap()
+    }
+
+    // text.txt, then 0x8000 bytes of xorshift noise (stored as uncompressed blocks), then text.txt again.
+    fn text_noise() -> Vec<u8> {
+        let text = read_test_file("text.txt");
+        let mut expected = text.clone();
+        expected.extend_from_slice(&noise(0x8000));
+        expected.extend_from_slice(&text);
+        expected
+    }
+
+    #[test]
+    fn test_golden_e8() {
+        // 64 KiB window, verbatim, aligned and uncompressed blocks, with E8 translation.
+        let expected = text_noise();
+        let src = read_test_file("text_noise_e8.lzx");
+        assert_eq!(decompress(&src, 16, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_golden_xmem() {
+        // 128 KiB window, no E8 translation.
+        let expected = text_noise();
+        let src = read_test_file("text_noise.xmem");
+        assert_eq!(decompress_xmem(&src, 17, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let expected = text_noise();
+        let src = read_test_file("text_noise.xmem");
+        assert_eq!(decompress_xmem(&src[..src.len() - 1], 17, expected.len()), Err(DecompressionError::Truncated));
+        assert!(decompress_xmem(&src, 17, expected.len() - 1).is_err());
+        assert_eq!(decompress(&src, 22, 1), Err(DecompressionError::InvalidHeader));
+
+        // An uncompressed block of "ab" with R0 = 1, then a verbatim block
+        // header with an invalid block type.
+        let mut src = vec![0x00, 0x30, 0x20, 0x00];
+        src.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        src.extend_from_slice(b"ab");
+        assert_eq!(decompress(&src, 15, 2).unwrap(), b"ab");
+        assert_eq!(decompress(&src, 15, 3), Err(DecompressionError::InvalidHeader));
+    }
+}
@@ -0,0 +1,102 @@
+// Nintendo MIO0 format.
+//
+// Similar to Yay0 (an earlier version, likely), except it has a few tweaks to the compressed data.
+//
+// Header (16 bytes):
+//   Magic: "MIO0" (4 bytes)
+//   Uncompressed size (4 bytes, big endian)
+//   Offset to Lengths substream (4 bytes, big endian)
+//   Offset to Data substream (4 bytes, big endian)
+//
+// Flags substream starts at 0x10 (directly after Header)
+//
+// Data:
+//   Read Flags from Flags substream (1 byte)
+//   For each bit in the Flags byte, from MSB to LSB:
+//     If flag is 1:
+//       Literal: copy one byte from Data substream to dest.
+//     If flag is 0:
+//       Read LZ77 from Lengths substream (2 bytes, big endian):
+//         Length: bits 0-4
+//         Offset: bits 5-15
+//         Copy Length+3 bytes from Offset back in the output buffer.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, check_uncompressed_size, copy_back_reference, read_u8, read_u16_be, read_u32_be};
+
+pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    if !src.starts_with(b"MIO0") {
+        return Err(DecompressionError::BadMagic);
+    }
+
+    let mut header_offs = 0x04;
+    let uncompressed_size = read_u32_be(src, &mut header_offs)? as usize;
+    let mut lengths_offs = read_u32_be(src, &mut header_offs)? as usize;
+    let mut data_offs = read_u32_be(src, &mut header_offs)? as usize;
+    let mut flags_offs = 0x10;
+
+    // The longest back-reference is 2 bytes long and copies 0x12 bytes.
+    check_uncompressed_size(uncompressed_size, src.len() - 0x10, 0x12 / 2)?;
+    let mut dst = vec![0x00; uncompressed_size];
+    let mut dst_offs = 0x00;
+    while dst_offs < uncompressed_size {
+        let command_byte = read_u8(src, &mut flags_offs)?;
+
+        for i in (0..8).rev() {
+            if dst_offs >= uncompressed_size {
+                break;
+            }
+
+            if (command_byte & (1 << i)) != 0 {
+                // Literal.
+                dst[dst_offs] = read_u8(src, &mut data_offs)?;
+                dst_offs += 1;
+            } else {
+                let tmp = read_u16_be(src, &mut lengths_offs)? as usize;
+
+                let window_offset = (tmp & 0x0FFF) + 1;
+                let window_length = (tmp >> 12) + 3;
+
+                copy_back_reference(&mut dst, dst_offs, window_offset, window_length)?;
+                dst_offs += window_length;
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn mio0dec(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // "abc", then copy 6 bytes from 3 back, then copy 0x12 bytes from 1 back.
+        let src = b"MIO0\x00\x00\x00\x1B\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x30\x02\xF0\x00abc";
+        let mut expected = b"abcabcabc".to_vec();
+        expected.extend_from_slice(&[b'c'; 0x12]);
+        assert_eq!(decompress(src).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(b"Yay0\x00\x00\x00\x01"), Err(DecompressionError::BadMagic));
+        assert_eq!(decompress(b"MIO0\x00\x00\x00\x01"), Err(DecompressionError::Truncated));
+
+        // Declares 0x0A bytes of output but the second copy would write past that.
+        let src = b"MIO0\x00\x00\x00\x0A\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x30\x02\xF0\x00abc";
+        assert_eq!(decompress(src), Err(DecompressionError::OutputOverrun));
+
+        // A 4GB header with a few bytes of data must fail without allocating.
+        let src = b"MIO0\xFF\xFF\xFF\xFF\x00\x00\x00\x14\x00\x00\x00\x16\x00\x00\x00\x00\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::Truncated));
+    }
+}
@@ -0,0 +1,315 @@
+// N64 RDP texel formats, ported from Common/N64/Image.ts.
+//
+// Textures are stored big-endian, in rows of line 64-bit words (or packed tightly when line is
+// 0). When a texture is loaded into TMEM with LOADBLOCK, the RDP swaps the 32-bit halves of each
+// 64-bit word on odd rows, so textures captured from TMEM need deinterleave set to read those
+// rows back in order.
+//
+// CI4 and CI8 look up a TLUT of RGBA16 or IA16 entries. For CI4, the TLUT is just the 16 entries
+// of the tile's palette.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::util;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum N64ImageFormat {
+    RGBA16,
+    RGBA32,
+    IA4,
+    IA8,
+    IA16,
+    I4,
+    I8,
+    CI4,
+    CI8,
+}
+
+impl N64ImageFormat {
+    // From the G_IM_FMT_* and G_IM_SIZ_* fields of SETTILE/SETTIMG.
+    pub fn from_fmt_siz(fmt: u8, siz: u8) -> Option<Self> {
+        match (fmt, siz) {
+            (0x00, 0x02) => Some(N64ImageFormat::RGBA16),
+            (0x00, 0x03) => Some(N64ImageFormat::RGBA32),
+            (0x02, 0x00) => Some(N64ImageFormat::CI4),
+            (0x02, 0x01) => Some(N64ImageFormat::CI8),
+            (0x03, 0x00) => Some(N64ImageFormat::IA4),
+            (0x03, 0x01) => Some(N64ImageFormat::IA8),
+            (0x03, 0x02) => Some(N64ImageFormat::IA16),
+            (0x04, 0x00) => Some(N64ImageFormat::I4),
+            (0x04, 0x01) => Some(N64ImageFormat::I8),
+            _ => None,
+        }
+    }
+
+    pub const fn bits_per_pixel(self) -> usize {
+        match self {
+            N64ImageFormat::IA4 | N64ImageFormat::I4 | N64ImageFormat::CI4 => 4,
+            N64ImageFormat::IA8 | N64ImageFormat::I8 | N64ImageFormat::CI8 => 8,
+            N64ImageFormat::RGBA16 | N64ImageFormat::IA16 => 16,
+            N64ImageFormat::RGBA32 => 32,
+        }
+    }
+}
+
+// The G_TT_* TLUT modes from SETOTHERMODE_H.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlutFormat {
+    RGBA16 = 0x02,
+    IA16 = 0x03,
+}
+
+fn decode_rgba5551_to_rgba8(dst: &mut [u8], p: u16) {
+    dst[0] = util::expand_n_to_8(5, ((p >> 11) & 0x1F) as u8);
+    dst[1] = util::expand_n_to_8(5, ((p >>  6) & 0x1F) as u8);
+    dst[2] = util::expand_n_to_8(5, ((p >>  1) & 0x1F) as u8);
+    dst[3] = if (p & 0x01) != 0 { 0xFF } else { 0x00 };
+}
+
+fn decode_ia16_to_rgba8(dst: &mut [u8], p: u16) {
+    let i = (p >> 8) as u8;
+    dst[0] = i;
+    dst[1] = i;
+    dst[2] = i;
+    dst[3] = p as u8;
+}
+
+// Decodes count TLUT entries to an RGBA8 palette.
+pub fn decode_tlut(tlut_fmt: TlutFormat, src: &[u8], count: usize) -> Vec<u8> {
+    let mut dst = vec![0x00; count * 4];
+    for (i, p) in dst.chunks_exact_mut(4).enumerate() {
+        let v = util::get_uint16_be(src, i * 2);
+        match tlut_fmt {
+            TlutFormat::RGBA16 => decode_rgba5551_to_rgba8(p, v),
+            TlutFormat::IA16 => decode_ia16_to_rgba8(p, v),
+        }
+    }
+    dst
+}
+
+// Like gx_texture's TiledDecoder, but the texels are in rows rather than tiles. idx is the index
+// of the texel in src, after the odd row swap.
+trait TexelDecoder {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]);
+    fn bits_per_pixel() -> usize;
+}
+
+fn decode_lines<T: TexelDecoder>(t: T, src: &[u8], w: usize, h: usize, line: usize, deinterleave: bool) -> Vec<u8> {
+    let mut dst = vec![0x00; w * h * 4];
+
+    let bpp = T::bits_per_pixel();
+    let stride = if line != 0 { line * 8 } else { (w * bpp).div_ceil(8) };
+    for y in 0..h {
+        let swap = if deinterleave && (y & 1) != 0 { 0x04 } else { 0x00 };
+        for x in 0..w {
+            let bit_offs = x * bpp;
+            let src_offs = (y * stride + (bit_offs >> 3)) ^ swap;
+            let idx = (src_offs * 8 + (bit_offs & 0x07)) / bpp;
+            let dst_offs = (y * w + x) * 4;
+            t.decode_single_pixel(src, idx, &mut dst[dst_offs..dst_offs + 4]);
+        }
+    }
+
+    dst
+}
+
+fn get_nibble(src: &[u8], idx: usize) -> u8 {
+    src[idx >> 1] >> (if (idx & 1) != 0 { 0 } else { 4 }) & 0x0F
+}
+
+struct TexelDecoderRGBA16 {}
+impl TexelDecoder for TexelDecoderRGBA16 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        decode_rgba5551_to_rgba8(dst, util::get_uint16_be(src, idx * 2));
+    }
+
+    fn bits_per_pixel() -> usize { 16 }
+}
+
+struct TexelDecoderRGBA32 {}
+impl TexelDecoder for TexelDecoderRGBA32 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        dst.copy_from_slice(&src[idx * 4..idx * 4 + 4]);
+    }
+
+    fn bits_per_pixel() -> usize { 32 }
+}
+
+struct TexelDecoderIA4 {}
+impl TexelDecoder for TexelDecoderIA4 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        let ia = get_nibble(src, idx);
+        let i = util::expand_n_to_8(3, ia >> 1);
+        dst[0] = i;
+        dst[1] = i;
+        dst[2] = i;
+        dst[3] = if (ia & 0x01) != 0 { 0xFF } else { 0x00 };
+    }
+
+    fn bits_per_pixel() -> usize { 4 }
+}
+
+struct TexelDecoderIA8 {}
+impl TexelDecoder for TexelDecoderIA8 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        let i = util::expand_n_to_8(4, src[idx] >> 4);
+        dst[0] = i;
+        dst[1] = i;
+        dst[2] = i;
+        dst[3] = util::expand_n_to_8(4, src[idx] & 0x0F);
+    }
+
+    fn bits_per_pixel() -> usize { 8 }
+}
+
+struct TexelDecoderIA16 {}
+impl TexelDecoder for TexelDecoderIA16 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        decode_ia16_to_rgba8(dst, util::get_uint16_be(src, idx * 2));
+    }
+
+    fn bits_per_pixel() -> usize { 16 }
+}
+
+struct TexelDecoderI4 {}
+impl TexelDecoder for TexelDecoderI4 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        let i = util::expand_n_to_8(4, get_nibble(src, idx));
+        dst.fill(i);
+    }
+
+    fn bits_per_pixel() -> usize { 4 }
+}
+
+struct TexelDecoderI8 {}
+impl TexelDecoder for TexelDecoderI8 {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        dst.fill(src[idx]);
+    }
+
+    fn bits_per_pixel() -> usize { 8 }
+}
+
+struct TexelDecoderCI4<'a> {
+    palette: &'a [u8],
+}
+
+impl TexelDecoder for TexelDecoderCI4<'_> {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        let idx = get_nibble(src, idx) as usize;
+        dst.copy_from_slice(&self.palette[idx * 4..idx * 4 + 4]);
+    }
+
+    fn bits_per_pixel() -> usize { 4 }
+}
+
+struct TexelDecoderCI8<'a> {
+    palette: &'a [u8],
+}
+
+impl TexelDecoder for TexelDecoderCI8<'_> {
+    fn decode_single_pixel(&self, src: &[u8], idx: usize, dst: &mut [u8]) {
+        let idx = src[idx] as usize;
+        dst.copy_from_slice(&self.palette[idx * 4..idx * 4 + 4]);
+    }
+
+    fn bits_per_pixel() -> usize { 8 }
+}
+
+// Decodes a texture to RGBA8. CI formats need the TLUT; RGBA32 is always read tightly packed,
+// like Image.ts.
+#[allow(clippy::too_many_arguments)]
+pub fn decode_texture(fmt: N64ImageFormat, tlut_fmt: Option<TlutFormat>, src: &[u8], tlut_src: Option<&[u8]>, w: usize, h: usize, line: usize, deinterleave: bool) -> Vec<u8> {
+    match fmt {
+        N64ImageFormat::RGBA16 => decode_lines(TexelDecoderRGBA16{}, src, w, h, line, deinterleave),
+        N64ImageFormat::RGBA32 => decode_lines(TexelDecoderRGBA32{}, src, w, h, 0, false),
+        N64ImageFormat::IA4 => decode_lines(TexelDecoderIA4{}, src, w, h, line, deinterleave),
+        N64ImageFormat::IA8 => decode_lines(TexelDecoderIA8{}, src, w, h, line, deinterleave),
+        N64ImageFormat::IA16 => decode_lines(TexelDecoderIA16{}, src, w, h, line, deinterleave),
+        N64ImageFormat::I4 => decode_lines(TexelDecoderI4{}, src, w, h, line, deinterleave),
+        N64ImageFormat::I8 => decode_lines(TexelDecoderI8{}, src, w, h, line, deinterleave),
+        N64ImageFormat::CI4 => {
+            let palette = decode_tlut(tlut_fmt.unwrap(), tlut_src.unwrap(), 0x10);
+            decode_lines(TexelDecoderCI4{ palette: &palette }, src, w, h, line, deinterleave)
+        },
+        N64ImageFormat::CI8 => {
+            let palette = decode_tlut(tlut_fmt.unwrap(), tlut_src.unwrap(), 0x100);
+            decode_lines(TexelDecoderCI8{ palette: &palette }, src, w, h, line, deinterleave)
+        },
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen]
+pub fn decode_n64_texture(fmt: N64ImageFormat, tlut_fmt: Option<TlutFormat>, src: &[u8], tlut_src: Option<Box<[u8]>>, w: usize, h: usize, line: usize, deinterleave: bool) -> Vec<u8> {
+    decode_texture(fmt, tlut_fmt, src, tlut_src.as_deref(), w, h, line, deinterleave)
+}
+
+#[wasm_bindgen]
+pub fn decode_n64_tlut(tlut_fmt: TlutFormat, src: &[u8], count: usize) -> Vec<u8> {
+    decode_tlut(tlut_fmt, src, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(fmt: N64ImageFormat, src: &[u8], w: usize, h: usize) -> Vec<u8> {
+        decode_texture(fmt, None, src, None, w, h, 0, false)
+    }
+
+    #[test]
+    fn test_direct_formats() {
+        // Red opaque, then blue transparent.
+        assert_eq!(decode(N64ImageFormat::RGBA16, &[0xF8, 0x01, 0x00, 0x3E], 2, 1), [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00]);
+        assert_eq!(decode(N64ImageFormat::RGBA32, &[0x01, 0x02, 0x03, 0x04], 1, 1), [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(decode(N64ImageFormat::IA4, &[0xF4], 2, 1), [0xFF, 0xFF, 0xFF, 0xFF, 0x49, 0x49, 0x49, 0x00]);
+        assert_eq!(decode(N64ImageFormat::IA8, &[0x3C], 1, 1), [0x33, 0x33, 0x33, 0xCC]);
+        assert_eq!(decode(N64ImageFormat::IA16, &[0x12, 0x34], 1, 1), [0x12, 0x12, 0x12, 0x34]);
+        assert_eq!(decode(N64ImageFormat::I4, &[0x1E], 2, 1), [0x11, 0x11, 0x11, 0x11, 0xEE, 0xEE, 0xEE, 0xEE]);
+        assert_eq!(decode(N64ImageFormat::I8, &[0x5A], 1, 1), [0x5A; 4]);
+    }
+
+    #[test]
+    fn test_tlut() {
+        let mut tlut = vec![0x00; 0x200];
+        tlut[0x02..0x04].copy_from_slice(&[0x07, 0xC1]);
+        tlut[0x1FE..0x200].copy_from_slice(&[0x80, 0x40]);
+
+        let dst = decode_texture(N64ImageFormat::CI4, Some(TlutFormat::RGBA16), &[0x10], Some(&tlut), 2, 1, 0, false);
+        assert_eq!(dst, [0x00, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00]);
+
+        let dst = decode_texture(N64ImageFormat::CI8, Some(TlutFormat::IA16), &[0xFF, 0x01], Some(&tlut), 2, 1, 0, false);
+        assert_eq!(dst, [0x80, 0x80, 0x80, 0x40, 0x07, 0x07, 0x07, 0xC1]);
+    }
+
+    #[test]
+    fn test_line_and_deinterleave() {
+        // Two rows of 4 I8 texels, padded out to a 64-bit line, with the second row's halves
+        // swapped like TMEM stores odd rows.
+        let src = [0, 1, 2, 3, 0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 4, 5, 6, 7];
+        let dst = decode_texture(N64ImageFormat::I8, None, &src, None, 4, 2, 1, true);
+        let reds: Vec<u8> = dst.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(reds, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let dst = decode_texture(N64ImageFormat::I8, None, &src, None, 4, 2, 1, false);
+        let reds: Vec<u8> = dst.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(reds, [0, 1, 2, 3, 0xF4, 0xF5, 0xF6, 0xF7]);
+
+        // Same for 4-bit texels, 16 to a line.
+        let src = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xEE, 0xDD, 0xCC];
+        let dst = decode_texture(N64ImageFormat::I4, None, &src, None, 8, 2, 1, true);
+        let reds: Vec<u8> = dst.chunks_exact(4).map(|p| p[0] & 0x0F).collect();
+        assert_eq!(reds, [0, 1, 2, 3, 4, 5, 6, 7, 0xF, 0xF, 0xE, 0xE, 0xD, 0xD, 0xC, 0xC]);
+    }
+
+    #[test]
+    fn test_from_fmt_siz() {
+        assert_eq!(N64ImageFormat::from_fmt_siz(0x00, 0x02), Some(N64ImageFormat::RGBA16));
+        assert_eq!(N64ImageFormat::from_fmt_siz(0x02, 0x01), Some(N64ImageFormat::CI8));
+        assert_eq!(N64ImageFormat::from_fmt_siz(0x04, 0x00), Some(N64ImageFormat::I4));
+        assert_eq!(N64ImageFormat::from_fmt_siz(0x01, 0x02), None);
+        assert_eq!(N64ImageFormat::IA16.bits_per_pixel(), 16);
+    }
+}
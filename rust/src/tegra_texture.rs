@@ -1,85 +1,511 @@
 
+use std::fmt;
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
 use crate::util;
 
 const GOB_SIZE_X: usize = 64;
 const GOB_SIZE_Y: usize = 8;
 
 #[wasm_bindgen]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     Bc1,
     Bc2,
     Bc3,
     Bc4,
     Bc5,
+    // Also used for B8G8R8A8; only the block size matters here.
+    R8G8B8A8,
+    R5G6B5,
+    Bc6h,
+    Bc7,
+    Astc4x4,
+    Astc5x4,
+    Astc5x5,
+    Astc6x5,
+    Astc6x6,
+    Astc8x5,
+    Astc8x6,
+    Astc8x8,
+    Astc10x5,
+    Astc10x6,
+    Astc10x8,
+    Astc10x10,
+    Astc12x10,
+    Astc12x12,
 }
 
 const fn get_format_bytes_per_block(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
+        R5G6B5 => 2,
+        R8G8B8A8 => 4,
         Bc1 | Bc4 => 8,
-        Bc2 | Bc3 | Bc5 => 16,
+        Bc2 | Bc3 | Bc5 | Bc6h | Bc7 => 16,
+        Astc4x4 | Astc5x4 | Astc5x5 | Astc6x5 | Astc6x6 | Astc8x5 | Astc8x6 | Astc8x8 |
+        Astc10x5 | Astc10x6 | Astc10x8 | Astc10x10 | Astc12x10 | Astc12x12 => 16,
     }
 }
 
-const fn get_format_block_width(channel_format: CompressionType) -> usize {
+pub(crate) const fn get_format_block_width(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
-        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 => 4,
+        R8G8B8A8 | R5G6B5 => 1,
+        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 | Bc6h | Bc7 | Astc4x4 => 4,
+        Astc5x4 | Astc5x5 => 5,
+        Astc6x5 | Astc6x6 => 6,
+        Astc8x5 | Astc8x6 | Astc8x8 => 8,
+        Astc10x5 | Astc10x6 | Astc10x8 | Astc10x10 => 10,
+        Astc12x10 | Astc12x12 => 12,
     }
 }
 
-const fn get_format_block_height(channel_format: CompressionType) -> usize {
+pub(crate) const fn get_format_block_height(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
-        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 => 4,
+        R8G8B8A8 | R5G6B5 => 1,
+        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 | Bc6h | Bc7 | Astc4x4 | Astc5x4 => 4,
+        Astc5x5 | Astc6x5 | Astc8x5 | Astc10x5 => 5,
+        Astc6x6 | Astc8x6 | Astc10x6 => 6,
+        Astc8x8 | Astc10x8 => 8,
+        Astc10x10 | Astc12x10 => 10,
+        Astc12x12 => 12,
     }
 }
 
-fn get_addr_block_linear(mut x: usize, y: usize, w: usize, bpp: usize, block_height: usize, base_addr: usize) -> usize {
-    let width_in_gobs = ((w * bpp) + GOB_SIZE_X - 1) / GOB_SIZE_X;
-    let mut gob_addr = base_addr;
+const GOB_SIZE: usize = GOB_SIZE_X * GOB_SIZE_Y;
 
-    gob_addr += (y / (GOB_SIZE_Y * block_height)) * 512 * block_height * width_in_gobs;
-    gob_addr += (x * bpp / 64) * 512 * block_height;
-    gob_addr += (y % (GOB_SIZE_Y * block_height) / 8) * 512;
+// Block-linear layout of one mip level. GOBs are grouped into blocks that are block_height GOBs
+// tall and block_depth GOBs deep; blocks are stored in row order, then slice order.
+struct BlockLinearLayout {
+    bpp: usize,
+    width_in_gobs: usize,
+    height_in_blocks_of_gobs: usize,
+    depth_in_blocks_of_gobs: usize,
+    block_height: usize,
+    block_depth: usize,
+}
+
+impl BlockLinearLayout {
+    fn new(width_in_blocks: usize, height_in_blocks: usize, depth: usize, bpp: usize, block_height: usize, block_depth: usize) -> Self {
+        BlockLinearLayout {
+            bpp,
+            width_in_gobs: (width_in_blocks * bpp).div_ceil(GOB_SIZE_X),
+            height_in_blocks_of_gobs: height_in_blocks.div_ceil(GOB_SIZE_Y * block_height),
+            depth_in_blocks_of_gobs: depth.div_ceil(block_depth),
+            block_height,
+            block_depth,
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        GOB_SIZE * self.block_height * self.block_depth
+    }
+
+    fn size(&self) -> usize {
+        self.block_size() * self.width_in_gobs * self.height_in_blocks_of_gobs * self.depth_in_blocks_of_gobs
+    }
+
+    fn get_addr(&self, x: usize, y: usize, z: usize) -> usize {
+        let row_size = self.block_size() * self.width_in_gobs;
+        let slice_size = row_size * self.height_in_blocks_of_gobs;
+
+        let mut gob_addr = 0;
+        gob_addr += (z / self.block_depth) * slice_size;
+        gob_addr += (y / (GOB_SIZE_Y * self.block_height)) * row_size;
+        gob_addr += (x * self.bpp / GOB_SIZE_X) * self.block_size();
+        gob_addr += (z % self.block_depth) * GOB_SIZE * self.block_height;
+        gob_addr += (y % (GOB_SIZE_Y * self.block_height) / GOB_SIZE_Y) * GOB_SIZE;
+
+        let x = x * self.bpp;
+        let mut addr = gob_addr;
+        addr += ((x % 64) / 32) * 256;
+        addr += ((y % 8) / 2) * 64;
+        addr += ((x % 32) / 16) * 32;
+        addr += (y % 2) * 16;
+        addr += x % 16;
+        addr
+    }
+}
+
+// Adjust block height down per mip to fit the image.
+fn get_mip_block_height(height_in_blocks: usize, block_height_mip0: usize) -> usize {
+    let mut block_height = block_height_mip0;
+    while block_height > 1 && (util::next_pow2(height_in_blocks) < (GOB_SIZE_Y * block_height)) {
+        block_height >>= 1;
+    }
+    block_height
+}
+
+// BNTX doesn't store a block depth; this is the heuristic the driver uses for 3D textures.
+fn get_block_depth(depth: usize) -> usize {
+    let depth_and_half = depth + depth / 2;
+    match depth_and_half {
+        d if d >= 16 => 16,
+        d if d >= 8 => 8,
+        d if d >= 4 => 4,
+        d if d >= 2 => 2,
+        _ => 1,
+    }
+}
 
-    x *= bpp;
-    let mut addr = gob_addr;
-    addr += (((x % 64) / 32) | 0) * 256;
-    addr += (((y % 8) / 2) | 0) * 64;
-    addr += (((x % 32) / 16) | 0) * 32;
-    addr += (y % 2) * 16;
-    addr += x % 16;
-    return addr;
+fn get_mip_block_depth(depth: usize, block_depth_mip0: usize) -> usize {
+    let mut block_depth = block_depth_mip0;
+    while block_depth > 1 && depth <= block_depth / 2 {
+        block_depth >>= 1;
+    }
+    block_depth
+}
+
+// Size in blocks of a mip level.
+fn get_mip_size_in_blocks(compression_type: CompressionType, w: usize, h: usize, depth: usize, mip: usize) -> (usize, usize, usize) {
+    let width_in_blocks = (w >> mip).div_ceil(get_format_block_width(compression_type)).max(1);
+    let height_in_blocks = (h >> mip).div_ceil(get_format_block_height(compression_type)).max(1);
+    (width_in_blocks, height_in_blocks, (depth >> mip).max(1))
 }
 
+fn deswizzle_mip(src: &[u8], layout: &BlockLinearLayout, width_in_blocks: usize, height_in_blocks: usize, depth: usize) -> Vec<u8> {
+    let bpp = layout.bpp;
+    let mut dst = vec![0x00; width_in_blocks * height_in_blocks * depth * bpp];
+
+    for z in 0..depth {
+        for y in 0..height_in_blocks {
+            for x in 0..width_in_blocks {
+                let src_offs = layout.get_addr(x, y, z);
+                let dst_offs = (((z * height_in_blocks) + y) * width_in_blocks + x) * bpp;
+                dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+            }
+        }
+    }
+
+    dst
+}
+
+fn swizzle_mip(src: &[u8], dst: &mut [u8], layout: &BlockLinearLayout, width_in_blocks: usize, height_in_blocks: usize, depth: usize) {
+    let bpp = layout.bpp;
+
+    for z in 0..depth {
+        for y in 0..height_in_blocks {
+            for x in 0..width_in_blocks {
+                let src_offs = (((z * height_in_blocks) + y) * width_in_blocks + x) * bpp;
+                let dst_offs = layout.get_addr(x, y, z);
+                dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+            }
+        }
+    }
+}
+
+// The inverse of tegra_deswizzle: packs linear blocks into a single block-linear surface.
 #[wasm_bindgen]
-pub fn tegra_deswizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Vec<u8> {
-    let format_block_width = get_format_block_width(compression_type);
-    let format_block_height = get_format_block_height(compression_type);
+pub fn tegra_swizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Vec<u8> {
+    let (width_in_blocks, height_in_blocks, _) = get_mip_size_in_blocks(compression_type, w, h, 1, 0);
+    let block_height = get_mip_block_height(height_in_blocks, 1 << block_height_log2);
+    let bpp = get_format_bytes_per_block(compression_type);
+    let layout = BlockLinearLayout::new(width_in_blocks, height_in_blocks, 1, bpp, block_height, 1);
 
-    let width_in_blocks = (w + format_block_width - 1) / format_block_width;
-    let height_in_blocks = (h + format_block_height - 1) / format_block_height;
+    let mut dst = vec![0x00; layout.size()];
+    swizzle_mip(src, &mut dst, &layout, width_in_blocks, height_in_blocks, 1);
+    dst
+}
 
-    let mut block_height = 1 << block_height_log2;
+#[wasm_bindgen]
+pub fn tegra_deswizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Vec<u8> {
+    let (width_in_blocks, height_in_blocks, _) = get_mip_size_in_blocks(compression_type, w, h, 1, 0);
+    let block_height = get_mip_block_height(height_in_blocks, 1 << block_height_log2);
     let bpp = get_format_bytes_per_block(compression_type);
+    let layout = BlockLinearLayout::new(width_in_blocks, height_in_blocks, 1, bpp, block_height, 1);
 
-    // Adjust block height down per mip to fit the image.
-    while block_height > 1 && (util::next_pow2(height_in_blocks) < (GOB_SIZE_Y * block_height)) {
+    // The result is padded out to the size of the swizzled data.
+    let mut dst = deswizzle_mip(src, &layout, width_in_blocks, height_in_blocks, 1);
+    dst.resize(dst.len().max(src.len()), 0x00);
+    dst
+}
+
+// Array layers start on a block of GOBs, using the block height of mip 0 cut down to the
+// height of the texture.
+fn align_layer_size(layer_size: usize, height_in_blocks: usize, depth: usize, block_height_mip0: usize, block_depth_mip0: usize) -> usize {
+    let mut block_height = block_height_mip0;
+    while block_height > 1 && height_in_blocks <= (block_height / 2) * GOB_SIZE_Y {
         block_height >>= 1;
-    };
+    }
+    let block_depth = get_mip_block_depth(depth, block_depth_mip0);
+    let alignment = GOB_SIZE * block_height * block_depth;
+    layer_size.div_ceil(alignment) * alignment
+}
 
-    let mut dst = vec![0x00; src.len() as usize];
+// Layout of a full surface, as stored in a BNTX: each array layer holds its full mip chain,
+// and layers are aligned to a block of GOBs. depth is 1 for anything but 3D textures.
+fn get_surface_layouts(compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, block_height_log2: usize) -> (Vec<(usize, BlockLinearLayout)>, usize) {
+    let bpp = get_format_bytes_per_block(compression_type);
+    let (_, height_in_blocks_mip0, _) = get_mip_size_in_blocks(compression_type, w, h, depth, 0);
+    let block_height_mip0 = 1 << block_height_log2;
+    let block_depth_mip0 = get_block_depth(depth);
 
-    for y in 0..height_in_blocks {
-        for x in 0..width_in_blocks {
-            let src_offs = get_addr_block_linear(x, y, width_in_blocks, bpp, block_height, 0);
-            let dst_offs = ((y * width_in_blocks) + x) * bpp;
-            dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+    let mut layouts = Vec::with_capacity(mip_count);
+    let mut offs = 0;
+    for mip in 0..mip_count {
+        let (width_in_blocks, height_in_blocks, mip_depth) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+        let block_height = get_mip_block_height(height_in_blocks, block_height_mip0);
+        let block_depth = get_mip_block_depth(mip_depth, block_depth_mip0);
+        let layout = BlockLinearLayout::new(width_in_blocks, height_in_blocks, mip_depth, bpp, block_height, block_depth);
+        let size = layout.size();
+        layouts.push((offs, layout));
+        offs += size;
+    }
+
+    let layer_size = align_layer_size(offs, height_in_blocks_mip0, depth, block_height_mip0, block_depth_mip0);
+    (layouts, layer_size)
+}
+
+#[wasm_bindgen]
+pub struct DeswizzledSurface {
+    mip_count: usize,
+    // Linear data for each layer and mip, with mips of the same layer next to each other.
+    #[wasm_bindgen(skip)]
+    pub subresources: Vec<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl DeswizzledSurface {
+    pub fn subresource(&self, layer: usize, mip: usize) -> Vec<u8> { self.subresources[layer * self.mip_count + mip].clone() }
+}
+
+// Size of a whole swizzled surface, including every layer and mip.
+#[wasm_bindgen]
+pub fn tegra_calc_surface_size(compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, layer_count: usize, block_height_log2: usize) -> usize {
+    let (layouts, layer_size) = get_surface_layouts(compression_type, w, h, depth, mip_count, block_height_log2);
+    if layer_count > 1 {
+        layer_size * layer_count
+    } else {
+        layouts.iter().map(|(_, layout)| layout.size()).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurfaceError {
+    // The swizzled data is shorter than tegra_calc_surface_size.
+    SourceTooShort { expected: usize, actual: usize },
+}
+
+impl fmt::Display for SurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SurfaceError::SourceTooShort { expected, actual } =>
+                write!(f, "surface data is {} bytes, expected at least {}", actual, expected),
         }
     }
+}
 
+impl std::error::Error for SurfaceError {}
+
+impl From<SurfaceError> for JsValue {
+    fn from(err: SurfaceError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+// Deswizzles every layer and mip of a surface, checking that src holds the whole surface first.
+#[allow(clippy::too_many_arguments)]
+pub fn try_tegra_deswizzle_surface(src: &[u8], compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, layer_count: usize, block_height_log2: usize) -> Result<DeswizzledSurface, SurfaceError> {
+    let expected = tegra_calc_surface_size(compression_type, w, h, depth, mip_count, layer_count, block_height_log2);
+    if src.len() < expected {
+        return Err(SurfaceError::SourceTooShort { expected, actual: src.len() });
+    }
+
+    let (layouts, layer_size) = get_surface_layouts(compression_type, w, h, depth, mip_count, block_height_log2);
+    let mut subresources = Vec::with_capacity(layer_count * mip_count);
+    for layer in 0..layer_count {
+        for (mip, (offs, layout)) in layouts.iter().enumerate() {
+            let (width_in_blocks, height_in_blocks, mip_depth) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+            let src_offs = layer * layer_size + offs;
+            subresources.push(deswizzle_mip(&src[src_offs..src_offs + layout.size()], layout, width_in_blocks, height_in_blocks, mip_depth));
+        }
+    }
+    Ok(DeswizzledSurface { mip_count, subresources })
+}
+
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen]
+pub fn tegra_deswizzle_surface(src: &[u8], compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, layer_count: usize, block_height_log2: usize) -> Result<DeswizzledSurface, JsValue> {
+    Ok(try_tegra_deswizzle_surface(src, compression_type, w, h, depth, mip_count, layer_count, block_height_log2)?)
+}
+
+// The inverse of tegra_deswizzle_surface: src holds the linear data for each layer and mip,
+// in the same order as DeswizzledSurface.
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen]
+pub fn tegra_swizzle_surface(src: &[u8], compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, layer_count: usize, block_height_log2: usize) -> Vec<u8> {
+    let (layouts, layer_size) = get_surface_layouts(compression_type, w, h, depth, mip_count, block_height_log2);
+    let bpp = get_format_bytes_per_block(compression_type);
+    let mut dst = vec![0x00; tegra_calc_surface_size(compression_type, w, h, depth, mip_count, layer_count, block_height_log2)];
+    let mut src_offs = 0;
+    for layer in 0..layer_count {
+        for (mip, (offs, layout)) in layouts.iter().enumerate() {
+            let (width_in_blocks, height_in_blocks, mip_depth) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+            let size = width_in_blocks * height_in_blocks * mip_depth * bpp;
+            let dst_offs = layer * layer_size + offs;
+            swizzle_mip(&src[src_offs..src_offs + size], &mut dst[dst_offs..dst_offs + layout.size()], layout, width_in_blocks, height_in_blocks, mip_depth);
+            src_offs += size;
+        }
+    }
     dst
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+
+    #[test]
+    fn test_deswizzle_formats() {
+        // A 30x20 texture, padded out to whole GOBs with a block height of 2.
+        for &(compression_type, w, h) in [
+            (CompressionType::R8G8B8A8, 30, 20),
+            (CompressionType::R5G6B5, 30, 20),
+            (CompressionType::Bc7, 120, 80),
+            (CompressionType::Astc5x4, 150, 80),
+            (CompressionType::Astc10x5, 300, 100),
+            (CompressionType::Astc12x12, 360, 240),
+        ].iter() {
+            let bpp = get_format_bytes_per_block(compression_type);
+            let width_in_blocks = w / get_format_block_width(compression_type);
+            let height_in_blocks = h / get_format_block_height(compression_type);
+            assert_eq!((width_in_blocks, height_in_blocks), (30, 20));
+
+            let layout = BlockLinearLayout::new(width_in_blocks, height_in_blocks, 1, bpp, 2, 1);
+            let mut src = vec![0x00; layout.size()];
+            for y in 0..height_in_blocks {
+                for x in 0..width_in_blocks {
+                    let addr = layout.get_addr(x, y, 0);
+                    for i in 0..bpp {
+                        src[addr + i] = (x ^ (y << 3) ^ (i << 5)) as u8;
+                    }
+                }
+            }
+
+            let dst = tegra_deswizzle(&src, compression_type, w, h, 1);
+            for y in 0..height_in_blocks {
+                for x in 0..width_in_blocks {
+                    for i in 0..bpp {
+                        assert_eq!(dst[(y * width_in_blocks + x) * bpp + i], (x ^ (y << 3) ^ (i << 5)) as u8, "{:?}", compression_type);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_surface_size() {
+        // Mips of 64x64, 32x32 and 16x16 with block heights of 8, 4 and 2: no padding.
+        assert_eq!(tegra_calc_surface_size(CompressionType::R8G8B8A8, 64, 64, 1, 3, 1, 3), 0x5400);
+        // Layers are aligned to 8 GOBs.
+        assert_eq!(tegra_calc_surface_size(CompressionType::R8G8B8A8, 64, 64, 1, 3, 2, 3), 0x6000 * 2);
+        // A 4x4 BC1 mip still takes a whole GOB.
+        assert_eq!(tegra_calc_surface_size(CompressionType::Bc1, 4, 4, 1, 1, 1, 4), 0x200);
+    }
+
+    fn check_surface(compression_type: CompressionType, w: usize, h: usize, depth: usize, mip_count: usize, layer_count: usize, block_height_log2: usize) {
+        let bpp = get_format_bytes_per_block(compression_type);
+        let (layouts, layer_size) = get_surface_layouts(compression_type, w, h, depth, mip_count, block_height_log2);
+        let tag = |layer: usize, mip: usize, x: usize, y: usize, z: usize, i: usize| (layer * 0x61 + mip * 0x35 + x * 7 + y * 13 + z * 29 + i) as u8;
+
+        let size = tegra_calc_surface_size(compression_type, w, h, depth, mip_count, layer_count, block_height_log2);
+        let mut src = vec![0x00; size];
+        let mut used = vec![false; size];
+        for layer in 0..layer_count {
+            for (mip, (offs, layout)) in layouts.iter().enumerate() {
+                let (bw, bh, bd) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+                for z in 0..bd {
+                    for y in 0..bh {
+                        for x in 0..bw {
+                            let addr = layer * layer_size + offs + layout.get_addr(x, y, z);
+                            assert!(addr + bpp <= offs + layout.size() + layer * layer_size);
+                            for i in 0..bpp {
+                                assert!(!used[addr + i]);
+                                used[addr + i] = true;
+                                src[addr + i] = tag(layer, mip, x, y, z, i);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let surface = try_tegra_deswizzle_surface(&src, compression_type, w, h, depth, mip_count, layer_count, block_height_log2).unwrap();
+        assert_eq!(surface.subresources.len(), layer_count * mip_count);
+        for layer in 0..layer_count {
+            for mip in 0..mip_count {
+                let (bw, bh, bd) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+                let mut expected = Vec::new();
+                for z in 0..bd {
+                    for y in 0..bh {
+                        for x in 0..bw {
+                            expected.extend((0..bpp).map(|i| tag(layer, mip, x, y, z, i)));
+                        }
+                    }
+                }
+                assert_eq!(surface.subresource(layer, mip), expected, "{:?} layer {} mip {}", compression_type, layer, mip);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deswizzle_surface() {
+        check_surface(CompressionType::R8G8B8A8, 64, 64, 1, 3, 2, 3);
+        // A cubemap with a non-power-of-two size.
+        check_surface(CompressionType::Bc1, 200, 200, 1, 8, 6, 4);
+        check_surface(CompressionType::Astc8x5, 100, 60, 1, 4, 3, 2);
+        // 3D textures use blocks of GOBs that are several slices deep.
+        check_surface(CompressionType::R8G8B8A8, 16, 16, 8, 4, 1, 1);
+        check_surface(CompressionType::R5G6B5, 40, 24, 5, 3, 1, 1);
+    }
+
+    #[test]
+    fn test_deswizzle_surface_short() {
+        let expected = tegra_calc_surface_size(CompressionType::R8G8B8A8, 64, 64, 1, 3, 2, 3);
+        let src = vec![0x00; expected - 1];
+        assert_eq!(try_tegra_deswizzle_surface(&src, CompressionType::R8G8B8A8, 64, 64, 1, 3, 2, 3).err(), Some(SurfaceError::SourceTooShort { expected, actual: expected - 1 }));
+        assert!(try_tegra_deswizzle_surface(&[], CompressionType::Bc1, 4, 4, 1, 1, 1, 4).is_err());
+    }
+
+    const ALL_COMPRESSION_TYPES: [CompressionType; 23] = [
+        CompressionType::Bc1, CompressionType::Bc2, CompressionType::Bc3, CompressionType::Bc4, CompressionType::Bc5,
+        CompressionType::R8G8B8A8, CompressionType::R5G6B5, CompressionType::Bc6h, CompressionType::Bc7,
+        CompressionType::Astc4x4, CompressionType::Astc5x4, CompressionType::Astc5x5, CompressionType::Astc6x5,
+        CompressionType::Astc6x6, CompressionType::Astc8x5, CompressionType::Astc8x6, CompressionType::Astc8x8,
+        CompressionType::Astc10x5, CompressionType::Astc10x6, CompressionType::Astc10x8, CompressionType::Astc10x10,
+        CompressionType::Astc12x10, CompressionType::Astc12x12,
+    ];
+
+    #[test]
+    fn test_swizzle_roundtrip() {
+        for &compression_type in ALL_COMPRESSION_TYPES.iter() {
+            for &(w, h, block_height_log2) in [(64, 64, 4), (123, 45, 3), (7, 300, 5), (1, 1, 0)].iter() {
+                let (width_in_blocks, height_in_blocks, _) = get_mip_size_in_blocks(compression_type, w, h, 1, 0);
+                let linear = noise(width_in_blocks * height_in_blocks * get_format_bytes_per_block(compression_type));
+                let swizzled = tegra_swizzle(&linear, compression_type, w, h, block_height_log2);
+                assert_eq!(swizzled.len(), tegra_calc_surface_size(compression_type, w, h, 1, 1, 1, block_height_log2));
+                let deswizzled = tegra_deswizzle(&swizzled, compression_type, w, h, block_height_log2);
+                assert_eq!(deswizzled[..linear.len()], linear[..], "{:?} {}x{}", compression_type, w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn test_swizzle_surface_roundtrip() {
+        for &compression_type in ALL_COMPRESSION_TYPES.iter() {
+            for &(w, h, depth, mip_count, layer_count) in [(200, 120, 1, 6, 6), (48, 40, 6, 4, 1)].iter() {
+                let bpp = get_format_bytes_per_block(compression_type);
+                let linear_size: usize = (0..mip_count).map(|mip| {
+                    let (bw, bh, bd) = get_mip_size_in_blocks(compression_type, w, h, depth, mip);
+                    bw * bh * bd * bpp
+                }).sum();
+                let linear = noise(linear_size * layer_count);
+                let swizzled = tegra_swizzle_surface(&linear, compression_type, w, h, depth, mip_count, layer_count, 4);
+                let surface = try_tegra_deswizzle_surface(&swizzled, compression_type, w, h, depth, mip_count, layer_count, 4).unwrap();
+                assert_eq!(surface.subresources.concat(), linear, "{:?}", compression_type);
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Fixtures shared by the unit tests.
+
+// The seed the noise in test_data was generated with; changing it breaks those files.
+const SEED: u64 = 0x2545F4914F6CDD1D;
+
+// Small xorshift generator so tests with random input are reproducible.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn new() -> Self {
+        Rng(SEED)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % (n as u64)) as usize
+    }
+}
+
+// `len` bytes of xorshift noise, the low byte of each step from the shared seed.
+pub(crate) fn noise(len: usize) -> Vec<u8> {
+    let mut rng = Rng::new();
+    (0..len).map(|_| rng.next_u8()).collect()
+}
@@ -0,0 +1,303 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use std::convert::TryFrom;
+use std::io::SeekFrom;
+use num_enum::TryFromPrimitive;
+
+use crate::compression::DecompressionError;
+use crate::lz4;
+use crate::lzma::{self, LzmaProperties};
+use crate::unity::asset::Endianness;
+use crate::unity::reader::{AssetReader, AssetReaderError, Result};
+
+// UnityFS bundles store their block info and data in blocks whose flags carry
+// the compression method in the low 6 bits.
+const COMPRESSION_TYPE_MASK: u32 = 0x3F;
+
+#[derive(Debug, Copy, Clone, PartialEq, TryFromPrimitive)]
+#[repr(u32)]
+pub enum CompressionType {
+    None = 0,
+    Lzma = 1,
+    Lz4 = 2,
+    Lz4HC = 3,
+}
+
+impl CompressionType {
+    pub fn from_flags(flags: u32) -> Result<CompressionType> {
+        let compression_type = flags & COMPRESSION_TYPE_MASK;
+        CompressionType::try_from(compression_type)
+            .map_err(|_| AssetReaderError::UnsupportedFeature(format!("compression type {}", compression_type)))
+    }
+}
+
+pub fn decompress_block(data: &[u8], flags: u32, uncompressed_size: usize) -> Result<Vec<u8>> {
+    match CompressionType::from_flags(flags)? {
+        CompressionType::None => Ok(data.to_vec()),
+        // LZ4HC only differs from LZ4 on the compression side.
+        CompressionType::Lz4 | CompressionType::Lz4HC => Ok(lz4::decompress_block(data, uncompressed_size)?),
+        CompressionType::Lzma => decompress_lzma_block(data, uncompressed_size),
+    }
+}
+
+// LZMA blocks start with the properties byte and the dictionary size (4 bytes,
+// little endian), followed by the raw LZMA data.
+fn decompress_lzma_block(data: &[u8], uncompressed_size: usize) -> Result<Vec<u8>> {
+    if data.len() < 5 {
+        return Err(DecompressionError::Truncated.into());
+    }
+
+    let props = LzmaProperties::from_byte(data[0])?;
+    let dict_size = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+    Ok(lzma::decompress_raw(&data[5..], props, dict_size, Some(uncompressed_size))?)
+}
+
+// Header flags. The low 6 bits are the block info's compression type.
+const FLAG_BLOCKS_INFO_AT_END: u32 = 0x80;
+const FLAG_BLOCKS_INFO_PADDING: u32 = 0x200;
+
+#[derive(Debug)]
+pub struct BundleHeader {
+    pub format_version: u32,
+    pub unity_version: String,
+    pub unity_revision: String,
+    pub size: i64,
+    pub compressed_blocks_info_size: u32,
+    pub uncompressed_blocks_info_size: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug)]
+pub struct BundleBlock {
+    pub uncompressed_size: u32,
+    pub compressed_size: u32,
+    pub flags: u16,
+}
+
+// A file stored in the bundle, as a range of the concatenated block data.
+#[derive(Debug)]
+pub struct BundleNode {
+    pub offset: i64,
+    pub size: i64,
+    pub flags: u32,
+    pub path: String,
+}
+
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct UnityBundle {
+    #[wasm_bindgen(skip)]
+    pub header: BundleHeader,
+    #[wasm_bindgen(skip)]
+    pub nodes: Vec<BundleNode>,
+    data: Vec<u8>,
+}
+
+impl UnityBundle {
+    pub fn node_data(&self, node: &BundleNode) -> Result<&[u8]> {
+        usize::try_from(node.offset).ok()
+            .zip(usize::try_from(node.size).ok())
+            .and_then(|(offset, size)| self.data.get(offset..offset.checked_add(size)?))
+            .ok_or_else(|| AssetReaderError::DeserializationError(format!("node {} is outside the bundle data", node.path)))
+    }
+}
+
+#[wasm_bindgen]
+impl UnityBundle {
+    pub fn deserialize(data: Vec<u8>) -> std::result::Result<UnityBundle, String> {
+        AssetReader::new(data).read_bundle()
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn file_path(&self, i: usize) -> Option<String> {
+        self.nodes.get(i).map(|node| node.path.clone())
+    }
+
+    pub fn file_data(&self, i: usize) -> std::result::Result<Vec<u8>, String> {
+        let node = self.nodes.get(i).ok_or_else(|| format!("no file {} in bundle", i))?;
+        self.node_data(node)
+            .map(|data| data.to_vec())
+            .map_err(|err| format!("{:?}", err))
+    }
+}
+
+// Only the UnityFS container (format versions 6 and up) is supported.
+impl AssetReader {
+    pub fn read_bundle(&mut self) -> Result<UnityBundle> {
+        self.set_endianness(Endianness::Big);
+        let header = self.read_bundle_header()?;
+
+        if header.format_version >= 7 {
+            self.align_to(16)?;
+        }
+
+        let blocks_info = if header.flags & FLAG_BLOCKS_INFO_AT_END != 0 {
+            let pos = self.current_pos()?;
+            self.seek(SeekFrom::End(-(header.compressed_blocks_info_size as i64)))?;
+            let blocks_info = self.read_bytes(header.compressed_blocks_info_size as usize)?;
+            self.seek(SeekFrom::Start(pos))?;
+            blocks_info
+        } else {
+            self.read_bytes(header.compressed_blocks_info_size as usize)?
+        };
+        let blocks_info = decompress_block(&blocks_info, header.flags, header.uncompressed_blocks_info_size as usize)?;
+
+        let mut blocks_info_reader = AssetReader::new(blocks_info);
+        blocks_info_reader.read_bytes(16)?; // uncompressed data hash
+        let block_count = blocks_info_reader.read_i32()?;
+        let mut blocks = Vec::new();
+        for _ in 0..block_count {
+            blocks.push(BundleBlock {
+                uncompressed_size: blocks_info_reader.read_u32()?,
+                compressed_size: blocks_info_reader.read_u32()?,
+                flags: blocks_info_reader.read_u16()?,
+            });
+        }
+        let node_count = blocks_info_reader.read_i32()?;
+        let mut nodes = Vec::new();
+        for _ in 0..node_count {
+            nodes.push(BundleNode {
+                offset: blocks_info_reader.read_i64()?,
+                size: blocks_info_reader.read_i64()?,
+                flags: blocks_info_reader.read_u32()?,
+                path: blocks_info_reader.read_null_terminated_string()?,
+            });
+        }
+
+        if header.flags & FLAG_BLOCKS_INFO_PADDING != 0 {
+            self.align_to(16)?;
+        }
+
+        let mut data = Vec::new();
+        for block in blocks.iter() {
+            let compressed = self.read_bytes(block.compressed_size as usize)?;
+            data.extend(decompress_block(&compressed, block.flags as u32, block.uncompressed_size as usize)?);
+        }
+
+        Ok(UnityBundle { header, nodes, data })
+    }
+
+    fn read_bundle_header(&mut self) -> Result<BundleHeader> {
+        let signature = self.read_null_terminated_string()?;
+        if signature != "UnityFS" {
+            return Err(AssetReaderError::UnsupportedFeature(format!("bundle signature {}", signature)));
+        }
+        let format_version = self.read_u32()?;
+        if format_version < 6 {
+            return Err(AssetReaderError::UnsupportedFileVersion(format_version));
+        }
+        Ok(BundleHeader {
+            format_version,
+            unity_version: self.read_null_terminated_string()?,
+            unity_revision: self.read_null_terminated_string()?,
+            size: self.read_i64()?,
+            compressed_blocks_info_size: self.read_u32()?,
+            uncompressed_blocks_info_size: self.read_u32()?,
+            flags: self.read_u32()?,
+        })
+    }
+
+    fn align_to(&mut self, alignment: u64) -> Result<()> {
+        let pos = self.current_pos()?;
+        self.seek(SeekFrom::Start((pos + alignment - 1) & !(alignment - 1)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_block() {
+        let src = b"\x45abcd\x04\x00\x10e";
+        assert_eq!(decompress_block(src, 0x43, 14).unwrap(), b"abcdabcdabcdae");
+        assert_eq!(decompress_block(src, 0x40, src.len()).unwrap(), src);
+        assert!(decompress_block(src, 0x05, 14).is_err());
+
+        let src = std::fs::read("test_data/compression/text.txt.lzma").unwrap();
+        let expected = std::fs::read("test_data/compression/text.txt").unwrap();
+        let block = [&src[..5], &src[13..]].concat();
+        assert_eq!(decompress_block(&block, 0x41, expected.len()).unwrap(), expected);
+    }
+
+    fn build_bundle(flags: u32, blocks_info: &[u8], uncompressed_blocks_info_size: usize, data: &[u8]) -> Vec<u8> {
+        let mut bundle = b"UnityFS\0".to_vec();
+        bundle.extend_from_slice(&7u32.to_be_bytes());
+        bundle.extend_from_slice(b"5.x.x\x002021.3.0f1\0");
+        bundle.extend_from_slice(&0i64.to_be_bytes());
+        bundle.extend_from_slice(&(blocks_info.len() as u32).to_be_bytes());
+        bundle.extend_from_slice(&(uncompressed_blocks_info_size as u32).to_be_bytes());
+        bundle.extend_from_slice(&flags.to_be_bytes());
+        bundle.resize((bundle.len() + 15) & !15, 0);
+        if flags & FLAG_BLOCKS_INFO_AT_END != 0 {
+            bundle.extend_from_slice(data);
+            bundle.extend_from_slice(blocks_info);
+        } else {
+            bundle.extend_from_slice(blocks_info);
+            bundle.extend_from_slice(data);
+        }
+        bundle
+    }
+
+    fn build_blocks_info(blocks: &[(u32, u32, u16)], nodes: &[(i64, i64, &str)]) -> Vec<u8> {
+        let mut info = vec![0; 16];
+        info.extend_from_slice(&(blocks.len() as i32).to_be_bytes());
+        for &(uncompressed_size, compressed_size, flags) in blocks {
+            info.extend_from_slice(&uncompressed_size.to_be_bytes());
+            info.extend_from_slice(&compressed_size.to_be_bytes());
+            info.extend_from_slice(&flags.to_be_bytes());
+        }
+        info.extend_from_slice(&(nodes.len() as i32).to_be_bytes());
+        for &(offset, size, path) in nodes {
+            info.extend_from_slice(&offset.to_be_bytes());
+            info.extend_from_slice(&size.to_be_bytes());
+            info.extend_from_slice(&4u32.to_be_bytes());
+            info.extend_from_slice(path.as_bytes());
+            info.push(0);
+        }
+        info
+    }
+
+    #[test]
+    fn test_read_bundle() {
+        // One LZ4HC block and one stored block, split across two files.
+        let blocks = [(14, 9, 0x03), (3, 3, 0x00)];
+        let nodes = [(0, 4, "CAB-a"), (4, 13, "CAB-a.resS")];
+        let data = b"\x45abcd\x04\x00\x10efgh";
+        let blocks_info = build_blocks_info(&blocks, &nodes);
+
+        for &flags in &[0x40, 0xC0] {
+            let bundle = build_bundle(flags, &blocks_info, blocks_info.len(), data);
+            let bundle = AssetReader::new(bundle).read_bundle().unwrap();
+            assert_eq!(bundle.header.unity_revision, "2021.3.0f1");
+            assert_eq!(bundle.file_count(), 2);
+            assert_eq!(bundle.file_path(1).unwrap(), "CAB-a.resS");
+            assert_eq!(bundle.node_data(&bundle.nodes[0]).unwrap(), b"abcd");
+            assert_eq!(bundle.node_data(&bundle.nodes[1]).unwrap(), b"abcdabcdaefgh");
+        }
+
+        // LZ4-compressed block info.
+        let compressed = [&[0xF0, blocks_info.len() as u8 - 0x0F][..], &blocks_info].concat();
+        let bundle = build_bundle(0x42, &compressed, blocks_info.len(), data);
+        let bundle = AssetReader::new(bundle).read_bundle().unwrap();
+        assert_eq!(bundle.node_data(&bundle.nodes[1]).unwrap(), b"abcdabcdaefgh");
+    }
+
+    #[test]
+    fn test_read_bundle_bad_input() {
+        assert!(AssetReader::new(b"UnityWeb\0".to_vec()).read_bundle().is_err());
+
+        let blocks_info = build_blocks_info(&[(3, 3, 0x00)], &[(2, 4, "CAB-a")]);
+        let bundle = build_bundle(0x40, &blocks_info, blocks_info.len(), b"abc");
+        let bundle = AssetReader::new(bundle).read_bundle().unwrap();
+        assert!(bundle.node_data(&bundle.nodes[0]).is_err());
+
+        let bundle = build_bundle(0x40, &blocks_info, blocks_info.len(), b"ab");
+        assert!(AssetReader::new(bundle).read_bundle().is_err());
+    }
+}
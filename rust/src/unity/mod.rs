@@ -8,6 +8,7 @@ pub mod reader;
 pub mod mesh;
 pub mod version;
 pub mod bitstream;
+pub mod bundle;
 
 #[wasm_bindgen]
 pub struct MeshMetadataArray {
@@ -8,6 +8,7 @@ use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use std::io::SeekFrom;
 use std::marker::Sized;
 
+use crate::compression::DecompressionError;
 use crate::unity::asset::*;
 use crate::unity::version::*;
 
@@ -20,6 +21,13 @@ pub enum AssetReaderError {
     UnsupportedFeature(String),
     InvalidVersion(VersionParseError),
     DeserializationError(String),
+    Decompression(DecompressionError),
+}
+
+impl From<DecompressionError> for AssetReaderError {
+    fn from(err: DecompressionError) -> Self {
+        AssetReaderError::Decompression(err)
+    }
 }
 
 impl From<VersionParseError> for AssetReaderError {
@@ -187,10 +195,12 @@ impl AssetReader {
 
     // possibly just return a &[u8]
     pub fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(n);
-        unsafe {
-            buf.set_len(n);
+        // Sizes often come straight from the file, so check them before allocating.
+        let remaining = (self.data.get_ref().len() as u64).saturating_sub(self.data.position());
+        if n as u64 > remaining {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
         }
+        let mut buf = vec![0; n];
         self.data.read_exact(&mut buf)?;
         Ok(buf)
     }
@@ -0,0 +1,108 @@
+// Nintendo Yay0 format.
+//
+// Similar to Yaz0 (an earlier version, likely), except it packs compressed data into three separate substreams.
+//
+// Header (16 bytes):
+//   Magic: "Yay0" (4 bytes)
+//   Uncompressed size (4 bytes, big endian)
+//   Offset to Lengths substream (4 bytes, big endian)
+//   Offset to Data substream (4 bytes, big endian)
+//
+// Flags substream starts at 0x10 (directly after Header)
+//
+// Data:
+//   Read Flags from Flags substream (1 byte)
+//   For each bit in the Flags byte, from MSB to LSB:
+//     If flag is 1:
+//       Literal: copy one byte from Data substream to dest.
+//     If flag is 0:
+//       Read LZ77 from Lengths substream (2 bytes, big endian):
+//         Length: bits 0-4
+//           If Length = 0, then read additional byte from Data (not Lengths!) substream, add 16, and add it to Length.
+//         Offset: bits 5-15
+//         Copy Length+2 bytes from Offset back in the output buffer.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::compression::{DecompressionError, Result, check_uncompressed_size, copy_back_reference, read_u8, read_u16_be, read_u32_be};
+
+pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    if !src.starts_with(b"Yay0") {
+        return Err(DecompressionError::BadMagic);
+    }
+
+    let mut header_offs = 0x04;
+    let uncompressed_size = read_u32_be(src, &mut header_offs)? as usize;
+    let mut lengths_offs = read_u32_be(src, &mut header_offs)? as usize;
+    let mut data_offs = read_u32_be(src, &mut header_offs)? as usize;
+    let mut flags_offs = 0x10;
+
+    // The longest back-reference is a 2-byte length plus an extra data byte, copying 0x111 bytes.
+    check_uncompressed_size(uncompressed_size, src.len() - 0x10, 0x111 / 3)?;
+    let mut dst = vec![0x00; uncompressed_size];
+    let mut dst_offs = 0x00;
+    while dst_offs < uncompressed_size {
+        let command_byte = read_u8(src, &mut flags_offs)?;
+
+        for i in (0..8).rev() {
+            if dst_offs >= uncompressed_size {
+                break;
+            }
+
+            if (command_byte & (1 << i)) != 0 {
+                // Literal.
+                dst[dst_offs] = read_u8(src, &mut data_offs)?;
+                dst_offs += 1;
+            } else {
+                let tmp = read_u16_be(src, &mut lengths_offs)? as usize;
+
+                let window_offset = (tmp & 0x0FFF) + 1;
+                let mut window_length = (tmp >> 12) + 2;
+                if window_length == 2 {
+                    window_length += (read_u8(src, &mut data_offs)? as usize) + 0x10;
+                }
+
+                copy_back_reference(&mut dst, dst_offs, window_offset, window_length)?;
+                dst_offs += window_length;
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn yay0dec(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // "abc", then copy 6 bytes from 3 back, then copy 0x14 bytes from 1 back.
+        let src = b"Yay0\x00\x00\x00\x1D\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x40\x02\x00\x00abc\x02";
+        let mut expected = b"abcabcabc".to_vec();
+        expected.extend_from_slice(&[b'c'; 0x14]);
+        assert_eq!(decompress(src).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(b"Yaz0\x00\x00\x00\x01"), Err(DecompressionError::BadMagic));
+        assert_eq!(decompress(b"Yay0\x00\x00\x00\x01"), Err(DecompressionError::Truncated));
+
+        let src = b"Yay0\x00\x00\x00\x1D\x00\x00\x00\x14\x00\x00\x00\x18\xE0\x00\x00\x00\x40\x02\x00\x00abc";
+        assert_eq!(decompress(src), Err(DecompressionError::Truncated));
+
+        let src = b"Yay0\x00\x00\x00\x04\x00\x00\x00\x14\x00\x00\x00\x16\x00\x00\x00\x00\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::InvalidBackReference { dst_offs: 0, distance: 1 }));
+
+        // A 4GB header with a few bytes of data must fail without allocating.
+        let src = b"Yay0\xFF\xFF\xFF\xFF\x00\x00\x00\x14\x00\x00\x00\x16\x00\x00\x00\x00\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::Truncated));
+    }
+}
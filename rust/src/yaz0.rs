@@ -1,4 +1,3 @@
-
 // Nintendo Yaz0 format.
 //
 // Header (8 bytes):
@@ -17,66 +16,551 @@
 //         Copy Length+2 bytes from Offset back in the output buffer.
 
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
 
 use std::convert::TryInto;
 
+use crate::compression::{DecompressionError, Result, check_uncompressed_size, copy_back_reference, read_u8, read_u16_be};
+
 fn get_u32_be(src: &[u8], i: usize) -> u32 {
     u32::from_be_bytes(src[i..i+4].try_into().unwrap())
 }
 
-fn get_u16_be(src: &[u8], i: usize) -> u16 {
-    u16::from_be_bytes(src[i..i+2].try_into().unwrap())
-}
-
-#[wasm_bindgen]
-pub fn yaz0dec(src: &[u8]) -> Vec<u8> {
-    let magic = &src[0..4];
+pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    if !src.starts_with(b"Yaz0") {
+        return Err(DecompressionError::BadMagic);
+    }
 
-    if magic != b"Yaz0" {
-        panic!("bad header instead of good header");
+    if src.len() < 0x10 {
+        return Err(DecompressionError::Truncated);
     }
 
-    let mut uncompressed_size = get_u32_be(src, 0x04);
-    let mut dst = vec![0x00; uncompressed_size as usize];
+    // The longest back-reference is 3 bytes long and copies 0x111 bytes.
+    let uncompressed_size = get_u32_be(src, 0x04) as usize;
+    check_uncompressed_size(uncompressed_size, src.len() - 0x10, 0x111 / 3)?;
+    let mut dst = vec![0x00; uncompressed_size];
 
     let mut src_offs = 0x10;
     let mut dst_offs = 0x00;
-    loop {
-        let command_byte = src[src_offs];
-        src_offs += 1;
+    while dst_offs < uncompressed_size {
+        let command_byte = read_u8(src, &mut src_offs)?;
 
         for i in (0..8).rev() {
+            if dst_offs >= uncompressed_size {
+                break;
+            }
+
             if (command_byte & (1 << i)) != 0 {
                 // Literal.
-                dst[dst_offs] = src[src_offs];
-                src_offs += 1;
+                dst[dst_offs] = read_u8(src, &mut src_offs)?;
                 dst_offs += 1;
-                uncompressed_size -= 1;
             } else {
-                let tmp = get_u16_be(src, src_offs);
-                src_offs += 2;
+                let tmp = read_u16_be(src, &mut src_offs)? as usize;
+
+                let window_offset = (tmp & 0x0FFF) + 1;
+                let mut window_length = (tmp >> 12) + 2;
+                if window_length == 2 {
+                    window_length += (read_u8(src, &mut src_offs)? as usize) + 0x10;
+                }
+
+                copy_back_reference(&mut dst, dst_offs, window_offset, window_length)?;
+                dst_offs += window_length;
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn yaz0dec(src: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+    Ok(decompress(src)?)
+}
+
+// Streaming decoder. Compressed data can be fed in arbitrarily-sized chunks as
+// it arrives; each call returns the bytes that could be decompressed so far.
+// Only the last 0x1000 bytes of output are kept around for back-references.
+
+const DECODER_WINDOW_SIZE: usize = 0x1000;
+
+#[wasm_bindgen]
+pub struct Yaz0Decoder {
+    header: Vec<u8>,
+    uncompressed_size: Option<usize>,
+    // Input that has been received but does not yet form a complete operation.
+    pending: Vec<u8>,
+    command_byte: u8,
+    command_bits_left: u8,
+    window: Box<[u8; DECODER_WINDOW_SIZE]>,
+    dst_offs: usize,
+}
+
+impl Default for Yaz0Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Yaz0Decoder {
+    fn emit(&mut self, v: u8, out: &mut Vec<u8>) {
+        self.window[self.dst_offs % DECODER_WINDOW_SIZE] = v;
+        self.dst_offs += 1;
+        out.push(v);
+    }
+
+    // Decompresses as much of chunk as possible, appending the output to out.
+    pub fn decode_chunk(&mut self, chunk: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut chunk = chunk;
+
+        let uncompressed_size = match self.uncompressed_size {
+            Some(v) => v,
+            None => {
+                let n = (0x10 - self.header.len()).min(chunk.len());
+                self.header.extend_from_slice(&chunk[..n]);
+                chunk = &chunk[n..];
+
+                let magic_len = self.header.len().min(4);
+                if self.header[..magic_len] != b"Yaz0"[..magic_len] {
+                    return Err(DecompressionError::BadMagic);
+                }
+
+                if self.header.len() < 0x10 {
+                    return Ok(());
+                }
+
+                let v = get_u32_be(&self.header, 0x04) as usize;
+                self.uncompressed_size = Some(v);
+                v
+            },
+        };
 
+        self.pending.extend_from_slice(chunk);
+
+        let mut src_offs = 0;
+        while self.dst_offs < uncompressed_size {
+            let src = &self.pending[src_offs..];
+
+            if self.command_bits_left == 0 {
+                match src.first() {
+                    Some(&v) => { self.command_byte = v; self.command_bits_left = 8; src_offs += 1; },
+                    None => break,
+                }
+                continue;
+            }
+
+            if (self.command_byte & 0x80) != 0 {
+                // Literal.
+                let v = match src.first() {
+                    Some(&v) => v,
+                    None => break,
+                };
+                src_offs += 1;
+                self.emit(v, out);
+            } else {
+                if src.len() < 2 {
+                    break;
+                }
+
+                let tmp = ((src[0] as usize) << 8) | (src[1] as usize);
                 let window_offset = (tmp & 0x0FFF) + 1;
                 let mut window_length = (tmp >> 12) + 2;
                 if window_length == 2 {
-                    window_length += (src[src_offs] as u16) + 0x10;
-                    src_offs += 1;
+                    match src.get(2) {
+                        Some(&v) => { window_length += (v as usize) + 0x10; src_offs += 1; },
+                        None => break,
+                    }
                 }
+                src_offs += 2;
 
-                assert!(window_length >= 3 && window_length <= 0x111);
+                if window_offset > self.dst_offs {
+                    return Err(DecompressionError::InvalidBackReference { dst_offs: self.dst_offs, distance: window_offset });
+                }
+
+                if self.dst_offs + window_length > uncompressed_size {
+                    return Err(DecompressionError::OutputOverrun);
+                }
 
-                let mut copy_offs = dst_offs - (window_offset as usize);
                 for _ in 0..window_length {
-                    dst[dst_offs] = dst[copy_offs];
-                    dst_offs += 1;
-                    copy_offs += 1;
-                    uncompressed_size -= 1;
+                    let v = self.window[(self.dst_offs - window_offset) % DECODER_WINDOW_SIZE];
+                    self.emit(v, out);
                 }
             }
 
-            if uncompressed_size <= 0 {
-                return dst
+            self.command_byte <<= 1;
+            self.command_bits_left -= 1;
+        }
+
+        self.pending.drain(..src_offs);
+        Ok(())
+    }
+
+    // Checks that the stream was complete once all the input has been fed in.
+    pub fn finish(&self) -> Result<()> {
+        if self.is_finished() {
+            Ok(())
+        } else {
+            Err(DecompressionError::Truncated)
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Yaz0Decoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Yaz0Decoder {
+            header: Vec::with_capacity(0x10),
+            uncompressed_size: None,
+            pending: Vec::new(),
+            command_byte: 0,
+            command_bits_left: 0,
+            window: Box::new([0x00; DECODER_WINDOW_SIZE]),
+            dst_offs: 0,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> std::result::Result<Vec<u8>, JsValue> {
+        let mut out = Vec::new();
+        self.decode_chunk(chunk, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn uncompressed_size(&self) -> Option<usize> {
+        self.uncompressed_size
+    }
+
+    pub fn bytes_written(&self) -> usize {
+        self.dst_offs
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.uncompressed_size == Some(self.dst_offs)
+    }
+}
+
+// Encoder. Matches are found with hash chains over the 0x1000-byte window.
+// Fast mode takes the first good-enough match greedily; Optimal mode searches
+// the whole window and uses lazy matching, deferring a match by one byte if
+// the next position has a longer one.
+
+const WINDOW_SIZE: usize = 0x1000;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x111;
+
+const HASH_BITS: usize = 15;
+const NIL: usize = usize::MAX;
+
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Yaz0Mode {
+    Fast,
+    Optimal,
+}
+
+struct MatchFinder<'a> {
+    src: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    max_chain: usize,
+    good_length: usize,
+    next_insert: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(src: &'a [u8], mode: Yaz0Mode) -> Self {
+        let (max_chain, good_length) = match mode {
+            Yaz0Mode::Fast => (16, 0x20),
+            Yaz0Mode::Optimal => (WINDOW_SIZE, MAX_MATCH),
+        };
+
+        MatchFinder {
+            src,
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: vec![NIL; WINDOW_SIZE],
+            max_chain,
+            good_length,
+            next_insert: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let v = ((self.src[pos] as u32) << 16) | ((self.src[pos + 1] as u32) << 8) | (self.src[pos + 2] as u32);
+        (v.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    // Insert every position up to (but not including) pos into the chains.
+    fn advance_to(&mut self, pos: usize) {
+        while self.next_insert < pos {
+            let p = self.next_insert;
+            if p + MIN_MATCH <= self.src.len() {
+                let h = self.hash(p);
+                self.prev[p % WINDOW_SIZE] = self.head[h];
+                self.head[h] = p;
+            }
+            self.next_insert += 1;
+        }
+    }
+
+    // Returns (length, distance) of the longest match at pos, if any.
+    fn find(&mut self, pos: usize) -> Option<(usize, usize)> {
+        self.advance_to(pos);
+
+        if pos + MIN_MATCH > self.src.len() {
+            return None;
+        }
+
+        let max_length = MAX_MATCH.min(self.src.len() - pos);
+        let mut best_length = MIN_MATCH - 1;
+        let mut best_dist = 0;
+
+        let mut cand = self.head[self.hash(pos)];
+        let mut chain = self.max_chain;
+        while cand != NIL && pos - cand <= WINDOW_SIZE && chain > 0 {
+            // Quick reject: the candidate must beat the current best.
+            if self.src[cand + best_length] == self.src[pos + best_length] {
+                let mut length = 0;
+                while length < max_length && self.src[cand + length] == self.src[pos + length] {
+                    length += 1;
+                }
+
+                if length > best_length {
+                    best_length = length;
+                    best_dist = pos - cand;
+                    if length >= max_length || length >= self.good_length {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[cand % WINDOW_SIZE];
+            if next == NIL || next >= cand {
+                break;
             }
+            cand = next;
+            chain -= 1;
         }
+
+        if best_length >= MIN_MATCH {
+            Some((best_length, best_dist))
+        } else {
+            None
+        }
+    }
+}
+
+struct Yaz0Writer {
+    dst: Vec<u8>,
+    flag_offs: usize,
+    flag_bit: u8,
+}
+
+impl Yaz0Writer {
+    fn new(uncompressed_size: usize) -> Self {
+        let mut dst = Vec::with_capacity(0x10 + uncompressed_size + uncompressed_size / 8 + 1);
+        dst.extend_from_slice(b"Yaz0");
+        dst.extend_from_slice(&(uncompressed_size as u32).to_be_bytes());
+        dst.extend_from_slice(&[0x00; 8]);
+        Yaz0Writer { dst, flag_offs: 0, flag_bit: 0 }
+    }
+
+    fn next_flag(&mut self) -> u8 {
+        if self.flag_bit == 0 {
+            self.flag_offs = self.dst.len();
+            self.dst.push(0x00);
+            self.flag_bit = 8;
+        }
+        self.flag_bit -= 1;
+        1 << self.flag_bit
+    }
+
+    fn literal(&mut self, v: u8) {
+        let flag = self.next_flag();
+        self.dst[self.flag_offs] |= flag;
+        self.dst.push(v);
+    }
+
+    fn back_reference(&mut self, length: usize, dist: usize) {
+        debug_assert!((MIN_MATCH..=MAX_MATCH).contains(&length));
+        debug_assert!((1..=WINDOW_SIZE).contains(&dist));
+
+        self.next_flag();
+        let window_offset = (dist - 1) as u16;
+        if length < 0x12 {
+            let tmp = (((length - 2) as u16) << 12) | window_offset;
+            self.dst.extend_from_slice(&tmp.to_be_bytes());
+        } else {
+            self.dst.extend_from_slice(&window_offset.to_be_bytes());
+            self.dst.push((length - 0x12) as u8);
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn yaz0enc(src: &[u8], mode: Yaz0Mode) -> Vec<u8> {
+    let mut finder = MatchFinder::new(src, mode);
+    let mut writer = Yaz0Writer::new(src.len());
+
+    let mut pos = 0;
+    while pos < src.len() {
+        let m = finder.find(pos);
+
+        let m = match (m, mode) {
+            (Some((length, _)), Yaz0Mode::Optimal) if length < MAX_MATCH => {
+                // Lazy matching: if the next byte starts a longer match,
+                // emit a literal here and take that one instead.
+                match finder.find(pos + 1) {
+                    Some((next_length, _)) if next_length > length + 1 => None,
+                    _ => m,
+                }
+            },
+            _ => m,
+        };
+
+        match m {
+            Some((length, dist)) => {
+                writer.back_reference(length, dist);
+                pos += length;
+            },
+            None => {
+                writer.literal(src[pos]);
+                pos += 1;
+            },
+        }
+    }
+
+    writer.dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+
+    // Random data with a mix of literals, short repeats, long runs and
+    // far-away copies, to exercise every kind of back-reference.
+    fn gen_input(rng: &mut Rng, len: usize) -> Vec<u8> {
+        let alphabet = 1 + rng.below(256);
+        let mut v = Vec::with_capacity(len);
+        while v.len() < len {
+            match rng.below(4) {
+                0 => v.push(rng.below(alphabet) as u8),
+                1 => {
+                    let b = rng.below(alphabet) as u8;
+                    let n = rng.below(0x200);
+                    v.resize(v.len() + n, b);
+                },
+                _ if !v.is_empty() => {
+                    let dist = 1 + rng.below(v.len().min(0x1400));
+                    let n = rng.below(0x180);
+                    for _ in 0..n {
+                        v.push(v[v.len() - dist]);
+                    }
+                },
+                _ => {},
+            }
+        }
+        v.truncate(len);
+        v
+    }
+
+    fn round_trip(src: &[u8]) {
+        for &mode in &[Yaz0Mode::Fast, Yaz0Mode::Optimal] {
+            let compressed = yaz0enc(src, mode);
+            assert_eq!(decompress(&compressed).unwrap(), src, "mode {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_simple() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcabcabcabcabcabcabc");
+        round_trip(&[0x00; 0x1000]);
+        round_trip(&[0xAB; MAX_MATCH * 3 + 1]);
+    }
+
+    #[test]
+    fn test_round_trip_random() {
+        let mut rng = Rng::new();
+        for _ in 0..200 {
+            let len = 1 + rng.below(0x4000);
+            let src = gen_input(&mut rng, len);
+            round_trip(&src);
+        }
+    }
+
+    #[test]
+    fn test_compresses() {
+        let src = [0x00; 0x1000];
+        let compressed = yaz0enc(&src, Yaz0Mode::Fast);
+        assert!(compressed.len() < 0x10 + 0x40);
+        assert_eq!(&compressed[0..4], b"Yaz0");
+        assert_eq!(get_u32_be(&compressed, 0x04), 0x1000);
+    }
+
+    #[test]
+    fn test_optimal_not_worse() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        let src = gen_input(&mut rng, 0x8000);
+        let fast = yaz0enc(&src, Yaz0Mode::Fast);
+        let optimal = yaz0enc(&src, Yaz0Mode::Optimal);
+        assert!(optimal.len() <= fast.len());
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert_eq!(decompress(b"Yay0\0\0\0\x01"), Err(DecompressionError::BadMagic));
+        assert_eq!(decompress(b"Yaz0\0\0\0\x01"), Err(DecompressionError::Truncated));
+
+        let compressed = yaz0enc(&[0x55; 0x100], Yaz0Mode::Fast);
+        for len in 0x10..compressed.len() {
+            assert_eq!(decompress(&compressed[..len]), Err(DecompressionError::Truncated));
+        }
+
+        // Back-reference of distance 1 with nothing written yet.
+        let src = b"Yaz0\0\0\0\x04\0\0\0\0\0\0\0\0\x00\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::InvalidBackReference { dst_offs: 0, distance: 1 }));
+
+        // Literal followed by a copy of 3 bytes, but only 3 bytes of output declared.
+        let src = b"Yaz0\0\0\0\x03\0\0\0\0\0\0\0\0\x80\xAA\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::OutputOverrun));
+
+        // A 4GB header with a few bytes of data must fail without allocating.
+        let src = b"Yaz0\xFF\xFF\xFF\xFF\0\0\0\0\0\0\0\0\x00\x10\x00";
+        assert_eq!(decompress(src), Err(DecompressionError::Truncated));
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for _ in 0x00..0x20 {
+            let len = 1 + rng.below(0x6000);
+            let src = gen_input(&mut rng, len);
+            let compressed = yaz0enc(&src, Yaz0Mode::Optimal);
+
+            let mut decoder = Yaz0Decoder::new();
+            let mut out = Vec::new();
+            let mut offs = 0;
+            while offs < compressed.len() {
+                let n = (1 + rng.below(0x40)).min(compressed.len() - offs);
+                decoder.decode_chunk(&compressed[offs..offs + n], &mut out).unwrap();
+                offs += n;
+            }
+
+            decoder.finish().unwrap();
+            assert_eq!(out, src);
+        }
+    }
+
+    #[test]
+    fn test_streaming_truncated() {
+        let compressed = yaz0enc(&[0x55; 0x100], Yaz0Mode::Fast);
+        let mut decoder = Yaz0Decoder::new();
+        let mut out = Vec::new();
+        decoder.decode_chunk(&compressed[..compressed.len() - 1], &mut out).unwrap();
+        assert_eq!(decoder.finish(), Err(DecompressionError::Truncated));
+
+        let mut decoder = Yaz0Decoder::new();
+        assert_eq!(decoder.decode_chunk(b"Yay", &mut out), Err(DecompressionError::BadMagic));
     }
 }
@@ -81,8 +81,25 @@ export function isChannelFormatSupported(channelFormat: ChannelFormat): boolean
     case ChannelFormat.Bc2:
     case ChannelFormat.Bc3:
     case ChannelFormat.Bc5:
+    case ChannelFormat.Bc6:
+    case ChannelFormat.Bc7:
     case ChannelFormat.R8_G8_B8_A8:
     case ChannelFormat.B8_G8_R8_A8:
+    case ChannelFormat.R5_G6_B5:
+    case ChannelFormat.Astc_4x4:
+    case ChannelFormat.Astc_5x4:
+    case ChannelFormat.Astc_5x5:
+    case ChannelFormat.Astc_6x5:
+    case ChannelFormat.Astc_6x6:
+    case ChannelFormat.Astc_8x5:
+    case ChannelFormat.Astc_8x6:
+    case ChannelFormat.Astc_8x8:
+    case ChannelFormat.Astc_10x5:
+    case ChannelFormat.Astc_10x6:
+    case ChannelFormat.Astc_10x8:
+    case ChannelFormat.Astc_10x10:
+    case ChannelFormat.Astc_12x10:
+    case ChannelFormat.Astc_12x12:
         return true;
     default:
         return false;
@@ -97,10 +114,28 @@ export function getFormatBytesPerPixel(channelFormat: ChannelFormat): number {
     case ChannelFormat.Bc2:
     case ChannelFormat.Bc3:
     case ChannelFormat.Bc5:
+    case ChannelFormat.Bc6:
+    case ChannelFormat.Bc7:
+    case ChannelFormat.Astc_4x4:
+    case ChannelFormat.Astc_5x4:
+    case ChannelFormat.Astc_5x5:
+    case ChannelFormat.Astc_6x5:
+    case ChannelFormat.Astc_6x6:
+    case ChannelFormat.Astc_8x5:
+    case ChannelFormat.Astc_8x6:
+    case ChannelFormat.Astc_8x8:
+    case ChannelFormat.Astc_10x5:
+    case ChannelFormat.Astc_10x6:
+    case ChannelFormat.Astc_10x8:
+    case ChannelFormat.Astc_10x10:
+    case ChannelFormat.Astc_12x10:
+    case ChannelFormat.Astc_12x12:
         return 16;
     case ChannelFormat.R8_G8_B8_A8:
     case ChannelFormat.B8_G8_R8_A8:
         return 4;
+    case ChannelFormat.R5_G6_B5:
+        return 2;
     default:
         throw "whoops";
     }
@@ -114,17 +149,81 @@ export interface SwizzledSurface {
     blockHeightLog2: number; // The block height of mip0.
 }
 
+// Loaded by deswizzle; decompress always runs on its output, so it can use this directly.
+let _wasm: typeof import("../../rust/pkg/index") | null = null;
+
 export async function deswizzle(swizzledSurface: SwizzledSurface): Promise<Uint8Array> {
-    const { tegra_deswizzle, CompressionType } = await import("../../rust/pkg/index");
-    const { buffer, channelFormat, width, height, blockHeightLog2 } = swizzledSurface;
-    const compressionType =
+    if (_wasm === null)
+        _wasm = await import("../../rust/pkg/index");
+    const { tegra_deswizzle } = _wasm;
+    const compressionType = getCompressionType(swizzledSurface.channelFormat);
+    const { buffer, width, height, blockHeightLog2 } = swizzledSurface;
+    return tegra_deswizzle(buffer.createTypedArray(Uint8Array), compressionType, width, height, blockHeightLog2);
+}
+
+function getCompressionType(channelFormat: ChannelFormat) {
+    const { CompressionType } = _wasm!;
+    return (
         channelFormat === ChannelFormat.Bc1 ? CompressionType.Bc1 :
         channelFormat === ChannelFormat.Bc2 ? CompressionType.Bc2 :
         channelFormat === ChannelFormat.Bc3 ? CompressionType.Bc3 :
         channelFormat === ChannelFormat.Bc4 ? CompressionType.Bc4 :
         channelFormat === ChannelFormat.Bc5 ? CompressionType.Bc5 :
-        undefined!;
-    return tegra_deswizzle(buffer.createTypedArray(Uint8Array), compressionType, width, height, blockHeightLog2);
+        channelFormat === ChannelFormat.Bc6 ? CompressionType.Bc6h :
+        channelFormat === ChannelFormat.Bc7 ? CompressionType.Bc7 :
+        channelFormat === ChannelFormat.R8_G8_B8_A8 ? CompressionType.R8G8B8A8 :
+        channelFormat === ChannelFormat.B8_G8_R8_A8 ? CompressionType.R8G8B8A8 :
+        channelFormat === ChannelFormat.R5_G6_B5 ? CompressionType.R5G6B5 :
+        channelFormat === ChannelFormat.Astc_4x4 ? CompressionType.Astc4x4 :
+        channelFormat === ChannelFormat.Astc_5x4 ? CompressionType.Astc5x4 :
+        channelFormat === ChannelFormat.Astc_5x5 ? CompressionType.Astc5x5 :
+        channelFormat === ChannelFormat.Astc_6x5 ? CompressionType.Astc6x5 :
+        channelFormat === ChannelFormat.Astc_6x6 ? CompressionType.Astc6x6 :
+        channelFormat === ChannelFormat.Astc_8x5 ? CompressionType.Astc8x5 :
+        channelFormat === ChannelFormat.Astc_8x6 ? CompressionType.Astc8x6 :
+        channelFormat === ChannelFormat.Astc_8x8 ? CompressionType.Astc8x8 :
+        channelFormat === ChannelFormat.Astc_10x5 ? CompressionType.Astc10x5 :
+        channelFormat === ChannelFormat.Astc_10x6 ? CompressionType.Astc10x6 :
+        channelFormat === ChannelFormat.Astc_10x8 ? CompressionType.Astc10x8 :
+        channelFormat === ChannelFormat.Astc_10x10 ? CompressionType.Astc10x10 :
+        channelFormat === ChannelFormat.Astc_12x10 ? CompressionType.Astc12x10 :
+        channelFormat === ChannelFormat.Astc_12x12 ? CompressionType.Astc12x12 :
+        undefined!
+    );
+}
+
+function expand5to8(n: number): number {
+    return (n << (8 - 5)) | (n >>> (10 - 8));
+}
+
+function expand6to8(n: number): number {
+    return (n << (8 - 6)) | (n >>> (12 - 8));
+}
+
+// Red is in the top five bits.
+function decodeR5G6B5(src: Uint8Array): Uint8Array {
+    const dst = new Uint8Array(src.length * 2);
+    for (let i = 0, j = 0; i < src.length; i += 2, j += 4) {
+        const p = src[i + 0] | (src[i + 1] << 8);
+        dst[j + 0] = expand5to8((p >>> 11) & 0x1F);
+        dst[j + 1] = expand6to8((p >>> 5) & 0x3F);
+        dst[j + 2] = expand5to8(p & 0x1F);
+        dst[j + 3] = 0xFF;
+    }
+    return dst;
+}
+
+// Software-decoded surfaces are 8-bit, so BC6H's half floats are clamped to [0, 1].
+function halfToUnorm8(src: Uint16Array): Uint8Array {
+    const dst = new Uint8Array(src.length);
+    for (let i = 0; i < src.length; i++) {
+        const sign = src[i] >>> 15;
+        const exponent = (src[i] >>> 10) & 0x1F;
+        const mantissa = src[i] & 0x3FF;
+        const v = exponent === 0 ? mantissa * Math.pow(2, -24) : (1 + mantissa / 0x400) * Math.pow(2, exponent - 15);
+        dst[i] = sign ? 0 : Math.min(Math.round(v * 0xFF), 0xFF);
+    }
+    return dst;
 }
 
 export function decompress(textureEntry: BRTI, pixels: Uint8Array): DecodedSurfaceSW {
@@ -147,6 +246,31 @@ export function decompress(textureEntry: BRTI, pixels: Uint8Array): DecodedSurfa
     case ChannelFormat.R8_G8_B8_A8:
         assert(typeFormat === TypeFormat.Unorm || typeFormat === TypeFormat.UnormSrgb);
         return { ... textureEntry, type: 'RGBA', flag: typeFormat === TypeFormat.Unorm ? 'UNORM' : 'SRGB', pixels };
+    case ChannelFormat.R5_G6_B5:
+        assert(typeFormat === TypeFormat.Unorm);
+        return { ...textureEntry, type: 'RGBA', flag: 'UNORM', pixels: decodeR5G6B5(pixels) };
+    case ChannelFormat.Bc6:
+        assert(typeFormat === TypeFormat.Ufloat || typeFormat === TypeFormat.Float);
+        return { ...textureEntry, type: 'RGBA', flag: 'UNORM', pixels: halfToUnorm8(_wasm!.decode_bc6h(pixels, textureEntry.width, textureEntry.height, typeFormat === TypeFormat.Float)) };
+    case ChannelFormat.Bc7:
+        assert(typeFormat === TypeFormat.Unorm || typeFormat === TypeFormat.UnormSrgb);
+        return { ...textureEntry, type: 'RGBA', flag: typeFormat === TypeFormat.Unorm ? 'UNORM' : 'SRGB', pixels: _wasm!.decode_bc(pixels, _wasm!.BcFormat.Bc7, textureEntry.width, textureEntry.height) };
+    case ChannelFormat.Astc_4x4:
+    case ChannelFormat.Astc_5x4:
+    case ChannelFormat.Astc_5x5:
+    case ChannelFormat.Astc_6x5:
+    case ChannelFormat.Astc_6x6:
+    case ChannelFormat.Astc_8x5:
+    case ChannelFormat.Astc_8x6:
+    case ChannelFormat.Astc_8x8:
+    case ChannelFormat.Astc_10x5:
+    case ChannelFormat.Astc_10x6:
+    case ChannelFormat.Astc_10x8:
+    case ChannelFormat.Astc_10x10:
+    case ChannelFormat.Astc_12x10:
+    case ChannelFormat.Astc_12x12:
+        assert(typeFormat === TypeFormat.Unorm || typeFormat === TypeFormat.UnormSrgb);
+        return { ...textureEntry, type: 'RGBA', flag: typeFormat === TypeFormat.Unorm ? 'UNORM' : 'SRGB', pixels: _wasm!.decode_astc_tegra(pixels, getCompressionType(channelFormat), textureEntry.width, textureEntry.height, typeFormat === TypeFormat.UnormSrgb) };
     default:
         console.error(channelFormat.toString(16));
         throw "whoops";
@@ -163,8 +287,42 @@ function getChannelFormatString(channelFormat: ChannelFormat): string {
         return 'BC4';
     case ChannelFormat.Bc5:
         return 'BC5';
+    case ChannelFormat.Bc6:
+        return 'BC6H';
+    case ChannelFormat.Bc7:
+        return 'BC7';
     case ChannelFormat.R8_G8_B8_A8:
         return 'R8_G8_B8_A8';
+    case ChannelFormat.R5_G6_B5:
+        return 'R5_G6_B5';
+    case ChannelFormat.Astc_4x4:
+        return 'ASTC 4x4';
+    case ChannelFormat.Astc_5x4:
+        return 'ASTC 5x4';
+    case ChannelFormat.Astc_5x5:
+        return 'ASTC 5x5';
+    case ChannelFormat.Astc_6x5:
+        return 'ASTC 6x5';
+    case ChannelFormat.Astc_6x6:
+        return 'ASTC 6x6';
+    case ChannelFormat.Astc_8x5:
+        return 'ASTC 8x5';
+    case ChannelFormat.Astc_8x6:
+        return 'ASTC 8x6';
+    case ChannelFormat.Astc_8x8:
+        return 'ASTC 8x8';
+    case ChannelFormat.Astc_10x5:
+        return 'ASTC 10x5';
+    case ChannelFormat.Astc_10x6:
+        return 'ASTC 10x6';
+    case ChannelFormat.Astc_10x8:
+        return 'ASTC 10x8';
+    case ChannelFormat.Astc_10x10:
+        return 'ASTC 10x10';
+    case ChannelFormat.Astc_12x10:
+        return 'ASTC 12x10';
+    case ChannelFormat.Astc_12x12:
+        return 'ASTC 12x12';
     default:
         throw "whoops";
     }
@@ -178,6 +336,10 @@ function getTypeFormatString(typeFormat: TypeFormat): string {
         return 'SNORM';
     case TypeFormat.UnormSrgb:
         return 'SRGB';
+    case TypeFormat.Ufloat:
+        return 'UFLOAT';
+    case TypeFormat.Float:
+        return 'FLOAT';
     default:
         throw "whoops";
     }
@@ -200,6 +362,10 @@ export function translateImageFormat(imageFormat: ImageFormat): GfxFormat {
         return GfxFormat.U8_RGBA_SRGB;
     case TypeFormat.Snorm:
         return GfxFormat.S8_RGBA_NORM;
+    // BC6H, which decompress clamps to 8 bits.
+    case TypeFormat.Ufloat:
+    case TypeFormat.Float:
+        return GfxFormat.U8_RGBA_NORM;
     default:
         throw "whoops";
     }